@@ -0,0 +1,63 @@
+use monkey_rs::environment::heap_stats;
+use monkey_rs::script::{run_source, Backend, RunOptions};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const PROGRAM: &str = "let fibonacci = fn(x) {
+    if (x == 0) {
+        0
+    } else {
+        if (x == 1) {
+            return 1;
+        } else {
+            fibonacci(x - 1) + fibonacci(x - 2);
+        }
+    }
+};
+fibonacci(N);
+";
+
+/// Each call leaves behind an environment holding a closure that captured it
+const CLOSURES: &str = "let make = fn(n) { let f = fn() { n }; f };
+let i = 0;
+while (i < 100000) { make(i); i += 1; }
+";
+
+const ITERATIONS: u32 = 3;
+
+fn bench(input: &str, backend: Backend) -> Duration {
+    let start = Instant::now();
+
+    let options = RunOptions {
+        backend,
+        ..RunOptions::default()
+    };
+    for _ in 0..ITERATIONS {
+        black_box(run_source(black_box(input), &options).unwrap());
+    }
+
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    for n in [15, 20, 25] {
+        let input = PROGRAM.replace('N', &n.to_string());
+        println!("fibonacci({}):", n);
+
+        for backend in [Backend::Eval, Backend::Vm] {
+            println!(
+                "  {:<6} {:?}",
+                format!("{:?}", backend),
+                bench(&input, backend)
+            );
+        }
+    }
+
+    let time = bench(CLOSURES, Backend::Eval);
+    let stats = heap_stats();
+    println!("closures:");
+    println!(
+        "  {:<6} {:?}, {} environments alive, {} collected",
+        "Eval", time, stats.environments, stats.collected
+    );
+}
@@ -0,0 +1,88 @@
+use monkey_rs::lexer::{Lexer, Scanner};
+use monkey_rs::token::Token;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const PROGRAM: &str = "let five = 5;
+let ten = 10;
+
+let add = fn(x, y) {
+    x + y;
+};
+
+let result = add(five, ten);
+!-/ *5;
+5 < 10 > 5;
+
+if (5 < 10) {
+    return true;
+} else {
+    return false;
+}
+
+10 == 10;
+10 != 9;
+";
+
+const ITERATIONS: u32 = 5;
+
+/// Lex the whole input, returning how many tokens were read
+fn lex(input: &str, scanner: Scanner) -> usize {
+    Lexer::with_scanner(input, scanner)
+        .take_while(|token| *token != Token::Eof)
+        .count()
+}
+
+fn bench(input: &str, scanner: Scanner) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        black_box(lex(black_box(input), scanner));
+    }
+
+    start.elapsed() / ITERATIONS
+}
+
+/// Print how long each scanner takes on the input, in total and per thousand chars
+fn report(input: &str, scanners: &[Scanner]) {
+    let chars = input.chars().count();
+    println!("{} chars:", chars);
+
+    for &scanner in scanners {
+        let elapsed = bench(input, scanner);
+        println!(
+            "  {:<8} {:>12?} {:>10?}/kchar",
+            format!("{:?}", scanner),
+            elapsed,
+            elapsed * 1000 / chars as u32
+        );
+    }
+}
+
+fn main() {
+    // `Chars` is quadratic, so the time per char grows with the input
+    for repeat in [10, 100, 400] {
+        report(
+            &PROGRAM.repeat(repeat),
+            &[Scanner::Chars, Scanner::CharVec, Scanner::Bytes],
+        );
+    }
+
+    // The others are linear, so the time per char stays flat up to multi-megabyte scripts
+    for repeat in [1_000, 10_000, 40_000] {
+        report(&PROGRAM.repeat(repeat), &[Scanner::CharVec, Scanner::Bytes]);
+    }
+
+    // Non-ASCII input makes `Bytes` fall back to `CharVec`
+    let unicode = PROGRAM.replace("five", "fünf");
+    report(&unicode.repeat(40_000), &[Scanner::CharVec, Scanner::Bytes]);
+
+    // Block comments are skipped with a string search, so a long license header costs little
+    // more than the code after it
+    let header = format!(
+        "/*{}*/\n{}",
+        " * Licensed under the terms in the LICENSE file.\n".repeat(20_000),
+        PROGRAM
+    );
+    report(&header, &[Scanner::Chars, Scanner::CharVec, Scanner::Bytes]);
+}
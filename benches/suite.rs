@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use monkey_rs::benchmark::{self, NESTED_DEPTH};
+use monkey_rs::script::{run_source, Backend, RunOptions};
+use std::hint::black_box;
+
+fn frontend(c: &mut Criterion) {
+    let source = benchmark::large_source();
+    let mut group = c.benchmark_group("lex");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("large file", |b| {
+        b.iter(|| benchmark::lex(black_box(&source)))
+    });
+    group.finish();
+
+    let source = benchmark::nested_expression(NESTED_DEPTH);
+    let mut group = c.benchmark_group("parse");
+    group.bench_function(format!("nested {} deep", NESTED_DEPTH), |b| {
+        b.iter(|| benchmark::parse(black_box(&source)))
+    });
+    group.finish();
+}
+
+fn backends(c: &mut Criterion) {
+    for (name, source) in benchmark::programs() {
+        let mut group = c.benchmark_group(name);
+        // Each run takes tens of milliseconds, so fewer samples still give a stable estimate
+        group.sample_size(20);
+
+        for backend in [Backend::Eval, Backend::Vm] {
+            let options = RunOptions {
+                backend,
+                ..RunOptions::default()
+            };
+            group.bench_with_input(
+                BenchmarkId::from_parameter(format!("{:?}", backend)),
+                &source,
+                |b, source| b.iter(|| run_source(source, &options).unwrap()),
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, frontend, backends);
+criterion_main!(benches);
@@ -0,0 +1,411 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::builtins;
+use crate::stdlib;
+use crate::token::Span;
+use std::collections::HashSet;
+use std::fmt;
+
+/// The kinds of problem the analysis finds
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FindingKind {
+    /// An identifier that isn't bound in any enclosing scope, nor a builtin
+    UndefinedVariable,
+    /// A `let` binding that's never read. Names starting with `_` are exempt.
+    UnusedBinding,
+    /// Statements after a `return`, `break` or `continue` in the same block
+    UnreachableCode,
+}
+
+/// A problem found without running the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub message: String,
+    /// The statement the problem is in, or the binding for an unused one, if the program was
+    /// parsed from source
+    pub span: Option<Span>,
+}
+
+impl Finding {
+    /// Whether the program would fail if run, rather than just containing something suspect
+    pub fn is_error(&self) -> bool {
+        self.kind == FindingKind::UndefinedVariable
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.is_error() { "error" } else { "warning" };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// Check a program for undefined variables, unused bindings and unreachable code, in the order
+/// they're found. Scoping follows the evaluator: only functions open a new scope, and a
+/// function body can refer to anything bound in its enclosing scopes by the time they end, as
+/// it won't run before then.
+///
+/// * `program` - The program to check
+pub fn analyze(program: &Program) -> Vec<Finding> {
+    let mut analyzer = Analyzer::default();
+
+    analyzer.scopes.push(Scope::default());
+    analyzer.statements(&program.statements, &program.spans);
+    analyzer.end_scope();
+
+    analyzer.findings
+}
+
+/// Where code follows a `return`, `break` or `continue` in the same block, giving the first such
+/// statement in each block. Programs built without a parser have no spans, so nothing is found.
+///
+/// * `program` - The program to check
+pub fn find_unreachable(program: &Program) -> Vec<Span> {
+    analyze(program)
+        .into_iter()
+        .filter(|finding| finding.kind == FindingKind::UnreachableCode)
+        .filter_map(|finding| finding.span)
+        .collect()
+}
+
+struct Binding {
+    name: String,
+    used: bool,
+    span: Option<Span>,
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    bindings: Vec<Binding>,
+    /// Function literals in this scope, checked once the scope's bindings are all known
+    functions: Vec<&'a Expression>,
+}
+
+#[derive(Default)]
+struct Analyzer<'a> {
+    scopes: Vec<Scope<'a>>,
+    findings: Vec<Finding>,
+    /// Names already reported as undefined, so each is only reported once
+    undefined: HashSet<String>,
+    /// The span of the statement being checked, if known
+    span: Option<Span>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn statements(&mut self, statements: &'a [Statement], spans: &[Span]) {
+        let mut terminated = None;
+        let outer = self.span;
+
+        for (i, statement) in statements.iter().enumerate() {
+            if let Statement::Comment(_) = statement {
+                continue;
+            }
+            self.span = spans.get(i).copied();
+            if let Some(keyword) = terminated.take() {
+                self.report(
+                    FindingKind::UnreachableCode,
+                    format!("unreachable code after {}", keyword),
+                );
+            }
+
+            match statement {
+                Statement::Let(name, value) => {
+                    self.expression(value);
+                    let span = self.span;
+                    self.scope().bindings.push(Binding {
+                        name: name.clone(),
+                        used: false,
+                        span,
+                    });
+                }
+                Statement::Assign(name, value) => {
+                    self.expression(value);
+                    if self.lookup(name).is_none() {
+                        self.undefined(name);
+                    }
+                }
+                Statement::Return(value) => {
+                    if let Some(value) = value {
+                        self.expression(value);
+                    }
+                    terminated = Some("return");
+                }
+                Statement::Expression(expression) => self.expression(expression),
+                Statement::Break => terminated = Some("break"),
+                Statement::Continue => terminated = Some("continue"),
+                Statement::Comment(_) => {}
+            }
+        }
+        self.span = outer;
+    }
+
+    fn block(&mut self, block: &'a BlockStatement) {
+        self.statements(&block.statements, &block.spans);
+    }
+
+    fn expression(&mut self, expression: &'a Expression) {
+        match expression {
+            Expression::Identifier(name) => match self.lookup(name) {
+                Some(binding) => binding.used = true,
+                None if builtins::lookup(name).is_some()
+                    || stdlib::defines(name)
+                    || name == "unquote"
+                    || name == "import" => {}
+                None => self.undefined(name),
+            },
+            Expression::Integer(_)
+            | Expression::Float(_)
+            | Expression::Boolean(_)
+            | Expression::String(_) => {}
+            Expression::Array(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expression::Index(left, index) => {
+                self.expression(left);
+                self.expression(index);
+            }
+            Expression::Hash(pairs) => {
+                for (key, value) in pairs {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expression::Prefix(_, right) => self.expression(right),
+            Expression::Infix(left, _, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expression(condition);
+                self.block(consequence);
+                if let Some(alternative) = alternative {
+                    self.block(alternative);
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expression(condition);
+                self.expression(consequence);
+                self.expression(alternative);
+            }
+            Expression::While { condition, body } => {
+                self.expression(condition);
+                self.block(body);
+            }
+            Expression::Function { .. } => self.scope().functions.push(expression),
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                // Quoted code is only data until it's unquoted somewhere else
+                if matches!(function.as_ref(), Expression::Identifier(name) if name == "quote") {
+                    return;
+                }
+                self.expression(function);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            // Macro bodies are templates for code that's checked where it's expanded
+            Expression::Macro { .. } => {}
+            Expression::Annotated { value, .. } => self.expression(value),
+        }
+    }
+
+    /// Check the functions deferred until the innermost scope's end, then report its unused
+    /// bindings and leave it
+    fn end_scope(&mut self) {
+        // Function bodies open scopes of their own, so nothing is added to this list meanwhile
+        for function in std::mem::take(&mut self.scope().functions) {
+            let Expression::Function { parameters, body } = function else {
+                unreachable!("only function literals are deferred");
+            };
+
+            let bindings = parameters
+                .iter()
+                .map(|name| Binding {
+                    name: name.clone(),
+                    used: true,
+                    span: None,
+                })
+                .collect();
+            self.scopes.push(Scope {
+                bindings,
+                functions: vec![],
+            });
+            self.block(body);
+            self.end_scope();
+        }
+
+        let scope = self.scopes.pop().expect("no scope to end");
+        for binding in scope.bindings {
+            if !binding.used && !binding.name.starts_with('_') {
+                self.findings.push(Finding {
+                    kind: FindingKind::UnusedBinding,
+                    message: format!("unused binding: {}", binding.name),
+                    span: binding.span,
+                });
+            }
+        }
+    }
+
+    fn scope(&mut self) -> &mut Scope<'a> {
+        self.scopes.last_mut().expect("no current scope")
+    }
+
+    /// Find the binding a name refers to, searching outwards from the innermost scope
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.bindings.iter_mut().rev().find(|b| b.name == name))
+    }
+
+    fn undefined(&mut self, name: &str) {
+        if self.undefined.insert(name.to_string()) {
+            self.report(
+                FindingKind::UndefinedVariable,
+                format!("undefined variable: {}", name),
+            );
+        }
+    }
+
+    /// Record a problem in the statement being checked
+    ///
+    /// * `kind` - The kind of problem
+    /// * `message` - A description of it
+    fn report(&mut self, kind: FindingKind, message: String) {
+        self.findings.push(Finding {
+            kind,
+            message,
+            span: self.span,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(input: &str) -> Vec<String> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+        analyze(&program).iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_clean_program() {
+        let input = "
+let fib = fn(n) { if (n < 2) { return n; } fib(n - 1) + fib(n - 2) };
+let later = fn() { helper() };
+let helper = fn() { len(\"abc\") };
+let total = 0;
+let i = 0;
+while (i < 3) { total += i; i += 1; }
+let unless = macro(c, a) { quote(if (!(unquote(c))) { unquote(a) }) };
+unless(false, puts(later(), total));
+let _ignored = 1;";
+
+        assert_eq!(check(input), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_findings() {
+        let input = "
+let unused = 1;
+let f = fn(x) {
+    let y = x + z;
+    return y;
+    puts(y);
+};
+f(missing, z);
+if (true) { break; continue; }
+undeclared = 2;";
+
+        assert_eq!(
+            check(input),
+            vec![
+                "error: undefined variable: missing",
+                "error: undefined variable: z",
+                "warning: unreachable code after break",
+                "error: undefined variable: undeclared",
+                "warning: unreachable code after return",
+                "warning: unused binding: unused",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_unreachable() {
+        let input = "
+let f = fn(x) {
+    if (x) { return 1; puts(x); } else { puts(x); }
+    return 2;
+    puts(x);
+    puts(x);
+};
+f(true);";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        // The else branch follows the return in the consequence, but isn't in the same block
+        let positions: Vec<_> = find_unreachable(&program)
+            .into_iter()
+            .map(|span| diagnostic::position_of(input, span.start).to_string())
+            .collect();
+        assert_eq!(positions, vec!["line 3, column 24", "line 5, column 5"]);
+
+        let mut parser = Parser::new(Lexer::new(
+            "let f = fn(x) { if (x) { return 1; } else { 2 } }; f(true);",
+        ));
+        assert_eq!(find_unreachable(&parser.parse_program()), vec![]);
+    }
+
+    #[test]
+    fn test_finding_spans() {
+        let input = "let unused = 1;\nlet f = fn() {\n    return 1;\n    puts(y);\n};\nf();";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        let findings: Vec<_> = analyze(&program)
+            .into_iter()
+            .map(|finding| {
+                let span = finding.span.unwrap();
+                let text: String = input
+                    .chars()
+                    .skip(span.start)
+                    .take(span.end - span.start)
+                    .collect();
+                (finding.message, text)
+            })
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                (
+                    "unreachable code after return".to_string(),
+                    "puts(y);".to_string()
+                ),
+                ("undefined variable: y".to_string(), "puts(y);".to_string()),
+                (
+                    "unused binding: unused".to_string(),
+                    "let unused = 1;".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use crate::token::{escape, Span, Token};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub statements: Vec<Statement>,
+    /// Where each statement was in the source, from its first token to its last, or empty if
+    /// the program wasn't parsed from source
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub spans: Vec<Span>,
+}
+
+// Spans are left out, so programs written differently but parsing the same way are equal
+impl PartialEq for Program {
+    fn eq(&self, other: &Self) -> bool {
+        self.statements == other.statements
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Statement {
+    Let(String, Expression),
+    /// Rebinding a name that's already bound, in the scope it was bound in
+    Assign(String, Expression),
+    /// Leaving the enclosing function, with null if no value is given
+    Return(Option<Expression>),
+    Expression(Expression),
+    Break,
+    Continue,
+    /// A comment, including its delimiters. Only parsed from a lexer that keeps comments.
+    Comment(String),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockStatement {
+    pub statements: Vec<Statement>,
+    /// Where each statement was in the source, as for `Program`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub spans: Vec<Span>,
+}
+
+impl PartialEq for BlockStatement {
+    fn eq(&self, other: &Self) -> bool {
+        self.statements == other.statements
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expression {
+    Identifier(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Expression>),
+    Index(Box<Expression>, Box<Expression>),
+    Hash(Vec<(Expression, Expression)>),
+    Prefix(Token<'static>, Box<Expression>),
+    Infix(Box<Expression>, Token<'static>, Box<Expression>),
+    If {
+        condition: Box<Expression>,
+        consequence: BlockStatement,
+        alternative: Option<BlockStatement>,
+    },
+    /// `condition ? consequence : alternative`, evaluating only the branch it picks
+    Conditional {
+        condition: Box<Expression>,
+        consequence: Box<Expression>,
+        alternative: Box<Expression>,
+    },
+    While {
+        condition: Box<Expression>,
+        body: BlockStatement,
+    },
+    Function {
+        parameters: Vec<String>,
+        body: BlockStatement,
+    },
+    Call {
+        function: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
+    Macro {
+        parameters: Vec<String>,
+        body: BlockStatement,
+    },
+    /// A value bound by `let` or passed as an argument, checked to have the type named by its
+    /// annotation, e.g. `int` in `let x: int = 5`
+    Annotated {
+        value: Box<Expression>,
+        type_name: String,
+    },
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for statement in &self.statements {
+            write!(f, "{}", statement)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Let(name, value) => write!(f, "let {} = {};", name, value),
+            Statement::Assign(name, value) => write!(f, "{} = {};", name, value),
+            Statement::Return(Some(value)) => write!(f, "return {};", value),
+            Statement::Return(None) => write!(f, "return;"),
+            Statement::Expression(expression) => write!(f, "{}", expression),
+            Statement::Break => write!(f, "break;"),
+            Statement::Continue => write!(f, "continue;"),
+            Statement::Comment(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl fmt::Display for BlockStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for statement in &self.statements {
+            write!(f, "{}", statement)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Identifier(name) => write!(f, "{}", name),
+            Expression::Integer(value) => write!(f, "{}", value),
+            // `Debug` keeps the `.0` on whole numbers, so they read back as floats
+            Expression::Float(value) => write!(f, "{:?}", value),
+            Expression::Boolean(value) => write!(f, "{}", value),
+            Expression::String(value) => write!(f, "\"{}\"", escape(value)),
+            Expression::Array(elements) => write!(f, "[{}]", join(elements)),
+            Expression::Index(left, index) => write!(f, "({}[{}])", left, index),
+            Expression::Hash(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Expression::Prefix(operator, right) => write!(f, "({}{})", operator, right),
+            Expression::Infix(left, operator, right) => {
+                write!(f, "({} {} {})", left, operator, right)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                write!(f, "if{} {}", condition, consequence)?;
+                match alternative {
+                    Some(alternative) => write!(f, "else {}", alternative),
+                    None => Ok(()),
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => write!(f, "({} ? {} : {})", condition, consequence, alternative),
+            Expression::While { condition, body } => write!(f, "while{} {}", condition, body),
+            Expression::Function { parameters, body } => {
+                write!(f, "fn({}) {}", parameters.join(", "), body)
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => write!(f, "{}({})", function, join(arguments)),
+            Expression::Macro { parameters, body } => {
+                write!(f, "macro({}) {}", parameters.join(", "), body)
+            }
+            Expression::Annotated { value, type_name } => write!(f, "({}: {})", value, type_name),
+        }
+    }
+}
+
+/// Rewrite every expression in a program, innermost first. The first error stops the rewrite.
+///
+/// * `program` - The program to rewrite
+/// * `modifier` - Called with each expression, returning what to replace it with
+pub fn modify_program<E>(
+    program: Program,
+    modifier: &mut impl FnMut(Expression) -> Result<Expression, E>,
+) -> Result<Program, E> {
+    let statements = program
+        .statements
+        .into_iter()
+        .map(|statement| modify_statement(statement, modifier))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Program {
+        statements,
+        spans: program.spans,
+    })
+}
+
+fn modify_statement<E>(
+    statement: Statement,
+    modifier: &mut impl FnMut(Expression) -> Result<Expression, E>,
+) -> Result<Statement, E> {
+    Ok(match statement {
+        Statement::Let(name, value) => Statement::Let(name, modify(value, modifier)?),
+        Statement::Assign(name, value) => Statement::Assign(name, modify(value, modifier)?),
+        Statement::Return(value) => {
+            Statement::Return(value.map(|value| modify(value, modifier)).transpose()?)
+        }
+        Statement::Expression(expression) => Statement::Expression(modify(expression, modifier)?),
+        statement @ (Statement::Break | Statement::Continue | Statement::Comment(_)) => statement,
+    })
+}
+
+fn modify_block<E>(
+    block: BlockStatement,
+    modifier: &mut impl FnMut(Expression) -> Result<Expression, E>,
+) -> Result<BlockStatement, E> {
+    let statements = block
+        .statements
+        .into_iter()
+        .map(|statement| modify_statement(statement, modifier))
+        .collect::<Result<_, _>>()?;
+
+    Ok(BlockStatement {
+        statements,
+        spans: block.spans,
+    })
+}
+
+/// Rewrite an expression and everything inside it, innermost first
+///
+/// * `expression` - The expression to rewrite
+/// * `modifier` - Called with each expression, returning what to replace it with
+pub fn modify<E>(
+    expression: Expression,
+    modifier: &mut impl FnMut(Expression) -> Result<Expression, E>,
+) -> Result<Expression, E> {
+    let expression = match expression {
+        Expression::Array(elements) => Expression::Array(modify_all(elements, modifier)?),
+        Expression::Index(left, index) => Expression::Index(
+            modify_boxed(left, modifier)?,
+            modify_boxed(index, modifier)?,
+        ),
+        Expression::Hash(pairs) => Expression::Hash(
+            pairs
+                .into_iter()
+                .map(|(key, value)| Ok((modify(key, modifier)?, modify(value, modifier)?)))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expression::Prefix(operator, right) => {
+            Expression::Prefix(operator, modify_boxed(right, modifier)?)
+        }
+        Expression::Infix(left, operator, right) => Expression::Infix(
+            modify_boxed(left, modifier)?,
+            operator,
+            modify_boxed(right, modifier)?,
+        ),
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+        } => Expression::If {
+            condition: modify_boxed(condition, modifier)?,
+            consequence: modify_block(consequence, modifier)?,
+            alternative: match alternative {
+                Some(alternative) => Some(modify_block(alternative, modifier)?),
+                None => None,
+            },
+        },
+        Expression::Conditional {
+            condition,
+            consequence,
+            alternative,
+        } => Expression::Conditional {
+            condition: modify_boxed(condition, modifier)?,
+            consequence: modify_boxed(consequence, modifier)?,
+            alternative: modify_boxed(alternative, modifier)?,
+        },
+        Expression::While { condition, body } => Expression::While {
+            condition: modify_boxed(condition, modifier)?,
+            body: modify_block(body, modifier)?,
+        },
+        Expression::Function { parameters, body } => Expression::Function {
+            parameters,
+            body: modify_block(body, modifier)?,
+        },
+        Expression::Call {
+            function,
+            arguments,
+        } => Expression::Call {
+            function: modify_boxed(function, modifier)?,
+            arguments: modify_all(arguments, modifier)?,
+        },
+        Expression::Macro { parameters, body } => Expression::Macro {
+            parameters,
+            body: modify_block(body, modifier)?,
+        },
+        Expression::Annotated { value, type_name } => Expression::Annotated {
+            value: modify_boxed(value, modifier)?,
+            type_name,
+        },
+        expression => expression,
+    };
+
+    modifier(expression)
+}
+
+/// Rewrite a boxed expression, reusing its allocation
+fn modify_boxed<E>(
+    mut expression: Box<Expression>,
+    modifier: &mut impl FnMut(Expression) -> Result<Expression, E>,
+) -> Result<Box<Expression>, E> {
+    let inner = std::mem::replace(&mut *expression, Expression::Boolean(false));
+    *expression = modify(inner, modifier)?;
+    Ok(expression)
+}
+
+fn modify_all<E>(
+    expressions: Vec<Expression>,
+    modifier: &mut impl FnMut(Expression) -> Result<Expression, E>,
+) -> Result<Vec<Expression>, E> {
+    expressions
+        .into_iter()
+        .map(|expression| modify(expression, modifier))
+        .collect()
+}
+
+/// Renders a list of expressions separated by commas
+///
+/// * `expressions` - The expressions to render
+fn join(expressions: &[Expression]) -> String {
+    expressions
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let program = Program {
+            statements: vec![Statement::Let(
+                "myVar".into(),
+                Expression::Identifier("anotherVar".into()),
+            )],
+            spans: vec![],
+        };
+
+        assert_eq!(program.to_string(), "let myVar = anotherVar;");
+    }
+
+    #[test]
+    fn test_modify() {
+        let one = || Expression::Integer(1);
+        let two = || Expression::Integer(2);
+        let mut turn_one_into_two = |expression| -> Result<_, ()> {
+            Ok(match expression {
+                Expression::Integer(1) => Expression::Integer(2),
+                expression => expression,
+            })
+        };
+
+        let tests = [
+            (one(), two()),
+            (
+                Expression::Infix(Box::new(one()), Token::Plus, Box::new(two())),
+                Expression::Infix(Box::new(two()), Token::Plus, Box::new(two())),
+            ),
+            (
+                Expression::Index(Box::new(one()), Box::new(one())),
+                Expression::Index(Box::new(two()), Box::new(two())),
+            ),
+            (
+                Expression::If {
+                    condition: Box::new(one()),
+                    consequence: BlockStatement {
+                        statements: vec![Statement::Expression(one())],
+                        spans: vec![],
+                    },
+                    alternative: Some(BlockStatement {
+                        statements: vec![Statement::Return(Some(one()))],
+                        spans: vec![],
+                    }),
+                },
+                Expression::If {
+                    condition: Box::new(two()),
+                    consequence: BlockStatement {
+                        statements: vec![Statement::Expression(two())],
+                        spans: vec![],
+                    },
+                    alternative: Some(BlockStatement {
+                        statements: vec![Statement::Return(Some(two()))],
+                        spans: vec![],
+                    }),
+                },
+            ),
+            (
+                Expression::Function {
+                    parameters: vec![],
+                    body: BlockStatement {
+                        statements: vec![Statement::Let("x".into(), one())],
+                        spans: vec![],
+                    },
+                },
+                Expression::Function {
+                    parameters: vec![],
+                    body: BlockStatement {
+                        statements: vec![Statement::Let("x".into(), two())],
+                        spans: vec![],
+                    },
+                },
+            ),
+            (
+                Expression::Conditional {
+                    condition: Box::new(one()),
+                    consequence: Box::new(one()),
+                    alternative: Box::new(two()),
+                },
+                Expression::Conditional {
+                    condition: Box::new(two()),
+                    consequence: Box::new(two()),
+                    alternative: Box::new(two()),
+                },
+            ),
+            (
+                Expression::Array(vec![one(), one()]),
+                Expression::Array(vec![two(), two()]),
+            ),
+            (
+                Expression::Hash(vec![(one(), one())]),
+                Expression::Hash(vec![(two(), two())]),
+            ),
+            (
+                Expression::Call {
+                    function: Box::new(Expression::Identifier("f".into())),
+                    arguments: vec![one()],
+                },
+                Expression::Call {
+                    function: Box::new(Expression::Identifier("f".into())),
+                    arguments: vec![two()],
+                },
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(modify(input, &mut turn_one_into_two), Ok(expected));
+        }
+    }
+}
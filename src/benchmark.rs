@@ -0,0 +1,225 @@
+use crate::ast::Program;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::script::{run_source, Backend, RunOptions};
+use crate::token::Token;
+use std::hint::black_box;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Which Fibonacci number the backends compute
+pub const FIBONACCI_N: u32 = 25;
+
+/// The number the sieve finds primes below
+pub const SIEVE_LIMIT: u32 = 1000;
+
+/// How deeply the parsed expression is nested, which stays within what the parser allows
+pub const NESTED_DEPTH: usize = 100;
+
+/// A statement of most kinds, repeated to make a large source for the lexer
+const SNIPPET: &str = "let five = 5;
+let ten = 10.5;
+
+let add = fn(x, y) {
+    x + y; // adds them
+};
+
+let result = add(five, ten);
+!-/ *5 % 2;
+5 < 10 > 5 <= 6 >= 7 && true || false;
+
+if (5 < 10) {
+    return true;
+} else {
+    return \"false\";
+}
+
+while (x != 9) { x += 1; break; }
+{\"a\": [1, 2][0]};
+";
+
+/// A source of around a megabyte, for lexing
+pub fn large_source() -> String {
+    SNIPPET.repeat(1_000_000 / SNIPPET.len())
+}
+
+/// An expression nested `depth` deep, cycling through grouping, arrays, calls and prefix
+/// operators
+///
+/// * `depth` - How deeply to nest it
+pub fn nested_expression(depth: usize) -> String {
+    let mut source = "x".to_string();
+
+    for i in 0..depth {
+        source = match i % 4 {
+            0 => format!("({} + {})", source, i),
+            1 => format!("[{}, {}]", source, i),
+            2 => format!("f({})", source),
+            _ => format!("-{}", source),
+        };
+    }
+
+    source
+}
+
+/// A program computing the `n`th Fibonacci number the slow, recursive way
+///
+/// * `n` - Which number to compute
+pub fn fibonacci(n: u32) -> String {
+    format!(
+        "let fibonacci = fn(x) {{
+    if (x < 2) {{
+        return x;
+    }}
+    fibonacci(x - 1) + fibonacci(x - 2)
+}};
+fibonacci({});",
+        n
+    )
+}
+
+/// A program counting the primes below a limit by sieving out the multiples of each in turn
+///
+/// * `limit` - The number to find primes below
+pub fn sieve(limit: u32) -> String {
+    format!(
+        "let sieve = fn(limit) {{
+    let numbers = [];
+    let i = 2;
+    while (i < limit) {{
+        numbers = push(numbers, i);
+        i += 1;
+    }}
+
+    let primes = [];
+    while (len(numbers) > 0) {{
+        let p = first(numbers);
+        primes = push(primes, p);
+        numbers = filter(rest(numbers), fn(n) {{ n % p != 0 }});
+    }}
+    primes
+}};
+len(sieve({}));",
+        limit
+    )
+}
+
+/// The programs run on each backend, by name
+pub fn programs() -> Vec<(String, String)> {
+    vec![
+        (
+            format!("fibonacci({})", FIBONACCI_N),
+            fibonacci(FIBONACCI_N),
+        ),
+        (format!("sieve({})", SIEVE_LIMIT), sieve(SIEVE_LIMIT)),
+    ]
+}
+
+/// Lex a whole source, returning how many tokens were read
+///
+/// * `source` - The source to lex
+pub fn lex(source: &str) -> usize {
+    Lexer::new(source)
+        .take_while(|token| *token != Token::Eof)
+        .count()
+}
+
+/// Parse a source that's known to be valid
+///
+/// * `source` - The source to parse
+pub fn parse(source: &str) -> Program {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+    assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+    program
+}
+
+/// Time each benchmark, writing a line with its average time. This is a quicker, rougher
+/// version of the Criterion suite in `benches/`, for reproducing its numbers without the
+/// source.
+///
+/// * `iterations` - How many times to run each benchmark
+/// * `out` - Where to write the results
+pub fn run<W: Write>(iterations: u32, out: &mut W) -> io::Result<()> {
+    let source = large_source();
+    let time = average(iterations, || lex(&source));
+    writeln!(
+        out,
+        "{:<24} {:>14?}",
+        format!("lex {} KiB", source.len() / 1024),
+        time
+    )?;
+
+    let source = nested_expression(NESTED_DEPTH);
+    let time = average(iterations, || parse(&source));
+    writeln!(
+        out,
+        "{:<24} {:>14?}",
+        format!("parse {} deep", NESTED_DEPTH),
+        time
+    )?;
+
+    for (name, source) in programs() {
+        for backend in [Backend::Eval, Backend::Vm] {
+            let options = RunOptions {
+                backend,
+                ..RunOptions::default()
+            };
+            let time = average(iterations, || {
+                run_source(&source, &options).expect("benchmarks run cleanly")
+            });
+            writeln!(
+                out,
+                "{:<24} {:>14?}",
+                format!("{} {:?}", name, backend),
+                time
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How long something takes on average
+///
+/// * `iterations` - How many times to run it
+/// * `f` - What to run
+fn average<T>(iterations: u32, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..iterations {
+        black_box(f());
+    }
+
+    start.elapsed() / iterations.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    #[test]
+    fn test_workloads() {
+        for backend in [Backend::Eval, Backend::Vm] {
+            let options = RunOptions {
+                backend,
+                ..RunOptions::default()
+            };
+            assert_eq!(
+                run_source(&fibonacci(10), &options).unwrap(),
+                Object::Integer(55)
+            );
+            assert_eq!(
+                run_source(&sieve(100), &options).unwrap(),
+                Object::Integer(25)
+            );
+        }
+
+        assert!(large_source().len() > 900_000);
+        assert_eq!(lex(SNIPPET), 102);
+        assert_eq!(nested_expression(4), "-f([(x + 0), 1])");
+        parse(&nested_expression(NESTED_DEPTH));
+    }
+}
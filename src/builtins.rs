@@ -0,0 +1,1239 @@
+use crate::error::RuntimeErrorKind;
+use crate::object::{Arity, Builtin, HashKey, Memoized, Object};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Every builtin function. Identifiers that aren't bound in the environment are looked up here,
+/// so adding a builtin only needs an entry in this list.
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "len",
+        func: len,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "first",
+        func: first,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "last",
+        func: last,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "rest",
+        func: rest,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "push",
+        func: push,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "puts",
+        func: puts,
+        arity: Arity::AtLeast(0),
+    },
+    Builtin {
+        name: "split",
+        func: split,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "join",
+        func: join,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "upper",
+        func: upper,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "lower",
+        func: lower,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "trim",
+        func: trim,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "sort",
+        func: sort,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "abs",
+        func: abs,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "sqrt",
+        func: sqrt,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "pow",
+        func: pow,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "floor",
+        func: floor,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "ceil",
+        func: ceil,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "min",
+        func: min,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "max",
+        func: max,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "type",
+        func: type_of,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "print",
+        func: print,
+        arity: Arity::AtLeast(0),
+    },
+    Builtin {
+        name: "format",
+        func: format,
+        arity: Arity::AtLeast(1),
+    },
+    Builtin {
+        name: "words",
+        func: words,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "split_whitespace",
+        func: words,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "group_by",
+        func: group_by,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "take",
+        func: take,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "drop",
+        func: drop,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "chunk",
+        func: chunk,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "flatten",
+        func: flatten,
+        arity: Arity::Between(1, 2),
+    },
+    Builtin {
+        name: "deep_equal",
+        func: deep_equal,
+        arity: Arity::Exactly(2),
+    },
+    Builtin {
+        name: "float",
+        func: float,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "memoize",
+        func: memoize,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "size",
+        func: size,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "depth",
+        func: depth,
+        arity: Arity::Exactly(1),
+    },
+    Builtin {
+        name: "assert_eq",
+        func: assert_eq,
+        arity: Arity::Between(2, 3),
+    },
+    Builtin {
+        name: "dict",
+        func: dict,
+        arity: Arity::Exactly(2),
+    },
+    #[cfg(feature = "serde")]
+    Builtin {
+        name: "to_json",
+        func: to_json,
+        arity: Arity::Exactly(1),
+    },
+    #[cfg(feature = "serde")]
+    Builtin {
+        name: "from_json",
+        func: from_json,
+        arity: Arity::Exactly(1),
+    },
+];
+
+/// What a builtin can reach of the backend running it
+pub trait Context {
+    /// Where builtins like `puts` write
+    fn output(&mut self) -> &mut dyn Write;
+
+    /// Call a function, e.g. one passed to a builtin as an argument, returning its value or
+    /// an error object
+    ///
+    /// * `function` - The function to call
+    /// * `args` - The arguments to call it with
+    fn call(&mut self, function: Object, args: Vec<Object>) -> Object;
+}
+
+/// Where builtins like `puts` write, which is standard output unless the host gives somewhere
+/// else
+pub struct Output<'a>(Box<dyn Write + 'a>);
+
+impl<'a> Output<'a> {
+    /// * `out` - Where to write
+    pub fn new<W: Write + 'a>(out: W) -> Output<'a> {
+        Output(Box::new(out))
+    }
+}
+
+impl Default for Output<'_> {
+    fn default() -> Self {
+        Output::new(io::stdout())
+    }
+}
+
+impl fmt::Debug for Output<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Output kept in memory, e.g. to read back what a script printed. Clones share the same
+/// buffer, so one can be handed to an interpreter while another reads from it.
+#[derive(Debug, Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    /// Everything written so far, leaving it in the buffer
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Take everything written so far, leaving the buffer empty
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Every builtin function, in the order the compiler numbers them
+pub fn all() -> &'static [Builtin] {
+    BUILTINS
+}
+
+/// Find a builtin function by name
+///
+/// * `name` - The name the builtin is called by
+pub fn lookup(name: &str) -> Option<Builtin> {
+    BUILTINS
+        .iter()
+        .find(|builtin| builtin.name == name)
+        .copied()
+}
+
+/// Check a builtin was called with the number of arguments it takes
+///
+/// * `args` - The arguments passed
+/// * `want` - The number of arguments expected
+fn check_arity(args: &[Object], want: usize) -> Result<(), Object> {
+    if args.len() == want {
+        Ok(())
+    } else {
+        Err(Object::error(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!(
+                "wrong number of arguments. got={}, want={}",
+                args.len(),
+                want
+            ),
+        ))
+    }
+}
+
+/// Get the array passed as the only argument to a builtin
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `args` - The arguments passed
+fn array_arg<'a>(name: &str, args: &'a [Object]) -> Result<&'a [Object], Object> {
+    check_arity(args, 1)?;
+    array_value(name, &args[0])
+}
+
+/// Get an array argument to a builtin
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `arg` - The argument
+fn array_value<'a>(name: &str, arg: &'a Object) -> Result<&'a [Object], Object> {
+    match arg {
+        Object::Array(elements) => Ok(elements),
+        arg => Err(Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!(
+                "argument to `{}` must be ARRAY, got {}",
+                name,
+                arg.type_name()
+            ),
+        )),
+    }
+}
+
+/// Get a string argument to a builtin
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `arg` - The argument
+fn string_arg<'a>(name: &str, arg: &'a Object) -> Result<&'a str, Object> {
+    match arg {
+        Object::String(value) => Ok(value),
+        arg => Err(Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!(
+                "argument to `{}` must be STRING, got {}",
+                name,
+                arg.type_name()
+            ),
+        )),
+    }
+}
+
+/// Get a numeric argument to a builtin, widening integers to floats
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `arg` - The argument
+fn number_arg(name: &str, arg: &Object) -> Result<f64, Object> {
+    match arg {
+        Object::Integer(value) => Ok(*value as f64),
+        Object::Float(value) => Ok(*value),
+        arg => Err(Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!(
+                "argument to `{}` must be a number, got {}",
+                name,
+                arg.type_name()
+            ),
+        )),
+    }
+}
+
+/// Get a count passed to a builtin, which must be a non-negative integer
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `arg` - The argument
+fn count_arg(name: &str, arg: &Object) -> Result<usize, Object> {
+    match arg {
+        Object::Integer(value) => usize::try_from(*value).map_err(|_| {
+            Object::error(
+                RuntimeErrorKind::InvalidArgument,
+                format!("argument to `{}` must not be negative, got {}", name, value),
+            )
+        }),
+        arg => Err(Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!(
+                "argument to `{}` must be INTEGER, got {}",
+                name,
+                arg.type_name()
+            ),
+        )),
+    }
+}
+
+fn overflow(name: &str) -> Object {
+    Object::error(
+        RuntimeErrorKind::IntegerOverflow,
+        format!("integer overflow in `{}`", name),
+    )
+}
+
+/// The number of elements in an array, or of chars in a string, i.e. Unicode scalar values
+/// rather than bytes or user-perceived characters, so `len("é")` is 2 when the accent is a
+/// combining mark
+fn len(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::String(value) => Object::Integer(value.chars().count() as i64),
+        Object::Array(elements) => Object::Integer(elements.len() as i64),
+        arg => Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!("argument to `len` not supported, got {}", arg.type_name()),
+        ),
+    }
+}
+
+fn first(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    match array_arg("first", &args) {
+        Ok(elements) => elements.first().cloned().unwrap_or(Object::Null),
+        Err(err) => err,
+    }
+}
+
+fn last(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    match array_arg("last", &args) {
+        Ok(elements) => elements.last().cloned().unwrap_or(Object::Null),
+        Err(err) => err,
+    }
+}
+
+/// A new array of everything but the first element, or `null` for an empty array
+fn rest(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    match array_arg("rest", &args) {
+        Ok([]) => Object::Null,
+        Ok([_, rest @ ..]) => Object::Array(rest.to_vec()),
+        Err(err) => err,
+    }
+}
+
+/// Split an array in two after some number of elements, for `take` and `drop`. Counts past
+/// the end split after the last element.
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `args` - The arguments passed
+fn split_array<'a>(name: &str, args: &'a [Object]) -> Result<(&'a [Object], &'a [Object]), Object> {
+    check_arity(args, 2)?;
+    let elements = array_value(name, &args[0])?;
+    let count = count_arg(name, &args[1])?;
+
+    Ok(elements.split_at(count.min(elements.len())))
+}
+
+/// The first `n` elements of an array, or all of them if there are fewer
+fn take(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    match split_array("take", &args) {
+        Ok((taken, _)) => Object::Array(taken.to_vec()),
+        Err(err) => err,
+    }
+}
+
+/// All but the first `n` elements of an array
+fn drop(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    match split_array("drop", &args) {
+        Ok((_, rest)) => Object::Array(rest.to_vec()),
+        Err(err) => err,
+    }
+}
+
+/// Split an array into arrays of `size` elements, the last of which holds what's left over
+fn chunk(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    let elements = match array_value("chunk", &args[0]) {
+        Ok(elements) => elements,
+        Err(err) => return err,
+    };
+    let size = match &args[1] {
+        Object::Integer(size) if *size > 0 => usize::try_from(*size).unwrap_or(usize::MAX),
+        Object::Integer(size) => {
+            return Object::error(
+                RuntimeErrorKind::InvalidArgument,
+                format!("size passed to `chunk` must be positive, got {}", size),
+            )
+        }
+        arg => {
+            return Object::error(
+                RuntimeErrorKind::InvalidArgument,
+                format!(
+                    "argument to `chunk` must be INTEGER, got {}",
+                    arg.type_name()
+                ),
+            )
+        }
+    };
+
+    Object::Array(
+        elements
+            .chunks(size)
+            .map(|chunk| Object::Array(chunk.to_vec()))
+            .collect(),
+    )
+}
+
+/// Replace arrays inside an array with their elements, one level deep or as many as the second
+/// argument gives. A negative depth flattens every level.
+fn flatten(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if !Arity::Between(1, 2).accepts(args.len()) {
+        return Object::error(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!("wrong number of arguments. got={}, want=1 or 2", args.len()),
+        );
+    }
+
+    let elements = match array_value("flatten", &args[0]) {
+        Ok(elements) => elements,
+        Err(err) => return err,
+    };
+    let depth = match args.get(1) {
+        None => Some(1),
+        Some(Object::Integer(depth)) => usize::try_from(*depth).ok(),
+        Some(arg) => {
+            return Object::error(
+                RuntimeErrorKind::InvalidArgument,
+                format!(
+                    "argument to `flatten` must be INTEGER, got {}",
+                    arg.type_name()
+                ),
+            )
+        }
+    };
+
+    let mut flattened = vec![];
+    flatten_into(elements, depth, &mut flattened);
+    Object::Array(flattened)
+}
+
+/// Push the elements of an array, flattening arrays among them
+///
+/// * `elements` - The elements to flatten
+/// * `depth` - How many levels of arrays to flatten, or `None` for all of them
+/// * `flattened` - Where to push the elements
+fn flatten_into(elements: &[Object], depth: Option<usize>, flattened: &mut Vec<Object>) {
+    for element in elements {
+        match element {
+            Object::Array(inner) if depth != Some(0) => {
+                flatten_into(inner, depth.map(|depth| depth - 1), flattened)
+            }
+            element => flattened.push(element.clone()),
+        }
+    }
+}
+
+/// Whether two values are equal, comparing arrays and hashes element by element. Integers equal
+/// floats of the same value, as with `==`, but unlike `==` NaN equals NaN, so a test can expect
+/// it.
+fn deep_equal(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    Object::Boolean(values_equal(&args[0], &args[1]))
+}
+
+/// Compare two values for `deep_equal`
+///
+/// * `left` - The first value
+/// * `right` - The second value
+fn values_equal(left: &Object, right: &Object) -> bool {
+    match (left, right) {
+        (Object::Float(left), Object::Float(right)) => {
+            left == right || (left.is_nan() && right.is_nan())
+        }
+        (Object::Integer(left), Object::Float(right))
+        | (Object::Float(right), Object::Integer(left)) => *left as f64 == *right,
+        (Object::Array(left), Object::Array(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .zip(right)
+                    .all(|(left, right)| values_equal(left, right))
+        }
+        (Object::Hash(left), Object::Hash(right)) => {
+            left.len() == right.len()
+                && left.iter().all(|(key, left)| {
+                    right
+                        .get(key)
+                        .is_some_and(|right| values_equal(left, right))
+                })
+        }
+        (left, right) => left == right,
+    }
+}
+
+/// Wrap a function so it's only called once for each list of arguments, giving back what it
+/// returned the first time after that
+fn memoize(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        function if function.arity().is_some() || matches!(function, Object::Native(_)) => {
+            Object::Memoized(Rc::new(Memoized::new(function.clone())))
+        }
+        arg => Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!(
+                "argument to `memoize` must be a function, got {}",
+                arg.type_name()
+            ),
+        ),
+    }
+}
+
+/// Count the values in a structure, looking inside nested arrays and hash values. Anything else
+/// counts as one.
+fn size(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    Object::Integer(size_of(&args[0]) as i64)
+}
+
+/// Count the values in a structure for `size`
+///
+/// * `value` - The structure
+fn size_of(value: &Object) -> usize {
+    match value {
+        Object::Array(elements) => elements.iter().map(size_of).sum(),
+        Object::Hash(pairs) => pairs.values().map(size_of).sum(),
+        _ => 1,
+    }
+}
+
+/// How deeply arrays and hashes are nested in a structure. Anything else has a depth of zero.
+fn depth(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    Object::Integer(depth_of(&args[0]) as i64)
+}
+
+/// Measure how deeply a structure is nested for `depth`
+///
+/// * `value` - The structure
+fn depth_of(value: &Object) -> usize {
+    let inner = match value {
+        Object::Array(elements) => elements.iter().map(depth_of).max(),
+        Object::Hash(pairs) => pairs.values().map(depth_of).max(),
+        _ => return 0,
+    };
+    inner.unwrap_or(0) + 1
+}
+
+/// Check two values are equal as `deep_equal` compares them, failing with both values, and the
+/// message given as the third argument if there is one, if they aren't
+fn assert_eq(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if !Arity::Between(2, 3).accepts(args.len()) {
+        return Object::error(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!("wrong number of arguments. got={}, want=2 or 3", args.len()),
+        );
+    }
+
+    let (actual, expected) = (&args[0], &args[1]);
+    if values_equal(actual, expected) {
+        return Object::Null;
+    }
+
+    let message = format!("expected {}, got {}", expected, actual);
+    Object::error(
+        RuntimeErrorKind::AssertionFailed,
+        match args.get(2) {
+            Some(context) => format!("assertion failed: {}: {}", context, message),
+            None => format!("assertion failed: {}", message),
+        },
+    )
+}
+
+/// Build a hash by pairing up an array of keys with an array of values
+fn dict(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    let (keys, values) = match (array_value("dict", &args[0]), array_value("dict", &args[1])) {
+        (Ok(keys), Ok(values)) => (keys, values),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    if keys.len() != values.len() {
+        return Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!(
+                "arrays passed to `dict` must be the same length, got {} keys and {} values",
+                keys.len(),
+                values.len()
+            ),
+        );
+    }
+
+    let mut pairs = HashMap::with_capacity(keys.len());
+    for (key, value) in keys.iter().zip(values) {
+        let hash_key = match key.hash_key() {
+            Ok(hash_key) => hash_key,
+            Err(err) => return err,
+        };
+        if pairs.insert(hash_key, value.clone()).is_some() {
+            return Object::error(
+                RuntimeErrorKind::InvalidArgument,
+                format!("duplicate key passed to `dict`: {}", key),
+            );
+        }
+    }
+
+    Object::Hash(pairs)
+}
+
+/// Serialize a value as JSON. Hashes must only have string keys, and floats must be finite.
+#[cfg(feature = "serde")]
+fn to_json(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match json_value(&args[0]) {
+        Ok(value) => Object::String(value.to_string()),
+        Err(err) => err,
+    }
+}
+
+/// Convert a value for `to_json`
+///
+/// * `value` - The value to convert
+#[cfg(feature = "serde")]
+fn json_value(value: &Object) -> Result<serde_json::Value, Object> {
+    use serde_json::{Number, Value};
+
+    let unsupported = |message: String| Object::error(RuntimeErrorKind::InvalidArgument, message);
+
+    Ok(match value {
+        Object::Integer(value) => Value::from(*value),
+        Object::Float(value) => match Number::from_f64(*value) {
+            Some(number) => Value::Number(number),
+            None => return Err(unsupported(format!("{:?} can't be written as JSON", value))),
+        },
+        Object::Boolean(value) => Value::Bool(*value),
+        Object::String(value) => Value::String(value.clone()),
+        Object::Null => Value::Null,
+        Object::Array(elements) => {
+            Value::Array(elements.iter().map(json_value).collect::<Result<_, _>>()?)
+        }
+        Object::Hash(pairs) => Value::Object(
+            pairs
+                .iter()
+                .map(|(key, value)| match key {
+                    HashKey::String(key) => Ok((key.clone(), json_value(value)?)),
+                    key => Err(unsupported(format!(
+                        "hash keys must be STRING to be written as JSON, got {}",
+                        Object::from(key.clone()).type_name()
+                    ))),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        value => {
+            return Err(unsupported(format!(
+                "{} can't be written as JSON",
+                value.type_name()
+            )))
+        }
+    })
+}
+
+/// Parse JSON into the matching values. Objects become hashes, and numbers become integers if
+/// they fit in one, otherwise floats.
+#[cfg(feature = "serde")]
+fn from_json(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    let json = match string_arg("from_json", &args[0]) {
+        Ok(json) => json,
+        Err(err) => return err,
+    };
+
+    match serde_json::from_str(json) {
+        Ok(value) => json_object(value),
+        Err(err) => Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!("could not parse JSON: {}", err),
+        ),
+    }
+}
+
+/// Convert a parsed value for `from_json`
+///
+/// * `value` - The value to convert
+#[cfg(feature = "serde")]
+fn json_object(value: serde_json::Value) -> Object {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => Object::Null,
+        Value::Bool(value) => Object::Boolean(value),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => Object::Integer(value),
+            None => Object::Float(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(value) => Object::String(value),
+        Value::Array(elements) => Object::Array(elements.into_iter().map(json_object).collect()),
+        Value::Object(pairs) => Object::Hash(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (HashKey::String(key), json_object(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// A new array with an element added to the end, leaving the original untouched
+fn push(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut elements = elements.clone();
+            elements.push(args[1].clone());
+            Object::Array(elements)
+        }
+        arg => Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!("argument to `push` must be ARRAY, got {}", arg.type_name()),
+        ),
+    }
+}
+
+/// Write each argument on a line of its own
+fn puts(args: Vec<Object>, ctx: &mut dyn Context) -> Object {
+    let out = ctx.output();
+    let written = args.iter().try_for_each(|arg| writeln!(out, "{}", arg));
+
+    match written {
+        Ok(()) => Object::Null,
+        Err(err) => output_error("puts", err),
+    }
+}
+
+/// Write the arguments one after another, with nothing between them and no newline after
+fn print(args: Vec<Object>, ctx: &mut dyn Context) -> Object {
+    let out = ctx.output();
+    let written = args
+        .iter()
+        .try_for_each(|arg| write!(out, "{}", arg))
+        .and_then(|()| out.flush());
+
+    match written {
+        Ok(()) => Object::Null,
+        Err(err) => output_error("print", err),
+    }
+}
+
+fn output_error(name: &str, err: io::Error) -> Object {
+    Object::error(
+        RuntimeErrorKind::Output,
+        format!("could not write the output of `{}`: {}", name, err),
+    )
+}
+
+/// Split a string on a separator, or into characters if the separator is empty
+fn split(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    let (value, separator) = match (string_arg("split", &args[0]), string_arg("split", &args[1])) {
+        (Ok(value), Ok(separator)) => (value, separator),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+
+    let parts = if separator.is_empty() {
+        value
+            .chars()
+            .map(|c| Object::String(c.to_string()))
+            .collect()
+    } else {
+        value
+            .split(separator)
+            .map(|part| Object::String(part.to_string()))
+            .collect()
+    };
+
+    Object::Array(parts)
+}
+
+/// Split a string on runs of whitespace, leaving out empty parts at either end
+fn words(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match string_arg("words", &args[0]) {
+        Ok(value) => Object::Array(
+            value
+                .split_whitespace()
+                .map(|word| Object::String(word.to_string()))
+                .collect(),
+        ),
+        Err(err) => err,
+    }
+}
+
+/// Join the elements of an array into a string, with a separator between each
+fn join(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    let separator = match string_arg("join", &args[1]) {
+        Ok(separator) => separator,
+        Err(err) => return err,
+    };
+
+    match &args[0] {
+        Object::Array(elements) => Object::String(
+            elements
+                .iter()
+                .map(|element| element.to_string())
+                .collect::<Vec<_>>()
+                .join(separator),
+        ),
+        arg => Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            format!("argument to `join` must be ARRAY, got {}", arg.type_name()),
+        ),
+    }
+}
+
+/// Apply a function to the only argument to a builtin, which must be a string
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `args` - The arguments passed
+/// * `f` - What the builtin does to the string
+fn map_string(name: &str, args: &[Object], f: fn(&str) -> String) -> Object {
+    if let Err(err) = check_arity(args, 1) {
+        return err;
+    }
+
+    match string_arg(name, &args[0]) {
+        Ok(value) => Object::String(f(value)),
+        Err(err) => err,
+    }
+}
+
+fn upper(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    map_string("upper", &args, str::to_uppercase)
+}
+
+fn lower(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    map_string("lower", &args, str::to_lowercase)
+}
+
+fn trim(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    map_string("trim", &args, |value| value.trim().to_string())
+}
+
+/// A new array of the same numbers or strings, in ascending order
+fn sort(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    let elements = match array_arg("sort", &args) {
+        Ok(elements) => elements,
+        Err(err) => return err,
+    };
+
+    let mut sorted = elements.to_vec();
+    if sorted.iter().all(|e| matches!(e, Object::String(_))) {
+        sorted.sort_by(|a, b| match (a, b) {
+            (Object::String(a), Object::String(b)) => a.cmp(b),
+            _ => unreachable!("only strings are sorted here"),
+        });
+    } else if sorted
+        .iter()
+        .all(|e| matches!(e, Object::Integer(_) | Object::Float(_)))
+    {
+        sorted.sort_by(|a, b| {
+            let (Ok(a), Ok(b)) = (number_arg("sort", a), number_arg("sort", b)) else {
+                unreachable!("only numbers are sorted here");
+            };
+            a.total_cmp(&b)
+        });
+    } else {
+        return Object::error(
+            RuntimeErrorKind::InvalidArgument,
+            "argument to `sort` must be ARRAY of all numbers or all strings",
+        );
+    }
+
+    Object::Array(sorted)
+}
+
+fn abs(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Integer(value) => value
+            .checked_abs()
+            .map_or_else(|| overflow("abs"), Object::Integer),
+        arg => match number_arg("abs", arg) {
+            Ok(value) => Object::Float(value.abs()),
+            Err(err) => err,
+        },
+    }
+}
+
+fn sqrt(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match number_arg("sqrt", &args[0]) {
+        Ok(value) => Object::Float(value.sqrt()),
+        Err(err) => err,
+    }
+}
+
+/// Convert an integer or a string to a float. Strings can also be `inf`, `-inf` or `nan` in any
+/// case. NaN isn't equal to anything under `==`, itself included.
+fn float(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::String(value) => match value.trim().parse() {
+            Ok(value) => Object::Float(value),
+            Err(_) => Object::error(
+                RuntimeErrorKind::InvalidArgument,
+                format!("could not parse {:?} as a float", value),
+            ),
+        },
+        arg => match number_arg("float", arg) {
+            Ok(value) => Object::Float(value),
+            Err(err) => err,
+        },
+    }
+}
+
+/// Raise a number to a power, staying an integer for integers raised to non-negative powers
+fn pow(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    if let (Object::Integer(base), Object::Integer(exponent)) = (&args[0], &args[1]) {
+        if let Ok(exponent) = u32::try_from(*exponent) {
+            return base
+                .checked_pow(exponent)
+                .map_or_else(|| overflow("pow"), Object::Integer);
+        }
+    }
+
+    match (number_arg("pow", &args[0]), number_arg("pow", &args[1])) {
+        (Ok(base), Ok(exponent)) => Object::Float(base.powf(exponent)),
+        (Err(err), _) | (_, Err(err)) => err,
+    }
+}
+
+/// Round a number to an integer
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `args` - The arguments passed
+/// * `round` - How to round a float
+fn round_with(name: &str, args: &[Object], round: fn(f64) -> f64) -> Object {
+    if let Err(err) = check_arity(args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Integer(value) => Object::Integer(*value),
+        arg => match number_arg(name, arg) {
+            Ok(value) => Object::Integer(round(value) as i64),
+            Err(err) => err,
+        },
+    }
+}
+
+fn floor(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    round_with("floor", &args, f64::floor)
+}
+
+fn ceil(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    round_with("ceil", &args, f64::ceil)
+}
+
+/// Pick the element of an array of numbers that compares first, or `null` for an empty array
+///
+/// * `name` - The name of the builtin, for error messages
+/// * `args` - The arguments passed
+/// * `wanted` - The ordering of a candidate against the best so far that replaces it
+fn extreme(name: &str, args: &[Object], wanted: std::cmp::Ordering) -> Object {
+    let elements = match array_arg(name, args) {
+        Ok(elements) => elements,
+        Err(err) => return err,
+    };
+
+    let mut best: Option<(&Object, f64)> = None;
+    for element in elements {
+        let value = match number_arg(name, element) {
+            Ok(value) => value,
+            Err(err) => return err,
+        };
+        if best.is_none_or(|(_, best)| value.total_cmp(&best) == wanted) {
+            best = Some((element, value));
+        }
+    }
+
+    best.map_or(Object::Null, |(element, _)| element.clone())
+}
+
+fn min(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    extreme("min", &args, std::cmp::Ordering::Less)
+}
+
+fn max(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    extreme("max", &args, std::cmp::Ordering::Greater)
+}
+
+/// The name of a value's type, as used in error messages
+fn type_of(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 1) {
+        return err;
+    }
+
+    Object::String(args[0].type_name().to_string())
+}
+
+/// Group the elements of an array into a hash, keyed by what a function returns for each.
+/// Elements keep their order within each group.
+fn group_by(args: Vec<Object>, ctx: &mut dyn Context) -> Object {
+    if let Err(err) = check_arity(&args, 2) {
+        return err;
+    }
+
+    let elements = match array_value("group_by", &args[0]) {
+        Ok(elements) => elements,
+        Err(err) => return err,
+    };
+
+    let mut groups: HashMap<HashKey, Vec<Object>> = HashMap::new();
+    for element in elements {
+        let key = ctx.call(args[1].clone(), vec![element.clone()]);
+        if key.is_error() {
+            return key;
+        }
+
+        match key.hash_key() {
+            Ok(key) => groups.entry(key).or_default().push(element.clone()),
+            Err(err) => return err,
+        }
+    }
+
+    Object::Hash(
+        groups
+            .into_iter()
+            .map(|(key, group)| (key, Object::Array(group)))
+            .collect(),
+    )
+}
+
+/// Fill in the `{}` placeholders in a template with the rest of the arguments, in order. `{{`
+/// and `}}` stand for literal braces.
+fn format(args: Vec<Object>, _ctx: &mut dyn Context) -> Object {
+    let Some((template, values)) = args.split_first() else {
+        return Object::error(
+            RuntimeErrorKind::WrongArgumentCount,
+            "wrong number of arguments. got=0, want=at least 1",
+        );
+    };
+    let template = match string_arg("format", template) {
+        Ok(template) => template,
+        Err(err) => return err,
+    };
+
+    let mut formatted = String::with_capacity(template.len());
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'}').is_some() => {
+                if let Some(value) = values.get(placeholders) {
+                    formatted += &value.to_string();
+                }
+                placeholders += 1;
+            }
+            '{' | '}' if chars.next_if_eq(&c).is_some() => formatted.push(c),
+            '{' | '}' => {
+                return Object::error(
+                    RuntimeErrorKind::InvalidArgument,
+                    format!(
+                        "unmatched `{}` in the template passed to `format`, expected `{{}}`, \
+                         `{{{{` or `}}}}`",
+                        c
+                    ),
+                )
+            }
+            c => formatted.push(c),
+        }
+    }
+
+    if placeholders != values.len() {
+        return Object::error(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!(
+                "wrong number of values for `format`. got={}, want={}",
+                values.len(),
+                placeholders
+            ),
+        );
+    }
+
+    Object::String(formatted)
+}
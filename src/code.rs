@@ -0,0 +1,268 @@
+use std::fmt;
+
+/// Encoded bytecode: each instruction is an opcode byte followed by its big-endian operands
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Instructions(pub Vec<u8>);
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Opcode {
+    Constant,
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    True,
+    False,
+    Null,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    GreaterEqual,
+    LessEqual,
+    Minus,
+    Bang,
+    Stringify,
+    JumpNotTruthy,
+    JumpNotNull,
+    Jump,
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    GetBuiltin,
+    GetFree,
+    Array,
+    Hash,
+    Index,
+    Call,
+    ReturnValue,
+    Return,
+    Closure,
+    CurrentClosure,
+    /// Check the value on top of the stack has the type named by a string constant
+    CheckType,
+}
+
+/// Every opcode, indexed by its byte
+const OPCODES: &[Opcode] = &[
+    Opcode::Constant,
+    Opcode::Pop,
+    Opcode::Add,
+    Opcode::Sub,
+    Opcode::Mul,
+    Opcode::Div,
+    Opcode::Mod,
+    Opcode::True,
+    Opcode::False,
+    Opcode::Null,
+    Opcode::Equal,
+    Opcode::NotEqual,
+    Opcode::GreaterThan,
+    Opcode::LessThan,
+    Opcode::GreaterEqual,
+    Opcode::LessEqual,
+    Opcode::Minus,
+    Opcode::Bang,
+    Opcode::Stringify,
+    Opcode::JumpNotTruthy,
+    Opcode::JumpNotNull,
+    Opcode::Jump,
+    Opcode::GetGlobal,
+    Opcode::SetGlobal,
+    Opcode::GetLocal,
+    Opcode::SetLocal,
+    Opcode::GetBuiltin,
+    Opcode::GetFree,
+    Opcode::Array,
+    Opcode::Hash,
+    Opcode::Index,
+    Opcode::Call,
+    Opcode::ReturnValue,
+    Opcode::Return,
+    Opcode::Closure,
+    Opcode::CurrentClosure,
+    Opcode::CheckType,
+];
+
+impl Opcode {
+    /// Decode an opcode from its byte
+    ///
+    /// * `byte` - The encoded opcode
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        OPCODES.get(byte as usize).copied()
+    }
+
+    /// The widths in bytes of this opcode's operands
+    pub fn operand_widths(self) -> &'static [usize] {
+        match self {
+            Opcode::Constant
+            | Opcode::JumpNotTruthy
+            | Opcode::JumpNotNull
+            | Opcode::Jump
+            | Opcode::GetGlobal
+            | Opcode::SetGlobal
+            | Opcode::Array
+            | Opcode::Hash
+            | Opcode::CheckType => &[2],
+            Opcode::GetLocal
+            | Opcode::SetLocal
+            | Opcode::GetBuiltin
+            | Opcode::GetFree
+            | Opcode::Call => &[1],
+            Opcode::Closure => &[2, 1],
+            _ => &[],
+        }
+    }
+}
+
+/// Encode an instruction
+///
+/// * `op` - The opcode
+/// * `operands` - One operand for each of the opcode's operand widths
+pub fn make(op: Opcode, operands: &[usize]) -> Vec<u8> {
+    let widths = op.operand_widths();
+    let mut instruction = Vec::with_capacity(1 + widths.iter().sum::<usize>());
+    instruction.push(op as u8);
+
+    for (&operand, &width) in operands.iter().zip(widths) {
+        match width {
+            2 => instruction.extend_from_slice(&(operand as u16).to_be_bytes()),
+            1 => instruction.push(operand as u8),
+            _ => unreachable!("operand width {}", width),
+        }
+    }
+
+    instruction
+}
+
+/// Decode the operands of an instruction, returning them along with how many bytes they took
+///
+/// * `op` - The opcode the operands belong to
+/// * `bytes` - The encoded operands, following the opcode byte
+pub fn read_operands(op: Opcode, bytes: &[u8]) -> (Vec<usize>, usize) {
+    let mut operands = Vec::with_capacity(op.operand_widths().len());
+    let mut offset = 0;
+
+    for &width in op.operand_widths() {
+        match width {
+            2 => operands.push(read_u16(&bytes[offset..])),
+            1 => operands.push(bytes[offset] as usize),
+            _ => unreachable!("operand width {}", width),
+        }
+        offset += width;
+    }
+
+    (operands, offset)
+}
+
+/// Read a big-endian two byte operand
+///
+/// * `bytes` - The bytes starting at the operand
+pub fn read_u16(bytes: &[u8]) -> usize {
+    u16::from_be_bytes([bytes[0], bytes[1]]) as usize
+}
+
+impl Instructions {
+    /// Decode the instructions into text, one per line after its offset, like
+    /// `0003 OpConstant 2`
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        let mut i = 0;
+
+        while i < self.0.len() {
+            let Some(op) = Opcode::from_byte(self.0[i]) else {
+                out += &format!("{:04} ERROR: unknown opcode {}\n", i, self.0[i]);
+                i += 1;
+                continue;
+            };
+
+            let (operands, read) = read_operands(op, &self.0[i + 1..]);
+            out += &format!("{:04} Op{:?}", i, op);
+            for operand in operands {
+                out += &format!(" {}", operand);
+            }
+            out.push('\n');
+
+            i += 1 + read;
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Instructions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.disassemble())
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Instructions {
+    fn from(instructions: Vec<Vec<u8>>) -> Instructions {
+        Instructions(instructions.concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make() {
+        assert_eq!(make(Opcode::Constant, &[65534]), vec![0, 255, 254]);
+        assert_eq!(make(Opcode::Add, &[]), vec![Opcode::Add as u8]);
+        assert_eq!(
+            make(Opcode::GetLocal, &[255]),
+            vec![Opcode::GetLocal as u8, 255]
+        );
+        assert_eq!(
+            make(Opcode::Closure, &[65534, 255]),
+            vec![Opcode::Closure as u8, 255, 254, 255]
+        );
+    }
+
+    #[test]
+    fn test_read_operands() {
+        for (op, operands, bytes_read) in [
+            (Opcode::Constant, vec![65535], 2),
+            (Opcode::GetLocal, vec![255], 1),
+            (Opcode::Closure, vec![65535, 255], 3),
+        ] {
+            let instruction = make(op, &operands);
+            assert_eq!(read_operands(op, &instruction[1..]), (operands, bytes_read));
+        }
+    }
+
+    #[test]
+    fn test_opcode_bytes() {
+        for (i, &op) in OPCODES.iter().enumerate() {
+            assert_eq!(op as usize, i);
+            assert_eq!(Opcode::from_byte(op as u8), Some(op));
+        }
+        assert_eq!(Opcode::from_byte(OPCODES.len() as u8), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let instructions = Instructions::from(vec![
+            make(Opcode::Add, &[]),
+            make(Opcode::GetLocal, &[1]),
+            make(Opcode::Constant, &[2]),
+            make(Opcode::Constant, &[65535]),
+            make(Opcode::Closure, &[65535, 255]),
+        ]);
+
+        assert_eq!(
+            instructions.to_string(),
+            "0000 OpAdd
+0001 OpGetLocal 1
+0003 OpConstant 2
+0006 OpConstant 65535
+0009 OpClosure 65535 255
+"
+        );
+    }
+}
@@ -0,0 +1,786 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::builtins;
+use crate::code::{make, Instructions, Opcode};
+use crate::object::{CompiledFunction, Object};
+use crate::symbol_table::{Symbol, SymbolScope, SymbolTable};
+use crate::token::{escape, Token};
+use std::rc::Rc;
+
+/// A compiled program, ready to be run by the VM
+#[derive(Debug, PartialEq)]
+pub struct Bytecode {
+    pub instructions: Instructions,
+    pub constants: Vec<Object>,
+}
+
+impl Bytecode {
+    /// Print the constant pool, with the instructions of each compiled function indented under
+    /// it, followed by the program's own instructions
+    pub fn disassemble(&self) -> String {
+        let mut out = String::from("constants:\n");
+
+        for (i, constant) in self.constants.iter().enumerate() {
+            match constant {
+                Object::String(value) => out += &format!("{:04} \"{}\"\n", i, escape(value)),
+                Object::CompiledFunction(function) => {
+                    out += &format!(
+                        "{:04} fn, {} parameters, {} locals\n",
+                        i, function.num_parameters, function.num_locals
+                    );
+                    for line in function.instructions.disassemble().lines() {
+                        out += &format!("    {}\n", line);
+                    }
+                }
+                constant => out += &format!("{:04} {}\n", i, constant),
+            }
+        }
+
+        out += "instructions:\n";
+        out + &self.instructions.disassemble()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmittedInstruction {
+    opcode: Opcode,
+    position: usize,
+}
+
+/// A loop being compiled, for `break` and `continue` to jump out of
+#[derive(Debug)]
+struct Loop {
+    /// Where the loop's condition starts, which `continue` jumps back to
+    start: usize,
+    /// The jumps emitted for `break`, to be patched once the end of the loop is known
+    breaks: Vec<usize>,
+}
+
+/// The instructions of the function currently being compiled
+#[derive(Debug, Default)]
+struct CompilationScope {
+    instructions: Instructions,
+    last_instruction: Option<EmittedInstruction>,
+    previous_instruction: Option<EmittedInstruction>,
+    /// The loops enclosing the code being compiled, innermost last
+    loops: Vec<Loop>,
+}
+
+#[derive(Debug)]
+pub struct Compiler {
+    constants: Vec<Object>,
+    symbol_table: SymbolTable,
+    scopes: Vec<CompilationScope>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        let mut symbol_table = SymbolTable::new();
+        for (i, builtin) in builtins::all().iter().enumerate() {
+            symbol_table.define_builtin(i, builtin.name);
+        }
+
+        Compiler {
+            constants: vec![],
+            symbol_table,
+            scopes: vec![CompilationScope::default()],
+        }
+    }
+
+    /// Compile a program. Like the evaluator, the program's value is that of its last
+    /// statement, or whatever it returns, so it always ends by returning.
+    ///
+    /// * `program` - The program to compile
+    pub fn compile(&mut self, program: &Program) -> Result<(), String> {
+        for statement in &program.statements {
+            self.compile_statement(statement)?;
+        }
+
+        if self.last_instruction_is(Opcode::Pop) {
+            self.replace_last_pop_with_return();
+        } else {
+            self.emit(Opcode::Return, &[]);
+        }
+
+        Ok(())
+    }
+
+    pub fn bytecode(self) -> Bytecode {
+        let mut scopes = self.scopes;
+
+        Bytecode {
+            instructions: scopes.swap_remove(0).instructions,
+            constants: self.constants,
+        }
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Expression(expression) => {
+                self.compile_expression(expression)?;
+                self.emit(Opcode::Pop, &[]);
+            }
+            Statement::Let(name, value) => {
+                // Defined first so the value can refer to itself, e.g. in a recursive function
+                let symbol = self.symbol_table.define(name);
+
+                self.compile_bound_value(value, name)?;
+
+                match symbol.scope {
+                    SymbolScope::Global => self.emit(Opcode::SetGlobal, &[symbol.index]),
+                    _ => self.emit(Opcode::SetLocal, &[symbol.index]),
+                };
+            }
+            Statement::Assign(name, value) => {
+                let symbol = match self.symbol_table.resolve(name) {
+                    Some(symbol) => symbol,
+                    None => return Err(format!("identifier not found: {}", name)),
+                };
+
+                self.compile_expression(value)?;
+
+                match symbol.scope {
+                    SymbolScope::Global => self.emit(Opcode::SetGlobal, &[symbol.index]),
+                    SymbolScope::Local => self.emit(Opcode::SetLocal, &[symbol.index]),
+                    // Builtins aren't bindings, as in the evaluator
+                    SymbolScope::Builtin => return Err(format!("identifier not found: {}", name)),
+                    // Closures hold copies of what they capture, so assigning to one couldn't
+                    // be seen outside it
+                    SymbolScope::Free | SymbolScope::Function => {
+                        return Err(format!(
+                            "cannot assign to {}, which is captured from an enclosing function",
+                            name
+                        ))
+                    }
+                };
+            }
+            Statement::Return(Some(value)) => {
+                self.compile_expression(value)?;
+                self.emit(Opcode::ReturnValue, &[]);
+            }
+            Statement::Return(None) => {
+                self.emit(Opcode::Return, &[]);
+            }
+            Statement::Break => {
+                let jump = self.emit(Opcode::Jump, &[0]);
+                match self.scope_mut().loops.last_mut() {
+                    Some(enclosing) => enclosing.breaks.push(jump),
+                    None => return Err("break outside loop".into()),
+                }
+            }
+            Statement::Continue => match self.scope_mut().loops.last() {
+                Some(enclosing) => {
+                    let start = enclosing.start;
+                    self.emit(Opcode::Jump, &[start]);
+                }
+                None => return Err("continue outside loop".into()),
+            },
+            // Comments emit nothing, so a block's value is still its last expression's
+            Statement::Comment(_) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Compile a block whose value is left on the stack, as for the branches of an `if`
+    ///
+    /// * `block` - The block to compile
+    fn compile_block_value(&mut self, block: &BlockStatement) -> Result<(), String> {
+        let start = self.current_instructions().0.len();
+
+        for statement in &block.statements {
+            self.compile_statement(statement)?;
+        }
+
+        let ends_in_pop =
+            self.current_instructions().0.len() > start && self.last_instruction_is(Opcode::Pop);
+        if ends_in_pop {
+            self.remove_last_pop();
+        } else {
+            self.emit(Opcode::Null, &[]);
+        }
+
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), String> {
+        match expression {
+            Expression::Integer(value) => {
+                let constant = self.add_constant(Object::Integer(*value));
+                self.emit(Opcode::Constant, &[constant]);
+            }
+            Expression::Float(value) => {
+                let constant = self.add_constant(Object::Float(*value));
+                self.emit(Opcode::Constant, &[constant]);
+            }
+            Expression::String(value) => {
+                let constant = self.add_constant(Object::String(value.clone()));
+                self.emit(Opcode::Constant, &[constant]);
+            }
+            Expression::Boolean(true) => {
+                self.emit(Opcode::True, &[]);
+            }
+            Expression::Boolean(false) => {
+                self.emit(Opcode::False, &[]);
+            }
+            Expression::Array(elements) => {
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                self.emit(Opcode::Array, &[elements.len()]);
+            }
+            Expression::Hash(pairs) => {
+                for (key, value) in pairs {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.emit(Opcode::Hash, &[pairs.len() * 2]);
+            }
+            Expression::Index(left, index) => {
+                self.compile_expression(left)?;
+                self.compile_expression(index)?;
+                self.emit(Opcode::Index, &[]);
+            }
+            Expression::Prefix(operator, right) => {
+                self.compile_expression(right)?;
+                match operator {
+                    Token::Bang => self.emit(Opcode::Bang, &[]),
+                    Token::Minus => self.emit(Opcode::Minus, &[]),
+                    Token::Dollar => self.emit(Opcode::Stringify, &[]),
+                    operator => return Err(format!("unknown operator: {}", operator)),
+                };
+            }
+            Expression::Infix(left, Token::And, right) => {
+                // `a && b` becomes `if (a) { !!b } else { false }`
+                self.compile_expression(left)?;
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[0]);
+
+                self.compile_expression(right)?;
+                self.emit(Opcode::Bang, &[]);
+                self.emit(Opcode::Bang, &[]);
+                let jump = self.emit(Opcode::Jump, &[0]);
+
+                let after_right = self.current_instructions().0.len();
+                self.change_operand(jump_not_truthy, after_right);
+                self.emit(Opcode::False, &[]);
+
+                let after_false = self.current_instructions().0.len();
+                self.change_operand(jump, after_false);
+            }
+            Expression::Infix(left, Token::Or, right) => {
+                // `a || b` becomes `if (!a) { !!b } else { true }`
+                self.compile_expression(left)?;
+                self.emit(Opcode::Bang, &[]);
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[0]);
+
+                self.compile_expression(right)?;
+                self.emit(Opcode::Bang, &[]);
+                self.emit(Opcode::Bang, &[]);
+                let jump = self.emit(Opcode::Jump, &[0]);
+
+                let after_right = self.current_instructions().0.len();
+                self.change_operand(jump_not_truthy, after_right);
+                self.emit(Opcode::True, &[]);
+
+                let after_true = self.current_instructions().0.len();
+                self.change_operand(jump, after_true);
+            }
+            Expression::Infix(left, Token::Coalesce, right) => {
+                // `a ?? b` keeps `a` unless it's null, in which case it's dropped for `b`
+                self.compile_expression(left)?;
+                let jump_not_null = self.emit(Opcode::JumpNotNull, &[0]);
+
+                self.compile_expression(right)?;
+
+                let after_right = self.current_instructions().0.len();
+                self.change_operand(jump_not_null, after_right);
+            }
+            Expression::Infix(left, operator, right) => {
+                self.compile_expression(left)?;
+                self.compile_expression(right)?;
+
+                let opcode = match operator {
+                    Token::Plus => Opcode::Add,
+                    Token::Minus => Opcode::Sub,
+                    Token::Asterisk => Opcode::Mul,
+                    Token::Slash => Opcode::Div,
+                    Token::Percent => Opcode::Mod,
+                    Token::Gt => Opcode::GreaterThan,
+                    Token::Lt => Opcode::LessThan,
+                    Token::GtEq => Opcode::GreaterEqual,
+                    Token::LtEq => Opcode::LessEqual,
+                    Token::Equal => Opcode::Equal,
+                    Token::NotEqual => Opcode::NotEqual,
+                    operator => return Err(format!("unknown operator: {}", operator)),
+                };
+                self.emit(opcode, &[]);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.compile_expression(condition)?;
+                // Jump targets are patched once the branches have been compiled
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[0]);
+
+                self.compile_block_value(consequence)?;
+                let jump = self.emit(Opcode::Jump, &[0]);
+
+                let after_consequence = self.current_instructions().0.len();
+                self.change_operand(jump_not_truthy, after_consequence);
+
+                match alternative {
+                    Some(alternative) => self.compile_block_value(alternative)?,
+                    None => {
+                        self.emit(Opcode::Null, &[]);
+                    }
+                }
+
+                let after_alternative = self.current_instructions().0.len();
+                self.change_operand(jump, after_alternative);
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.compile_expression(condition)?;
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[0]);
+
+                self.compile_expression(consequence)?;
+                let jump = self.emit(Opcode::Jump, &[0]);
+
+                let after_consequence = self.current_instructions().0.len();
+                self.change_operand(jump_not_truthy, after_consequence);
+                self.compile_expression(alternative)?;
+
+                let after_alternative = self.current_instructions().0.len();
+                self.change_operand(jump, after_alternative);
+            }
+            Expression::While { condition, body } => {
+                let start = self.current_instructions().0.len();
+                self.scope_mut().loops.push(Loop {
+                    start,
+                    breaks: vec![],
+                });
+
+                self.compile_expression(condition)?;
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[0]);
+
+                for statement in &body.statements {
+                    self.compile_statement(statement)?;
+                }
+                self.emit(Opcode::Jump, &[start]);
+
+                let after_body = self.current_instructions().0.len();
+                self.change_operand(jump_not_truthy, after_body);
+                let enclosing = self.scope_mut().loops.pop().expect("no enclosing loop");
+                for jump in enclosing.breaks {
+                    self.change_operand(jump, after_body);
+                }
+
+                // Like an `if` without an alternative, a loop has no value of its own
+                self.emit(Opcode::Null, &[]);
+            }
+            Expression::Identifier(name) => match self.symbol_table.resolve(name) {
+                Some(symbol) => self.load_symbol(&symbol),
+                None => return Err(format!("identifier not found: {}", name)),
+            },
+            Expression::Function { parameters, body } => {
+                self.compile_function(parameters, body, None)?
+            }
+            Expression::Macro { .. } => {
+                return Err("macros can only be defined by a top-level let".into())
+            }
+            Expression::Annotated { value, type_name } => {
+                self.compile_expression(value)?;
+                self.emit_type_check(type_name);
+            }
+            Expression::Call { function, .. } if matches!(function.as_ref(), Expression::Identifier(name) if name == "import") => {
+                return Err("import is only supported by the eval backend".into())
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                self.compile_expression(function)?;
+                for argument in arguments {
+                    self.compile_expression(argument)?;
+                }
+                self.emit(Opcode::Call, &[arguments.len()]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compile the value bound by a `let`, naming function literals after the binding so they
+    /// can refer to themselves
+    ///
+    /// * `value` - The value being bound
+    /// * `name` - The name it's bound to
+    fn compile_bound_value(&mut self, value: &Expression, name: &str) -> Result<(), String> {
+        match value {
+            Expression::Function { parameters, body } => {
+                self.compile_function(parameters, body, Some(name))?
+            }
+            Expression::Annotated { value, type_name } => {
+                self.compile_bound_value(value, name)?;
+                self.emit_type_check(type_name);
+            }
+            value => self.compile_expression(value)?,
+        }
+
+        Ok(())
+    }
+
+    /// Emit a check that the value on top of the stack has the type an annotation names
+    ///
+    /// * `type_name` - The name of the type
+    fn emit_type_check(&mut self, type_name: &str) {
+        let constant = self.add_constant(Object::String(type_name.to_string()));
+        self.emit(Opcode::CheckType, &[constant]);
+    }
+
+    /// Compile a function literal into a constant and emit a closure over it
+    ///
+    /// * `parameters` - The function's parameters
+    /// * `body` - The function's body
+    /// * `name` - The name the function is bound to, if it's the value of a `let`
+    fn compile_function(
+        &mut self,
+        parameters: &[String],
+        body: &BlockStatement,
+        name: Option<&str>,
+    ) -> Result<(), String> {
+        self.enter_scope();
+
+        if let Some(name) = name {
+            self.symbol_table.define_function_name(name);
+        }
+        for parameter in parameters {
+            self.symbol_table.define(parameter);
+        }
+
+        for statement in &body.statements {
+            self.compile_statement(statement)?;
+        }
+
+        if self.last_instruction_is(Opcode::Pop) {
+            self.replace_last_pop_with_return();
+        }
+        if !self.last_instruction_is(Opcode::ReturnValue) {
+            self.emit(Opcode::Return, &[]);
+        }
+
+        let free_symbols = self.symbol_table.free_symbols.clone();
+        let num_locals = self.symbol_table.num_definitions();
+        let instructions = self.leave_scope();
+
+        for symbol in &free_symbols {
+            self.load_symbol(symbol);
+        }
+
+        let function = Object::CompiledFunction(Rc::new(CompiledFunction {
+            instructions,
+            num_locals,
+            num_parameters: parameters.len(),
+        }));
+        let constant = self.add_constant(function);
+        self.emit(Opcode::Closure, &[constant, free_symbols.len()]);
+
+        Ok(())
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Opcode::GetGlobal, &[symbol.index]),
+            SymbolScope::Local => self.emit(Opcode::GetLocal, &[symbol.index]),
+            SymbolScope::Builtin => self.emit(Opcode::GetBuiltin, &[symbol.index]),
+            SymbolScope::Free => self.emit(Opcode::GetFree, &[symbol.index]),
+            SymbolScope::Function => self.emit(Opcode::CurrentClosure, &[]),
+        };
+    }
+
+    fn add_constant(&mut self, object: Object) -> usize {
+        self.constants.push(object);
+        self.constants.len() - 1
+    }
+
+    /// Append an instruction to the current scope, returning its position
+    ///
+    /// * `op` - The opcode
+    /// * `operands` - The opcode's operands
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> usize {
+        let instruction = make(op, operands);
+        let scope = self.scope_mut();
+        let position = scope.instructions.0.len();
+        scope.instructions.0.extend(instruction);
+
+        scope.previous_instruction = scope.last_instruction;
+        scope.last_instruction = Some(EmittedInstruction {
+            opcode: op,
+            position,
+        });
+
+        position
+    }
+
+    fn current_instructions(&self) -> &Instructions {
+        &self
+            .scopes
+            .last()
+            .expect("no compilation scope")
+            .instructions
+    }
+
+    fn scope_mut(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().expect("no compilation scope")
+    }
+
+    fn last_instruction_is(&self, op: Opcode) -> bool {
+        let scope = self.scopes.last().expect("no compilation scope");
+        scope
+            .last_instruction
+            .is_some_and(|instruction| instruction.opcode == op)
+    }
+
+    fn remove_last_pop(&mut self) {
+        let scope = self.scope_mut();
+        if let Some(last) = scope.last_instruction {
+            scope.instructions.0.truncate(last.position);
+            scope.last_instruction = scope.previous_instruction;
+        }
+    }
+
+    fn replace_last_pop_with_return(&mut self) {
+        let scope = self.scope_mut();
+        if let Some(last) = scope.last_instruction.as_mut() {
+            scope.instructions.0[last.position] = Opcode::ReturnValue as u8;
+            last.opcode = Opcode::ReturnValue;
+        }
+    }
+
+    /// Replace the operand of an already emitted instruction, e.g. to patch a jump target
+    ///
+    /// * `position` - The position of the instruction
+    /// * `operand` - The new operand
+    fn change_operand(&mut self, position: usize, operand: usize) {
+        let instructions = &mut self.scope_mut().instructions.0;
+        let op = Opcode::from_byte(instructions[position]).expect("unknown opcode");
+        let instruction = make(op, &[operand]);
+
+        instructions[position..position + instruction.len()].copy_from_slice(&instruction);
+    }
+
+    fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
+    }
+
+    fn leave_scope(&mut self) -> Instructions {
+        let scope = self.scopes.pop().expect("no compilation scope");
+
+        let inner = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = inner.into_outer().expect("left the global scope");
+
+        scope.instructions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn compile(input: &str) -> Bytecode {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert_eq!(parser.errors(), &[], "input: {}", input);
+
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).unwrap();
+        compiler.bytecode()
+    }
+
+    fn function(constant: &Object) -> &CompiledFunction {
+        match constant {
+            Object::CompiledFunction(function) => function,
+            constant => panic!("not a compiled function: {:?}", constant),
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let bytecode = compile("1 + 2; -3");
+
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Add, &[]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Constant, &[2]),
+                make(Opcode::Minus, &[]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+        assert_eq!(
+            bytecode.constants,
+            vec![Object::Integer(1), Object::Integer(2), Object::Integer(3)]
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let bytecode = compile("if (true) { 10 }; 3333;");
+
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![
+                make(Opcode::True, &[]),
+                make(Opcode::JumpNotTruthy, &[10]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Jump, &[11]),
+                make(Opcode::Null, &[]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_global_let_statements() {
+        let bytecode = compile("let one = 1; let two = one;");
+
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::SetGlobal, &[1]),
+                make(Opcode::Return, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let bytecode = compile("fn(a) { fn(b) { a + b } }");
+
+        assert_eq!(
+            function(&bytecode.constants[0]).instructions,
+            Instructions::from(vec![
+                make(Opcode::GetFree, &[0]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Add, &[]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+        assert_eq!(
+            function(&bytecode.constants[1]).instructions,
+            Instructions::from(vec![
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Closure, &[0, 1]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![
+                make(Opcode::Closure, &[1, 0]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let bytecode = compile("fn() { let f = fn(x) { f(x - 1) }; f(1) }");
+        assert_eq!(
+            function(&bytecode.constants[1]).instructions,
+            Instructions::from(vec![
+                make(Opcode::CurrentClosure, &[]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Sub, &[]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let bytecode = compile("while (true) { break; continue; }");
+
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![
+                make(Opcode::True, &[]),
+                make(Opcode::JumpNotTruthy, &[13]),
+                make(Opcode::Jump, &[13]),
+                make(Opcode::Jump, &[0]),
+                make(Opcode::Jump, &[0]),
+                make(Opcode::Null, &[]),
+                make(Opcode::ReturnValue, &[]),
+            ])
+        );
+
+        for input in ["break;", "while (true) { fn() { continue; } }"] {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            assert!(
+                Compiler::new().compile(&program).is_err(),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_assign_errors() {
+        for (input, message) in [
+            ("x = 1", "identifier not found: x"),
+            ("len = 1", "identifier not found: len"),
+            (
+                "fn(x) { fn() { x = 1 } }",
+                "cannot assign to x, which is captured from an enclosing function",
+            ),
+        ] {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            assert_eq!(
+                Compiler::new().compile(&program),
+                Err(message.to_string()),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_identifier() {
+        let mut parser = Parser::new(Lexer::new("x + 1"));
+        let program = parser.parse_program();
+
+        assert_eq!(
+            Compiler::new().compile(&program),
+            Err("identifier not found: x".to_string())
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::environment::Env;
+use crate::evaluator::{EvalHook, Node};
+use crate::token::Span;
+use std::collections::HashSet;
+
+/// Records which statements the evaluator runs, for `coverage_report`. Statements are told
+/// apart by their spans, so only those parsed from source are recorded.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    executed: HashSet<Span>,
+}
+
+impl EvalHook for Coverage {
+    fn before(&mut self, _node: Node<'_>, _env: &Env, _depth: usize) {}
+
+    fn statement_span(&mut self, span: Span) {
+        self.executed.insert(span);
+    }
+}
+
+/// How much of a program ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    /// How many statements ran
+    pub executed: usize,
+    /// How many statements there are, leaving out comments
+    pub total: usize,
+    /// Where the statements that never ran are, in source order. Statements inside one that
+    /// never ran aren't listed separately.
+    pub uncovered: Vec<Span>,
+}
+
+impl CoverageReport {
+    /// The share of statements that ran, from 0 to 100. An empty program is fully covered.
+    pub fn percentage(&self) -> f64 {
+        if self.total == 0 {
+            100.0
+        } else {
+            self.executed as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+/// Summarize which of a program's statements ran, including those in blocks and function
+/// bodies. Statements without spans count as never having run.
+///
+/// * `program` - The program that was evaluated, as parsed from source
+/// * `coverage` - What ran, as recorded while evaluating it
+pub fn coverage_report(program: &Program, coverage: &Coverage) -> CoverageReport {
+    let mut walker = Walker {
+        coverage,
+        report: CoverageReport {
+            executed: 0,
+            total: 0,
+            uncovered: vec![],
+        },
+    };
+    walker.statements(&program.statements, &program.spans, true);
+
+    walker.report
+}
+
+struct Walker<'a> {
+    coverage: &'a Coverage,
+    report: CoverageReport,
+}
+
+impl Walker<'_> {
+    /// Count a list of statements and those nested in them
+    ///
+    /// * `statements` - The statements
+    /// * `spans` - Their spans, if known
+    /// * `listing` - Whether to list those that didn't run, which is only wanted while the
+    ///   statements around them did
+    fn statements(&mut self, statements: &[Statement], spans: &[Span], listing: bool) {
+        for (i, statement) in statements.iter().enumerate() {
+            if let Statement::Comment(_) = statement {
+                continue;
+            }
+
+            self.report.total += 1;
+            let span = spans.get(i);
+            let executed = span.is_some_and(|span| self.coverage.executed.contains(span));
+            if executed {
+                self.report.executed += 1;
+            } else if listing {
+                self.report.uncovered.extend(span);
+            }
+
+            match statement {
+                Statement::Let(_, value)
+                | Statement::Assign(_, value)
+                | Statement::Return(Some(value))
+                | Statement::Expression(value) => self.expression(value, listing && executed),
+                Statement::Return(None)
+                | Statement::Break
+                | Statement::Continue
+                | Statement::Comment(_) => {}
+            }
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement, listing: bool) {
+        self.statements(&block.statements, &block.spans, listing);
+    }
+
+    fn expression(&mut self, expression: &Expression, listing: bool) {
+        match expression {
+            Expression::Identifier(_)
+            | Expression::Integer(_)
+            | Expression::Float(_)
+            | Expression::Boolean(_)
+            | Expression::String(_) => {}
+            Expression::Array(elements) => {
+                for element in elements {
+                    self.expression(element, listing);
+                }
+            }
+            Expression::Index(left, index) => {
+                self.expression(left, listing);
+                self.expression(index, listing);
+            }
+            Expression::Hash(pairs) => {
+                for (key, value) in pairs {
+                    self.expression(key, listing);
+                    self.expression(value, listing);
+                }
+            }
+            Expression::Prefix(_, right) => self.expression(right, listing),
+            Expression::Infix(left, _, right) => {
+                self.expression(left, listing);
+                self.expression(right, listing);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expression(condition, listing);
+                self.block(consequence, listing);
+                if let Some(alternative) = alternative {
+                    self.block(alternative, listing);
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expression(condition, listing);
+                self.expression(consequence, listing);
+                self.expression(alternative, listing);
+            }
+            Expression::While { condition, body } => {
+                self.expression(condition, listing);
+                self.block(body, listing);
+            }
+            Expression::Function { body, .. } | Expression::Macro { body, .. } => {
+                self.block(body, listing)
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                self.expression(function, listing);
+                for argument in arguments {
+                    self.expression(argument, listing);
+                }
+            }
+            Expression::Annotated { value, .. } => self.expression(value, listing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_coverage_report() {
+        let input = "let sign = fn(x) {
+  if (x < 0) {
+    let negative = true;
+    return -1;
+  }
+  1
+};
+// Only ever called with a positive number
+sign(5);";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+
+        let mut coverage = Coverage::default();
+        Evaluator::with_hook(&mut coverage).eval(&program, &Environment::new());
+        let report = coverage_report(&program, &coverage);
+
+        let uncovered: Vec<_> = report
+            .uncovered
+            .iter()
+            .map(|span| {
+                let text: String = input
+                    .chars()
+                    .skip(span.start)
+                    .take(span.end - span.start)
+                    .collect();
+                text
+            })
+            .collect();
+        assert_eq!(uncovered, vec!["let negative = true;", "return -1;"]);
+        assert_eq!((report.executed, report.total), (4, 6));
+        assert!((report.percentage() - 66.7).abs() < 0.1);
+
+        let empty = Program {
+            statements: vec![],
+            spans: vec![],
+        };
+        assert_eq!(coverage_report(&empty, &coverage).percentage(), 100.0);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::error::MonkeyError;
+use crate::token::{Position, Span};
+use std::fmt;
+
+/// The name to report for source typed into the REPL
+pub const REPL_FILE: &str = "<repl>";
+
+/// Which step of running a program a diagnostic came from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stage {
+    Io,
+    Lex,
+    Parse,
+    Macro,
+    Compile,
+    Runtime,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Io => write!(f, "io"),
+            Stage::Lex => write!(f, "lexer"),
+            Stage::Parse => write!(f, "parse"),
+            Stage::Macro => write!(f, "macro"),
+            Stage::Compile => write!(f, "compile"),
+            Stage::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+/// A single problem with a program, in a plain form for tooling. `Display` gives one line of
+/// `file:line:column: stage error: message`; `render` gives a snippet of the source for people.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub stage: Stage,
+    pub message: String,
+    /// The file the source was read from, or `REPL_FILE`
+    pub file: String,
+    /// Where in the source the problem is, if known
+    pub span: Option<Span>,
+    /// The position of the start of `span`
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    /// The diagnostics for an error, which is one per error found for parse errors and one
+    /// otherwise
+    ///
+    /// * `err` - The error to report
+    /// * `file` - The name of the file the source came from
+    /// * `source` - The source the error is about
+    pub fn from_error(err: &MonkeyError, file: &str, source: &str) -> Vec<Diagnostic> {
+        let diagnostic = |stage, message: String, span: Option<Span>| Diagnostic {
+            stage,
+            message,
+            file: file.to_string(),
+            span,
+            position: span.map(|span| position_of(source, span.start)),
+        };
+
+        match err {
+            MonkeyError::Io(err) => vec![diagnostic(Stage::Io, err.to_string(), None)],
+            MonkeyError::Lex(err) => {
+                vec![diagnostic(Stage::Lex, err.to_string(), Some(err.span()))]
+            }
+            MonkeyError::Parse(errors) => errors
+                .iter()
+                .map(|err| Diagnostic {
+                    position: Some(err.position),
+                    ..diagnostic(Stage::Parse, err.message.clone(), Some(err.span))
+                })
+                .collect(),
+            MonkeyError::Macro(err) => {
+                vec![diagnostic(Stage::Macro, err.message.clone(), err.span)]
+            }
+            MonkeyError::Compile(message) => {
+                vec![diagnostic(Stage::Compile, message.clone(), None)]
+            }
+            MonkeyError::Runtime(err) => {
+                vec![diagnostic(Stage::Runtime, err.message.clone(), err.span)]
+            }
+            MonkeyError::LimitExceeded(limit) => {
+                vec![diagnostic(
+                    Stage::Runtime,
+                    format!("limit exceeded: {}", limit),
+                    None,
+                )]
+            }
+        }
+    }
+
+    /// Render the diagnostic for people, showing the line it's about with the span underlined,
+    /// e.g.
+    ///
+    /// ```text
+    /// parse error: expected next token to be Ident, got = instead
+    ///  --> <repl>:1:5
+    ///   |
+    /// 1 | let = 1;
+    ///   |     ^
+    /// ```
+    ///
+    /// * `source` - The source the diagnostic is about
+    pub fn render(&self, source: &str) -> String {
+        let mut rendered = format!("{} error: {}\n --> {}", self.stage, self.message, self.file);
+        let (Some(span), Some(position)) = (self.span, self.position) else {
+            return rendered;
+        };
+
+        let line = source.lines().nth(position.line - 1).unwrap_or("");
+        let number = position.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        // Keep tabs in the padding, so the carets line up however wide tabs are shown
+        let padding: String = line
+            .chars()
+            .take(position.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Spans running onto later lines are underlined up to the end of this one
+        let rest = line.chars().count().saturating_sub(position.column - 1);
+        let width = (span.end - span.start).min(rest).max(1);
+
+        rendered += &format!(
+            ":{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}",
+            position.line,
+            position.column,
+            number,
+            line,
+            padding,
+            "^".repeat(width)
+        );
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.file)?;
+        if let Some(position) = self.position {
+            write!(f, "{}:{}:", position.line, position.column)?;
+        }
+        write!(f, " {} error: {}", self.stage, self.message)
+    }
+}
+
+/// Render every diagnostic for an error, separated by blank lines
+///
+/// * `err` - The error to report
+/// * `file` - The name of the file the source came from
+/// * `source` - The source the error is about
+pub fn render_error(err: &MonkeyError, file: &str, source: &str) -> String {
+    Diagnostic::from_error(err, file, source)
+        .iter()
+        .map(|diagnostic| diagnostic.render(source))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// The line and column of a char offset into the source
+///
+/// * `source` - The source the offset is into
+/// * `offset` - The offset, in chars
+pub fn position_of(source: &str, offset: usize) -> Position {
+    let mut position = Position { line: 1, column: 1 };
+
+    for c in source.chars().take(offset) {
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    }
+
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::script::{run_source, RunOptions};
+
+    #[test]
+    fn test_render_parse_errors() {
+        let source = "let x = 1;\n\tlet = 2;";
+        let mut parser = Parser::new(Lexer::new(source));
+        parser.parse_program();
+        let err = MonkeyError::Parse(parser.errors().to_vec());
+
+        let diagnostics = Diagnostic::from_error(&err, "test.monkey", source);
+
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "test.monkey:2:6: parse error: expected next token to be Ident, got = instead"
+        );
+        assert_eq!(
+            render_error(&err, "test.monkey", source),
+            "parse error: expected next token to be Ident, got = instead
+ --> test.monkey:2:6
+  |
+2 | \tlet = 2;
+  | \t    ^
+
+parse error: no prefix parse function for = found
+ --> test.monkey:2:6
+  |
+2 | \tlet = 2;
+  | \t    ^"
+        );
+    }
+
+    #[test]
+    fn test_render_lex_error() {
+        let source = "let s = \"abc\nlet t = 1;";
+        let mut lexer = Lexer::new(source);
+        let err = loop {
+            if let Err(err) = lexer.try_next_token() {
+                break MonkeyError::Lex(err);
+            }
+        };
+
+        assert_eq!(
+            render_error(&err, REPL_FILE, source),
+            "lexer error: unterminated string literal
+ --> <repl>:1:9
+  |
+1 | let s = \"abc
+  |         ^^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let source = "1 / 0";
+        let err = run_source(source, &RunOptions::default()).unwrap_err();
+
+        assert_eq!(
+            Diagnostic::from_error(&err, REPL_FILE, source)[0].to_string(),
+            "<repl>: runtime error: division by zero"
+        );
+        assert_eq!(
+            render_error(&err, REPL_FILE, source),
+            "runtime error: division by zero\n --> <repl>"
+        );
+    }
+}
@@ -0,0 +1,487 @@
+use crate::object::{Function, Object};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// How many environments can be captured before the first collection
+const MIN_THRESHOLD: usize = 1024;
+
+/// A shared handle to an environment, as captured by closures
+pub type Env = Rc<RefCell<Environment>>;
+
+/// The bindings in one scope, along with the scope enclosing it
+#[derive(Debug)]
+pub struct Environment {
+    store: HashMap<String, Object>,
+    outer: Option<Env>,
+    /// Whether a closure has captured this environment, so it's watched for cycles
+    captured: Cell<bool>,
+}
+
+/// Counts of the environments alive on this thread, for hosts keeping an eye on memory.
+/// Environments are the only part of the heap that can leak, as a closure stored in the
+/// environment it captured keeps it alive in a reference cycle, until `collect_garbage` frees it.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct HeapStats {
+    /// Environments alive, whether or not anything can reach them
+    pub environments: usize,
+    /// Environments alive that closures have captured
+    pub captured: usize,
+    /// How many times garbage has been collected
+    pub collections: u64,
+    /// Environments freed by collecting garbage
+    pub collected: u64,
+}
+
+/// The environments on this thread
+struct Heap {
+    environments: Cell<usize>,
+    /// The captured environments, which are where cycles can form
+    captured: RefCell<Vec<Weak<RefCell<Environment>>>>,
+    /// How many captured environments there can be before collecting again
+    threshold: Cell<usize>,
+    collections: Cell<u64>,
+    collected: Cell<u64>,
+}
+
+thread_local! {
+    static HEAP: Heap = const {
+        Heap {
+            environments: Cell::new(0),
+            captured: RefCell::new(Vec::new()),
+            threshold: Cell::new(MIN_THRESHOLD),
+            collections: Cell::new(0),
+            collected: Cell::new(0),
+        }
+    };
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        HEAP.with(|heap| heap.environments.set(heap.environments.get() + 1));
+
+        Environment {
+            store: HashMap::new(),
+            outer: None,
+            captured: Cell::new(false),
+        }
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        // The heap may already be gone if the thread is exiting
+        let _ = HEAP.try_with(|heap| heap.environments.set(heap.environments.get() - 1));
+    }
+}
+
+impl Environment {
+    pub fn new() -> Env {
+        Rc::new(RefCell::new(Environment::default()))
+    }
+
+    /// Create a scope nested inside another, e.g. for a function call. Names not bound in the
+    /// new scope are looked up in `outer`.
+    ///
+    /// * `outer` - The enclosing scope
+    pub fn new_enclosed(outer: Env) -> Env {
+        let mut env = Environment::default();
+        env.outer = Some(outer);
+        Rc::new(RefCell::new(env))
+    }
+
+    /// Note that a closure has captured an environment, so it's watched for reference cycles.
+    /// Garbage is collected once captured environments have doubled since the last collection.
+    ///
+    /// * `env` - The environment captured
+    pub fn capture(env: &Env) {
+        if env.borrow().captured.replace(true) {
+            return;
+        }
+
+        let due = HEAP.with(|heap| {
+            let mut captured = heap.captured.borrow_mut();
+            captured.push(Rc::downgrade(env));
+            captured.len() >= heap.threshold.get()
+        });
+        if due {
+            collect_garbage();
+        }
+    }
+
+    /// Look up a binding, searching enclosing scopes from the innermost outwards
+    ///
+    /// * `name` - The name to look up
+    pub fn get(&self, name: &str) -> Option<Object> {
+        match self.store.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.outer.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Bind a name in this scope, shadowing any binding in enclosing scopes
+    ///
+    /// * `name` - The name to bind
+    /// * `value` - The value to bind it to
+    pub fn set(&mut self, name: String, value: Object) {
+        self.store.insert(name, value);
+    }
+
+    /// Rebind a name in the innermost scope that binds it, returning whether any scope did
+    ///
+    /// * `name` - The name to rebind
+    /// * `value` - The value to bind it to
+    pub fn assign(&mut self, name: &str, value: Object) -> bool {
+        match self.store.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &self.outer {
+                Some(outer) => outer.borrow_mut().assign(name, value),
+                None => false,
+            },
+        }
+    }
+
+    /// The scope enclosing this one, if any
+    #[cfg(feature = "serde")]
+    pub(crate) fn outer(&self) -> Option<&Env> {
+        self.outer.as_ref()
+    }
+
+    /// The bindings made in this scope, not including enclosing ones, sorted by name
+    pub fn bindings(&self) -> Vec<(&str, &Object)> {
+        let mut bindings: Vec<_> = self
+            .store
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        bindings.sort_by_key(|(name, _)| *name);
+        bindings
+    }
+}
+
+/// The statistics for the environments on this thread
+pub fn heap_stats() -> HeapStats {
+    HEAP.with(|heap| HeapStats {
+        environments: heap.environments.get(),
+        captured: heap
+            .captured
+            .borrow()
+            .iter()
+            .filter(|env| env.strong_count() > 0)
+            .count(),
+        collections: heap.collections.get(),
+        collected: heap.collected.get(),
+    })
+}
+
+/// Free the environments on this thread that only reference cycles keep alive, returning how
+/// many were freed. Anything referenced from outside the environments and the functions in
+/// them, like the environment a program runs in or a function a host holds on to, is kept
+/// along with everything it can reach, so it's safe to collect at any time.
+pub fn collect_garbage() -> usize {
+    let captured = HEAP.with(|heap| {
+        let mut captured = heap.captured.borrow_mut();
+        captured.retain(|env| env.strong_count() > 0);
+        captured
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>()
+    });
+
+    let graph = Graph::new(captured);
+    let garbage = graph.garbage();
+
+    // Cycles are broken by emptying their environments, which are freed once the graph goes
+    let mut contents = Vec::with_capacity(garbage.len());
+    for &i in &garbage {
+        let mut env = graph.envs[i].borrow_mut();
+        contents.push((std::mem::take(&mut env.store), env.outer.take()));
+    }
+    drop(contents);
+    drop(graph);
+
+    HEAP.with(|heap| {
+        let mut captured = heap.captured.borrow_mut();
+        captured.retain(|env| env.strong_count() > 0);
+        heap.threshold.set(MIN_THRESHOLD.max(captured.len() * 2));
+        heap.collections.set(heap.collections.get() + 1);
+        heap.collected
+            .set(heap.collected.get() + garbage.len() as u64);
+    });
+
+    garbage.len()
+}
+
+/// The environments reachable from the captured ones and the functions in them, along with the
+/// references between them. The graph holds one reference to each of its own.
+#[derive(Default)]
+struct Graph {
+    envs: Vec<Env>,
+    env_indexes: HashMap<*const RefCell<Environment>, usize>,
+    /// How many references to each environment come from within the graph
+    env_refs: Vec<usize>,
+    /// The environments and functions each environment references
+    env_edges: Vec<Vec<Node>>,
+    /// Environments in use right now, which are kept along with what they reference
+    pinned: Vec<bool>,
+    functions: Vec<Rc<Function>>,
+    function_indexes: HashMap<*const Function, usize>,
+    function_refs: Vec<usize>,
+    /// The environment each function captured
+    function_envs: Vec<usize>,
+    /// What's been added but not looked into yet. Closures can nest arbitrarily deep, so this
+    /// is worked through in a loop rather than by recursing.
+    pending: Vec<Node>,
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+    Env(usize),
+    Function(usize),
+}
+
+impl Graph {
+    /// Build the graph from the environments closures have captured
+    ///
+    /// * `captured` - The captured environments
+    fn new(captured: Vec<Env>) -> Graph {
+        let mut graph = Graph::default();
+        for env in captured {
+            graph.add_env(env);
+        }
+
+        while let Some(node) = graph.pending.pop() {
+            match node {
+                Node::Env(i) => graph.scan_env(i),
+                Node::Function(i) => {
+                    let j = graph.add_env(graph.functions[i].env.clone());
+                    graph.env_refs[j] += 1;
+                    graph.function_envs[i] = j;
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// The index of an environment, adding it if it's new
+    ///
+    /// * `env` - The environment
+    fn add_env(&mut self, env: Env) -> usize {
+        if let Some(&i) = self.env_indexes.get(&Rc::as_ptr(&env)) {
+            return i;
+        }
+
+        let i = self.envs.len();
+        self.env_indexes.insert(Rc::as_ptr(&env), i);
+        self.envs.push(env);
+        self.env_refs.push(0);
+        self.env_edges.push(vec![]);
+        self.pinned.push(false);
+        self.pending.push(Node::Env(i));
+
+        i
+    }
+
+    /// The index of a function, adding it if it's new
+    ///
+    /// * `function` - The function
+    fn add_function(&mut self, function: Rc<Function>) -> usize {
+        if let Some(&i) = self.function_indexes.get(&Rc::as_ptr(&function)) {
+            return i;
+        }
+
+        let i = self.functions.len();
+        self.function_indexes.insert(Rc::as_ptr(&function), i);
+        self.functions.push(function);
+        self.function_refs.push(0);
+        self.function_envs.push(0);
+        self.pending.push(Node::Function(i));
+
+        i
+    }
+
+    /// Add what an environment references to the graph
+    ///
+    /// * `i` - The environment's index
+    fn scan_env(&mut self, i: usize) {
+        // An environment being changed can't be looked into, so it's assumed to be in use
+        let Ok(env) = self.envs[i].try_borrow() else {
+            self.pinned[i] = true;
+            return;
+        };
+
+        let mut functions = vec![];
+        for value in env.store.values() {
+            functions_in(value, &mut functions);
+        }
+        let outer = env.outer.clone();
+        drop(env);
+
+        for function in functions {
+            let j = self.add_function(function);
+            self.function_refs[j] += 1;
+            self.env_edges[i].push(Node::Function(j));
+        }
+        if let Some(outer) = outer {
+            let j = self.add_env(outer);
+            self.env_refs[j] += 1;
+            self.env_edges[i].push(Node::Env(j));
+        }
+    }
+
+    /// The environments that nothing outside the graph can reach
+    fn garbage(&self) -> Vec<usize> {
+        // Anything with more references than the graph accounts for is referenced from outside
+        let mut stack: Vec<_> = (0..self.envs.len())
+            .filter(|&i| self.pinned[i] || Rc::strong_count(&self.envs[i]) > self.env_refs[i] + 1)
+            .map(Node::Env)
+            .chain(
+                (0..self.functions.len())
+                    .filter(|&i| Rc::strong_count(&self.functions[i]) > self.function_refs[i] + 1)
+                    .map(Node::Function),
+            )
+            .collect();
+        let mut reached_envs = vec![false; self.envs.len()];
+        let mut reached_functions = vec![false; self.functions.len()];
+
+        while let Some(node) = stack.pop() {
+            match node {
+                Node::Env(i) if !reached_envs[i] => {
+                    reached_envs[i] = true;
+                    stack.extend(&self.env_edges[i]);
+                }
+                Node::Function(i) if !reached_functions[i] => {
+                    reached_functions[i] = true;
+                    stack.push(Node::Env(self.function_envs[i]));
+                }
+                _ => {}
+            }
+        }
+
+        (0..self.envs.len()).filter(|&i| !reached_envs[i]).collect()
+    }
+}
+
+/// Collect the functions a value holds, which keep the environments they captured alive
+///
+/// * `object` - The value
+/// * `functions` - Where to put the functions
+fn functions_in(object: &Object, functions: &mut Vec<Rc<Function>>) {
+    match object {
+        Object::Function(function) | Object::Macro(function) => functions.push(function.clone()),
+        Object::Array(elements) => {
+            for element in elements {
+                functions_in(element, functions);
+            }
+        }
+        Object::Hash(pairs) => {
+            for value in pairs.values() {
+                functions_in(value, functions);
+            }
+        }
+        Object::ReturnValue(value) => functions_in(value, functions),
+        Object::TailCall(function, args) => {
+            functions.push(function.clone());
+            for arg in args {
+                functions_in(arg, functions);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_enclosed_lookup() {
+        let outer = Environment::new();
+        outer.borrow_mut().set("x".into(), Object::Integer(1));
+        outer.borrow_mut().set("y".into(), Object::Integer(2));
+
+        let inner = Environment::new_enclosed(outer.clone());
+        inner.borrow_mut().set("x".into(), Object::Integer(3));
+
+        assert_eq!(inner.borrow().get("x"), Some(Object::Integer(3)));
+        assert_eq!(inner.borrow().get("y"), Some(Object::Integer(2)));
+        assert_eq!(inner.borrow().get("z"), None);
+        assert_eq!(outer.borrow().get("x"), Some(Object::Integer(1)));
+    }
+
+    #[test]
+    fn test_assign() {
+        let outer = Environment::new();
+        outer.borrow_mut().set("x".into(), Object::Integer(1));
+        outer.borrow_mut().set("y".into(), Object::Integer(2));
+
+        let inner = Environment::new_enclosed(outer.clone());
+        inner.borrow_mut().set("x".into(), Object::Integer(3));
+
+        assert!(inner.borrow_mut().assign("x", Object::Integer(4)));
+        assert!(inner.borrow_mut().assign("y", Object::Integer(5)));
+        assert!(!inner.borrow_mut().assign("z", Object::Integer(6)));
+
+        assert_eq!(outer.borrow().get("x"), Some(Object::Integer(1)));
+        assert_eq!(outer.borrow().get("y"), Some(Object::Integer(5)));
+        assert_eq!(inner.borrow().get("x"), Some(Object::Integer(4)));
+        assert_eq!(inner.borrow().get("z"), None);
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let mut interpreter = Interpreter::bare();
+        let program = "
+let make = fn(n) { let f = fn() { n }; f };
+let kept = make(42);
+let i = 0;
+while (i < 10) { make(i); i += 1; }";
+        interpreter.eval_str(program).unwrap();
+
+        let before = heap_stats();
+        // Each call to `make` left an environment holding a function that captured it
+        assert_eq!(collect_garbage(), 10);
+
+        let after = heap_stats();
+        assert_eq!(after.environments, before.environments - 10);
+        assert_eq!(after.captured, before.captured - 10);
+        assert_eq!(after.collections, before.collections + 1);
+        assert_eq!(after.collected, before.collected + 10);
+        assert_eq!(interpreter.eval_str("kept()").unwrap(), Object::Integer(42));
+
+        // A function the host holds on to keeps its environment alive
+        let held = interpreter.eval_str("make(7)").unwrap();
+        drop(interpreter);
+        assert_eq!(collect_garbage(), 0);
+        let Object::Function(function) = held else {
+            panic!("expected a function, got {}", held);
+        };
+        assert_eq!(function.env.borrow().get("n"), Some(Object::Integer(7)));
+    }
+
+    #[test]
+    fn test_collect_automatically() {
+        let program = "
+let make = fn(n) { let f = fn() { n }; f };
+let i = 0;
+while (i < 5000) { make(i); i += 1; }
+let cons = fn(head, tail) { fn(f) { f(head, tail) } };
+let list = 0;
+i = 0;
+while (i < 100000) { list = cons(i, list); i += 1; }
+list(fn(head, tail) { head })";
+
+        assert_eq!(
+            Interpreter::bare().eval_str(program).unwrap(),
+            Object::Integer(99999)
+        );
+        let stats = heap_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.collected >= 4000, "{:?}", stats);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::lexer::LexError;
+use crate::parser::ParseError;
+use crate::token::Span;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// What went wrong while running a program
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RuntimeErrorKind {
+    IdentifierNotFound,
+    /// An infix operator applied to values of two different types
+    TypeMismatch,
+    /// An operator applied to a type it doesn't support
+    UnknownOperator,
+    DivisionByZero,
+    IntegerOverflow,
+    WrongArgumentCount,
+    NotAFunction,
+    UnusableHashKey,
+    /// Indexing into a value that can't be indexed
+    UnsupportedIndex,
+    /// A builtin given an argument of a type it doesn't accept
+    InvalidArgument,
+    /// `unquote` given a value that has no form as code
+    InvalidUnquote,
+    /// A macro that returned something other than quoted code
+    InvalidMacroResult,
+    StackOverflow,
+    InvalidBytecode,
+    /// A `break` or `continue` that isn't inside a loop
+    OutsideLoop,
+    /// An error from a function registered by the host, other than a runtime error
+    Native,
+    /// A program that went past one of the evaluator's limits
+    LimitExceeded,
+    /// A module that couldn't be read or parsed, or that imports itself
+    Import,
+    /// A builtin like `puts` that couldn't write its output
+    Output,
+    /// A value that doesn't have the type named by its annotation, or an annotation naming no
+    /// type
+    TypeAnnotation,
+    /// A failed `assert_eq`
+    AssertionFailed,
+}
+
+/// An error that stopped a program while it was running
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    /// Where in the source the error happened, if known
+    pub span: Option<Span>,
+}
+
+impl RuntimeError {
+    pub fn new<S: Into<String>>(kind: RuntimeErrorKind, message: S) -> RuntimeError {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// A limit on evaluation, as set in an `EvalConfig`, that a program went past
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Limit {
+    /// The most statements and expressions to evaluate
+    Steps(u64),
+    /// The deepest function calls can nest
+    Depth(usize),
+    /// The longest a program can run for
+    Time(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps(steps) => write!(f, "took more than {} steps", steps),
+            Limit::Depth(depth) => write!(f, "calls nested more than {} deep", depth),
+            Limit::Time(time) => write!(f, "ran for more than {:?}", time),
+        }
+    }
+}
+
+/// Any error from reading, lexing, parsing, expanding, compiling or running a program
+#[derive(Debug)]
+pub enum MonkeyError {
+    Io(io::Error),
+    Lex(LexError),
+    /// Every error found while parsing, in the order they were found
+    Parse(Vec<ParseError>),
+    Macro(RuntimeError),
+    Compile(String),
+    Runtime(RuntimeError),
+    /// Evaluation stopped for going past one of its limits
+    LimitExceeded(Limit),
+}
+
+impl MonkeyError {
+    /// The span of source the error is about, if known. For parse errors this is the span of
+    /// the first error.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            MonkeyError::Io(_) | MonkeyError::Compile(_) | MonkeyError::LimitExceeded(_) => None,
+            MonkeyError::Lex(err) => Some(err.span()),
+            MonkeyError::Parse(errors) => errors.first().map(|err| err.span),
+            MonkeyError::Macro(err) | MonkeyError::Runtime(err) => err.span,
+        }
+    }
+}
+
+impl fmt::Display for MonkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonkeyError::Io(err) => write!(f, "could not read script: {}", err),
+            MonkeyError::Lex(err) => write!(f, "{}", err),
+            MonkeyError::Parse(errors) => {
+                write!(f, "parser errors:")?;
+                for err in errors {
+                    write!(f, "\n\t{}", err)?;
+                }
+                Ok(())
+            }
+            MonkeyError::Macro(err) => write!(f, "macro error: {}", err),
+            MonkeyError::Compile(message) => write!(f, "compile error: {}", message),
+            MonkeyError::Runtime(err) => write!(f, "ERROR: {}", err),
+            MonkeyError::LimitExceeded(limit) => write!(f, "limit exceeded: {}", limit),
+        }
+    }
+}
+
+impl Error for MonkeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MonkeyError::Io(err) => Some(err),
+            MonkeyError::Lex(err) => Some(err),
+            MonkeyError::Parse(errors) => errors.first().map(|err| err as _),
+            MonkeyError::Macro(err) | MonkeyError::Runtime(err) => Some(err),
+            MonkeyError::Compile(_) | MonkeyError::LimitExceeded(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for MonkeyError {
+    fn from(err: io::Error) -> MonkeyError {
+        MonkeyError::Io(err)
+    }
+}
+
+impl From<LexError> for MonkeyError {
+    fn from(err: LexError) -> MonkeyError {
+        MonkeyError::Lex(err)
+    }
+}
+
+impl From<RuntimeError> for MonkeyError {
+    fn from(err: RuntimeError) -> MonkeyError {
+        MonkeyError::Runtime(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_runtime_error() {
+        let mut parser = Parser::new(Lexer::new("let a = 1; a / 0"));
+        let program = parser.parse_program();
+
+        let err = Evaluator::new()
+            .try_eval(&program, &Environment::new())
+            .unwrap_err();
+
+        assert!(matches!(
+            &err,
+            MonkeyError::Runtime(RuntimeError {
+                kind: RuntimeErrorKind::DivisionByZero,
+                ..
+            })
+        ));
+        assert_eq!(err.to_string(), "ERROR: division by zero");
+        assert_eq!(err.source().unwrap().to_string(), "division by zero");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let mut parser = Parser::new(Lexer::new("let = 1;\nlet 2"));
+        parser.parse_program();
+        let err = MonkeyError::Parse(parser.errors().to_vec());
+
+        assert_eq!(err.span(), Some(Span { start: 4, end: 5 }));
+        assert_eq!(
+            err.to_string(),
+            "parser errors:
+\tline 1, column 5: expected next token to be Ident, got = instead
+\tline 1, column 5: no prefix parse function for = found
+\tline 2, column 5: expected next token to be Ident, got 2 instead"
+        );
+    }
+}
@@ -0,0 +1,2388 @@
+use crate::ast::{self, BlockStatement, Expression, Program, Statement};
+use crate::builtins::{self, Capture, Context, Output};
+use crate::environment::{Env, Environment};
+use crate::error::{Limit, MonkeyError, RuntimeErrorKind};
+use crate::lexer::Lexer;
+use crate::macro_expansion::{define_macros, expand_macros};
+use crate::object::{Function, HashKey, Object};
+use crate::parser::Parser;
+use crate::token::{Span, Token};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How often, in steps, the evaluator checks whether it has run out of time
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// A piece of the program the evaluator is about to evaluate
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'a> {
+    Statement(&'a Statement),
+    Expression(&'a Expression),
+}
+
+/// Where the value of a statement or expression goes, which decides whether a call there is a
+/// tail call
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+enum Position {
+    /// Somewhere it's used before the function returns, or outside any function
+    Inner,
+    /// A statement of a function whose value is dropped. A `return` in it still leaves the
+    /// function directly, so what it returns is in tail position.
+    Statement,
+    /// The result of the function
+    Tail,
+}
+
+/// Watches a program being evaluated, e.g. to trace, instrument or step through it
+pub trait EvalHook {
+    /// Called before each statement and expression is evaluated
+    ///
+    /// * `node` - The statement or expression about to be evaluated
+    /// * `env` - The environment it's evaluated in, whose own bindings are the locals in scope
+    /// * `depth` - How many function calls deep the evaluator is, 0 at the top level
+    fn before(&mut self, node: Node<'_>, env: &Env, depth: usize);
+
+    /// Called after each statement and expression is evaluated
+    ///
+    /// * `node` - The statement or expression just evaluated
+    /// * `result` - What it evaluated to, which may be an error, or a value still unwinding
+    ///   from a `return`
+    /// * `depth` - How many function calls deep the evaluator is, 0 at the top level
+    fn after(&mut self, _node: Node<'_>, _result: &Object, _depth: usize) {}
+
+    /// Called before `before` for each statement that has a span, i.e. one parsed from source
+    ///
+    /// * `span` - Where the statement is in the source
+    fn statement_span(&mut self, _span: Span) {}
+}
+
+impl<H: EvalHook + ?Sized> EvalHook for &mut H {
+    fn before(&mut self, node: Node<'_>, env: &Env, depth: usize) {
+        (**self).before(node, env, depth)
+    }
+
+    fn after(&mut self, node: Node<'_>, result: &Object, depth: usize) {
+        (**self).after(node, result, depth)
+    }
+
+    fn statement_span(&mut self, span: Span) {
+        (**self).statement_span(span)
+    }
+}
+
+/// Calls a closure before each statement is evaluated, for `Evaluator::with_tracer`
+struct StatementTracer<F>(F);
+
+impl<F: FnMut(&Statement)> EvalHook for StatementTracer<F> {
+    fn before(&mut self, node: Node<'_>, _env: &Env, _depth: usize) {
+        if let Node::Statement(statement) = node {
+            (self.0)(statement);
+        }
+    }
+}
+
+/// How deep calls can nest unless configured otherwise. Each call takes up to about 25KB of
+/// stack in a debug build and 6KB in a release build, so this fits well within `STACK_SIZE`.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// How much stack evaluating programs needs for calls to nest `DEFAULT_MAX_DEPTH` deep without
+/// overflowing. The command line runs programs on a thread this size, and hosts should too or
+/// else lower `EvalConfig::max_depth`.
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Limits on how much work evaluating a program can take, e.g. to run untrusted scripts. Steps
+/// and time are unlimited unless set, calls nest at most `DEFAULT_MAX_DEPTH` deep by default,
+/// and going past any of them stops the program with `MonkeyError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EvalConfig {
+    /// The most statements and expressions to evaluate
+    pub max_steps: Option<u64>,
+    /// The deepest function calls can nest
+    pub max_depth: Option<usize>,
+    /// The longest a program can run for
+    pub timeout: Option<Duration>,
+    /// Keep programs from touching anything outside the interpreter: what builtins like `puts`
+    /// write is kept for `Evaluator::take_captured`, and `import` fails rather than reading
+    /// files
+    pub pure: bool,
+}
+
+impl Default for EvalConfig {
+    fn default() -> EvalConfig {
+        EvalConfig {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            timeout: None,
+            pure: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Evaluator<'h> {
+    hook: Option<Box<dyn EvalHook + 'h>>,
+    config: EvalConfig,
+    /// How many function calls deep evaluation is
+    depth: usize,
+    /// How many statements and expressions the current program has evaluated
+    steps: u64,
+    /// When the current program runs out of time
+    deadline: Option<Instant>,
+    /// The limit the current program went past, if any
+    exceeded: Option<Limit>,
+    /// The file programs are read from, if any, which imports are resolved relative to
+    path: Option<PathBuf>,
+    /// The standard library's bindings, if modules can use it, which each imported module gets
+    /// a fresh scope inside of
+    stdlib: Option<Env>,
+    /// The exports of each module imported so far, by canonical path
+    modules: HashMap<PathBuf, Object>,
+    /// The files being evaluated, innermost last, with the program's own file first if known
+    importing: Vec<PathBuf>,
+    /// Where builtins like `puts` write
+    output: Output<'h>,
+    /// Where builtins write instead in pure mode
+    captured: Capture,
+}
+
+impl fmt::Debug for Evaluator<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Evaluator")
+            .field("hooked", &self.hook.is_some())
+            .field("config", &self.config)
+            .field("depth", &self.depth)
+            .field("steps", &self.steps)
+            .finish()
+    }
+}
+
+impl<'h> Evaluator<'h> {
+    pub fn new() -> Evaluator<'h> {
+        Evaluator::default()
+    }
+
+    /// Create an evaluator that calls a hook before and after evaluating each statement and
+    /// expression
+    ///
+    /// * `hook` - The hook to call
+    pub fn with_hook(hook: &'h mut dyn EvalHook) -> Evaluator<'h> {
+        Evaluator {
+            hook: Some(Box::new(hook)),
+            ..Evaluator::default()
+        }
+    }
+
+    /// Create an evaluator that calls a closure before evaluating each statement, e.g. to step
+    /// through a program or see which statements run. `with_hook` sees expressions as well.
+    ///
+    /// * `tracer` - The closure to call with each statement
+    pub fn with_tracer<F: FnMut(&Statement) + 'h>(tracer: F) -> Evaluator<'h> {
+        Evaluator {
+            hook: Some(Box::new(StatementTracer(tracer))),
+            ..Evaluator::default()
+        }
+    }
+
+    /// Limit the work each program evaluated can take
+    ///
+    /// * `config` - The limits to keep to
+    pub fn with_config(mut self, config: EvalConfig) -> Evaluator<'h> {
+        self.config = config;
+        self
+    }
+
+    /// The limits each program is kept to
+    pub fn config(&self) -> EvalConfig {
+        self.config
+    }
+
+    /// Send what builtins like `puts` write somewhere other than standard output, e.g. to
+    /// capture it
+    ///
+    /// * `output` - Where to write
+    pub fn with_output<W: Write + 'h>(mut self, output: W) -> Evaluator<'h> {
+        self.output = Output::new(output);
+        self
+    }
+
+    /// Where builtins like `puts` write
+    pub fn output(&mut self) -> &mut Output<'h> {
+        &mut self.output
+    }
+
+    /// What builtins have written in pure mode so far, without taking it
+    pub fn captured_output(&self) -> String {
+        self.captured.contents()
+    }
+
+    /// Take what builtins have written in pure mode, leaving nothing to take next time
+    pub fn take_captured(&self) -> String {
+        self.captured.take()
+    }
+
+    /// Write what builtins write in pure mode to the same place as another evaluator
+    ///
+    /// * `captured` - Where the other evaluator's builtins write
+    pub(crate) fn with_captured(mut self, captured: Capture) -> Evaluator<'h> {
+        self.captured = captured;
+        self
+    }
+
+    /// Where builtins write in pure mode
+    pub(crate) fn captured(&self) -> Capture {
+        self.captured.clone()
+    }
+
+    /// Set the file programs are read from, so `import`s in them are resolved relative to it.
+    /// Otherwise they're resolved relative to the working directory.
+    ///
+    /// * `path` - The program's file
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Evaluator<'h> {
+        let path = path.as_ref();
+        self.path = Some(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        self
+    }
+
+    /// Let imported modules use the standard library. Otherwise they only have the builtins.
+    ///
+    /// * `stdlib` - An environment the standard library has been evaluated in
+    pub fn with_stdlib(mut self, stdlib: Env) -> Evaluator<'h> {
+        self.stdlib = Some(stdlib);
+        self
+    }
+
+    /// The standard library imported modules can use, if any
+    pub(crate) fn stdlib(&self) -> Option<Env> {
+        self.stdlib.clone()
+    }
+
+    /// Evaluate a program, returning the value of its last statement, the value it returned,
+    /// or the first error encountered
+    ///
+    /// * `program` - The program to evaluate
+    /// * `env` - The environment to evaluate in, which receives any top-level bindings
+    pub fn eval(&mut self, program: &Program, env: &Env) -> Object {
+        self.start();
+        self.eval_program(program, env)
+    }
+
+    /// Evaluate a program, returning the value of every top-level statement, e.g. to show each
+    /// cell's result in a notebook. `let` and `return` statements give `Null`, and evaluation
+    /// stops after a `return` or an error, which is the last value.
+    ///
+    /// * `program` - The program to evaluate
+    /// * `env` - The environment to evaluate in, which receives any top-level bindings
+    pub fn eval_collect(&mut self, program: &Program, env: &Env) -> Vec<Object> {
+        self.start();
+
+        let mut values = vec![];
+        for (i, statement) in program.statements.iter().enumerate() {
+            if let Statement::Comment(_) = statement {
+                continue;
+            }
+            self.locate(&program.spans, i);
+
+            let value = match self.eval_statement(statement, env, Position::Inner) {
+                Object::ReturnValue(_) => {
+                    values.push(Object::Null);
+                    break;
+                }
+                value @ (Object::Break | Object::Continue) => outside_loop(&value),
+                value => value,
+            };
+            let error = value.is_error();
+            values.push(value);
+            if error {
+                break;
+            }
+        }
+
+        values
+    }
+
+    /// Tell the hook where a statement about to be evaluated is, if it's known
+    ///
+    /// * `spans` - The spans of the statements in its block
+    /// * `i` - Its index in the block
+    fn locate(&mut self, spans: &[Span], i: usize) {
+        if let (Some(hook), Some(&span)) = (self.hook.as_deref_mut(), spans.get(i)) {
+            hook.statement_span(span);
+        }
+    }
+
+    /// Reset the limits and imports in progress, ready to evaluate another program
+    fn start(&mut self) {
+        self.steps = 0;
+        self.deadline = self.config.timeout.map(|timeout| Instant::now() + timeout);
+        self.exceeded = None;
+        self.importing = self.path.iter().cloned().collect();
+    }
+
+    /// Evaluate the statements of a program or module
+    ///
+    /// * `program` - The program to evaluate
+    /// * `env` - The environment to evaluate in, which receives any top-level bindings
+    fn eval_program(&mut self, program: &Program, env: &Env) -> Object {
+        let mut result = Object::Null;
+
+        for (i, statement) in program.statements.iter().enumerate() {
+            // Comments have no value, so they mustn't replace the previous statement's
+            if let Statement::Comment(_) = statement {
+                continue;
+            }
+            self.locate(&program.spans, i);
+            result = self.eval_statement(statement, env, Position::Inner);
+
+            match result {
+                Object::ReturnValue(value) => return *value,
+                Object::Error(_) => return result,
+                Object::Break | Object::Continue => return outside_loop(&result),
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    /// Evaluate a program like `eval`, but reporting an error as a `MonkeyError` rather than an
+    /// error object
+    ///
+    /// * `program` - The program to evaluate
+    /// * `env` - The environment to evaluate in, which receives any top-level bindings
+    pub fn try_eval(&mut self, program: &Program, env: &Env) -> Result<Object, MonkeyError> {
+        match self.eval(program, env) {
+            Object::Error(err) => match self.exceeded.take() {
+                Some(limit) => Err(MonkeyError::LimitExceeded(limit)),
+                None => Err(MonkeyError::Runtime(err)),
+            },
+            result => Ok(result),
+        }
+    }
+
+    /// Evaluate a block, stopping early at a return, a `break` or `continue`, or an error.
+    /// Unlike `eval`, returned values stay wrapped so they keep unwinding through any enclosing
+    /// blocks.
+    ///
+    /// * `block` - The block to evaluate
+    /// * `env` - The environment to evaluate in
+    /// * `position` - Where the block's value goes
+    fn eval_block(&mut self, block: &BlockStatement, env: &Env, position: Position) -> Object {
+        let mut result = Object::Null;
+        // Only the last statement gives the block its value
+        let last = block
+            .statements
+            .iter()
+            .rposition(|statement| !matches!(statement, Statement::Comment(_)));
+
+        for (i, statement) in block.statements.iter().enumerate() {
+            if let Statement::Comment(_) = statement {
+                continue;
+            }
+            let position = match position {
+                Position::Tail if Some(i) != last => Position::Statement,
+                position => position,
+            };
+            self.locate(&block.spans, i);
+            result = self.eval_statement(statement, env, position);
+
+            if matches!(
+                result,
+                Object::ReturnValue(_) | Object::Break | Object::Continue | Object::Error(_)
+            ) {
+                return result;
+            }
+        }
+
+        result
+    }
+
+    fn eval_statement(&mut self, statement: &Statement, env: &Env, position: Position) -> Object {
+        if let Err(err) = self.step() {
+            return err;
+        }
+        if let Some(hook) = self.hook.as_deref_mut() {
+            hook.before(Node::Statement(statement), env, self.depth);
+        }
+
+        let result = match statement {
+            Statement::Expression(expression) => self.eval_expression_in(expression, env, position),
+            Statement::Return(value) => {
+                // What's returned leaves the function directly, unless something's in the way
+                let position = match position {
+                    Position::Inner => Position::Inner,
+                    Position::Statement | Position::Tail => Position::Tail,
+                };
+
+                let value = match value {
+                    Some(value) => self.eval_expression_in(value, env, position),
+                    None => Object::Null,
+                };
+
+                match value {
+                    value @ Object::Error(_) => value,
+                    value => Object::ReturnValue(Box::new(value)),
+                }
+            }
+            Statement::Assign(name, value) => match self.eval_expression(value, env) {
+                value @ (Object::Error(_) | Object::Break | Object::Continue) => value,
+                value => {
+                    if env.borrow_mut().assign(name, value) {
+                        Object::Null
+                    } else {
+                        Object::error(
+                            RuntimeErrorKind::IdentifierNotFound,
+                            format!("identifier not found: {}", name),
+                        )
+                    }
+                }
+            },
+            Statement::Break => Object::Break,
+            Statement::Continue => Object::Continue,
+            Statement::Comment(_) => Object::Null,
+            Statement::Let(name, value) => match self.eval_expression(value, env) {
+                value @ (Object::Error(_) | Object::Break | Object::Continue) => value,
+                value => {
+                    env.borrow_mut().set(name.clone(), value);
+                    Object::Null
+                }
+            },
+        };
+
+        if let Some(hook) = self.hook.as_deref_mut() {
+            hook.after(Node::Statement(statement), &result, self.depth);
+        }
+        result
+    }
+
+    fn eval_expression(&mut self, expression: &Expression, env: &Env) -> Object {
+        self.eval_expression_in(expression, env, Position::Inner)
+    }
+
+    fn eval_expression_in(
+        &mut self,
+        expression: &Expression,
+        env: &Env,
+        position: Position,
+    ) -> Object {
+        if let Err(err) = self.step() {
+            return err;
+        }
+        if let Some(hook) = self.hook.as_deref_mut() {
+            hook.before(Node::Expression(expression), env, self.depth);
+        }
+
+        let result = self.eval_node(expression, env, position);
+
+        if let Some(hook) = self.hook.as_deref_mut() {
+            hook.after(Node::Expression(expression), &result, self.depth);
+        }
+        result
+    }
+
+    /// Evaluate an expression once it's been counted as a step and shown to the hook
+    ///
+    /// * `expression` - The expression to evaluate
+    /// * `env` - The environment to evaluate in
+    /// * `position` - Where the expression's value goes
+    fn eval_node(&mut self, expression: &Expression, env: &Env, position: Position) -> Object {
+        match expression {
+            Expression::Integer(value) => Object::Integer(*value),
+            Expression::Float(value) => Object::Float(*value),
+            Expression::Boolean(value) => Object::Boolean(*value),
+            Expression::String(value) => Object::String(value.clone()),
+            Expression::Array(elements) => match self.eval_expressions(elements, env) {
+                Ok(elements) => Object::Array(elements),
+                Err(err) => err,
+            },
+            Expression::Index(left, index) => {
+                let left = self.eval_expression(left, env);
+                if left.is_error() {
+                    return left;
+                }
+
+                let index = self.eval_expression(index, env);
+                if index.is_error() {
+                    return index;
+                }
+
+                eval_index_expression(left, index)
+            }
+            Expression::Hash(pairs) => {
+                let mut hash = HashMap::with_capacity(pairs.len());
+
+                for (key, value) in pairs {
+                    let key = self.eval_expression(key, env);
+                    if key.is_error() {
+                        return key;
+                    }
+
+                    let key = match key.hash_key() {
+                        Ok(key) => key,
+                        Err(err) => return err,
+                    };
+
+                    let value = self.eval_expression(value, env);
+                    if value.is_error() {
+                        return value;
+                    }
+
+                    hash.insert(key, value);
+                }
+
+                Object::Hash(hash)
+            }
+            Expression::Prefix(operator, right) => {
+                let right = self.eval_expression(right, env);
+                if right.is_error() {
+                    return right;
+                }
+
+                eval_prefix_expression(operator, right)
+            }
+            Expression::Infix(left, operator, right) => {
+                let left = self.eval_expression(left, env);
+                if left.is_error() {
+                    return left;
+                }
+
+                // The right side of `??` is only evaluated if the left is null, and is then the
+                // result
+                if *operator == Token::Coalesce {
+                    return match left {
+                        Object::Null => self.eval_expression_in(right, env, position),
+                        left => left,
+                    };
+                }
+
+                // The right side of `&&` and `||` is only evaluated if it decides the result
+                match (operator, is_truthy(&left)) {
+                    (Token::And, false) => return Object::Boolean(false),
+                    (Token::Or, true) => return Object::Boolean(true),
+                    (Token::And | Token::Or, _) => {
+                        let right = self.eval_expression(right, env);
+                        if right.is_error() {
+                            return right;
+                        }
+
+                        return Object::Boolean(is_truthy(&right));
+                    }
+                    _ => {}
+                }
+
+                let right = self.eval_expression(right, env);
+                if right.is_error() {
+                    return right;
+                }
+
+                eval_infix_expression(operator, left, right)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                let condition = self.eval_expression(condition, env);
+                if condition.is_error() {
+                    return condition;
+                }
+
+                if is_truthy(&condition) {
+                    self.eval_block(consequence, env, position)
+                } else if let Some(alternative) = alternative {
+                    self.eval_block(alternative, env, position)
+                } else {
+                    Object::Null
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                let condition = self.eval_expression(condition, env);
+                if condition.is_error() {
+                    return condition;
+                }
+
+                if is_truthy(&condition) {
+                    self.eval_expression_in(consequence, env, position)
+                } else {
+                    self.eval_expression_in(alternative, env, position)
+                }
+            }
+            Expression::While { condition, body } => loop {
+                let condition = self.eval_expression(condition, env);
+                if condition.is_error() {
+                    return condition;
+                }
+                if !is_truthy(&condition) {
+                    return Object::Null;
+                }
+
+                // The loop's value is null, so its body is never the function's result
+                match self.eval_block(body, env, position.min(Position::Statement)) {
+                    Object::Break => return Object::Null,
+                    result @ (Object::ReturnValue(_) | Object::Error(_)) => return result,
+                    _ => {}
+                }
+            },
+            Expression::Identifier(name) => {
+                if let Some(value) = env.borrow().get(name) {
+                    return value;
+                }
+
+                match builtins::lookup(name) {
+                    Some(builtin) => Object::Builtin(builtin),
+                    None => Object::error(
+                        RuntimeErrorKind::IdentifierNotFound,
+                        format!("identifier not found: {}", name),
+                    ),
+                }
+            }
+            Expression::Function { parameters, body } => {
+                Environment::capture(env);
+                Object::Function(Rc::new(Function {
+                    parameters: parameters.clone(),
+                    body: body.clone(),
+                    env: env.clone(),
+                }))
+            }
+            Expression::Macro { parameters, body } => {
+                Environment::capture(env);
+                Object::Macro(Rc::new(Function {
+                    parameters: parameters.clone(),
+                    body: body.clone(),
+                    env: env.clone(),
+                }))
+            }
+            Expression::Annotated { value, type_name } => match self.eval_expression(value, env) {
+                value @ Object::Error(_) => value,
+                value => check_type(value, type_name),
+            },
+            Expression::Call {
+                function,
+                arguments,
+            } if matches!(function.as_ref(), Expression::Identifier(name) if name == "quote") => {
+                match arguments.as_slice() {
+                    [argument] => self.quote(argument, env),
+                    arguments => Object::error(
+                        RuntimeErrorKind::WrongArgumentCount,
+                        format!("wrong number of arguments. got={}, want=1", arguments.len()),
+                    ),
+                }
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } if matches!(function.as_ref(), Expression::Identifier(name) if name == "import") => {
+                let path = match arguments.as_slice() {
+                    [argument] => self.eval_expression(argument, env),
+                    arguments => {
+                        return Object::error(
+                            RuntimeErrorKind::WrongArgumentCount,
+                            format!("wrong number of arguments. got={}, want=1", arguments.len()),
+                        )
+                    }
+                };
+
+                match path {
+                    Object::String(path) => self.import(&path),
+                    err @ Object::Error(_) => err,
+                    path => Object::error(
+                        RuntimeErrorKind::InvalidArgument,
+                        format!(
+                            "argument to `import` must be STRING, got {}",
+                            path.type_name()
+                        ),
+                    ),
+                }
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                let function = self.eval_expression(function, env);
+                if function.is_error() {
+                    return function;
+                }
+
+                match self.eval_expressions(arguments, env) {
+                    // The calling function has nothing left to do, so it's left to make the call
+                    Ok(args) => match function {
+                        Object::Function(function) if position == Position::Tail => {
+                            Object::TailCall(function, args)
+                        }
+                        function => self.apply_function(function, args),
+                    },
+                    Err(err) => err,
+                }
+            }
+        }
+    }
+
+    /// Evaluate a module the first time it's imported, returning its top-level bindings as a
+    /// hash from name to value. Names starting with `_` are kept private.
+    ///
+    /// * `path` - The module's file, relative to the file importing it
+    fn import(&mut self, path: &str) -> Object {
+        if self.config.pure {
+            return Object::error(
+                RuntimeErrorKind::Import,
+                format!(
+                    "could not import {}: imports are disabled in pure mode",
+                    path
+                ),
+            );
+        }
+
+        let dir = match self.importing.last() {
+            Some(file) => file.parent().unwrap_or(Path::new("")),
+            None => Path::new(""),
+        };
+        let file = match fs::canonicalize(dir.join(path)) {
+            Ok(file) => file,
+            Err(err) => {
+                return Object::error(
+                    RuntimeErrorKind::Import,
+                    format!("could not import {}: {}", path, err),
+                )
+            }
+        };
+
+        if let Some(exports) = self.modules.get(&file) {
+            return exports.clone();
+        }
+        if let Some(start) = self.importing.iter().position(|f| *f == file) {
+            let cycle: Vec<_> = self.importing[start..]
+                .iter()
+                .chain([&file])
+                .map(|f| f.display().to_string())
+                .collect();
+            return Object::error(
+                RuntimeErrorKind::Import,
+                format!("import cycle: {}", cycle.join(" -> ")),
+            );
+        }
+
+        let program = match read_module(&file) {
+            Ok(program) => program,
+            Err(message) => {
+                return Object::error(
+                    RuntimeErrorKind::Import,
+                    format!("could not import {}: {}", path, message),
+                )
+            }
+        };
+
+        let env = match &self.stdlib {
+            Some(stdlib) => Environment::new_enclosed(stdlib.clone()),
+            None => Environment::new(),
+        };
+        self.importing.push(file.clone());
+        let result = self.eval_program(&program, &env);
+        self.importing.pop();
+        if result.is_error() {
+            return result;
+        }
+
+        let exports = env
+            .borrow()
+            .bindings()
+            .into_iter()
+            .filter(|(name, _)| !name.starts_with('_'))
+            .map(|(name, value)| (HashKey::String(name.to_string()), value.clone()))
+            .collect();
+        let exports = Object::Hash(exports);
+        self.modules.insert(file, exports.clone());
+
+        exports
+    }
+
+    /// Count a step of evaluation, failing if that takes the program past its step limit or its
+    /// time has run out
+    fn step(&mut self) -> Result<(), Object> {
+        self.steps += 1;
+
+        if let Some(max_steps) = self.config.max_steps {
+            if self.steps > max_steps {
+                return Err(self.exceed(Limit::Steps(max_steps)));
+            }
+        }
+        // Reading the clock every step would slow everything down
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.config.timeout) {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && Instant::now() >= deadline {
+                return Err(self.exceed(Limit::Time(timeout)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record that the program went past a limit, returning the error that stops it
+    ///
+    /// * `limit` - The limit it went past
+    fn exceed(&mut self, limit: Limit) -> Object {
+        self.exceeded = Some(limit);
+        Object::error(RuntimeErrorKind::LimitExceeded, limit.to_string())
+    }
+
+    /// Quote an expression, leaving it unevaluated apart from any `unquote(...)` calls inside
+    /// it, which are replaced with the code for their values
+    ///
+    /// * `expression` - The expression to quote
+    /// * `env` - The environment to evaluate unquoted expressions in
+    fn quote(&mut self, expression: &Expression, env: &Env) -> Object {
+        let quoted = ast::modify(expression.clone(), &mut |expression| match expression {
+            Expression::Call {
+                function,
+                arguments,
+            } if arguments.len() == 1
+                && matches!(function.as_ref(), Expression::Identifier(name) if name == "unquote") =>
+            {
+                object_to_expression(self.eval_expression(&arguments[0], env))
+            }
+            expression => Ok(expression),
+        });
+
+        match quoted {
+            Ok(expression) => Object::Quote(expression),
+            Err(err) => err,
+        }
+    }
+
+    /// Evaluate a list of expressions in order, stopping at the first error
+    ///
+    /// * `expressions` - The expressions to evaluate
+    /// * `env` - The environment to evaluate in
+    fn eval_expressions(
+        &mut self,
+        expressions: &[Expression],
+        env: &Env,
+    ) -> Result<Vec<Object>, Object> {
+        let mut objects = Vec::with_capacity(expressions.len());
+
+        for expression in expressions {
+            let object = self.eval_expression(expression, env);
+            if object.is_error() {
+                return Err(object);
+            }
+            objects.push(object);
+        }
+
+        Ok(objects)
+    }
+
+    /// Call a function with already evaluated arguments
+    ///
+    /// * `function` - The function to call
+    /// * `args` - The arguments to bind to its parameters
+    fn apply_function(&mut self, function: Object, args: Vec<Object>) -> Object {
+        let mut function = match function {
+            Object::Function(function) => function,
+            Object::Builtin(builtin) => return (builtin.func)(args, self),
+            Object::Native(native) => return native.call(&args).unwrap_or_else(Object::Error),
+            Object::Memoized(memoized) => return memoized.call(args, self),
+            function => {
+                return Object::error(
+                    RuntimeErrorKind::NotAFunction,
+                    format!("not a function: {}", function.type_name()),
+                );
+            }
+        };
+
+        let mut env = match bind_arguments(&function, args) {
+            Ok(env) => env,
+            Err(err) => return err,
+        };
+
+        if let Some(max_depth) = self.config.max_depth {
+            if self.depth >= max_depth {
+                return self.exceed(Limit::Depth(max_depth));
+            }
+        }
+
+        self.depth += 1;
+        // Tail calls are made here in place of the call that produced them, at the same depth
+        let result = loop {
+            let result = match self.eval_block(&function.body, &env, Position::Tail) {
+                Object::ReturnValue(value) => *value,
+                result => result,
+            };
+
+            match result {
+                Object::TailCall(next, args) => match bind_arguments(&next, args) {
+                    Ok(next_env) => {
+                        function = next;
+                        env = next_env;
+                    }
+                    Err(err) => break err,
+                },
+                result @ (Object::Break | Object::Continue) => break outside_loop(&result),
+                result => break result,
+            }
+        };
+        self.depth -= 1;
+
+        result
+    }
+}
+
+impl Context for Evaluator<'_> {
+    fn output(&mut self) -> &mut dyn Write {
+        if self.config.pure {
+            &mut self.captured
+        } else {
+            &mut self.output
+        }
+    }
+
+    fn call(&mut self, function: Object, args: Vec<Object>) -> Object {
+        self.apply_function(function, args)
+    }
+}
+
+/// Check a value has the type named by an annotation, giving it back if it does
+///
+/// * `value` - The annotated value
+/// * `type_name` - The name of the type, as written in the annotation
+pub(crate) fn check_type(value: Object, type_name: &str) -> Object {
+    let matches = match type_name {
+        "int" => matches!(value, Object::Integer(_)),
+        "float" => matches!(value, Object::Float(_)),
+        "bool" => matches!(value, Object::Boolean(_)),
+        "string" => matches!(value, Object::String(_)),
+        "array" => matches!(value, Object::Array(_)),
+        "hash" => matches!(value, Object::Hash(_)),
+        "null" => matches!(value, Object::Null),
+        "fn" => matches!(
+            value,
+            Object::Function(_)
+                | Object::Builtin(_)
+                | Object::Native(_)
+                | Object::Memoized(_)
+                | Object::Closure(_)
+        ),
+        _ => {
+            return Object::error(
+                RuntimeErrorKind::TypeAnnotation,
+                format!("unknown type in annotation: {}", type_name),
+            )
+        }
+    };
+
+    if matches {
+        value
+    } else {
+        Object::error(
+            RuntimeErrorKind::TypeAnnotation,
+            format!(
+                "type annotation mismatch: expected {}, got {}",
+                type_name,
+                value.type_name()
+            ),
+        )
+    }
+}
+
+/// Create the environment for a call to a function, with its parameters bound to the arguments
+///
+/// * `function` - The function being called
+/// * `args` - The arguments to bind to its parameters
+fn bind_arguments(function: &Function, args: Vec<Object>) -> Result<Env, Object> {
+    if function.parameters.len() != args.len() {
+        return Err(Object::error(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!(
+                "wrong number of arguments: want={}, got={}",
+                function.parameters.len(),
+                args.len()
+            ),
+        ));
+    }
+
+    let env = Environment::new_enclosed(function.env.clone());
+    for (parameter, arg) in function.parameters.iter().zip(args) {
+        env.borrow_mut().set(parameter.clone(), arg);
+    }
+
+    Ok(env)
+}
+
+/// Read, parse and expand a module
+///
+/// * `file` - The module's file
+fn read_module(file: &Path) -> Result<Program, String> {
+    let source = fs::read_to_string(file).map_err(|err| err.to_string())?;
+    let mut parser = Parser::new(Lexer::new(&source));
+    let mut program = parser.parse_program();
+
+    if let Some(err) = parser.errors().first() {
+        return Err(err.to_string());
+    }
+
+    let macro_env = Environment::new();
+    define_macros(&mut program, &macro_env);
+    expand_macros(program, &macro_env).map_err(|err| err.to_string())
+}
+
+/// The error for a `break` or `continue` that unwound out of a function or program without
+/// meeting a loop
+///
+/// * `control` - The `Object::Break` or `Object::Continue` that escaped
+fn outside_loop(control: &Object) -> Object {
+    Object::error(
+        RuntimeErrorKind::OutsideLoop,
+        format!("{} outside loop", control),
+    )
+}
+
+/// Turn an unquoted value back into code
+///
+/// * `object` - The value to convert
+fn object_to_expression(object: Object) -> Result<Expression, Object> {
+    match object {
+        Object::Integer(value) => Ok(Expression::Integer(value)),
+        Object::Float(value) => Ok(Expression::Float(value)),
+        Object::Boolean(value) => Ok(Expression::Boolean(value)),
+        Object::String(value) => Ok(Expression::String(value)),
+        Object::Quote(expression) => Ok(expression),
+        err @ Object::Error(_) => Err(err),
+        object => Err(Object::error(
+            RuntimeErrorKind::InvalidUnquote,
+            format!("cannot unquote {}", object.type_name()),
+        )),
+    }
+}
+
+/// Whether a value counts as true in a condition. Everything except `false` and `null` does.
+///
+/// * `object` - The value to check
+pub(crate) fn is_truthy(object: &Object) -> bool {
+    !matches!(object, Object::Boolean(false) | Object::Null)
+}
+
+pub(crate) fn eval_prefix_expression(operator: &Token, right: Object) -> Object {
+    match (operator, right) {
+        (Token::Bang, right) => Object::Boolean(!is_truthy(&right)),
+        (Token::Minus, Object::Integer(value)) => match value.checked_neg() {
+            Some(value) => Object::Integer(value),
+            None => Object::error(
+                RuntimeErrorKind::IntegerOverflow,
+                format!("integer overflow: -{}", value),
+            ),
+        },
+        (Token::Minus, Object::Float(value)) => Object::Float(-value),
+        // Interpolated into a string
+        (Token::Dollar, right) => Object::String(right.to_string()),
+        (operator, right) => Object::error(
+            RuntimeErrorKind::UnknownOperator,
+            format!("unknown operator: {}{}", operator, right.type_name()),
+        ),
+    }
+}
+
+pub(crate) fn eval_infix_expression(operator: &Token, left: Object, right: Object) -> Object {
+    match (left, right) {
+        (Object::Integer(left), Object::Integer(right)) => {
+            eval_integer_infix_expression(operator, left, right)
+        }
+        (Object::Float(left), Object::Float(right)) => {
+            eval_float_infix_expression(operator, left, right)
+        }
+        (Object::Integer(left), Object::Float(right)) => {
+            eval_float_infix_expression(operator, left as f64, right)
+        }
+        (Object::Float(left), Object::Integer(right)) => {
+            eval_float_infix_expression(operator, left, right as f64)
+        }
+        (Object::String(left), Object::String(right)) => {
+            eval_string_infix_expression(operator, left, right)
+        }
+        (Object::Boolean(left), Object::Boolean(right)) if *operator == Token::Equal => {
+            Object::Boolean(left == right)
+        }
+        (Object::Boolean(left), Object::Boolean(right)) if *operator == Token::NotEqual => {
+            Object::Boolean(left != right)
+        }
+        (left, right) if left.type_name() != right.type_name() => Object::error(
+            RuntimeErrorKind::TypeMismatch,
+            format!(
+                "type mismatch: {} {} {}",
+                left.type_name(),
+                operator,
+                right.type_name()
+            ),
+        ),
+        (left, right) => Object::error(
+            RuntimeErrorKind::UnknownOperator,
+            format!(
+                "unknown operator: {} {} {}",
+                left.type_name(),
+                operator,
+                right.type_name()
+            ),
+        ),
+    }
+}
+
+fn eval_integer_infix_expression(operator: &Token, left: i64, right: i64) -> Object {
+    let result = match operator {
+        Token::Plus => left.checked_add(right),
+        Token::Minus => left.checked_sub(right),
+        Token::Asterisk => left.checked_mul(right),
+        Token::Slash if right == 0 => {
+            return Object::error(RuntimeErrorKind::DivisionByZero, "division by zero")
+        }
+        Token::Slash => left.checked_div(right),
+        Token::Percent if right == 0 => {
+            return Object::error(RuntimeErrorKind::DivisionByZero, "division by zero")
+        }
+        Token::Percent => left.checked_rem(right),
+        Token::Lt => return Object::Boolean(left < right),
+        Token::Gt => return Object::Boolean(left > right),
+        Token::LtEq => return Object::Boolean(left <= right),
+        Token::GtEq => return Object::Boolean(left >= right),
+        Token::Equal => return Object::Boolean(left == right),
+        Token::NotEqual => return Object::Boolean(left != right),
+        operator => {
+            return Object::error(
+                RuntimeErrorKind::UnknownOperator,
+                format!("unknown operator: INTEGER {} INTEGER", operator),
+            )
+        }
+    };
+
+    match result {
+        Some(value) => Object::Integer(value),
+        None => Object::error(
+            RuntimeErrorKind::IntegerOverflow,
+            format!("integer overflow: {} {} {}", left, operator, right),
+        ),
+    }
+}
+
+/// Apply an infix operator to two floats. Integers mixed in with floats are converted to floats
+/// first.
+///
+/// * `operator` - The operator to apply
+/// * `left` - The left operand
+/// * `right` - The right operand
+fn eval_float_infix_expression(operator: &Token, left: f64, right: f64) -> Object {
+    match operator {
+        Token::Plus => Object::Float(left + right),
+        Token::Minus => Object::Float(left - right),
+        Token::Asterisk => Object::Float(left * right),
+        Token::Slash if right == 0.0 => {
+            Object::error(RuntimeErrorKind::DivisionByZero, "division by zero")
+        }
+        Token::Slash => Object::Float(left / right),
+        Token::Percent if right == 0.0 => {
+            Object::error(RuntimeErrorKind::DivisionByZero, "division by zero")
+        }
+        Token::Percent => Object::Float(left % right),
+        Token::Lt => Object::Boolean(left < right),
+        Token::Gt => Object::Boolean(left > right),
+        Token::LtEq => Object::Boolean(left <= right),
+        Token::GtEq => Object::Boolean(left >= right),
+        Token::Equal => Object::Boolean(left == right),
+        Token::NotEqual => Object::Boolean(left != right),
+        operator => Object::error(
+            RuntimeErrorKind::UnknownOperator,
+            format!("unknown operator: FLOAT {} FLOAT", operator),
+        ),
+    }
+}
+
+/// Index into a value. Indexing outside an array's bounds, or with a key missing from a hash,
+/// gives `null` rather than an error.
+///
+/// * `left` - The value being indexed
+/// * `index` - The index
+pub(crate) fn eval_index_expression(left: Object, index: Object) -> Object {
+    match (left, index) {
+        (Object::Array(elements), Object::Integer(i)) => usize::try_from(i)
+            .ok()
+            .and_then(|i| elements.get(i).cloned())
+            .unwrap_or(Object::Null),
+        // Strings are indexed by char, as `len` counts them
+        (Object::String(value), Object::Integer(i)) => usize::try_from(i)
+            .ok()
+            .and_then(|i| value.chars().nth(i))
+            .map_or(Object::Null, |c| Object::String(c.to_string())),
+        (Object::Hash(pairs), index) => match index.hash_key() {
+            Ok(key) => pairs.get(&key).cloned().unwrap_or(Object::Null),
+            Err(err) => err,
+        },
+        (left, _) => Object::error(
+            RuntimeErrorKind::UnsupportedIndex,
+            format!("index operator not supported: {}", left.type_name()),
+        ),
+    }
+}
+
+fn eval_string_infix_expression(operator: &Token, left: String, right: String) -> Object {
+    match operator {
+        Token::Plus => Object::String(left + &right),
+        Token::Equal => Object::Boolean(left == right),
+        Token::NotEqual => Object::Boolean(left != right),
+        operator => Object::error(
+            RuntimeErrorKind::UnknownOperator,
+            format!("unknown operator: STRING {} STRING", operator),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::object::HashKey;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert_eq!(parser.errors(), &[], "input: {}", input);
+
+        program
+    }
+
+    fn eval(input: &str) -> Object {
+        Evaluator::new().eval(&parse(input), &Environment::new())
+    }
+
+    #[test]
+    fn test_integer_expressions() {
+        let tests = [
+            ("5", 5),
+            ("10", 10),
+            ("-5", -5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("-50 + 100 + -50", 0),
+            ("5 * 2 + 10", 20),
+            ("5 + 2 * 10", 25),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("2 * (5 + 10)", 30),
+            ("3 * 3 * 3 + 10", 37),
+            ("3 * (3 * 3) + 10", 37),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_float_expressions() {
+        let tests = [
+            ("2.5", Object::Float(2.5)),
+            ("-0.5", Object::Float(-0.5)),
+            ("1.5 + 2.25", Object::Float(3.75)),
+            ("1 + 0.5", Object::Float(1.5)),
+            ("0.5 * 4", Object::Float(2.0)),
+            ("7 / 2.0", Object::Float(3.5)),
+            ("1.5 < 2", Object::Boolean(true)),
+            ("2 == 2.0", Object::Boolean(true)),
+            ("0.1 != 0.1", Object::Boolean(false)),
+            (
+                "1.0 / 0",
+                Object::error(RuntimeErrorKind::DivisionByZero, "division by zero"),
+            ),
+            (
+                "1.5 + true",
+                Object::error(
+                    RuntimeErrorKind::TypeMismatch,
+                    "type mismatch: FLOAT + BOOLEAN",
+                ),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_comparison_and_logical_operators() {
+        let tests = [
+            ("7 % 3", Object::Integer(1)),
+            ("-7 % 3", Object::Integer(-1)),
+            ("7.5 % 2", Object::Float(1.5)),
+            ("1 <= 1", Object::Boolean(true)),
+            ("2 <= 1", Object::Boolean(false)),
+            ("1 >= 1.5", Object::Boolean(false)),
+            ("true && 1", Object::Boolean(true)),
+            ("1 < 2 && 2 < 1", Object::Boolean(false)),
+            ("false || if (false) { 1 }", Object::Boolean(false)),
+            ("false || 0", Object::Boolean(true)),
+            // The right side would be an error if it were evaluated
+            ("false && missing", Object::Boolean(false)),
+            ("true || missing", Object::Boolean(true)),
+            ("false ?? missing", Object::Boolean(false)),
+            ("[][0] ?? {}[1] ?? 3", Object::Integer(3)),
+            ("if (false) { 1 } ?? 2", Object::Integer(2)),
+            (
+                "true && missing",
+                Object::error(
+                    RuntimeErrorKind::IdentifierNotFound,
+                    "identifier not found: missing",
+                ),
+            ),
+            (
+                "1 % 0",
+                Object::error(RuntimeErrorKind::DivisionByZero, "division by zero"),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_while_loops() {
+        let tests = [
+            (
+                "let i = 0; while (i < 5) { let i = i + 1; } i",
+                Object::Integer(5),
+            ),
+            ("while (false) { 1 }", Object::Null),
+            (
+                "let i = 0; let sum = 0;
+                while (true) {
+                    let i = i + 1;
+                    if (i > 10) { break; }
+                    if (i % 2 == 0) { continue; }
+                    let sum = sum + i;
+                }
+                sum",
+                Object::Integer(25),
+            ),
+            (
+                "let i = 0; let n = 0;
+                while (i < 3) {
+                    let i = i + 1;
+                    while (true) { let n = n + 1; break; }
+                }
+                n",
+                Object::Integer(3),
+            ),
+            ("fn() { while (true) { return 3; } }()", Object::Integer(3)),
+            (
+                "break;",
+                Object::error(RuntimeErrorKind::OutsideLoop, "break outside loop"),
+            ),
+            (
+                "while (true) { fn() { continue; }() }",
+                Object::error(RuntimeErrorKind::OutsideLoop, "continue outside loop"),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_assignment() {
+        let tests = [
+            ("let x = 1; x = x + 1; x", Object::Integer(2)),
+            (
+                "let x = 10; x -= 3; x *= 2; x /= 7; x += 5; x",
+                Object::Integer(7),
+            ),
+            ("let x = 1; x = 2", Object::Null),
+            (
+                "let i = 0; let sum = 0; while (i < 4) { i += 1; sum += i; } sum",
+                Object::Integer(10),
+            ),
+            // Assignment rebinds in the scope the name was bound in, not the current one
+            (
+                "let count = 0; let bump = fn() { count += 1; }; bump(); bump(); count",
+                Object::Integer(2),
+            ),
+            (
+                "let x = 1; let f = fn(x) { x = 5; x }; f(2) + x",
+                Object::Integer(6),
+            ),
+            (
+                "x = 1",
+                Object::error(
+                    RuntimeErrorKind::IdentifierNotFound,
+                    "identifier not found: x",
+                ),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        let tests = [
+            ("true", true),
+            ("false", false),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 < 1", false),
+            ("1 > 1", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("1 == 2", false),
+            ("1 != 2", true),
+            ("true == true", true),
+            ("false == false", true),
+            ("true == false", false),
+            ("true != false", true),
+            ("false != true", true),
+            ("(1 < 2) == true", true),
+            ("(1 < 2) == false", false),
+            ("(1 > 2) == true", false),
+            ("(1 > 2) == false", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Boolean(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_bang_operator() {
+        let tests = [
+            ("!true", false),
+            ("!false", true),
+            ("!5", false),
+            ("!!true", true),
+            ("!!false", false),
+            ("!!5", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Boolean(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        let tests = [
+            ("if (true) { 10 }", Object::Integer(10)),
+            ("if (false) { 10 }", Object::Null),
+            ("if (1) { 10 }", Object::Integer(10)),
+            ("if (1 < 2) { 10 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 }", Object::Null),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (1 < 2) { 10 } else { 20 }", Object::Integer(10)),
+            (
+                "if (false) { 10 } else if (true) { 20 } else { 30 }",
+                Object::Integer(20),
+            ),
+            (
+                "if (false) { 10 } else if (0 > 1) { 20 } else { 30 }",
+                Object::Integer(30),
+            ),
+            ("if (false) { 10 } else if (0 > 1) { 20 }", Object::Null),
+            ("1 < 2 ? 10 : 20", Object::Integer(10)),
+            ("false ? 10 : 0 ? 20 : 30", Object::Integer(20)),
+            // Only the branch picked is evaluated
+            ("true ? 1 : missing", Object::Integer(1)),
+            ("false ? missing : 2", Object::Integer(2)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let tests = [
+            ("return 10;", 10),
+            ("return 10; 9;", 10),
+            ("return 2 * 5; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+            (
+                "if (10 > 1) {
+  if (10 > 1) {
+    return 10;
+  }
+
+  return 1;
+}",
+                10,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+
+        assert_eq!(eval("fn() { return; }()"), Object::Null);
+        assert_eq!(eval("fn() { if (true) { return; } 1 }()"), Object::Null);
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let tests = [
+            ("let x: int = 5; x", "5"),
+            (
+                "let x: float = 1.5; let y: null = puts; y",
+                "ERROR: type annotation mismatch: expected null, got BUILTIN",
+            ),
+            ("let s: string = \"a\"; let f: fn = len; f(s)", "1"),
+            (
+                "let x: int = \"5\"; x",
+                "ERROR: type annotation mismatch: expected int, got STRING",
+            ),
+            ("fn(a: array, b) { len(a) + b }([1], 2)", "3"),
+            (
+                "fn(a: array, b) { len(a) + b }({}, 2)",
+                "ERROR: type annotation mismatch: expected array, got HASH",
+            ),
+            (
+                "let x: integer = 5;",
+                "ERROR: unknown type in annotation: integer",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let tests = [
+            (
+                "5 + true;",
+                RuntimeErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            (
+                "5 + true; 5;",
+                RuntimeErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            (
+                "-true",
+                RuntimeErrorKind::UnknownOperator,
+                "unknown operator: -BOOLEAN",
+            ),
+            (
+                "true + false;",
+                RuntimeErrorKind::UnknownOperator,
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            (
+                "5; true + false; 5",
+                RuntimeErrorKind::UnknownOperator,
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            (
+                "if (10 > 1) { true + false; }",
+                RuntimeErrorKind::UnknownOperator,
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            (
+                "if (10 > 1) {
+  if (10 > 1) {
+    return true + false;
+  }
+
+  return 1;
+}",
+                RuntimeErrorKind::UnknownOperator,
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            (
+                "foobar",
+                RuntimeErrorKind::IdentifierNotFound,
+                "identifier not found: foobar",
+            ),
+            (
+                "\"Hello\" - \"World\"",
+                RuntimeErrorKind::UnknownOperator,
+                "unknown operator: STRING - STRING",
+            ),
+            (
+                "\"Hello\" + 1",
+                RuntimeErrorKind::TypeMismatch,
+                "type mismatch: STRING + INTEGER",
+            ),
+            (
+                "{\"name\": \"Monkey\"}[fn(x) { x }];",
+                RuntimeErrorKind::UnusableHashKey,
+                "unusable as hash key: FUNCTION",
+            ),
+            (
+                "{[1]: 2}",
+                RuntimeErrorKind::UnusableHashKey,
+                "unusable as hash key: ARRAY",
+            ),
+            (
+                "5 / (2 - 2)",
+                RuntimeErrorKind::DivisionByZero,
+                "division by zero",
+            ),
+            (
+                "9223372036854775807 + 1",
+                RuntimeErrorKind::IntegerOverflow,
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+        ];
+
+        for (input, kind, message) in tests {
+            assert_eq!(
+                eval(input),
+                Object::error(kind, message),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let tests = [
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; a;", 25),
+            ("let a = 5; let b = a; b;", 5),
+            ("let a = 5; let b = a; let c = a + b + 5; c;", 15),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_collect() {
+        let collect = |input| {
+            Evaluator::new()
+                .eval_collect(&parse(input), &Environment::new())
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(collect("1 + 1; \"a\"; [true]"), vec!["2", "a", "[true]"]);
+        assert_eq!(
+            collect("let x = 2; // two\nx * 3; return x; 4"),
+            vec!["null", "6", "null"]
+        );
+        assert_eq!(
+            collect("1; missing; 2"),
+            vec!["1", "ERROR: identifier not found: missing"]
+        );
+        assert_eq!(collect(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_function_object() {
+        match eval("fn(x) { x + 2; };") {
+            Object::Function(function) => {
+                assert_eq!(function.parameters, vec!["x".to_string()]);
+                assert_eq!(function.body.to_string(), "(x + 2)");
+            }
+            object => panic!("expected a function, got {:?}", object),
+        }
+    }
+
+    #[test]
+    fn test_function_application() {
+        let tests = [
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2; }; double(5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5, 5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x; }(5)", 5),
+            ("let add(x, y) { x + y } add(2, 3);", 5),
+            (
+                "let factorial = fn(n) { if (n < 2) { 1 } else { n * factorial(n - 1) } };
+factorial(5);",
+                120,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Object::Integer(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let tests = [
+            (
+                "let count = fn(n) { if (n == 0) { return 0; } count(n - 1) }; count(1000000)",
+                Object::Integer(0),
+            ),
+            (
+                "let sum = fn(n, total) { if (n > 0) { return sum(n - 1, total + n); } total };
+sum(100000, 0)",
+                Object::Integer(5000050000),
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+[even(100000), odd(7)]",
+                Object::Array(vec![Object::Boolean(true), Object::Boolean(true)]),
+            ),
+            (
+                "let f = fn(n) { while (n > 0) { n -= 1; if (n == 10) { return f(0); } } n }; f(20)",
+                Object::Integer(0),
+            ),
+            ("let f = fn(n) { let x = g(n); x }; let g = fn(n) { n * 2 }; f(2)", Object::Integer(4)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+
+        // Tail calls don't nest, but other calls still do
+        let config = EvalConfig {
+            max_depth: Some(10),
+            ..EvalConfig::default()
+        };
+        let program =
+            parse("let f = fn(n) { if (n > 0) { f(n - 1) } else { len(\"done\") } }; f(100)");
+        assert_eq!(
+            Evaluator::new()
+                .with_config(config)
+                .eval(&program, &Environment::new()),
+            Object::Integer(4)
+        );
+        let program = parse("let f = fn(n) { if (n > 0) { 1 + f(n - 1) } else { 0 } }; f(100)");
+        assert!(Evaluator::new()
+            .with_config(config)
+            .eval(&program, &Environment::new())
+            .is_error());
+    }
+
+    #[test]
+    fn test_function_errors() {
+        let tests = [
+            (
+                "let f = fn(x) { x }; f(1, 2)",
+                RuntimeErrorKind::WrongArgumentCount,
+                "wrong number of arguments: want=1, got=2",
+            ),
+            (
+                "5(1)",
+                RuntimeErrorKind::NotAFunction,
+                "not a function: INTEGER",
+            ),
+            (
+                "let f = fn(x) { x }; f(y)",
+                RuntimeErrorKind::IdentifierNotFound,
+                "identifier not found: y",
+            ),
+        ];
+
+        for (input, kind, message) in tests {
+            assert_eq!(
+                eval(input),
+                Object::error(kind, message),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = "let newAdder = fn(x) {
+  fn(y) { x + y };
+};
+
+let addTwo = newAdder(2);
+addTwo(2);";
+
+        assert_eq!(eval(input), Object::Integer(4));
+    }
+
+    #[test]
+    fn test_strings() {
+        let tests = [
+            ("\"Hello World!\"", Object::String("Hello World!".into())),
+            (
+                "\"Hello\" + \" \" + \"World!\"",
+                Object::String("Hello World!".into()),
+            ),
+            ("\"a\\tb\" == \"a\\tb\"", Object::Boolean(true)),
+            ("\"a\" == \"b\"", Object::Boolean(false)),
+            ("\"a\" != \"b\"", Object::Boolean(true)),
+            ("len(\"naïve 🐒\")", Object::Integer(7)),
+            ("\"naïve 🐒\"[2]", Object::String("ï".into())),
+            ("\"naïve 🐒\"[6]", Object::String("🐒".into())),
+            ("\"naïve\"[5]", Object::Null),
+            ("\"naïve\"[-1]", Object::Null),
+            (
+                "let café = \"€\" + \"1\"; café",
+                Object::String("€1".into()),
+            ),
+            (
+                "let name = \"world\"; \"Hello, ${name}!\"",
+                Object::String("Hello, world!".into()),
+            ),
+            (
+                "\"${1 + 1} ${2.5} ${[true, \"a\"]} ${if (false) { 1 }}\"",
+                Object::String("2 2.5 [true, a] null".into()),
+            ),
+            (
+                "let f = fn(s) { s + \"!\" }; \"a ${f(\"b${1}\")} c\"",
+                Object::String("a b1! c".into()),
+            ),
+            ("\"\\${x}\"", Object::String("${x}".into())),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_literals() {
+        assert_eq!(
+            eval("[1, 2 * 2, 3 + 3]"),
+            Object::Array(vec![
+                Object::Integer(1),
+                Object::Integer(4),
+                Object::Integer(6)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_array_index_expressions() {
+        let tests = [
+            ("[1, 2, 3][0]", Object::Integer(1)),
+            ("[1, 2, 3][1]", Object::Integer(2)),
+            ("[1, 2, 3][2]", Object::Integer(3)),
+            ("let i = 0; [1][i];", Object::Integer(1)),
+            ("[1, 2, 3][1 + 1];", Object::Integer(3)),
+            ("let myArray = [1, 2, 3]; myArray[2];", Object::Integer(3)),
+            (
+                "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+                Object::Integer(6),
+            ),
+            (
+                "let myArray = [1, 2, 3]; let i = myArray[0]; myArray[i]",
+                Object::Integer(2),
+            ),
+            ("[1, 2, 3][3]", Object::Null),
+            ("[1, 2, 3][-1]", Object::Null),
+            ("let a = [1, 2 * 2, fn(x) { x }]; a[1]", Object::Integer(4)),
+            (
+                "1[0]",
+                Object::error(
+                    RuntimeErrorKind::UnsupportedIndex,
+                    "index operator not supported: INTEGER",
+                ),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let input = "let two = \"two\";
+{
+    \"one\": 10 - 9,
+    two: 1 + 1,
+    \"thr\" + \"ee\": 6 / 2,
+    4: 4,
+    true: 5,
+    false: 6
+}";
+
+        assert_eq!(
+            eval(input),
+            Object::Hash(HashMap::from([
+                (HashKey::String("one".into()), Object::Integer(1)),
+                (HashKey::String("two".into()), Object::Integer(2)),
+                (HashKey::String("three".into()), Object::Integer(3)),
+                (HashKey::Integer(4), Object::Integer(4)),
+                (HashKey::Boolean(true), Object::Integer(5)),
+                (HashKey::Boolean(false), Object::Integer(6)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_hash_index_expressions() {
+        let tests = [
+            ("{\"foo\": 5}[\"foo\"]", Object::Integer(5)),
+            ("{\"foo\": 5}[\"bar\"]", Object::Null),
+            ("let key = \"foo\"; {\"foo\": 5}[key]", Object::Integer(5)),
+            ("{}[\"foo\"]", Object::Null),
+            ("{5: 5}[5]", Object::Integer(5)),
+            ("{true: 5}[true]", Object::Integer(5)),
+            ("{false: 5}[false]", Object::Integer(5)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let tests = [
+            ("len(\"\")", Object::Integer(0)),
+            ("len(\"four\")", Object::Integer(4)),
+            ("len(\"hello world\")", Object::Integer(11)),
+            (
+                "len(1)",
+                Object::error(
+                    RuntimeErrorKind::InvalidArgument,
+                    "argument to `len` not supported, got INTEGER",
+                ),
+            ),
+            (
+                "len(\"one\", \"two\")",
+                Object::error(
+                    RuntimeErrorKind::WrongArgumentCount,
+                    "wrong number of arguments. got=2, want=1",
+                ),
+            ),
+            ("len([1, 2, 3])", Object::Integer(3)),
+            ("len([])", Object::Integer(0)),
+            ("first([1, 2, 3])", Object::Integer(1)),
+            ("first([])", Object::Null),
+            (
+                "first(1)",
+                Object::error(
+                    RuntimeErrorKind::InvalidArgument,
+                    "argument to `first` must be ARRAY, got INTEGER",
+                ),
+            ),
+            ("last([1, 2, 3])", Object::Integer(3)),
+            ("last([])", Object::Null),
+            (
+                "last(1)",
+                Object::error(
+                    RuntimeErrorKind::InvalidArgument,
+                    "argument to `last` must be ARRAY, got INTEGER",
+                ),
+            ),
+            (
+                "rest([1, 2, 3])",
+                Object::Array(vec![Object::Integer(2), Object::Integer(3)]),
+            ),
+            ("rest([])", Object::Null),
+            ("push([], 1)", Object::Array(vec![Object::Integer(1)])),
+            (
+                "let a = [1]; push(a, 2); a",
+                Object::Array(vec![Object::Integer(1)]),
+            ),
+            (
+                "push(1, 1)",
+                Object::error(
+                    RuntimeErrorKind::InvalidArgument,
+                    "argument to `push` must be ARRAY, got INTEGER",
+                ),
+            ),
+            ("puts()", Object::Null),
+            ("let len = fn(x) { 42 }; len(\"a\")", Object::Integer(42)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_library_builtins() {
+        let tests = [
+            ("split(\"a, b, c\", \", \")", "[a, b, c]"),
+            ("split(\"héllo\", \"\")", "[h, é, l, l, o]"),
+            ("words(\"  one two\t\tthree \n\")", "[one, two, three]"),
+            ("len(words(\"a  b   c\"))", "3"),
+            ("words(\"   \")", "[]"),
+            ("split_whitespace(\" a  b \")", "[a, b]"),
+            (
+                "words(1)",
+                "ERROR: argument to `words` must be STRING, got INTEGER",
+            ),
+            (
+                "let g = group_by([1, 2, 3, 4, 5], fn(x) { x % 2 == 0 }); [g[true], g[false]]",
+                "[[2, 4], [1, 3, 5]]",
+            ),
+            ("group_by([], fn(x) { x })", "{}"),
+            (
+                "group_by([1], fn(x) { [x] })",
+                "ERROR: unusable as hash key: ARRAY",
+            ),
+            (
+                "group_by([1, 0], fn(x) { 1 / x })",
+                "ERROR: division by zero",
+            ),
+            (
+                "group_by(1, fn(x) { x })",
+                "ERROR: argument to `group_by` must be ARRAY, got INTEGER",
+            ),
+            ("take([1, 2, 3], 2)", "[1, 2]"),
+            ("drop([1, 2, 3], 2)", "[3]"),
+            ("take([1, 2], 5)", "[1, 2]"),
+            ("drop([1, 2], 5)", "[]"),
+            ("let a = [1, 2]; take(a, 1); drop(a, 1); a", "[1, 2]"),
+            (
+                "take([1], -1)",
+                "ERROR: argument to `take` must not be negative, got -1",
+            ),
+            (
+                "drop([1], \"1\")",
+                "ERROR: argument to `drop` must be INTEGER, got STRING",
+            ),
+            ("chunk([1, 2, 3, 4], 2)", "[[1, 2], [3, 4]]"),
+            ("chunk([1, 2, 3, 4, 5], 2)", "[[1, 2], [3, 4], [5]]"),
+            ("chunk([1, 2], 5)", "[[1, 2]]"),
+            ("chunk([], 3)", "[]"),
+            (
+                "chunk([1, 2], 0)",
+                "ERROR: size passed to `chunk` must be positive, got 0",
+            ),
+            (
+                "chunk([1, 2], -2)",
+                "ERROR: size passed to `chunk` must be positive, got -2",
+            ),
+            ("flatten([[1, 2], [3], []])", "[1, 2, 3]"),
+            ("flatten([1, [2, [3, [4]]], \"a\"])", "[1, 2, [3, [4]], a]"),
+            ("flatten([1, [2, [3, [4]]]], 2)", "[1, 2, 3, [4]]"),
+            ("flatten([1, [2, [3, [4]]]], -1)", "[1, 2, 3, 4]"),
+            ("flatten([[1]], 0)", "[[1]]"),
+            (
+                "flatten(1)",
+                "ERROR: argument to `flatten` must be ARRAY, got INTEGER",
+            ),
+            (
+                "flatten([], 1, 2)",
+                "ERROR: wrong number of arguments. got=3, want=1 or 2",
+            ),
+            (
+                "deep_equal([1, {\"a\": [2, 3.0]}], [1.0, {\"a\": [2, 3]}])",
+                "true",
+            ),
+            ("deep_equal([1, [2, 3]], [1, [2, 4]])", "false"),
+            ("deep_equal({\"a\": 1}, {\"a\": 1, \"b\": 2})", "false"),
+            ("deep_equal([1, 2], [1, 2, 3])", "false"),
+            ("deep_equal(\"1\", 1)", "false"),
+            ("let nan = sqrt(-1.0); [nan == nan, deep_equal([nan], [nan])]", "[false, true]"),
+            ("float(\"2.5\") + float(\" -1e1 \")", "-7.5"),
+            ("float(3)", "3.0"),
+            ("float(1.5)", "1.5"),
+            (
+                "[float(\"inf\"), float(\"-INF\"), float(\"NaN\")]",
+                "[inf, -inf, NaN]",
+            ),
+            ("let nan = float(\"nan\"); nan == nan", "false"),
+            ("float(\"1.5x\")", "ERROR: could not parse \"1.5x\" as a float"),
+            (
+                "float(true)",
+                "ERROR: argument to `float` must be a number, got BOOLEAN",
+            ),
+            (
+                "let fib = memoize(fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }); fib(40)",
+                "102334155",
+            ),
+            ("memoize(len)(\"abc\")", "3"),
+            ("[size([1, 2, 3]), depth([1, 2, 3])]", "[3, 1]"),
+            (
+                "let h = {\"a\": [1, [2, 3]], \"b\": 4}; [size(h), depth(h)]",
+                "[4, 3]",
+            ),
+            ("[size(\"abc\"), depth(\"abc\")]", "[1, 0]"),
+            ("[size([[], {}]), depth([[], {}])]", "[0, 2]"),
+            (
+                "let h = dict([\"a\", 1, true], [1, [2], \"c\"]); [h[\"a\"], h[1], h[true]]",
+                "[1, [2], c]",
+            ),
+            ("dict([], [])", "{}"),
+            ("assert_eq([1, {\"a\": 2}], [1, {\"a\": 2}])", "null"),
+            ("assert_eq(1, 1.0, \"numbers\")", "null"),
+            (
+                "assert_eq(len(\"abc\"), 4)",
+                "ERROR: assertion failed: expected 4, got 3",
+            ),
+            (
+                "assert_eq([1, 2], [1, 3], \"second element\")",
+                "ERROR: assertion failed: second element: expected [1, 3], got [1, 2]",
+            ),
+            (
+                "assert_eq(1)",
+                "ERROR: wrong number of arguments. got=1, want=2 or 3",
+            ),
+            (
+                "dict([1, 2], [3])",
+                "ERROR: arrays passed to `dict` must be the same length, got 2 keys and 1 values",
+            ),
+            (
+                "dict([\"a\", \"b\", \"a\"], [1, 2, 3])",
+                "ERROR: duplicate key passed to `dict`: a",
+            ),
+            ("dict([[1]], [1])", "ERROR: unusable as hash key: ARRAY"),
+            (
+                "dict(1, [])",
+                "ERROR: argument to `dict` must be ARRAY, got INTEGER",
+            ),
+            (
+                "size()",
+                "ERROR: wrong number of arguments. got=0, want=1",
+            ),
+            (
+                "memoize(1)",
+                "ERROR: argument to `memoize` must be a function, got INTEGER",
+            ),
+            (
+                "deep_equal(1)",
+                "ERROR: wrong number of arguments. got=1, want=2",
+            ),
+            ("join([1, \"b\", true], \"-\")", "1-b-true"),
+            ("upper(\"abc\") + lower(\"DEF\")", "ABCdef"),
+            ("len(trim(\"  x \\n\"))", "1"),
+            ("sort([3, 1.5, -2, 2])", "[-2, 1.5, 2, 3]"),
+            ("sort([\"b\", \"c\", \"a\"])", "[a, b, c]"),
+            (
+                "sort([1, \"a\"])",
+                "ERROR: argument to `sort` must be ARRAY of all numbers or all strings",
+            ),
+            ("abs(-3) + abs(2)", "5"),
+            ("abs(-1.5)", "1.5"),
+            (
+                "abs(-9223372036854775807 - 1)",
+                "ERROR: integer overflow in `abs`",
+            ),
+            ("sqrt(16)", "4.0"),
+            ("pow(2, 10)", "1024"),
+            ("pow(2, -1)", "0.5"),
+            ("pow(2, 64)", "ERROR: integer overflow in `pow`"),
+            ("floor(2.7) + ceil(2.2) + floor(-1)", "4"),
+            ("min([3, 1.5, 2])", "1.5"),
+            ("max([3, 1.5, 2])", "3"),
+            ("max([])", "null"),
+            (
+                "max([1, \"a\"])",
+                "ERROR: argument to `max` must be a number, got STRING",
+            ),
+            (
+                "type(1) + type(\"\") + type(fn() {}) + type([])",
+                "INTEGERSTRINGFUNCTIONARRAY",
+            ),
+            (
+                "upper(1)",
+                "ERROR: argument to `upper` must be STRING, got INTEGER",
+            ),
+            ("format(\"{} + {} = {}\", 1, 2.5, [3])", "1 + 2.5 = [3]"),
+            ("format(\"{{{}}}, }}{{\", \"x\")", "{x}, }{"),
+            ("format(\"none\")", "none"),
+            (
+                "format(\"{} {}\", 1)",
+                "ERROR: wrong number of values for `format`. got=1, want=2",
+            ),
+            (
+                "format(\"{}\", 1, 2)",
+                "ERROR: wrong number of values for `format`. got=2, want=1",
+            ),
+            (
+                "format(\"a } b\")",
+                "ERROR: unmatched `}` in the template passed to `format`, expected `{}`, `{{` or `}}`",
+            ),
+            (
+                "format(\"{x}\")",
+                "ERROR: unmatched `{` in the template passed to `format`, expected `{}`, `{{` or `}}`",
+            ),
+            (
+                "format(1)",
+                "ERROR: argument to `format` must be STRING, got INTEGER",
+            ),
+            (
+                "format()",
+                "ERROR: wrong number of arguments. got=0, want=at least 1",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_builtins() {
+        let tests = [
+            (
+                "to_json({\"b\": [1, 2.5, if (false) { 1 }], \"a\": {\"ok\": true, \"name\": \"x\\ty\"}})",
+                r#"{"a":{"name":"x\ty","ok":true},"b":[1,2.5,null]}"#,
+            ),
+            ("to_json([])", "[]"),
+            (
+                "to_json({\"a\": {1: 2}})",
+                "ERROR: hash keys must be STRING to be written as JSON, got INTEGER",
+            ),
+            (
+                "to_json([fn(x) { x }])",
+                "ERROR: FUNCTION can't be written as JSON",
+            ),
+            ("to_json(sqrt(-1.0))", "ERROR: NaN can't be written as JSON"),
+            (
+                "let data = {\"a\": [1, -2.5, true, \"s\"], \"b\": {\"c\": if (false) { 1 }}};
+deep_equal(from_json(to_json(data)), data)",
+                "true",
+            ),
+            (
+                "from_json(\"[1, 1.0, 1e2, 18446744073709551615]\")",
+                "[1, 1.0, 100.0, 1.8446744073709552e19]",
+            ),
+            (
+                "from_json(\"{\\\"a\\\": [1,\")",
+                "ERROR: could not parse JSON: EOF while parsing a value at line 1 column 9",
+            ),
+            (
+                "from_json(1)",
+                "ERROR: argument to `from_json` must be STRING, got INTEGER",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_quote_unquote() {
+        let tests = [
+            ("quote(5)", "QUOTE(5)"),
+            ("quote(foobar + barfoo)", "QUOTE((foobar + barfoo))"),
+            ("quote(unquote(4 + 4))", "QUOTE(8)"),
+            ("quote(8 + unquote(4 + 4))", "QUOTE((8 + 8))"),
+            ("quote(unquote(\"a\" + \"b\"))", "QUOTE(\"ab\")"),
+            ("quote(unquote(true == false))", "QUOTE(false)"),
+            ("quote(unquote(quote(4 + 4)))", "QUOTE((4 + 4))"),
+            (
+                "let quoted = quote(4 + 4); quote(unquote(4 + 4) + unquote(quoted))",
+                "QUOTE((8 + (4 + 4)))",
+            ),
+            ("quote(f(unquote(1 + 1)))", "QUOTE(f(2))"),
+            ("quote(unquote([1]))", "ERROR: cannot unquote ARRAY"),
+            ("quote(unquote(x))", "ERROR: identifier not found: x"),
+            (
+                "quote(1, 2)",
+                "ERROR: wrong number of arguments. got=2, want=1",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        let program = parse(
+            "puts(\"a\", [1, 2]); print(\"b\", 1.5); print(); puts(); puts(format(\"{}!\", true))",
+        );
+        let mut output = vec![];
+        let result = Evaluator::new()
+            .with_output(&mut output)
+            .eval(&program, &Environment::new());
+
+        assert_eq!(result, Object::Null);
+        assert_eq!(String::from_utf8(output).unwrap(), "a\n[1, 2]\nb1.5true!\n");
+    }
+
+    #[test]
+    fn test_pure_mode() {
+        let config = EvalConfig {
+            pure: true,
+            ..EvalConfig::default()
+        };
+        let program = Parser::new(Lexer::new("puts(\"a\", 1); print([2]); 3")).parse_program();
+        let mut evaluator = Evaluator::new().with_config(config);
+
+        assert_eq!(
+            evaluator.eval(&program, &Environment::new()),
+            Object::Integer(3)
+        );
+        assert_eq!(evaluator.captured_output(), "a\n1\n[2]");
+        assert_eq!(evaluator.captured_output(), "a\n1\n[2]");
+        assert_eq!(evaluator.take_captured(), "a\n1\n[2]");
+        assert_eq!(evaluator.captured_output(), "");
+    }
+
+    #[test]
+    fn test_tracer() {
+        let input = "let x = 1;
+let f = fn(a) { let b = a + x; b };
+if (f(2) > x) { x } else { 0 };";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+
+        let mut visited = vec![];
+        let result = Evaluator::with_tracer(|statement| visited.push(statement.to_string()))
+            .eval(&program, &Environment::new());
+
+        assert_eq!(result, Object::Integer(1));
+        assert_eq!(
+            visited,
+            vec![
+                "let x = 1;",
+                "let f = fn(a) let b = (a + x);b;",
+                "if(f(2) > x) xelse 0",
+                "let b = (a + x);",
+                "b",
+                "x",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eval_hook() {
+        #[derive(Default)]
+        struct Tracer(Vec<String>);
+
+        impl EvalHook for Tracer {
+            fn before(&mut self, node: Node<'_>, env: &Env, depth: usize) {
+                let node = match node {
+                    Node::Statement(statement) => format!("statement {}", statement),
+                    Node::Expression(expression) => format!("expression {}", expression),
+                };
+                let locals = env.borrow().bindings().len();
+                self.0
+                    .push(format!("{} {} ({} locals)", depth, node, locals));
+            }
+        }
+
+        let mut parser = Parser::new(Lexer::new("let f = fn(x) { x }; f(1)"));
+        let program = parser.parse_program();
+        let mut tracer = Tracer::default();
+        let result = Evaluator::with_hook(&mut tracer).eval(&program, &Environment::new());
+
+        assert_eq!(result, Object::Integer(1));
+        assert_eq!(
+            tracer.0,
+            vec![
+                "0 statement let f = fn(x) x; (0 locals)",
+                "0 expression fn(x) x (0 locals)",
+                "0 statement f(1) (1 locals)",
+                "0 expression f(1) (1 locals)",
+                "0 expression f (1 locals)",
+                "0 expression 1 (1 locals)",
+                "1 statement x (1 locals)",
+                "1 expression x (1 locals)",
+            ]
+        );
+    }
+}
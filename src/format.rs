@@ -0,0 +1,626 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::error::MonkeyError;
+use crate::lexer::Lexer;
+use crate::parser::{precedence_of, Parser, Precedence};
+use crate::token::{escape, Token};
+
+const INDENT: &str = "    ";
+
+/// Parse some source, keeping its comments, and print it back in canonical form
+///
+/// * `source` - The source to format
+pub fn format_source(source: &str) -> Result<String, MonkeyError> {
+    let mut parser = Parser::new(Lexer::new(source).with_comments());
+    let program = parser.parse_program();
+
+    if !parser.errors().is_empty() {
+        return Err(MonkeyError::Parse(parser.errors().to_vec()));
+    }
+
+    Ok(format_program(&program))
+}
+
+/// Print a program in canonical form: one statement per line, each ending in a semicolon
+/// unless it ends in a block and the next statement can't be read as carrying it on, with
+/// blocks indented by four spaces, single spaces around operators and only the parentheses the
+/// grammar needs. String interpolations are printed back as written rather than as the
+/// concatenation they parse into. Top-level statements spanning several lines are set apart by
+/// blank lines. Comments are kept, but on lines of their own before the statement they were in
+/// or after.
+///
+/// * `program` - The program to print
+pub fn format_program(program: &Program) -> String {
+    // Each statement along with the comments leading up to it, and whether it spans lines
+    let mut units: Vec<(String, bool)> = vec![];
+    let mut comments = String::new();
+    // The unit ending in an `if` or `while` that the next statement could carry on
+    let mut open: Option<usize> = None;
+
+    for statement in &program.statements {
+        let mut printer = Printer::default();
+        printer.statement(statement);
+
+        if let Statement::Comment(_) = statement {
+            comments += &printer.out;
+            comments.push('\n');
+        } else {
+            if let Some(i) = open.filter(|_| continues(&printer.out)) {
+                units[i].0.push(';');
+            }
+            open = ends_in_block(statement).then_some(units.len());
+
+            let multi_line = printer.out.contains('\n');
+            units.push((std::mem::take(&mut comments) + &printer.out, multi_line));
+        }
+    }
+    if !comments.is_empty() {
+        units.push((comments.trim_end().to_string(), false));
+    }
+
+    let mut out = String::new();
+    for (i, (text, multi_line)) in units.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+            if *multi_line || units[i - 1].1 {
+                out.push('\n');
+            }
+        }
+        out += text;
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    out
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    depth: usize,
+}
+
+impl Printer {
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(name, Expression::Annotated { value, type_name }) => {
+                self.out += &format!("let {}: {} = ", name, type_name);
+                self.expression(value);
+                self.out.push(';');
+            }
+            Statement::Let(name, value) => {
+                self.out += &format!("let {} = ", name);
+                self.expression(value);
+                self.out.push(';');
+            }
+            Statement::Assign(name, value) => {
+                // Compound assignments are parsed into plain ones, so they're found again here
+                match value {
+                    Expression::Infix(left, operator, right)
+                        if matches!(
+                            operator,
+                            Token::Plus | Token::Minus | Token::Asterisk | Token::Slash
+                        ) && **left == Expression::Identifier(name.clone()) =>
+                    {
+                        self.out += &format!("{} {}= ", name, operator);
+                        self.expression(right);
+                    }
+                    value => {
+                        self.out += &format!("{} = ", name);
+                        self.expression(value);
+                    }
+                }
+                self.out.push(';');
+            }
+            Statement::Return(Some(value)) => {
+                self.out += "return ";
+                self.expression(value);
+                self.out.push(';');
+            }
+            Statement::Return(None) => self.out += "return;",
+            Statement::Expression(expression) => {
+                self.expression(expression);
+                if !matches!(expression, Expression::If { .. } | Expression::While { .. }) {
+                    self.out.push(';');
+                }
+            }
+            Statement::Break => self.out += "break;",
+            Statement::Continue => self.out += "continue;",
+            Statement::Comment(text) => self.out += text,
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        if block.statements.is_empty() {
+            self.out += "{}";
+            return;
+        }
+
+        self.out.push('{');
+        self.depth += 1;
+        // Where to put a semicolon if the next statement could carry on an `if` or `while`
+        let mut open = None;
+        for statement in &block.statements {
+            self.newline();
+            let start = self.out.len();
+            self.statement(statement);
+
+            if let Statement::Comment(_) = statement {
+                continue;
+            }
+            if let Some(end) = open.filter(|_| continues(&self.out[start..])) {
+                self.out.insert(end, ';');
+            }
+            open = ends_in_block(statement).then_some(self.out.len());
+        }
+        self.depth -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.depth {
+            self.out += INDENT;
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        if let Some(pieces) = interpolation(expression) {
+            self.out.push('"');
+            for piece in pieces {
+                match piece {
+                    Piece::Text(text) => self.out += &escape(text),
+                    Piece::Value(value) => {
+                        self.out += "${";
+                        self.expression(value);
+                        self.out.push('}');
+                    }
+                }
+            }
+            self.out.push('"');
+            return;
+        }
+
+        match expression {
+            Expression::Identifier(name) => self.out += name,
+            Expression::Integer(value) => self.out += &value.to_string(),
+            // `Debug` keeps the `.0` on whole numbers, so they read back as floats
+            Expression::Float(value) => self.out += &format!("{:?}", value),
+            Expression::Boolean(value) => self.out += &value.to_string(),
+            Expression::String(value) => self.out += &format!("\"{}\"", escape(value)),
+            Expression::Array(elements) => {
+                self.out.push('[');
+                self.list(elements);
+                self.out.push(']');
+            }
+            Expression::Index(left, index) => {
+                self.operand(left, Precedence::Call, false);
+                self.out.push('[');
+                self.expression(index);
+                self.out.push(']');
+            }
+            Expression::Hash(pairs) => {
+                self.out.push('{');
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        self.out += ", ";
+                    }
+                    self.expression(key);
+                    self.out += ": ";
+                    self.expression(value);
+                }
+                self.out.push('}');
+            }
+            Expression::Prefix(operator, right) => {
+                self.out += &operator.to_string();
+                self.operand(right, Precedence::Prefix, false);
+            }
+            Expression::Infix(left, operator, right) => {
+                let precedence = precedence_of(operator);
+                self.operand(left, precedence, false);
+                self.out += &format!(" {} ", operator);
+                self.operand(right, precedence, true);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.out += "if (";
+                self.expression(condition);
+                self.out += ") ";
+                self.block(consequence);
+                if let Some(alternative) = alternative {
+                    self.out += " else ";
+                    // An alternative holding just another `if` is how `else if` is parsed
+                    match alternative.statements.as_slice() {
+                        [Statement::Expression(elif @ Expression::If { .. })] => {
+                            self.expression(elif)
+                        }
+                        _ => self.block(alternative),
+                    }
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.operand(condition, Precedence::Conditional, true);
+                self.out += " ? ";
+                self.expression(consequence);
+                self.out += " : ";
+                self.expression(alternative);
+            }
+            Expression::While { condition, body } => {
+                self.out += "while (";
+                self.expression(condition);
+                self.out += ") ";
+                self.block(body);
+            }
+            Expression::Function { parameters, body } => {
+                self.out += &format!("fn({}) ", parameters.join(", "));
+                self.block(body);
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                self.operand(function, Precedence::Call, false);
+                self.out.push('(');
+                self.list(arguments);
+                self.out.push(')');
+            }
+            Expression::Macro { parameters, body } => {
+                self.out += &format!("macro({}) ", parameters.join(", "));
+                self.block(body);
+            }
+            // Annotations can only be written on bindings, which print their own
+            Expression::Annotated { value, .. } => self.expression(value),
+        }
+    }
+
+    /// Print an operand, in parentheses if it binds more loosely than its operator. Operators
+    /// associate to the left, so a right operand also needs them if it binds just as tightly.
+    ///
+    /// * `operand` - The operand to print
+    /// * `precedence` - The precedence of the operator it belongs to
+    /// * `right` - Whether it's the right operand of an infix operator
+    fn operand(&mut self, operand: &Expression, precedence: Precedence, right: bool) {
+        let binding = binding_of(operand);
+
+        if binding < precedence || (right && binding == precedence) {
+            self.out.push('(');
+            self.expression(operand);
+            self.out.push(')');
+        } else {
+            self.expression(operand);
+        }
+    }
+
+    fn list(&mut self, expressions: &[Expression]) {
+        for (i, expression) in expressions.iter().enumerate() {
+            if i > 0 {
+                self.out += ", ";
+            }
+            self.expression(expression);
+        }
+    }
+}
+
+/// Whether a statement is an `if` or `while`, which is printed without a semicolon
+fn ends_in_block(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Expression(Expression::If { .. } | Expression::While { .. })
+    )
+}
+
+/// Whether a printed statement would be read as carrying on an expression just before it,
+/// as a call, an index or a subtraction, if no semicolon came between them
+///
+/// * `text` - The printed statement
+fn continues(text: &str) -> bool {
+    text.starts_with(['(', '[', '-'])
+}
+
+/// A piece of an interpolated string
+enum Piece<'a> {
+    Text(&'a str),
+    Value(&'a Expression),
+}
+
+/// The pieces of the string literal an expression was parsed from, if it's an interpolation:
+/// a `$` prefix expression, or a chain of `+` joining them to text, with no empty text and no
+/// two texts in a row
+///
+/// * `expression` - The expression to check
+fn interpolation<'a>(expression: &'a Expression) -> Option<Vec<Piece<'a>>> {
+    let piece = |expression: &'a Expression| match expression {
+        Expression::String(text) if !text.is_empty() => Some(Piece::Text(text.as_str())),
+        Expression::Prefix(Token::Dollar, value) => Some(Piece::Value(value.as_ref())),
+        _ => None,
+    };
+
+    // `+` associates to the left, so the pieces are found from last to first
+    let mut pieces = vec![];
+    let mut rest = expression;
+    loop {
+        let (next, last) = match rest {
+            Expression::Infix(left, Token::Plus, right) => (Some(left.as_ref()), right.as_ref()),
+            expression => (None, expression),
+        };
+
+        let piece = piece(last)?;
+        if let (Piece::Text(_), Some(Piece::Text(_))) = (&piece, pieces.last()) {
+            return None;
+        }
+        pieces.push(piece);
+
+        match next {
+            Some(left) => rest = left,
+            None => break,
+        }
+    }
+
+    if !pieces.iter().any(|piece| matches!(piece, Piece::Value(_))) {
+        return None;
+    }
+    pieces.reverse();
+
+    Some(pieces)
+}
+
+/// How tightly an expression holds together when it's an operand. Expressions ending in a block
+/// bind loosest, so they're always parenthesized as operands.
+///
+/// * `expression` - The operand
+fn binding_of(expression: &Expression) -> Precedence {
+    if interpolation(expression).is_some() {
+        return Precedence::Index;
+    }
+
+    match expression {
+        Expression::Infix(_, operator, _) => precedence_of(operator),
+        Expression::Prefix(..) => Precedence::Prefix,
+        Expression::Call { .. } => Precedence::Call,
+        Expression::Conditional { .. } => Precedence::Conditional,
+        Expression::If { .. }
+        | Expression::While { .. }
+        | Expression::Function { .. }
+        | Expression::Macro { .. } => Precedence::Lowest,
+        _ => Precedence::Index,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::{nested_expression, NESTED_DEPTH};
+    use crate::token::lookup_ident;
+    use proptest::prelude::*;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        program
+    }
+
+    #[test]
+    fn test_format_source() {
+        let input = "// Adds things
+let add=fn(a,b){return a+b};let x=add(1,2)*(3-  4) ;
+x=x+1; x -= 1 ;
+if(x>1&&!false){ let y = -(x+1); /* inner */ y[0] } else {}
+while (true) { break }
+let h = {\"a\":[1,2.5],true:fn(){}}; // trailing
+(fn(x){x})(1)[0]; a - (b - c) - d;
+/* the end */";
+
+        assert_eq!(
+            format_source(input).unwrap(),
+            "// Adds things
+let add = fn(a, b) {
+    return a + b;
+};
+
+let x = add(1, 2) * (3 - 4);
+x += 1;
+x -= 1;
+
+if (x > 1 && !false) {
+    let y = -(x + 1);
+    /* inner */
+    y[0];
+} else {}
+
+while (true) {
+    break;
+}
+
+let h = {\"a\": [1, 2.5], true: fn() {}};
+
+// trailing
+(fn(x) {
+    x;
+})(1)[0];
+
+a - (b - c) - d;
+/* the end */
+"
+        );
+    }
+
+    #[test]
+    fn test_format_is_stable() {
+        let inputs = [
+            "let a = (1 + 2) * 3 / -4 % 5 <= 6 == !true || false && 7 >= 8;",
+            "f(g(1)[2])(3); -a[0]; (-a)[0]; (a + b)(c);",
+            "let m = macro(a, b) { quote(unquote(a) - unquote(b)) }; m(5, 2);",
+            "let s = \"say \\\"hi\\\"\\n\"; x = x * (y / 2); x = y + x;",
+            "(if (a) { 1 } else { 2 }) + 1; while (i < 3) { i += 1; continue; }",
+            "if (a) {}; [1]; while (b) { if (c) {} /* c */; -1 }",
+            "if (a) { 1 } else if (b) { 2 } else { if (c) { 3 } }; x ?? y ?? (z ?? 0);",
+            "let m = a ? b ? 1 : 2 : c ? 3 : (d ? 4 : 5) + 1; (a ? b : c) ? d : e ?? f;",
+            "let x: int = 5; let f: fn = fn(a: string, b) { let c: array = [a, b]; c };",
+            "\"a${b}c\" + \"${d}\" + e + \"f\" + \"${g}\"; -\"${x}${y}\"[0]; \"$\" + \"{\\${${ {1: 2} }}$\";",
+        ];
+
+        for input in inputs {
+            let formatted = format_source(input).unwrap();
+
+            assert_eq!(parse(&formatted), parse(input), "{}", formatted);
+            assert_eq!(format_source(&formatted).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert!(matches!(
+            format_source("let = 1;"),
+            Err(MonkeyError::Parse(_))
+        ));
+    }
+
+    fn arbitrary_name() -> impl Strategy<Value = String> + Clone {
+        // Names like `_5` lex as malformed numbers, so underscores are followed by a letter
+        "_?[a-z][a-z0-9_]{0,4}".prop_filter("keyword", |name| {
+            matches!(lookup_ident(name), Token::Ident(_))
+        })
+    }
+
+    fn arbitrary_block(expression: BoxedStrategy<Expression>) -> BoxedStrategy<BlockStatement> {
+        let statement = prop_oneof![
+            (arbitrary_name(), expression.clone()).prop_map(|(n, v)| Statement::Let(n, v)),
+            (arbitrary_name(), arbitrary_name(), expression.clone()).prop_map(|(n, t, v)| {
+                let value = Expression::Annotated {
+                    value: Box::new(v),
+                    type_name: t,
+                };
+                Statement::Let(n, value)
+            }),
+            (arbitrary_name(), expression.clone()).prop_map(|(n, v)| Statement::Assign(n, v)),
+            prop::option::of(expression.clone()).prop_map(Statement::Return),
+            expression.prop_map(Statement::Expression),
+            Just(Statement::Break),
+            Just(Statement::Continue),
+        ];
+
+        prop::collection::vec(statement, 0..3)
+            .prop_map(|statements| BlockStatement {
+                statements,
+                spans: vec![],
+            })
+            .boxed()
+    }
+
+    fn arbitrary_expression() -> BoxedStrategy<Expression> {
+        let leaf = prop_oneof![
+            arbitrary_name().prop_map(Expression::Identifier),
+            (0..=i64::MAX).prop_map(Expression::Integer),
+            // Eighths print exactly, and small ones without an exponent
+            (0..100_000u32).prop_map(|n| Expression::Float(n as f64 / 8.0)),
+            any::<bool>().prop_map(Expression::Boolean),
+            "[a-z \"\\\\\n\té${}]{0,6}".prop_map(Expression::String),
+        ];
+        let operator = prop::sample::select(vec![
+            Token::Plus,
+            Token::Minus,
+            Token::Asterisk,
+            Token::Slash,
+            Token::Percent,
+            Token::Lt,
+            Token::Gt,
+            Token::LtEq,
+            Token::GtEq,
+            Token::Equal,
+            Token::NotEqual,
+            Token::And,
+            Token::Or,
+            Token::Coalesce,
+        ]);
+
+        leaf.prop_recursive(4, 32, 3, move |inner| {
+            let list = prop::collection::vec(inner.clone(), 0..3);
+            let parameters = prop::collection::vec(arbitrary_name(), 0..3);
+
+            prop_oneof![
+                list.clone().prop_map(Expression::Array),
+                (inner.clone(), inner.clone())
+                    .prop_map(|(l, i)| Expression::Index(Box::new(l), Box::new(i))),
+                prop::collection::vec((inner.clone(), inner.clone()), 0..3)
+                    .prop_map(Expression::Hash),
+                (
+                    prop::sample::select(vec![Token::Minus, Token::Bang, Token::Dollar]),
+                    inner.clone()
+                )
+                    .prop_map(|(o, r)| Expression::Prefix(o, Box::new(r))),
+                (inner.clone(), operator.clone(), inner.clone())
+                    .prop_map(|(l, o, r)| Expression::Infix(Box::new(l), o, Box::new(r))),
+                (
+                    inner.clone(),
+                    arbitrary_block(inner.clone()),
+                    prop::option::of(arbitrary_block(inner.clone())),
+                )
+                    .prop_map(|(c, consequence, alternative)| Expression::If {
+                        condition: Box::new(c),
+                        consequence,
+                        alternative,
+                    }),
+                (inner.clone(), inner.clone(), inner.clone()).prop_map(|(c, a, b)| {
+                    Expression::Conditional {
+                        condition: Box::new(c),
+                        consequence: Box::new(a),
+                        alternative: Box::new(b),
+                    }
+                }),
+                (inner.clone(), arbitrary_block(inner.clone())).prop_map(|(c, body)| {
+                    Expression::While {
+                        condition: Box::new(c),
+                        body,
+                    }
+                }),
+                (parameters.clone(), arbitrary_block(inner.clone()))
+                    .prop_map(|(parameters, body)| Expression::Function { parameters, body }),
+                (parameters, arbitrary_block(inner.clone()))
+                    .prop_map(|(parameters, body)| Expression::Macro { parameters, body }),
+                (inner, list).prop_map(|(f, arguments)| Expression::Call {
+                    function: Box::new(f),
+                    arguments,
+                }),
+            ]
+        })
+        .boxed()
+    }
+
+    proptest! {
+        #[test]
+        fn test_format_roundtrip(block in arbitrary_block(arbitrary_expression())) {
+            let program = Program {
+                statements: block.statements,
+                spans: vec![],
+            };
+            let formatted = format_program(&program);
+
+            let mut parser = Parser::new(Lexer::new(&formatted));
+            prop_assert_eq!(parser.parse_program(), program, "{}", formatted);
+            prop_assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        }
+
+        #[test]
+        fn test_format_deep_nesting(depth in 0..=NESTED_DEPTH) {
+            let formatted = format_source(&nested_expression(depth)).unwrap();
+            prop_assert_eq!(format_source(&formatted).unwrap(), formatted);
+        }
+
+        #[test]
+        fn test_format_arbitrary_source(input in "\\PC{0,64}") {
+            // Whatever parses has to format into something that parses the same way
+            if let Ok(formatted) = format_source(&input) {
+                prop_assert_eq!(format_source(&formatted).unwrap(), formatted);
+            }
+        }
+    }
+}
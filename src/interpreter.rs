@@ -0,0 +1,434 @@
+use crate::ast::Program;
+use crate::environment::{Env, Environment};
+use crate::error::MonkeyError;
+use crate::evaluator::{EvalConfig, EvalHook, Evaluator};
+use crate::lexer::Lexer;
+use crate::macro_expansion::{define_macros, expand_macros};
+use crate::object::{NativeFunction, Object};
+use crate::optimize::{optimize, OptLevel};
+use crate::parser::Parser;
+#[cfg(feature = "serde")]
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::stdlib;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Runs Monkey source for a host application. Bindings and macros made by one call to
+/// `eval_str` are visible to the next, and the host can add its own values or read back what
+/// the scripts bound.
+#[derive(Debug)]
+pub struct Interpreter {
+    evaluator: Evaluator<'static>,
+    env: Env,
+    macro_env: Env,
+    opt_level: OptLevel,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Create an interpreter with the standard library loaded
+    pub fn new() -> Interpreter {
+        // Kept in a scope of its own, so `bindings` only lists what hosts and scripts bind
+        let stdlib = stdlib::env();
+
+        Interpreter {
+            evaluator: Evaluator::new().with_stdlib(stdlib.clone()),
+            env: Environment::new_enclosed(stdlib),
+            macro_env: Environment::new(),
+            opt_level: OptLevel::default(),
+        }
+    }
+
+    /// Create an interpreter without the standard library, leaving only the builtins
+    pub fn bare() -> Interpreter {
+        Interpreter {
+            evaluator: Evaluator::new(),
+            env: Environment::new(),
+            macro_env: Environment::new(),
+            opt_level: OptLevel::default(),
+        }
+    }
+
+    /// Limit the work each call to `eval_str` can take, e.g. for untrusted scripts
+    ///
+    /// * `config` - The limits to keep to
+    pub fn with_config(mut self, config: EvalConfig) -> Interpreter {
+        self.evaluator = self.evaluator.with_config(config);
+        self
+    }
+
+    /// Send what builtins like `puts` write somewhere other than standard output, e.g. a
+    /// `Capture` to read it back
+    ///
+    /// * `output` - Where to write
+    pub fn with_output<W: Write + 'static>(mut self, output: W) -> Interpreter {
+        self.evaluator = self.evaluator.with_output(output);
+        self
+    }
+
+    /// What builtins have written in pure mode so far, without taking it
+    pub fn captured_output(&self) -> String {
+        self.evaluator.captured_output()
+    }
+
+    /// Take what builtins have written in pure mode, leaving nothing to take next time
+    pub fn take_captured(&self) -> String {
+        self.evaluator.take_captured()
+    }
+
+    /// Optimize each program before running it
+    ///
+    /// * `opt_level` - How far to optimize
+    pub fn with_opt_level(mut self, opt_level: OptLevel) -> Interpreter {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Parse, expand and evaluate some source, returning the value it produced
+    ///
+    /// * `src` - The source to run
+    pub fn eval_str(&mut self, src: &str) -> Result<Object, MonkeyError> {
+        let program = self.prepare(src)?;
+
+        self.evaluator.try_eval(&program, &self.env)
+    }
+
+    /// Evaluate some source like `eval_str`, calling a hook before each statement and
+    /// expression is evaluated
+    ///
+    /// * `src` - The source to run
+    /// * `hook` - The hook to call
+    pub fn eval_str_with_hook(
+        &mut self,
+        src: &str,
+        hook: &mut dyn EvalHook,
+    ) -> Result<Object, MonkeyError> {
+        let program = self.prepare(src)?;
+
+        let captured = self.evaluator.captured();
+        let stdlib = self.evaluator.stdlib();
+        let mut evaluator = Evaluator::with_hook(hook)
+            .with_config(self.evaluator.config())
+            .with_output(self.evaluator.output())
+            .with_captured(captured);
+        if let Some(stdlib) = stdlib {
+            evaluator = evaluator.with_stdlib(stdlib);
+        }
+        evaluator.try_eval(&program, &self.env)
+    }
+
+    /// Parse some source, then expand and optimize it ready to evaluate
+    ///
+    /// * `src` - The source to prepare
+    fn prepare(&mut self, src: &str) -> Result<Program, MonkeyError> {
+        let mut parser = Parser::new(Lexer::new(src));
+        let mut program = parser.parse_program();
+
+        if !parser.errors().is_empty() {
+            return Err(MonkeyError::Parse(parser.errors().to_vec()));
+        }
+
+        define_macros(&mut program, &self.macro_env);
+        let program = expand_macros(program, &self.macro_env).map_err(MonkeyError::Macro)?;
+
+        Ok(optimize(program, self.opt_level))
+    }
+
+    /// Bind a value for scripts to use, replacing any existing binding of the name
+    ///
+    /// * `name` - The name to bind
+    /// * `value` - The value to bind it to
+    pub fn set<S: Into<String>>(&mut self, name: S, value: Object) {
+        self.env.borrow_mut().set(name.into(), value);
+    }
+
+    /// Register a Rust function for scripts to call under a name. Errors it returns become
+    /// runtime errors in the calling script.
+    ///
+    /// * `name` - The name to bind the function to
+    /// * `func` - The function, which is given the evaluated arguments
+    pub fn register<S, F>(&mut self, name: S, func: F)
+    where
+        S: Into<String>,
+        F: Fn(&[Object]) -> Result<Object, MonkeyError> + 'static,
+    {
+        let name = name.into();
+        let native = NativeFunction {
+            name: name.clone(),
+            func: Box::new(func),
+        };
+        self.set(name, Object::Native(Rc::new(native)));
+    }
+
+    /// Look up a binding, whether made by the host or a script
+    ///
+    /// * `name` - The name to look up
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.env.borrow().get(name)
+    }
+
+    /// Every binding made so far, sorted by name
+    pub fn bindings(&self) -> Vec<(String, Object)> {
+        self.env
+            .borrow()
+            .bindings()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    /// Save the bindings and macros made so far, including the standard library's, to restore
+    /// later
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&self) -> Result<Snapshot, SnapshotError> {
+        Snapshot::capture(&self.env, &self.macro_env)
+    }
+
+    /// Replace the bindings and macros with those saved in a snapshot. Functions the host
+    /// registered aren't saved, only their names, so they have to be registered again first.
+    ///
+    /// * `snapshot` - The snapshot to restore
+    #[cfg(feature = "serde")]
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let (env, macro_env) = snapshot.restore(&self.env)?;
+        self.env = env;
+        self.macro_env = macro_env;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::Capture;
+    use crate::error::{Limit, RuntimeError, RuntimeErrorKind};
+    use crate::evaluator::{Node, DEFAULT_MAX_DEPTH, STACK_SIZE};
+    use crate::script::{run_source, RunOptions};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_eval_str() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set("base", Object::Integer(10));
+
+        assert_eq!(
+            interpreter
+                .eval_str("let add = fn(x) { base + x };")
+                .unwrap(),
+            Object::Null
+        );
+        assert_eq!(
+            interpreter
+                .eval_str("let total = add(5); total * 2")
+                .unwrap(),
+            Object::Integer(30)
+        );
+        assert_eq!(interpreter.get("total"), Some(Object::Integer(15)));
+        assert_eq!(interpreter.get("missing"), None);
+        assert!(matches!(interpreter.get("map"), Some(Object::Function(_))));
+        assert_eq!(Interpreter::bare().get("map"), None);
+
+        let mut optimized = Interpreter::new().with_opt_level(OptLevel::Basic);
+        assert_eq!(
+            optimized
+                .eval_str("let y = 2 * 3; if (y > 5) { y } else { 0 }")
+                .unwrap(),
+            Object::Integer(6)
+        );
+
+        let names: Vec<_> = interpreter
+            .bindings()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["add", "base", "total"]);
+    }
+
+    struct Quiet;
+
+    impl EvalHook for Quiet {
+        fn before(&mut self, _node: Node<'_>, _env: &Env, _depth: usize) {}
+    }
+
+    #[test]
+    fn test_output() {
+        let capture = Capture::default();
+        let mut interpreter = Interpreter::new().with_output(capture.clone());
+
+        interpreter
+            .eval_str("let greet = fn(name) { puts(\"Hello, \" + name) }; greet(\"a\")")
+            .unwrap();
+        interpreter
+            .eval_str_with_hook("greet(\"b\")", &mut Quiet)
+            .unwrap();
+
+        assert_eq!(capture.take(), "Hello, a\nHello, b\n");
+    }
+
+    #[test]
+    fn test_eval_str_errors() {
+        let mut interpreter = Interpreter::new();
+
+        assert!(matches!(
+            interpreter.eval_str("let = 1;"),
+            Err(MonkeyError::Parse(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_str("missing"),
+            Err(MonkeyError::Runtime(err)) if err.kind == RuntimeErrorKind::IdentifierNotFound
+        ));
+    }
+
+    #[test]
+    fn test_limits() {
+        let looping = "let i = 0; while (true) { i += 1; }";
+        let recursing = "let f = fn(n) { 1 + f(n + 1) }; f(0)";
+        let tests = [
+            (
+                EvalConfig {
+                    max_steps: Some(1000),
+                    ..EvalConfig::default()
+                },
+                looping,
+                Limit::Steps(1000),
+            ),
+            (
+                EvalConfig {
+                    max_depth: Some(50),
+                    ..EvalConfig::default()
+                },
+                recursing,
+                Limit::Depth(50),
+            ),
+            (
+                EvalConfig {
+                    timeout: Some(Duration::from_millis(20)),
+                    ..EvalConfig::default()
+                },
+                looping,
+                Limit::Time(Duration::from_millis(20)),
+            ),
+        ];
+
+        for (config, input, limit) in tests {
+            let mut interpreter = Interpreter::new().with_config(config);
+
+            assert!(matches!(
+                interpreter.eval_str(input),
+                Err(MonkeyError::LimitExceeded(exceeded)) if exceeded == limit
+            ));
+            // Each program gets a fresh budget
+            assert_eq!(interpreter.eval_str("1 + 1").unwrap(), Object::Integer(2));
+        }
+
+        let err = Interpreter::new()
+            .with_config(EvalConfig {
+                max_depth: Some(3),
+                ..EvalConfig::default()
+            })
+            .eval_str(recursing)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "limit exceeded: calls nested more than 3 deep"
+        );
+    }
+
+    #[test]
+    fn test_default_depth() {
+        let recursing = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(N)";
+        let deep = recursing.replace('N', "5000");
+
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let mut interpreter = Interpreter::new();
+                assert_eq!(
+                    interpreter
+                        .eval_str(&recursing.replace('N', "900"))
+                        .unwrap(),
+                    Object::Integer(900)
+                );
+                assert!(matches!(
+                    interpreter.eval_str(&deep),
+                    Err(MonkeyError::LimitExceeded(Limit::Depth(DEFAULT_MAX_DEPTH)))
+                ));
+                assert!(matches!(
+                    run_source(&deep, &RunOptions::default()),
+                    Err(MonkeyError::LimitExceeded(Limit::Depth(DEFAULT_MAX_DEPTH)))
+                ));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pure_mode() {
+        let mut interpreter = Interpreter::new().with_config(EvalConfig {
+            pure: true,
+            ..EvalConfig::default()
+        });
+
+        assert_eq!(
+            interpreter
+                .eval_str("puts(\"a\", 1); print([2]); \"done\"")
+                .unwrap(),
+            Object::String("done".into())
+        );
+        interpreter
+            .eval_str_with_hook("print(3)", &mut Quiet)
+            .unwrap();
+        assert_eq!(interpreter.captured_output(), "a\n1\n[2]3");
+        assert_eq!(interpreter.take_captured(), "a\n1\n[2]3");
+        assert_eq!(interpreter.take_captured(), "");
+
+        let err = interpreter.eval_str("import(\"Cargo.toml\")").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERROR: could not import Cargo.toml: imports are disabled in pure mode"
+        );
+    }
+
+    #[test]
+    fn test_register() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("sum", |args| {
+            let mut total = 0;
+            for arg in args {
+                total += i64::try_from(arg.clone())?;
+            }
+            Ok(total.into())
+        });
+        interpreter.register("greet", |args| match args {
+            [name] => Ok(format!("hello, {}", String::try_from(name.clone())?).into()),
+            _ => Err(RuntimeError::new(RuntimeErrorKind::Native, "greet takes one name").into()),
+        });
+
+        assert_eq!(
+            interpreter.eval_str("sum(1, 2, 3) * 2").unwrap(),
+            Object::Integer(12)
+        );
+        assert_eq!(
+            interpreter.eval_str("greet(\"monkey\")").unwrap(),
+            Object::String("hello, monkey".into())
+        );
+
+        let err = interpreter.eval_str("sum(1, true)").unwrap_err();
+        assert_eq!(err.to_string(), "ERROR: expected INTEGER, got BOOLEAN");
+
+        let err = interpreter.eval_str("greet()").unwrap_err();
+        assert!(matches!(
+            &err,
+            MonkeyError::Runtime(err) if err.kind == RuntimeErrorKind::Native
+        ));
+        assert_eq!(err.to_string(), "ERROR: greet takes one name");
+    }
+}
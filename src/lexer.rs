@@ -1,86 +1,528 @@
-use crate::token::{lookup_ident, Token};
+use crate::token::{lookup_ident, Position, Span, Spanned, Token, TokenTag};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+/// Lexes the whole input in one go. The trailing `Token::Eof` is not included.
+///
+/// * `input` - The source to tokenize
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    Lexer::new(input)
+        .take_while(|token| *token != Token::Eof)
+        .collect()
+}
+
+/// Lexes the whole input into parallel arrays of token kinds, spans, and payloads, which are
+/// simpler to marshal across an FFI boundary than owned `Token`s. Tokens without a payload have
+/// an empty string in the payload array. The trailing `Token::Eof` is not included.
+///
+/// * `input` - The source to tokenize
+pub fn tokenize_with_positions(input: &str) -> (Vec<TokenTag>, Vec<Span>, Vec<String>) {
+    let mut lexer = Lexer::new(input);
+    let mut tags = Vec::new();
+    let mut spans = Vec::new();
+    let mut payloads = Vec::new();
+
+    loop {
+        let (token, span) = lexer.next_spanned();
+        let payload = match token {
+            Token::Eof => break,
+            Token::Illegal(ref s)
+            | Token::Ident(ref s)
+            | Token::Int(ref s)
+            | Token::Float(ref s)
+            | Token::String(ref s) => s.to_string(),
+            _ => String::new(),
+        };
+
+        tags.push(token.tag());
+        spans.push(span);
+        payloads.push(payload);
+    }
+
+    (tags, spans, payloads)
+}
+
+/// The ways a piece of input can fail to lex
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LexError {
+    /// A run of characters that can't start any token
+    UnknownChar(String, Span),
+    /// A number with misplaced `_` separators
+    MalformedNumber(String, Span),
+    /// An integer literal that doesn't fit in an `i64`
+    IntegerOverflow(String, Span),
+    /// A string literal missing its closing quote
+    UnterminatedString(Span),
+    /// A backslash in a string literal followed by a character with no escape meaning
+    InvalidEscape(char, Span),
+    /// A `/*` block comment missing its closing `*/`
+    UnterminatedComment(Span),
+}
+
+impl LexError {
+    /// The span of input that failed to lex
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnknownChar(_, span)
+            | LexError::MalformedNumber(_, span)
+            | LexError::IntegerOverflow(_, span)
+            | LexError::UnterminatedString(span)
+            | LexError::InvalidEscape(_, span)
+            | LexError::UnterminatedComment(span) => *span,
+        }
+    }
+
+    /// Convert to the `Token::Illegal` that `Lexer::next_token` reports. Unknown characters
+    /// keep their source text, while everything else carries the formatted error.
+    pub(crate) fn into_illegal(self) -> Token<'static> {
+        match self {
+            LexError::UnknownChar(text, _) => Token::Illegal(text.into()),
+            err => Token::Illegal(err.to_string().into()),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnknownChar(text, _) => write!(f, "unknown character {}", text),
+            LexError::MalformedNumber(literal, _) => {
+                write!(f, "malformed number literal {}", literal)
+            }
+            LexError::IntegerOverflow(literal, _) => write!(
+                f,
+                "integer literal {} is too large for a 64-bit integer",
+                literal
+            ),
+            LexError::UnterminatedString(_) => write!(f, "unterminated string literal"),
+            LexError::InvalidEscape(c, _) => write!(f, "invalid escape sequence \\{}", c),
+            LexError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+        }
+    }
+}
+
+impl Error for LexError {}
+
+/// How the lexer reads characters out of its input
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Scanner {
+    /// Walk the input string from the start on every read. Lexing with this is quadratic in the
+    /// length of the input, so it's only kept as a baseline for benchmarks.
+    Chars,
+    /// Collect the byte offset of every char in the input up front and index into that
+    CharVec,
+    /// Index ASCII input byte by byte, falling back to `CharVec` if any non-ASCII is present
+    #[default]
+    Bytes,
+}
 
 #[derive(Debug)]
-pub struct Lexer {
-    input: String,
+enum Source<'a> {
+    Str(&'a str),
+    CharVec(&'a str, Vec<(usize, char)>),
+    Ascii(&'a str),
+}
+
+impl<'a> Source<'a> {
+    /// Replace the input, reusing the existing buffer where the scanner allows it
+    ///
+    /// * `input` - The new source
+    /// * `scanner` - The strategy used to read characters from `input`
+    fn refill(&mut self, input: &'a str, scanner: Scanner) {
+        match self {
+            Source::CharVec(s, chars) if scanner == Scanner::CharVec => {
+                *s = input;
+                chars.clear();
+                chars.extend(input.char_indices());
+            }
+            _ => *self = Source::new(input, scanner),
+        }
+    }
+
+    fn new(input: &'a str, scanner: Scanner) -> Source<'a> {
+        match scanner {
+            Scanner::Chars => Source::Str(input),
+            Scanner::Bytes if input.is_ascii() => Source::Ascii(input),
+            Scanner::CharVec | Scanner::Bytes => {
+                Source::CharVec(input, input.char_indices().collect())
+            }
+        }
+    }
+
+    /// Get the character at a char index, if it is in bounds
+    ///
+    /// * `i` - The char index to read
+    fn char_at(&self, i: usize) -> Option<char> {
+        match self {
+            Source::Str(s) => s.chars().nth(i),
+            Source::CharVec(_, chars) => chars.get(i).map(|&(_, c)| c),
+            Source::Ascii(s) => s.as_bytes().get(i).map(|&b| b as char),
+        }
+    }
+
+    /// Get the byte offset of a char index. The index one past the last char maps to the
+    /// length of the input.
+    ///
+    /// * `i` - The char index to convert
+    fn byte_offset(&self, i: usize) -> usize {
+        match self {
+            Source::Str(s) => s.char_indices().nth(i).map_or(s.len(), |(b, _)| b),
+            Source::CharVec(s, chars) => chars.get(i).map_or(s.len(), |&(b, _)| b),
+            Source::Ascii(_) => i,
+        }
+    }
+
+    /// Borrow a range of characters from the input
+    ///
+    /// * `start` - The char index to start reading range from. This value is inclusive.
+    /// * `end` - The char index to stop reading range from. This value is exclusive.
+    fn slice(&self, start: usize, end: usize) -> &'a str {
+        let s = match *self {
+            Source::Str(s) | Source::CharVec(s, _) | Source::Ascii(s) => s,
+        };
+
+        &s[self.byte_offset(start)..self.byte_offset(end)]
+    }
+
+    /// Borrow the rest of the input from a char index
+    ///
+    /// * `start` - The char index to start from
+    fn rest(&self, start: usize) -> &'a str {
+        let s = match *self {
+            Source::Str(s) | Source::CharVec(s, _) | Source::Ascii(s) => s,
+        };
+
+        &s[self.byte_offset(start)..]
+    }
+}
+
+#[derive(Debug)]
+pub struct Lexer<'a> {
+    input: Source<'a>,
+    scanner: Scanner,
     position: usize,
     read_position: usize,
     ch: Option<char>,
+    line: usize,
+    column: usize,
+    aliases: HashMap<String, Token<'static>>,
+    int_check: bool,
+    /// Whether comments are lexed as tokens rather than skipped
+    comments: bool,
+    /// Whether curly double quotes delimit strings like `"`
+    smart_quotes: bool,
+    /// Whether iterating has already yielded `Token::Eof`
+    finished: bool,
+    /// How many braces are open in each `${...}` being lexed, innermost last
+    interpolations: Vec<usize>,
+    /// Whether the last string part stopped at a `${`, so the next `{` opens an interpolation
+    interpolating: bool,
+    /// Whether the last token closed an interpolation, so the string around it carries on
+    resuming: bool,
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Lexer {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Lexer<'a> {
+        Self::with_scanner(input, Scanner::default())
+    }
+
+    /// Create a lexer that reads its input with a particular scanning strategy. Every strategy
+    /// produces the same tokens; they only differ in performance.
+    ///
+    /// * `input` - The source to lex
+    /// * `scanner` - The strategy used to read characters from `input`
+    pub fn with_scanner(input: &'a str, scanner: Scanner) -> Lexer<'a> {
         let mut l = Lexer {
-            input,
+            input: Source::new(input, scanner),
+            scanner,
             position: 0,
             read_position: 0,
             ch: None,
+            line: 1,
+            column: 0,
+            aliases: HashMap::new(),
+            int_check: false,
+            comments: false,
+            smart_quotes: false,
+            finished: false,
+            interpolations: vec![],
+            interpolating: false,
+            resuming: false,
         };
 
         l.read_char();
         l
     }
 
-    pub fn next_token(&mut self) -> Token {
+    /// Lex words from an alias map as other tokens, e.g. `not` as `!`, for prototyping
+    /// dialects without touching the core tokens. Aliases take priority over keywords.
+    ///
+    /// * `aliases` - The words to alias, mapped to the token each should lex as
+    pub fn with_aliases(mut self, aliases: HashMap<String, Token<'static>>) -> Lexer<'a> {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Lex integer literals that don't fit in an `i64` as `Token::Illegal` rather than
+    /// `Token::Int`, so they're caught before anything tries to parse them
+    pub fn with_int_check(mut self) -> Lexer<'a> {
+        self.int_check = true;
+        self
+    }
+
+    /// Lex comments as `Token::Comment` rather than skipping them, for tools that need to keep
+    /// them, such as the formatter
+    pub fn with_comments(mut self) -> Lexer<'a> {
+        self.comments = true;
+        self
+    }
+
+    /// Lex curly double quotes (`“` and `”`) as string delimiters, so code pasted from a word
+    /// processor works. Otherwise they're illegal.
+    pub fn with_smart_quotes(mut self) -> Lexer<'a> {
+        self.smart_quotes = true;
+        self
+    }
+
+    /// Start lexing a new input, reusing this lexer's buffers. Afterwards the lexer behaves
+    /// exactly as a freshly constructed one would, keeping its scanner and aliases.
+    ///
+    /// * `input` - The new source to lex
+    pub fn reset(&mut self, input: &'a str) {
+        self.input.refill(input, self.scanner);
+        self.position = 0;
+        self.read_position = 0;
+        self.ch = None;
+        self.line = 1;
+        self.column = 0;
+        self.finished = false;
+        self.interpolations.clear();
+        self.interpolating = false;
+        self.resuming = false;
+
+        self.read_char();
+    }
+
+    pub fn next_token(&mut self) -> Token<'a> {
+        self.try_next_token()
+            .unwrap_or_else(|err| err.into_illegal())
+    }
+
+    /// Reads the next token, reporting anything that can't be lexed as a `LexError` rather than
+    /// a `Token::Illegal`
+    pub fn try_next_token(&mut self) -> Result<Token<'a>, LexError> {
         self.skip_whitespace();
 
+        let start = self.position;
+        if self.resuming {
+            self.resuming = false;
+            return self.read_string(start).map(Token::String);
+        }
+
         let token = match self.ch {
-            None => Token::Eof,
+            None => return Ok(Token::Eof),
             Some(c) => match c {
-                '=' => match self.peek_char() {
-                    Some('=') => {
-                        self.read_char();
-                        Token::Equal
-                    }
-                    _ => Token::Assign,
-                },
+                '=' => self.read_paired('=', Token::Equal, Token::Assign),
                 ';' => Token::Semicolon,
+                ':' => Token::Colon,
                 '(' => Token::Lparen,
                 ')' => Token::Rparen,
-                '{' => Token::Lbrace,
-                '}' => Token::Rbrace,
+                '{' => {
+                    if self.interpolating {
+                        self.interpolating = false;
+                        self.interpolations.push(0);
+                    } else if let Some(open) = self.interpolations.last_mut() {
+                        *open += 1;
+                    }
+                    Token::Lbrace
+                }
+                '}' => {
+                    match self.interpolations.last_mut() {
+                        Some(0) => {
+                            self.interpolations.pop();
+                            self.resuming = true;
+                        }
+                        Some(open) => *open -= 1,
+                        None => {}
+                    }
+                    Token::Rbrace
+                }
+                '[' => Token::Lbracket,
+                ']' => Token::Rbracket,
                 ',' => Token::Comma,
-                '+' => Token::Plus,
-                '-' => Token::Minus,
-                '!' => match self.peek_char() {
-                    Some('=') => {
+                '+' => self.read_paired('=', Token::PlusAssign, Token::Plus),
+                '-' => self.read_paired('=', Token::MinusAssign, Token::Minus),
+                '!' => self.read_paired('=', Token::NotEqual, Token::Bang),
+                '*' => self.read_paired('=', Token::AsteriskAssign, Token::Asterisk),
+                '/' if self.comments && self.peek_char() == Some('/') => {
+                    while self.ch.is_some_and(|c| c != '\n') {
+                        self.read_char();
+                    }
+                    return Ok(Token::Comment(self.read_range(start, self.position).into()));
+                }
+                '/' if self.comments
+                    && self.peek_char() == Some('*')
+                    && self.skip_block_comment() =>
+                {
+                    return Ok(Token::Comment(self.read_range(start, self.position).into()));
+                }
+                // Any block comment that gets here never closes
+                '/' if self.peek_char() == Some('*') => {
+                    while self.ch.is_some() {
                         self.read_char();
-                        Token::NotEqual
                     }
-                    _ => Token::Bang,
-                },
-                '*' => Token::Asterisk,
-                '/' => Token::Slash,
-                '<' => Token::Lt,
-                '>' => Token::Gt,
+                    return Err(LexError::UnterminatedComment(self.span_from(start)));
+                }
+                '/' => self.read_paired('=', Token::SlashAssign, Token::Slash),
+                '%' => Token::Percent,
+                '<' => self.read_paired('=', Token::LtEq, Token::Lt),
+                '>' => self.read_paired('=', Token::GtEq, Token::Gt),
+                '&' if self.peek_char() == Some('&') => {
+                    self.read_char();
+                    Token::And
+                }
+                '|' if self.peek_char() == Some('|') => {
+                    self.read_char();
+                    Token::Or
+                }
+                '?' => self.read_paired('?', Token::Coalesce, Token::Question),
+                '$' => Token::Dollar,
+                '"' => {
+                    self.read_char();
+                    return self.read_string(start).map(Token::String);
+                }
+                '“' | '”' if self.smart_quotes => {
+                    self.read_char();
+                    return self.read_string(start).map(Token::String);
+                }
                 _ => {
-                    if Self::is_letter(c) {
+                    if Self::is_ident_start(c) {
                         let literal = self.read_identifier();
-                        return lookup_ident(&literal);
+                        // Digit separators can't lead a number, so `_1_000` isn't a name
+                        if literal
+                            .trim_start_matches('_')
+                            .starts_with(|c: char| c.is_ascii_digit())
+                        {
+                            let literal = literal.to_string();
+                            return Err(LexError::MalformedNumber(literal, self.span_from(start)));
+                        }
+                        return Ok(match self.aliases.get(literal) {
+                            Some(token) => token.clone(),
+                            None => lookup_ident(literal),
+                        });
                     } else if c.is_ascii_digit() {
-                        return Token::Int(self.read_number());
+                        let literal = self.read_number();
+                        let Some(literal) = Self::strip_separators(literal) else {
+                            let literal = literal.to_string();
+                            return Err(LexError::MalformedNumber(literal, self.span_from(start)));
+                        };
+                        if literal.contains('.') {
+                            return Ok(Token::Float(literal));
+                        }
+                        if self.int_check && literal.parse::<i64>().is_err() {
+                            let literal = literal.into_owned();
+                            return Err(LexError::IntegerOverflow(literal, self.span_from(start)));
+                        }
+                        return Ok(Token::Int(literal));
                     } else {
-                        return Token::Illegal(c.to_string());
+                        let literal = self.read_illegal().to_string();
+                        return Err(LexError::UnknownChar(literal, self.span_from(start)));
                     }
                 }
             },
         };
 
         self.read_char();
-        token
+        Ok(token)
+    }
+
+    /// Read a two-char token if the next char completes it, or a one-char token otherwise
+    ///
+    /// * `next` - The char that completes the two-char token
+    /// * `paired` - The token to read if `next` follows
+    /// * `single` - The token to read otherwise
+    fn read_paired(&mut self, next: char, paired: Token<'a>, single: Token<'a>) -> Token<'a> {
+        if self.peek_char() == Some(next) {
+            self.read_char();
+            paired
+        } else {
+            single
+        }
+    }
+
+    /// The span from a starting position up to the current one
+    ///
+    /// * `start` - The position the span starts at
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.position,
+        }
+    }
+
+    /// Reads the next token along with the span of input it was read from, so callers can
+    /// slice the exact lexeme out of the source. `Token::Eof` has a zero-width span at the end
+    /// of the input.
+    pub fn next_spanned(&mut self) -> (Token<'a>, Span) {
+        let token = self.next_positioned();
+        (token.node, token.span)
+    }
+
+    /// Reads the next token along with its span and the line and column it starts at
+    pub fn next_positioned(&mut self) -> Spanned<Token<'a>> {
+        let token = self.try_next_positioned();
+
+        Spanned {
+            node: token.node.unwrap_or_else(|err| err.into_illegal()),
+            span: token.span,
+            position: token.position,
+        }
+    }
+
+    /// Reads the next token along with its span and position, reporting anything that can't be
+    /// lexed as a `LexError`
+    pub fn try_next_positioned(&mut self) -> Spanned<Result<Token<'a>, LexError>> {
+        self.skip_whitespace();
+
+        let start = self.position;
+        let position = Position {
+            line: self.line,
+            column: self.column,
+        };
+        let node = self.try_next_token();
+
+        Spanned {
+            node,
+            span: self.span_from(start),
+            position,
+        }
     }
 
     fn read_char(&mut self) {
+        if self.ch == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
         self.ch = self.peek_char();
 
         self.position = self.read_position;
         self.read_position += 1;
     }
 
-    fn read_identifier(&mut self) -> String {
+    /// Read an identifier, which is a char that can start one followed by any that can continue
+    /// one
+    fn read_identifier(&mut self) -> &'a str {
         let pos = self.position;
+
+        self.read_char();
         while let Some(c) = self.ch {
-            if Self::is_letter(c) {
+            if Self::is_ident_continue(c) {
                 self.read_char();
             } else {
                 break;
@@ -90,65 +532,246 @@ impl Lexer {
         self.read_range(pos, self.position)
     }
 
-    /// Read a range of characters from a string
+    /// Borrow a range of characters from the input
     ///
     /// * `start` - The index to start reading range from. This value is inclusive.
     /// * `end` - The index to stop reading range from. This value is exclusive.
-    fn read_range(&mut self, start: usize, end: usize) -> String {
-        self.input
-            .chars()
-            .skip(start)
-            .take(end - start)
-            .collect::<String>()
+    fn read_range(&self, start: usize, end: usize) -> &'a str {
+        self.input.slice(start, end)
     }
 
-    fn read_number(&mut self) -> String {
+    /// Read a number, including any `_` digit separators and a fractional part. A `.` is only
+    /// part of the number if a digit follows it.
+    fn read_number(&mut self) -> &'a str {
         let pos = self.position;
 
+        self.read_digits();
+        if self.ch == Some('.') && self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+            self.read_char();
+            self.read_digits();
+        }
+
+        self.read_range(pos, self.position)
+    }
+
+    /// Read a run of digits and `_` digit separators
+    fn read_digits(&mut self) {
         while let Some(c) = self.ch {
-            if c.is_ascii_digit() {
+            if c.is_ascii_digit() || c == '_' {
                 self.read_char();
             } else {
                 break;
             }
         }
+    }
+
+    /// Read a string literal, or the part of one up to an interpolation or after it, resolving
+    /// escape sequences. The value borrows from the input unless it has escapes to resolve.
+    /// Afterwards the lexer is past the closing quote, or on the `$` of an interpolation, which
+    /// the following tokens lex the code in.
+    ///
+    /// * `start` - The position of the opening quote, or the `}` the part follows
+    fn read_string(&mut self, start: usize) -> Result<Cow<'a, str>, LexError> {
+        let content = self.position;
+        let mut escaped: Option<String> = None;
+        let mut invalid_escape = None;
+
+        loop {
+            match self.ch {
+                None => return Err(LexError::UnterminatedString(self.span_from(start))),
+                Some('"') => break,
+                Some('“' | '”') if self.smart_quotes => break,
+                Some('$') if self.input.char_at(self.read_position) == Some('{') => {
+                    self.interpolating = true;
+                    break;
+                }
+                Some('\\') => {
+                    let value = escaped
+                        .get_or_insert_with(|| self.read_range(content, self.position).into());
+                    self.read_char();
+
+                    match self.ch {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                        Some(c) => {
+                            let span = Span {
+                                start: self.position - 1,
+                                end: self.read_position,
+                            };
+                            invalid_escape.get_or_insert(LexError::InvalidEscape(c, span));
+                        }
+                        None => return Err(LexError::UnterminatedString(self.span_from(start))),
+                    }
+                }
+                Some(c) => {
+                    if let Some(value) = &mut escaped {
+                        value.push(c);
+                    }
+                }
+            }
+
+            self.read_char();
+        }
+
+        let value = match escaped {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(self.read_range(content, self.position)),
+        };
+        if !self.interpolating {
+            self.read_char();
+        }
+
+        match invalid_escape {
+            Some(err) => Err(err),
+            None => Ok(value),
+        }
+    }
+
+    /// Remove the `_` separators from a number, provided each one sits between two digits. The
+    /// number is only copied if it has separators to remove.
+    ///
+    /// * `literal` - The number as written in the source
+    fn strip_separators(literal: &str) -> Option<Cow<'_, str>> {
+        if literal.ends_with('_') || literal.contains("__") || literal.contains("_.") {
+            None
+        } else if literal.contains('_') {
+            Some(Cow::Owned(literal.replace('_', "")))
+        } else {
+            Some(Cow::Borrowed(literal))
+        }
+    }
+
+    /// Read a run of characters that can't start any valid token
+    fn read_illegal(&mut self) -> &'a str {
+        let pos = self.position;
+
+        // The first char can't start a token here, even if it can elsewhere, e.g. a lone `&`
+        self.read_char();
+        while let Some(c) = self.ch {
+            if Self::starts_token(c) {
+                break;
+            } else {
+                self.read_char();
+            }
+        }
 
         self.read_range(pos, self.position)
     }
 
+    /// Skip whitespace along with `//` and `/* */` comments. An unterminated block comment is
+    /// left in place so it can be reported as an error.
     fn skip_whitespace(&mut self) {
+        // Whitespace after an interpolation is part of the string
+        if self.resuming {
+            return;
+        }
+
         while let Some(c) = self.ch {
             if c.is_whitespace() {
                 self.read_char();
+            } else if self.comments && c == '/' && matches!(self.peek_char(), Some('/' | '*')) {
+                break;
+            } else if c == '/' && self.peek_char() == Some('/') {
+                while self.ch.is_some_and(|c| c != '\n') {
+                    self.read_char();
+                }
+            } else if c == '/' && self.peek_char() == Some('*') {
+                if !self.skip_block_comment() {
+                    break;
+                }
             } else {
                 break;
             }
         }
     }
 
-    fn peek_char(&mut self) -> Option<char> {
-        if self.read_position >= self.input.chars().count() {
-            None
-        } else {
-            self.input.chars().nth(self.read_position)
+    /// Skip a block comment starting at the current `/*`, returning whether it was closed. If
+    /// it wasn't, the lexer is left where it was, on the `/`. The closing `*/` is found with a
+    /// string search rather than a char at a time, as license headers can be long.
+    fn skip_block_comment(&mut self) -> bool {
+        let start = self.position + 2;
+        let rest = self.input.rest(start);
+        let Some(end) = rest.find("*/") else {
+            return false;
+        };
+
+        // Move onto the `*` as reading each char would have, then past the `*/`
+        let skipped = &rest[..end];
+        let skipped_chars = skipped.chars().count();
+        self.read_char();
+        self.read_char();
+        match skipped.rfind('\n') {
+            Some(last) => {
+                self.line += skipped.bytes().filter(|&b| b == b'\n').count();
+                self.column = skipped[last + 1..].chars().count() + 1;
+            }
+            None => self.column += skipped_chars,
         }
+        self.position = start + skipped_chars;
+        self.read_position = self.position + 1;
+        self.ch = self.input.char_at(self.position);
+
+        self.read_char();
+        self.read_char();
+        true
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.input.char_at(self.read_position)
     }
 
-    /// Checks whether a character is in Monkey's permitted alphabet
+    /// Checks whether a character can start an identifier, following Unicode's UAX #31: anything
+    /// with the `XID_Start` property, which excludes digits, or `_`
     ///
     /// * `ch` - The character to check
-    fn is_letter(ch: char) -> bool {
-        ch.is_ascii_alphabetic() || ch == '_'
+    fn is_ident_start(ch: char) -> bool {
+        is_xid_start(ch) || ch == '_'
+    }
+
+    /// Checks whether a character can continue an identifier, which includes digits and
+    /// combining marks
+    ///
+    /// * `ch` - The character to check
+    fn is_ident_continue(ch: char) -> bool {
+        is_xid_continue(ch)
+    }
+
+    /// Checks whether a character begins a valid token, or is whitespace between tokens. This
+    /// must be kept in sync with the operators and delimiters matched in `next_token`.
+    ///
+    /// * `ch` - The character to check
+    fn starts_token(ch: char) -> bool {
+        "=;:(){}[],+-!*/%<>&|?$\"".contains(ch)
+            || ch.is_whitespace()
+            || ch.is_ascii_digit()
+            || Self::is_ident_start(ch)
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    /// Yields each token in turn, ending with a single `Token::Eof`
+    fn next(&mut self) -> Option<Token<'a>> {
+        if self.finished {
+            return None;
+        }
+
+        let token = self.next_token();
+        self.finished = token == Token::Eof;
+        Some(token)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_next_token() {
-        let input = "let five = 5;
+        let input = r#"let five = 5;
 let ten = 10;
 
 let add = fn(x, y) {
@@ -156,7 +779,7 @@ let add = fn(x, y) {
 };
 
 let result = add(five, ten);
-!-/*5;
+!-/ *5;
 5 < 10 > 5;
 
 if (5 < 10) {
@@ -167,9 +790,15 @@ if (5 < 10) {
 
 10 == 10;
 10 != 9;
-";
+$x;
+"foobar"
+"foo bar"
+"tab\tquote\"slash\\"
+[1, 2];
+{"foo": "bar"}
+"#;
 
-        let mut l = Lexer::new(input.to_string());
+        let mut l = Lexer::new(input);
 
         assert_eq!(l.next_token(), Token::Let);
         assert_eq!(l.next_token(), Token::Ident("five".into()));
@@ -244,6 +873,737 @@ if (5 < 10) {
         assert_eq!(l.next_token(), Token::NotEqual);
         assert_eq!(l.next_token(), Token::Int("9".into()));
         assert_eq!(l.next_token(), Token::Semicolon);
+        assert_eq!(l.next_token(), Token::Dollar);
+        assert_eq!(l.next_token(), Token::Ident("x".into()));
+        assert_eq!(l.next_token(), Token::Semicolon);
+        assert_eq!(l.next_token(), Token::String("foobar".into()));
+        assert_eq!(l.next_token(), Token::String("foo bar".into()));
+        assert_eq!(l.next_token(), Token::String("tab\tquote\"slash\\".into()));
+        assert_eq!(l.next_token(), Token::Lbracket);
+        assert_eq!(l.next_token(), Token::Int("1".into()));
+        assert_eq!(l.next_token(), Token::Comma);
+        assert_eq!(l.next_token(), Token::Int("2".into()));
+        assert_eq!(l.next_token(), Token::Rbracket);
+        assert_eq!(l.next_token(), Token::Semicolon);
+        assert_eq!(l.next_token(), Token::Lbrace);
+        assert_eq!(l.next_token(), Token::String("foo".into()));
+        assert_eq!(l.next_token(), Token::Colon);
+        assert_eq!(l.next_token(), Token::String("bar".into()));
+        assert_eq!(l.next_token(), Token::Rbrace);
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let mut l = Lexer::new("let café = 5;");
+
+        assert_eq!(l.next_token(), Token::Let);
+        assert_eq!(l.next_token(), Token::Ident("café".into()));
+        assert_eq!(l.next_token(), Token::Assign);
+        assert_eq!(l.next_token(), Token::Int("5".into()));
+        assert_eq!(l.next_token(), Token::Semicolon);
+        assert_eq!(l.next_token(), Token::Eof);
+
+        let mut l = Lexer::new("λ(αβ)");
+
+        assert_eq!(l.next_token(), Token::Ident("λ".into()));
+        assert_eq!(l.next_token(), Token::Lparen);
+        assert_eq!(l.next_token(), Token::Ident("αβ".into()));
+        assert_eq!(l.next_token(), Token::Rparen);
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_xid_identifiers() {
+        // "é" written as "e" and a combining acute accent, which can continue a name
+        let mut l = Lexer::new("x1 cafe\u{301} _9 a·b ℘ 🐒");
+
+        assert_eq!(l.next_token(), Token::Ident("x1".into()));
+        assert_eq!(l.next_token(), Token::Ident("cafe\u{301}".into()));
+        assert_eq!(
+            l.next_token(),
+            Token::Illegal("malformed number literal _9".into())
+        );
+        assert_eq!(l.next_token(), Token::Ident("a·b".into()));
+        assert_eq!(l.next_token(), Token::Ident("℘".into()));
+        assert_eq!(l.next_token(), Token::Illegal("🐒".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_multibyte_strings() {
+        let mut l = Lexer::new("\"héllo\" \"€\\n🐒\" \"日本\\q\" x");
+
+        assert_eq!(
+            l.next_spanned(),
+            (Token::String("héllo".into()), Span { start: 0, end: 7 })
+        );
+        assert_eq!(
+            l.next_spanned(),
+            (Token::String("€\n🐒".into()), Span { start: 8, end: 14 })
+        );
+        assert_eq!(
+            l.try_next_token(),
+            Err(LexError::InvalidEscape('q', Span { start: 18, end: 20 }))
+        );
+        assert_eq!(l.next_token(), Token::Ident("x".into()));
+    }
+
+    #[test]
+    fn test_identifiers_cannot_start_with_digit() {
+        let mut l = Lexer::new("5five");
+
+        assert_eq!(l.next_token(), Token::Int("5".into()));
+        assert_eq!(l.next_token(), Token::Ident("five".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_multibyte_identifier_ranges() {
+        let mut l = Lexer::new("let naïve = имя + 5٣;");
+
+        assert_eq!(l.next_token(), Token::Let);
+        assert_eq!(
+            l.next_spanned(),
+            (Token::Ident("naïve".into()), Span { start: 4, end: 9 })
+        );
+        assert_eq!(l.next_token(), Token::Assign);
+        assert_eq!(
+            l.next_spanned(),
+            (Token::Ident("имя".into()), Span { start: 12, end: 15 })
+        );
+        assert_eq!(l.next_token(), Token::Plus);
+        assert_eq!(l.next_token(), Token::Int("5".into()));
+        assert_eq!(l.next_token(), Token::Illegal("٣".into()));
+        assert_eq!(l.next_token(), Token::Semicolon);
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_illegal_runs() {
+        let mut l = Lexer::new("x@@#+ 5 ~~?? y");
+
+        assert_eq!(l.next_token(), Token::Ident("x".into()));
+        assert_eq!(l.next_token(), Token::Illegal("@@#".into()));
+        assert_eq!(l.next_token(), Token::Plus);
+        assert_eq!(l.next_token(), Token::Int("5".into()));
+        assert_eq!(l.next_token(), Token::Illegal("~~".into()));
+        assert_eq!(l.next_token(), Token::Coalesce);
+        assert_eq!(l.next_token(), Token::Ident("y".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("1 + 2"),
+            vec![Token::Int("1".into()), Token::Plus, Token::Int("2".into())]
+        );
+        assert_eq!(tokenize(""), vec![]);
+    }
+
+    #[test]
+    fn test_next_spanned() {
+        let mut l = Lexer::new("let five == 55");
+
+        assert_eq!(l.next_spanned(), (Token::Let, Span { start: 0, end: 3 }));
+        assert_eq!(
+            l.next_spanned(),
+            (Token::Ident("five".into()), Span { start: 4, end: 8 })
+        );
+        assert_eq!(l.next_spanned(), (Token::Equal, Span { start: 9, end: 11 }));
+        assert_eq!(
+            l.next_spanned(),
+            (Token::Int("55".into()), Span { start: 12, end: 14 })
+        );
+        assert_eq!(l.next_spanned(), (Token::Eof, Span { start: 14, end: 14 }));
+        assert_eq!(l.next_spanned(), (Token::Eof, Span { start: 14, end: 14 }));
+    }
+
+    #[test]
+    fn test_aliases() {
+        let aliases = HashMap::from([
+            ("not".to_string(), Token::Bang),
+            ("is".to_string(), Token::Equal),
+        ]);
+        let mut l = Lexer::new("not x is nothing").with_aliases(aliases);
+
+        assert_eq!(l.next_token(), Token::Bang);
+        assert_eq!(l.next_token(), Token::Ident("x".into()));
+        assert_eq!(l.next_token(), Token::Equal);
+        assert_eq!(l.next_token(), Token::Ident("nothing".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_scanners_agree() {
+        let inputs = [
+            "let add = fn(x, y) { x + y; }; add(5, 10) != 15;",
+            "let café = 5 @@ λ == 10;",
+        ];
+
+        for input in inputs {
+            let tokens = |scanner| {
+                let mut l = Lexer::with_scanner(input, scanner);
+                let mut tokens = vec![];
+
+                loop {
+                    match l.next_spanned() {
+                        (Token::Eof, _) => break,
+                        spanned => tokens.push(spanned),
+                    }
+                }
+
+                tokens
+            };
+
+            assert_eq!(tokens(Scanner::Chars), tokens(Scanner::CharVec));
+            assert_eq!(tokens(Scanner::Chars), tokens(Scanner::Bytes));
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        let inputs = ["let five = 5;", "fn(x) { x != 10 }", ""];
+
+        for scanner in [Scanner::Chars, Scanner::CharVec, Scanner::Bytes] {
+            let mut reused = Lexer::with_scanner("", scanner);
+
+            for input in inputs {
+                reused.reset(input);
+                let mut fresh = Lexer::with_scanner(input, scanner);
+
+                loop {
+                    let token = fresh.next_spanned();
+                    assert_eq!(reused.next_spanned(), token);
+
+                    if token.0 == Token::Eof {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_int_check() {
+        let input = "9223372036854775807 9223372036854775808 5";
+
+        let mut l = Lexer::new(input).with_int_check();
+
+        assert_eq!(l.next_token(), Token::Int("9223372036854775807".into()));
+        assert_eq!(
+            l.next_token(),
+            Token::Illegal(
+                "integer literal 9223372036854775808 is too large for a 64-bit integer".into()
+            )
+        );
+        assert_eq!(l.next_token(), Token::Int("5".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+
+        let mut l = Lexer::new(input);
+
+        assert_eq!(l.next_token(), Token::Int("9223372036854775807".into()));
+        assert_eq!(l.next_token(), Token::Int("9223372036854775808".into()));
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let mut l = Lexer::new("x @ 99999999999999999999").with_int_check();
+
+        assert_eq!(l.try_next_token(), Ok(Token::Ident("x".into())));
+        assert_eq!(
+            l.try_next_token(),
+            Err(LexError::UnknownChar("@".into(), Span { start: 2, end: 3 }))
+        );
+        assert_eq!(
+            l.try_next_token(),
+            Err(LexError::IntegerOverflow(
+                "99999999999999999999".into(),
+                Span { start: 4, end: 24 }
+            ))
+        );
+        assert_eq!(l.try_next_token(), Ok(Token::Eof));
+    }
+
+    #[test]
+    fn test_floats() {
+        let mut l = Lexer::new("3.14 0.5 5. 1.x");
+
+        assert_eq!(l.next_token(), Token::Float("3.14".into()));
+        assert_eq!(l.next_token(), Token::Float("0.5".into()));
+        assert_eq!(l.next_token(), Token::Int("5".into()));
+        assert_eq!(l.next_token(), Token::Illegal(".".into()));
+        assert_eq!(l.next_token(), Token::Int("1".into()));
+        assert_eq!(l.next_token(), Token::Illegal(".".into()));
+        assert_eq!(l.next_token(), Token::Ident("x".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        let mut l = Lexer::new("1_000 12_34_5 _foo _");
+
+        assert_eq!(l.next_token(), Token::Int("1000".into()));
+        assert_eq!(l.next_token(), Token::Int("12345".into()));
+        assert_eq!(l.next_token(), Token::Ident("_foo".into()));
+        assert_eq!(l.next_token(), Token::Ident("_".into()));
+        assert_eq!(l.next_token(), Token::Eof);
+
+        for (input, start, end) in [("_5", 0, 2), ("5_", 0, 2), ("1__2", 0, 4)] {
+            let mut l = Lexer::new(input);
+
+            assert_eq!(
+                l.try_next_token(),
+                Err(LexError::MalformedNumber(input.into(), Span { start, end }))
+            );
+            assert_eq!(l.next_token(), Token::Eof);
+        }
+
+        let mut l = Lexer::new("1_000.000_5 1_.5");
+
+        assert_eq!(l.next_token(), Token::Float("1000.0005".into()));
+        assert_eq!(
+            l.next_token(),
+            Token::Illegal("malformed number literal 1_.5".into())
+        );
+        assert_eq!(l.next_token(), Token::Eof);
+
+        let mut l = Lexer::new("5_;");
+
+        assert_eq!(
+            l.next_token(),
+            Token::Illegal("malformed number literal 5_".into())
+        );
+        assert_eq!(l.next_token(), Token::Semicolon);
+    }
+
+    fn arbitrary_token() -> impl Strategy<Value = Token<'static>> {
+        prop_oneof![
+            "[a-zA-Z_]{1,8}".prop_map(|s| lookup_ident(&s).into_owned()),
+            "[0-9]{1,18}".prop_map(|s| Token::Int(s.into())),
+            "[0-9]{1,9}\\.[0-9]{1,9}".prop_map(|s| Token::Float(s.into())),
+            "[a-z \"\\\\\n\t]{0,8}".prop_map(|s| Token::String(s.into())),
+            prop::sample::select(vec![
+                Token::Assign,
+                Token::Plus,
+                Token::Minus,
+                Token::Bang,
+                Token::Asterisk,
+                Token::Slash,
+                Token::Lt,
+                Token::Gt,
+                Token::Equal,
+                Token::NotEqual,
+                Token::Comma,
+                Token::Semicolon,
+                Token::Colon,
+                Token::Lparen,
+                Token::Rparen,
+                Token::Lbrace,
+                Token::Rbrace,
+                Token::Lbracket,
+                Token::Rbracket,
+                Token::Dollar,
+                Token::Percent,
+                Token::LtEq,
+                Token::GtEq,
+                Token::And,
+                Token::Or,
+                Token::Coalesce,
+                Token::Question,
+            ]),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_display_roundtrip(tokens in prop::collection::vec(arbitrary_token(), 0..64)) {
+            let source = tokens
+                .iter()
+                .map(Token::to_string)
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            prop_assert_eq!(tokenize(&source), tokens);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_with_positions() {
+        let input = "let x = add(5, y) @;";
+        let (tags, spans, payloads) = tokenize_with_positions(input);
+
+        assert_eq!(tags.len(), spans.len());
+        assert_eq!(tags.len(), payloads.len());
+        assert_eq!(tags[1], TokenTag::Ident);
+        assert_eq!(spans[1], Span { start: 4, end: 5 });
+        assert_eq!(payloads[1], "x");
+        assert_eq!(payloads[2], "");
+
+        let rebuilt = tags
+            .into_iter()
+            .zip(payloads)
+            .map(|(tag, payload)| Token::from_tag(tag, payload))
+            .collect::<Vec<_>>();
+
+        assert_eq!(rebuilt, tokenize(input));
+    }
+
+    #[test]
+    fn test_string_errors() {
+        let mut l = Lexer::new("\"a\\qb\" x \"open");
+
+        assert_eq!(
+            l.try_next_token(),
+            Err(LexError::InvalidEscape('q', Span { start: 2, end: 4 }))
+        );
+        assert_eq!(l.try_next_token(), Ok(Token::Ident("x".into())));
+        assert_eq!(
+            l.try_next_token(),
+            Err(LexError::UnterminatedString(Span { start: 9, end: 14 }))
+        );
+        assert_eq!(l.try_next_token(), Ok(Token::Eof));
+
+        let mut l = Lexer::new("x @ \"open");
+
+        assert_eq!(l.try_next_token(), Ok(Token::Ident("x".into())));
+        assert!(matches!(l.try_next_token(), Err(LexError::UnknownChar(..))));
+        assert!(matches!(
+            l.try_next_token(),
+            Err(LexError::UnterminatedString(_))
+        ));
+    }
+
+    #[test]
+    fn test_next_positioned() {
+        let mut l = Lexer::new("let x = 5;\n\n  \"a\nb\" +\n}");
+        let mut positions = vec![];
+
+        loop {
+            let token = l.next_positioned();
+            positions.push((
+                token.node.clone(),
+                token.position.line,
+                token.position.column,
+            ));
+
+            if token.node == Token::Eof {
+                break;
+            }
+        }
+
+        assert_eq!(
+            positions,
+            vec![
+                (Token::Let, 1, 1),
+                (Token::Ident("x".into()), 1, 5),
+                (Token::Assign, 1, 7),
+                (Token::Int("5".into()), 1, 9),
+                (Token::Semicolon, 1, 10),
+                (Token::String("a\nb".into()), 3, 3),
+                (Token::Plus, 4, 4),
+                (Token::Rbrace, 5, 1),
+                (Token::Eof, 5, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "// leading\nlet x = 10 / 2; // trailing\n/* block\n * spanning */ x /**/ */";
+
+        assert_eq!(
+            tokenize(input),
+            vec![
+                Token::Let,
+                Token::Ident("x".into()),
+                Token::Assign,
+                Token::Int("10".into()),
+                Token::Slash,
+                Token::Int("2".into()),
+                Token::Semicolon,
+                Token::Ident("x".into()),
+                Token::Asterisk,
+                Token::Slash,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lexing_comments() {
+        let input = "// leading\nx /* block\n */ / 2 // trailing";
+        let tokens: Vec<_> = Lexer::new(input).with_comments().collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Comment("// leading".into()),
+                Token::Ident("x".into()),
+                Token::Comment("/* block\n */".into()),
+                Token::Slash,
+                Token::Int("2".into()),
+                Token::Comment("// trailing".into()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            Lexer::new("/* open").with_comments().next_token(),
+            Token::Illegal("unterminated block comment".into())
+        );
+    }
+
+    #[test]
+    fn test_large_block_comment() {
+        let input = format!(
+            "/*{}\n© */ let x /* a */ = 1;\n/**/y",
+            " * licensed under the terms below\n".repeat(1000)
+        );
+
+        for scanner in [Scanner::Chars, Scanner::CharVec, Scanner::Bytes] {
+            for comments in [false, true] {
+                let mut l = Lexer::with_scanner(&input, scanner);
+                if comments {
+                    l = l.with_comments();
+                }
+
+                let mut positions = vec![];
+                loop {
+                    let token = l.next_positioned();
+                    if token.node == Token::Eof {
+                        break;
+                    }
+                    if !matches!(token.node, Token::Comment(_)) {
+                        positions.push((token.node, token.position.line, token.position.column));
+                    }
+                }
+
+                assert_eq!(
+                    positions,
+                    vec![
+                        (Token::Let, 1002, 6),
+                        (Token::Ident("x".into()), 1002, 10),
+                        (Token::Assign, 1002, 20),
+                        (Token::Int("1".into()), 1002, 22),
+                        (Token::Semicolon, 1002, 23),
+                        (Token::Ident("y".into()), 1003, 5),
+                    ],
+                    "scanner: {:?}",
+                    scanner
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_smart_quotes() {
+        let input = "let s = “hi ${name}”; \"plain\"";
+
+        assert_eq!(
+            Lexer::new(input).with_smart_quotes().collect::<Vec<_>>(),
+            vec![
+                Token::Let,
+                Token::Ident("s".into()),
+                Token::Assign,
+                Token::String("hi ".into()),
+                Token::Dollar,
+                Token::Lbrace,
+                Token::Ident("name".into()),
+                Token::Rbrace,
+                Token::String("".into()),
+                Token::Semicolon,
+                Token::String("plain".into()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokenize("“hi”"),
+            vec![
+                Token::Illegal("“".into()),
+                Token::Ident("hi".into()),
+                Token::Illegal("”".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let input = "\"a ${x + \"${{1: 2}[1]}\"} b${y}\\${z}\" {}";
+
+        assert_eq!(
+            tokenize(input),
+            vec![
+                Token::String("a ".into()),
+                Token::Dollar,
+                Token::Lbrace,
+                Token::Ident("x".into()),
+                Token::Plus,
+                Token::String("".into()),
+                Token::Dollar,
+                Token::Lbrace,
+                Token::Lbrace,
+                Token::Int("1".into()),
+                Token::Colon,
+                Token::Int("2".into()),
+                Token::Rbrace,
+                Token::Lbracket,
+                Token::Int("1".into()),
+                Token::Rbracket,
+                Token::Rbrace,
+                Token::String("".into()),
+                Token::Rbrace,
+                Token::String(" b".into()),
+                Token::Dollar,
+                Token::Lbrace,
+                Token::Ident("y".into()),
+                Token::Rbrace,
+                Token::String("${z}".into()),
+                Token::Lbrace,
+                Token::Rbrace,
+            ]
+        );
+
+        let mut l = Lexer::new("\"${x} \" y");
+        l.next_token();
+        l.next_token();
+        l.next_token();
+        l.next_token();
+        assert_eq!(l.next_token(), Token::Rbrace);
+        assert_eq!(
+            l.next_spanned(),
+            (Token::String(" ".into()), Span { start: 5, end: 7 })
+        );
+        assert_eq!(l.next_token(), Token::Ident("y".into()));
+        assert_eq!(
+            Lexer::new("\"${x} open").nth(5),
+            Some(Token::Illegal("unterminated string literal".into()))
+        );
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let mut l = Lexer::new("1;\n  /* never * closed\n2;");
+        l.next_token();
+        l.next_token();
+
+        let token = l.next_positioned();
+        assert_eq!(
+            token.node,
+            Token::Illegal("unterminated block comment".into())
+        );
+        assert_eq!(token.span, Span { start: 5, end: 25 });
+        assert_eq!(token.position, Position { line: 2, column: 3 });
         assert_eq!(l.next_token(), Token::Eof);
     }
+
+    #[test]
+    fn test_comparison_and_logical_operators() {
+        let mut l = Lexer::new("a <= b >= c % d && e || f & g | h ?? i ? j : k");
+
+        for token in [
+            Token::Ident("a".into()),
+            Token::LtEq,
+            Token::Ident("b".into()),
+            Token::GtEq,
+            Token::Ident("c".into()),
+            Token::Percent,
+            Token::Ident("d".into()),
+            Token::And,
+            Token::Ident("e".into()),
+            Token::Or,
+            Token::Ident("f".into()),
+            Token::Illegal("&".into()),
+            Token::Ident("g".into()),
+            Token::Illegal("|".into()),
+            Token::Ident("h".into()),
+            Token::Coalesce,
+            Token::Ident("i".into()),
+            Token::Question,
+            Token::Ident("j".into()),
+            Token::Colon,
+            Token::Ident("k".into()),
+            Token::Eof,
+        ] {
+            assert_eq!(l.next_token(), token);
+        }
+    }
+
+    #[test]
+    fn test_assignment_operators() {
+        let tokens = tokenize("x = 1; x += 2 -= 3 *= 4 /= 5 + - * /");
+
+        assert_eq!(
+            tokens[4..],
+            [
+                Token::Ident("x".into()),
+                Token::PlusAssign,
+                Token::Int("2".into()),
+                Token::MinusAssign,
+                Token::Int("3".into()),
+                Token::AsteriskAssign,
+                Token::Int("4".into()),
+                Token::SlashAssign,
+                Token::Int("5".into()),
+                Token::Plus,
+                Token::Minus,
+                Token::Asterisk,
+                Token::Slash,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_iterator() {
+        let mut l = Lexer::new("let x = 5;");
+        let idents = l
+            .by_ref()
+            .filter(|token| matches!(token, Token::Ident(_)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(idents, vec![Token::Ident("x".into())]);
+        assert_eq!(l.next(), None);
+        assert_eq!(l.next_token(), Token::Eof);
+
+        l.reset("1 + 2");
+        assert_eq!(
+            l.collect::<Vec<_>>(),
+            vec![
+                Token::Int("1".into()),
+                Token::Plus,
+                Token::Int("2".into()),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_borrowed_literals() {
+        let input = "naïve 1_000 10 \"plain\" \"esc\\n\"";
+
+        for scanner in [Scanner::Chars, Scanner::CharVec, Scanner::Bytes] {
+            let tokens = Lexer::with_scanner(input, scanner).collect::<Vec<_>>();
+            let borrowed = tokens
+                .iter()
+                .map(|token| match token {
+                    Token::Ident(s) | Token::Int(s) | Token::String(s) => {
+                        matches!(s, Cow::Borrowed(_))
+                    }
+                    _ => false,
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                tokens,
+                vec![
+                    Token::Ident("naïve".into()),
+                    Token::Int("1000".into()),
+                    Token::Int("10".into()),
+                    Token::String("plain".into()),
+                    Token::String("esc\n".into()),
+                    Token::Eof
+                ]
+            );
+            assert_eq!(borrowed, vec![true, false, true, true, false, false]);
+        }
+    }
 }
@@ -0,0 +1,35 @@
+pub mod analysis;
+pub mod ast;
+pub mod benchmark;
+pub mod builtins;
+pub mod code;
+pub mod compiler;
+pub mod coverage;
+pub mod diagnostic;
+pub mod environment;
+pub mod error;
+pub mod evaluator;
+pub mod format;
+pub mod interpreter;
+pub mod lexer;
+pub mod lint;
+pub mod macro_expansion;
+pub mod object;
+pub mod optimize;
+pub mod parser;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod repl;
+pub mod script;
+#[cfg(feature = "serde")]
+pub mod serialize;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+pub mod stdlib;
+pub mod symbol_table;
+pub mod token;
+pub mod trace;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use interpreter::Interpreter;
@@ -0,0 +1,62 @@
+use crate::lexer::Lexer;
+use crate::token::{Span, Token};
+
+/// Integer literals with more digits than this get a grouping suggestion
+const GROUPING_THRESHOLD: usize = 5;
+
+/// Finds long integer literals written without digit separators and suggests a grouped form,
+/// e.g. `1000000` -> `1_000_000`. This is advisory only, as ungrouped literals are still valid.
+///
+/// * `input` - The source to scan
+pub fn suggest_digit_grouping(input: &str) -> Vec<(Span, String)> {
+    let mut lexer = Lexer::new(input);
+    let mut suggestions = Vec::new();
+
+    loop {
+        match lexer.next_spanned() {
+            (Token::Eof, _) => break,
+            // Literals already written with separators are longer than their digits
+            (Token::Int(digits), span)
+                if digits.len() > GROUPING_THRESHOLD && span.end - span.start == digits.len() =>
+            {
+                suggestions.push((span, group_digits(&digits)));
+            }
+            _ => {}
+        }
+    }
+
+    suggestions
+}
+
+/// Inserts a `_` between every group of three digits, counting from the right
+///
+/// * `digits` - The digits of an integer literal
+fn group_digits(digits: &str) -> String {
+    let mut grouped = String::new();
+
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push('_');
+        }
+        grouped.push(c);
+    }
+
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggest_digit_grouping() {
+        let input = "let big = 1000000;
+let small = 1000;
+let grouped = 1_000_000;";
+
+        assert_eq!(
+            suggest_digit_grouping(input),
+            vec![(Span { start: 10, end: 17 }, "1_000_000".to_string())]
+        );
+    }
+}
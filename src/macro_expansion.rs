@@ -0,0 +1,204 @@
+use crate::ast::{self, Expression, Program, Statement};
+use crate::environment::{Env, Environment};
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::evaluator::Evaluator;
+use crate::object::{Function, Object};
+use std::rc::Rc;
+
+/// Remove the top-level `let name = macro(...) { ... };` statements from a program, binding
+/// each macro in an environment for `expand_macros` to use
+///
+/// * `program` - The program to take the definitions out of
+/// * `env` - Where to bind the macros
+pub fn define_macros(program: &mut Program, env: &Env) {
+    let mut spans = std::mem::take(&mut program.spans).into_iter();
+    let mut kept = vec![];
+
+    program.statements.retain(|statement| {
+        let span = spans.next();
+        if define_macro(statement, env) {
+            return false;
+        }
+        kept.extend(span);
+        true
+    });
+    program.spans = kept;
+}
+
+/// Bind the macro a statement defines, if it's a macro definition, returning whether it was
+///
+/// * `statement` - The statement
+/// * `env` - Where to bind the macro
+fn define_macro(statement: &Statement, env: &Env) -> bool {
+    match statement {
+        Statement::Let(name, Expression::Macro { parameters, body }) => {
+            let definition = Object::Macro(Rc::new(Function {
+                parameters: parameters.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            }));
+            env.borrow_mut().set(name.clone(), definition);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Replace every call to a macro bound in `env` with the code the macro returns. The
+/// arguments are passed to the macro quoted, i.e. as unevaluated code.
+///
+/// * `program` - The program to expand
+/// * `env` - The macros, as bound by `define_macros`
+pub fn expand_macros(program: Program, env: &Env) -> Result<Program, RuntimeError> {
+    ast::modify_program(program, &mut |expression| {
+        let Expression::Call {
+            function,
+            arguments,
+        } = &expression
+        else {
+            return Ok(expression);
+        };
+
+        let definition = match function.as_ref() {
+            Expression::Identifier(name) => env.borrow().get(name),
+            _ => None,
+        };
+        let Some(Object::Macro(definition)) = definition else {
+            return Ok(expression);
+        };
+
+        if definition.parameters.len() != arguments.len() {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::WrongArgumentCount,
+                format!(
+                    "wrong number of arguments: want={}, got={}",
+                    definition.parameters.len(),
+                    arguments.len()
+                ),
+            ));
+        }
+
+        let macro_env = Environment::new_enclosed(definition.env.clone());
+        for (parameter, argument) in definition.parameters.iter().zip(arguments) {
+            let quoted = Object::Quote(argument.clone());
+            macro_env.borrow_mut().set(parameter.clone(), quoted);
+        }
+
+        let body = Program {
+            statements: definition.body.statements.clone(),
+            spans: definition.body.spans.clone(),
+        };
+
+        match Evaluator::new().eval(&body, &macro_env) {
+            Object::Quote(expansion) => Ok(expansion),
+            Object::Error(err) => Err(err),
+            result => Err(RuntimeError::new(
+                RuntimeErrorKind::InvalidMacroResult,
+                format!(
+                    "macros must return a quoted expression, got {}",
+                    result.type_name()
+                ),
+            )),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert_eq!(parser.errors(), &[], "input: {}", input);
+        program
+    }
+
+    fn expand(input: &str) -> Result<Program, RuntimeError> {
+        let mut program = parse(input);
+        let env = Environment::new();
+        define_macros(&mut program, &env);
+        expand_macros(program, &env)
+    }
+
+    #[test]
+    fn test_define_macros() {
+        let mut program = parse(
+            "let number = 1;
+            let function = fn(x, y) { x + y };
+            let mymacro = macro(x, y) { x + y; };",
+        );
+        let env = Environment::new();
+        define_macros(&mut program, &env);
+
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(env.borrow().get("number"), None);
+        assert_eq!(env.borrow().get("function"), None);
+
+        let Some(Object::Macro(definition)) = env.borrow().get("mymacro") else {
+            panic!("mymacro not defined");
+        };
+        assert_eq!(definition.parameters, vec!["x", "y"]);
+        assert_eq!(definition.body.to_string(), "(x + y)");
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let tests = [
+            (
+                "let infixExpression = macro() { quote(1 + 2); }; infixExpression();",
+                "(1 + 2)",
+            ),
+            (
+                "let reverse = macro(a, b) { quote(unquote(b) - unquote(a)); }; reverse(2 + 2, 10 - 5);",
+                "((10 - 5) - (2 + 2))",
+            ),
+            (
+                "let unless = macro(cond, conseq, alt) {
+                    quote(if (!(unquote(cond))) { unquote(conseq); } else { unquote(alt); });
+                };
+                unless(10 > 5, puts(\"not greater\"), puts(\"greater\"));",
+                "if(!(10 > 5)) puts(\"not greater\")else puts(\"greater\")",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                expand(input).unwrap().to_string(),
+                expected,
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_macro_errors() {
+        for (input, kind, message) in [
+            (
+                "let m = macro(a) { quote(unquote(a)) }; m();",
+                RuntimeErrorKind::WrongArgumentCount,
+                "wrong number of arguments: want=1, got=0",
+            ),
+            (
+                "let m = macro() { 1 }; m();",
+                RuntimeErrorKind::InvalidMacroResult,
+                "macros must return a quoted expression, got INTEGER",
+            ),
+            (
+                "let m = macro() { 1 + true }; m();",
+                RuntimeErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+        ] {
+            assert_eq!(
+                expand(input),
+                Err(RuntimeError::new(kind, message)),
+                "input: {}",
+                input
+            );
+        }
+    }
+}
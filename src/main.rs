@@ -1,9 +1,298 @@
-mod lexer;
-mod repl;
-mod token;
+use monkey_rs::analysis::{analyze, Finding};
+use monkey_rs::benchmark;
+use monkey_rs::diagnostic::{position_of, render_error};
+use monkey_rs::error::MonkeyError;
+use monkey_rs::evaluator::STACK_SIZE;
+use monkey_rs::format::format_source;
+use monkey_rs::lexer::Lexer;
+use monkey_rs::object::Object;
+use monkey_rs::optimize::OptLevel;
+use monkey_rs::parser::Parser;
+use monkey_rs::repl::{start, ReplOptions};
+use monkey_rs::script::{compile_source, run_source, Backend, RunOptions};
+use monkey_rs::trace::trace_source;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
 
-use repl::start;
+const USAGE: &str =
+    "usage: monkey-rs [--backend=eval|vm] [--opt=0|1] [--no-stdlib] [--check] [--emit=bytecode]
+                 [--trace=FILE] [script]
+       monkey-rs [--no-stdlib] [--show-null]
+       monkey-rs fmt [--write] script
+       monkey-rs bench [--iterations=N]";
 
-fn main() {
-    start();
+/// How many times `monkey-rs bench` runs each benchmark unless told otherwise
+const BENCH_ITERATIONS: u32 = 5;
+
+fn main() -> ExitCode {
+    // Calls nest on the stack while the evaluator runs, so it needs more than the main thread has
+    match thread::Builder::new().stack_size(STACK_SIZE).spawn(run) {
+        // A panic has already been reported by the thread
+        Ok(handle) => handle.join().unwrap_or(ExitCode::FAILURE),
+        Err(err) => {
+            eprintln!("could not start: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> ExitCode {
+    let mut backend = None;
+    let mut opt_level = None;
+    let mut check = false;
+    let mut emit_bytecode = false;
+    let mut stdlib = true;
+    let mut show_null = false;
+    let mut trace = None;
+    let mut path = None;
+
+    let mut args = env::args_os().skip(1).peekable();
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        return format_file(args.collect());
+    }
+    if args.next_if(|arg| arg == "bench").is_some() {
+        return run_benchmarks(args.collect());
+    }
+
+    for arg in args {
+        if arg == "--check" {
+            check = true;
+            continue;
+        }
+        if arg == "--emit=bytecode" {
+            emit_bytecode = true;
+            continue;
+        }
+        if arg == "--no-stdlib" {
+            stdlib = false;
+            continue;
+        }
+        if arg == "--show-null" {
+            show_null = true;
+            continue;
+        }
+
+        if let Some(file) = arg.to_str().and_then(|arg| arg.strip_prefix("--trace=")) {
+            trace = Some(file.to_string());
+            continue;
+        }
+
+        if let Some(level) = arg.to_str().and_then(|arg| arg.strip_prefix("--opt=")) {
+            match level.parse() {
+                Ok(level) => opt_level = Some(level),
+                Err(err) => {
+                    eprintln!("{}\n{}", err, USAGE);
+                    return ExitCode::FAILURE;
+                }
+            }
+            continue;
+        }
+
+        match arg.to_str().and_then(|arg| arg.strip_prefix("--backend=")) {
+            Some(name) => match name.parse() {
+                Ok(name) => backend = Some(name),
+                Err(err) => {
+                    eprintln!("{}\n{}", err, USAGE);
+                    return ExitCode::FAILURE;
+                }
+            },
+            None if path.is_none() => path = Some(arg),
+            None => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let Some(path) = path else {
+        if backend.is_some() || opt_level.is_some() || check || emit_bytecode || trace.is_some() {
+            eprintln!(
+                "--backend, --opt, --check, --emit and --trace only apply to scripts\n{}",
+                USAGE
+            );
+            return ExitCode::FAILURE;
+        }
+
+        start(ReplOptions { stdlib, show_null });
+        return ExitCode::SUCCESS;
+    };
+
+    if show_null {
+        eprintln!("--show-null only applies to the REPL\n{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    if trace.is_some() && backend == Some(Backend::Vm) {
+        eprintln!("--trace only works with the eval backend\n{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}", MonkeyError::Io(err));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let file = Path::new(&path).display().to_string();
+    if check {
+        return check_source(&source, &file);
+    }
+
+    let options = RunOptions {
+        backend: backend.unwrap_or(Backend::Eval),
+        opt_level: opt_level.unwrap_or(OptLevel::None),
+        stdlib,
+        path: Some(PathBuf::from(&path)),
+    };
+    if emit_bytecode {
+        return match compile_source(&source, &options) {
+            Ok(bytecode) => {
+                print!("{}", bytecode.disassemble());
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("{}", render_error(&err, &file, &source));
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let result = match trace {
+        Some(trace) => match File::create(&trace) {
+            Ok(out) => trace_source(&source, &options, BufWriter::new(out)),
+            Err(err) => {
+                eprintln!("could not create trace: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => run_source(&source, &options),
+    };
+    match result {
+        Ok(Object::Null) => ExitCode::SUCCESS,
+        Ok(result) => {
+            println!("{}", result);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", render_error(&err, &file, &source));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Report what static analysis finds in a script without running it, failing if the script
+/// doesn't parse or would certainly fail
+///
+/// * `source` - The script
+/// * `file` - The name of the script's file
+fn check_source(source: &str, file: &str) -> ExitCode {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+
+    if !parser.errors().is_empty() {
+        let err = MonkeyError::Parse(parser.errors().to_vec());
+        eprintln!("{}", render_error(&err, file, source));
+        return ExitCode::FAILURE;
+    }
+
+    for warning in parser.warnings() {
+        eprintln!("{}: warning: {}", file, warning);
+    }
+
+    let findings = analyze(&program);
+    for finding in &findings {
+        match finding.span {
+            Some(span) => eprintln!("{}: {}: {}", file, position_of(source, span.start), finding),
+            None => eprintln!("{}: {}", file, finding),
+        }
+    }
+
+    if findings.iter().any(Finding::is_error) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Format a script, printing the result or, with `--write`, replacing the script with it
+///
+/// * `args` - The arguments after `fmt`
+fn format_file(args: Vec<OsString>) -> ExitCode {
+    let (write, path) = match args.as_slice() {
+        [path] => (false, path),
+        [flag, path] if flag == "--write" => (true, path),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}", MonkeyError::Io(err));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let formatted = match format_source(&source) {
+        Ok(formatted) => formatted,
+        Err(err) => {
+            let file = Path::new(path).display().to_string();
+            eprintln!("{}", render_error(&err, &file, &source));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !write {
+        print!("{}", formatted);
+    } else if let Err(err) = fs::write(path, formatted) {
+        eprintln!("could not write script: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Run the benchmarks, printing the average time each took
+///
+/// * `args` - The arguments after `bench`
+fn run_benchmarks(args: Vec<OsString>) -> ExitCode {
+    let iterations = match args.as_slice() {
+        [] => Ok(BENCH_ITERATIONS),
+        [arg] => match arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix("--iterations="))
+        {
+            Some(count) => count
+                .parse()
+                .map_err(|_| format!("invalid iteration count {}", count)),
+            None => Err(format!("unknown option {}", arg.to_string_lossy())),
+        },
+        _ => Err("too many arguments".to_string()),
+    };
+    let iterations = match iterations {
+        Ok(iterations) => iterations,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cfg!(debug_assertions) {
+        eprintln!("warning: this is a debug build, so it's much slower than a release one");
+    }
+    if let Err(err) = benchmark::run(iterations, &mut std::io::stdout()) {
+        eprintln!("could not write results: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,529 @@
+use crate::ast::{BlockStatement, Expression};
+use crate::builtins::Context;
+use crate::code::Instructions;
+use crate::environment::Env;
+use crate::error::{MonkeyError, RuntimeError, RuntimeErrorKind};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Object>),
+    Hash(HashMap<HashKey, Object>),
+    Null,
+    ReturnValue(Box<Object>),
+    /// A call to make in place of the call to the function that produced it, as produced by a
+    /// call in tail position, so tail recursion doesn't grow the stack
+    TailCall(Rc<Function>, Vec<Object>),
+    /// Unwinds to the enclosing loop and leaves it, as produced by `break`
+    Break,
+    /// Unwinds to the enclosing loop and starts its next iteration, as produced by `continue`
+    Continue,
+    Error(RuntimeError),
+    Function(Rc<Function>),
+    Builtin(Builtin),
+    /// A function registered by the host application
+    Native(Rc<NativeFunction>),
+    /// A function wrapped by `memoize`
+    Memoized(Rc<Memoized>),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure(Rc<Closure>),
+    /// An unevaluated piece of code, as produced by `quote`
+    Quote(Expression),
+    /// A macro literal, which shares its shape with a function
+    Macro(Rc<Function>),
+}
+
+/// The objects that can be used as hash keys
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum HashKey {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl From<HashKey> for Object {
+    fn from(key: HashKey) -> Object {
+        match key {
+            HashKey::Integer(value) => Object::Integer(value),
+            HashKey::Boolean(value) => Object::Boolean(value),
+            HashKey::String(value) => Object::String(value),
+        }
+    }
+}
+
+impl From<i64> for Object {
+    fn from(value: i64) -> Object {
+        Object::Integer(value)
+    }
+}
+
+impl From<f64> for Object {
+    fn from(value: f64) -> Object {
+        Object::Float(value)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(value: bool) -> Object {
+        Object::Boolean(value)
+    }
+}
+
+impl From<String> for Object {
+    fn from(value: String) -> Object {
+        Object::String(value)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(value: &str) -> Object {
+        Object::String(value.to_string())
+    }
+}
+
+impl From<()> for Object {
+    fn from(_: ()) -> Object {
+        Object::Null
+    }
+}
+
+impl<T: Into<Object>> From<Option<T>> for Object {
+    fn from(value: Option<T>) -> Object {
+        value.map_or(Object::Null, Into::into)
+    }
+}
+
+impl<T: Into<Object>> From<Vec<T>> for Object {
+    fn from(values: Vec<T>) -> Object {
+        Object::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// The error for converting an object to a Rust type it doesn't hold
+///
+/// * `want` - The type name of the objects that can be converted
+/// * `got` - The object given
+fn conversion_error(want: &str, got: &Object) -> RuntimeError {
+    RuntimeError::new(
+        RuntimeErrorKind::InvalidArgument,
+        format!("expected {}, got {}", want, got.type_name()),
+    )
+}
+
+impl TryFrom<Object> for i64 {
+    type Error = RuntimeError;
+
+    fn try_from(object: Object) -> Result<i64, RuntimeError> {
+        match object {
+            Object::Integer(value) => Ok(value),
+            object => Err(conversion_error("INTEGER", &object)),
+        }
+    }
+}
+
+impl TryFrom<Object> for f64 {
+    type Error = RuntimeError;
+
+    /// Integers are widened, as they are in mixed arithmetic
+    fn try_from(object: Object) -> Result<f64, RuntimeError> {
+        match object {
+            Object::Float(value) => Ok(value),
+            Object::Integer(value) => Ok(value as f64),
+            object => Err(conversion_error("FLOAT", &object)),
+        }
+    }
+}
+
+impl TryFrom<Object> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(object: Object) -> Result<bool, RuntimeError> {
+        match object {
+            Object::Boolean(value) => Ok(value),
+            object => Err(conversion_error("BOOLEAN", &object)),
+        }
+    }
+}
+
+impl TryFrom<Object> for String {
+    type Error = RuntimeError;
+
+    fn try_from(object: Object) -> Result<String, RuntimeError> {
+        match object {
+            Object::String(value) => Ok(value),
+            object => Err(conversion_error("STRING", &object)),
+        }
+    }
+}
+
+impl TryFrom<Object> for Vec<Object> {
+    type Error = RuntimeError;
+
+    fn try_from(object: Object) -> Result<Vec<Object>, RuntimeError> {
+        match object {
+            Object::Array(elements) => Ok(elements),
+            object => Err(conversion_error("ARRAY", &object)),
+        }
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashKey::Integer(value) => write!(f, "{}", value),
+            HashKey::Boolean(value) => write!(f, "{}", value),
+            HashKey::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The signature of a builtin function's implementation, which is given the arguments and
+/// the backend running it, e.g. to write output or call functions it was passed
+pub type BuiltinFunction = fn(Vec<Object>, &mut dyn Context) -> Object;
+
+/// How many arguments a function takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    /// Any number of arguments from this many up
+    AtLeast(usize),
+    /// Any number of arguments from the first number up to the second
+    Between(usize, usize),
+}
+
+impl Arity {
+    /// Whether a call with some number of arguments passes the right number
+    ///
+    /// * `count` - The number of arguments passed
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exactly(want) => count == want,
+            Arity::AtLeast(want) => count >= want,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exactly(want) => write!(f, "{}", want),
+            Arity::AtLeast(want) => write!(f, "at least {}", want),
+            Arity::Between(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+/// A function implemented in Rust
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: BuiltinFunction,
+    pub arity: Arity,
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl PartialEq for Builtin {
+    // Builtins are registered under unique names, and comparing function pointers is unreliable
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// The signature of a function registered by the host application
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object, MonkeyError>;
+
+/// A Rust closure registered by the host application, so scripts can call back into it
+pub struct NativeFunction {
+    pub name: String,
+    pub func: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    /// Call the function, turning any error it returns into a runtime error
+    ///
+    /// * `args` - The arguments to call it with
+    pub fn call(&self, args: &[Object]) -> Result<Object, RuntimeError> {
+        (self.func)(args).map_err(|err| match err {
+            MonkeyError::Runtime(err) => err,
+            err => RuntimeError::new(RuntimeErrorKind::Native, format!("{}: {}", self.name, err)),
+        })
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    // Closures can't be compared, so only the same registration is equal to itself
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// A function wrapped by the `memoize` builtin, which remembers what it returned for each list of
+/// arguments
+pub struct Memoized {
+    pub function: Object,
+    /// Return values, keyed by the `Debug` form of the arguments
+    cache: RefCell<HashMap<String, Object>>,
+}
+
+impl Memoized {
+    /// * `function` - The function to wrap
+    pub fn new(function: Object) -> Memoized {
+        Memoized {
+            function,
+            cache: RefCell::default(),
+        }
+    }
+
+    /// Call the function, unless it's been called with the same arguments before. Errors
+    /// aren't remembered, so a call that failed is made again.
+    ///
+    /// * `args` - The arguments to call it with
+    /// * `ctx` - The backend to call it on
+    pub fn call(&self, args: Vec<Object>, ctx: &mut dyn Context) -> Object {
+        let key = format!("{:?}", args);
+        if let Some(value) = self.cache.borrow().get(&key) {
+            return value.clone();
+        }
+
+        let value = ctx.call(self.function.clone(), args);
+        if !value.is_error() {
+            self.cache.borrow_mut().insert(key, value.clone());
+        }
+        value
+    }
+}
+
+impl fmt::Debug for Memoized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memoized")
+            .field("function", &self.function)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Memoized {
+    // Each call to `memoize` has a cache of its own, so only the same wrapper is equal to itself
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// A function literal along with the environment it was defined in
+pub struct Function {
+    pub parameters: Vec<String>,
+    pub body: BlockStatement,
+    pub env: Env,
+}
+
+impl Function {
+    /// The number of arguments the function takes
+    pub fn arity(&self) -> usize {
+        self.parameters.len()
+    }
+
+    /// The names of the function's parameters, in order
+    pub fn param_names(&self) -> &[String] {
+        &self.parameters
+    }
+}
+
+impl fmt::Debug for Function {
+    // The environment is left out, as a function bound by `let` lives inside its own
+    // environment and would recurse forever
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("parameters", &self.parameters)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Function {
+    // Environments are compared by identity for the same reason they're left out of `Debug`
+    fn eq(&self, other: &Self) -> bool {
+        self.parameters == other.parameters
+            && self.body == other.body
+            && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+/// A function literal compiled to bytecode
+#[derive(Debug, PartialEq)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    pub num_locals: usize,
+    pub num_parameters: usize,
+}
+
+/// A compiled function along with the variables it captured when it was created
+#[derive(Debug, PartialEq)]
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub free: Vec<Object>,
+}
+
+impl Object {
+    /// The name of this object's type, as used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::Null => "NULL",
+            Object::ReturnValue(_) => "RETURN_VALUE",
+            Object::TailCall(..) => "TAIL_CALL",
+            Object::Break => "BREAK",
+            Object::Continue => "CONTINUE",
+            Object::Error(_) => "ERROR",
+            Object::Function(_) => "FUNCTION",
+            Object::Builtin(_) => "BUILTIN",
+            Object::Native(_) => "NATIVE",
+            Object::Memoized(_) => "MEMOIZED",
+            Object::CompiledFunction(_) => "COMPILED_FUNCTION",
+            Object::Closure(_) => "CLOSURE",
+            Object::Quote(_) => "QUOTE",
+            Object::Macro(_) => "MACRO",
+        }
+    }
+
+    /// The key this object is stored under when used as a hash key, or an error object if its
+    /// type can't be used as one
+    pub fn hash_key(&self) -> Result<HashKey, Object> {
+        match self {
+            Object::Integer(value) => Ok(HashKey::Integer(*value)),
+            Object::Boolean(value) => Ok(HashKey::Boolean(*value)),
+            Object::String(value) => Ok(HashKey::String(value.clone())),
+            object => Err(Object::error(
+                RuntimeErrorKind::UnusableHashKey,
+                format!("unusable as hash key: {}", object.type_name()),
+            )),
+        }
+    }
+
+    /// How many arguments a callable object takes, or `None` if it isn't callable or the host
+    /// function it wraps doesn't say
+    pub fn arity(&self) -> Option<Arity> {
+        match self {
+            Object::Function(function) => Some(Arity::Exactly(function.arity())),
+            Object::Builtin(builtin) => Some(builtin.arity),
+            Object::Closure(closure) => Some(Arity::Exactly(closure.function.num_parameters)),
+            Object::Memoized(memoized) => memoized.function.arity(),
+            _ => None,
+        }
+    }
+
+    /// Create an error object
+    ///
+    /// * `kind` - What went wrong
+    /// * `message` - A description of the error
+    pub fn error<S: Into<String>>(kind: RuntimeErrorKind, message: S) -> Object {
+        Object::Error(RuntimeError::new(kind, message))
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Object::Error(_))
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Integer(value) => write!(f, "{}", value),
+            Object::Float(value) => write!(f, "{:?}", value),
+            Object::Boolean(value) => write!(f, "{}", value),
+            Object::String(value) => write!(f, "{}", value),
+            Object::Array(elements) => {
+                let elements = elements.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Object::Hash(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Object::Null => write!(f, "null"),
+            Object::ReturnValue(value) => write!(f, "{}", value),
+            Object::TailCall(..) => write!(f, "tail call"),
+            Object::Break => write!(f, "break"),
+            Object::Continue => write!(f, "continue"),
+            Object::Error(err) => write!(f, "ERROR: {}", err),
+            Object::Function(function) => write!(
+                f,
+                "fn({}) {{\n{}\n}}",
+                function.parameters.join(", "),
+                function.body
+            ),
+            Object::Builtin(_) => write!(f, "builtin function"),
+            Object::Native(native) => write!(f, "native function {}", native.name),
+            Object::Memoized(memoized) => write!(f, "memoized {}", memoized.function),
+            Object::CompiledFunction(_) => write!(f, "compiled function"),
+            Object::Closure(_) => write!(f, "closure"),
+            Object::Quote(node) => write!(f, "QUOTE({})", node),
+            Object::Macro(function) => write!(
+                f,
+                "macro({}) {{\n{}\n}}",
+                function.parameters.join(", "),
+                function.body
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_arity() {
+        let program = Parser::new(Lexer::new("fn(a, b) { a + b }")).parse_program();
+        let function = Evaluator::new().eval(&program, &Environment::new());
+
+        let Object::Function(ref inner) = function else {
+            panic!("expected a function, got {}", function);
+        };
+        assert_eq!(inner.arity(), 2);
+        assert_eq!(inner.param_names(), &["a".to_string(), "b".to_string()]);
+        assert_eq!(function.arity(), Some(Arity::Exactly(2)));
+
+        let format = Object::Builtin(builtins::lookup("format").unwrap());
+        assert_eq!(format.arity(), Some(Arity::AtLeast(1)));
+        assert!(!Arity::AtLeast(1).accepts(0));
+        assert!(Arity::AtLeast(1).accepts(3));
+        assert_eq!(Arity::AtLeast(1).to_string(), "at least 1");
+
+        assert_eq!(
+            Object::Builtin(builtins::lookup("len").unwrap()).arity(),
+            Some(Arity::Exactly(1))
+        );
+        assert_eq!(Object::Integer(1).arity(), None);
+    }
+}
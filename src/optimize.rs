@@ -0,0 +1,369 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::evaluator::{eval_infix_expression, eval_prefix_expression, is_truthy};
+use crate::object::Object;
+use crate::token::Token;
+use std::str::FromStr;
+
+/// How much work to put into optimizing a program before it runs
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Run the program as written
+    #[default]
+    None,
+    /// Fold constant expressions, pick the branch of `if`s with constant conditions and drop
+    /// statements that can't be reached
+    Basic,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OptLevel, String> {
+        match s {
+            "0" => Ok(OptLevel::None),
+            "1" => Ok(OptLevel::Basic),
+            s => Err(format!("unknown optimization level {}, expected 0 or 1", s)),
+        }
+    }
+}
+
+/// Rewrite a program into one that produces the same value with less work. Expressions that
+/// would fail at runtime, like `1 / 0`, are left for the backend to report.
+///
+/// * `program` - The program to optimize, after macro expansion
+/// * `level` - How far to optimize it
+pub fn optimize(program: Program, level: OptLevel) -> Program {
+    match level {
+        OptLevel::None => program,
+        // Statements move between blocks, so their spans are dropped rather than kept in line
+        OptLevel::Basic => Program {
+            statements: statements(program.statements),
+            spans: vec![],
+        },
+    }
+}
+
+fn statements(statements: Vec<Statement>) -> Vec<Statement> {
+    let count = statements.len();
+    let mut optimized = Vec::with_capacity(count);
+
+    for (i, statement) in statements.into_iter().enumerate() {
+        let last = i + 1 == count;
+
+        match statement {
+            Statement::Let(name, value) => optimized.push(Statement::Let(name, expression(value))),
+            Statement::Assign(name, value) => {
+                optimized.push(Statement::Assign(name, expression(value)))
+            }
+            Statement::Return(value) => optimized.push(Statement::Return(value.map(expression))),
+            Statement::Expression(value) => match expression(value) {
+                // Blocks don't open scopes, so the branch taken can stand in for the `if`
+                Expression::If {
+                    condition,
+                    consequence,
+                    alternative,
+                } if decided(&condition).is_some() => {
+                    let taken = if decided(&condition) == Some(true) {
+                        &consequence.statements[..]
+                    } else {
+                        alternative.as_ref().map_or(&[][..], |b| &b.statements)
+                    };
+
+                    // The `if` is the value of the list, so it stays unless the branch ends in
+                    // a value of its own
+                    if !last || ends_in_value(taken) {
+                        optimized.extend(taken.to_vec());
+                    } else {
+                        optimized.push(Statement::Expression(Expression::If {
+                            condition,
+                            consequence,
+                            alternative,
+                        }));
+                    }
+                }
+                value => optimized.push(Statement::Expression(value)),
+            },
+            statement => optimized.push(statement),
+        }
+
+        if optimized.last().is_some_and(terminates) {
+            break;
+        }
+    }
+
+    optimized
+}
+
+fn block(block: BlockStatement) -> BlockStatement {
+    BlockStatement {
+        statements: statements(block.statements),
+        spans: vec![],
+    }
+}
+
+fn expression(expression: Expression) -> Expression {
+    match expression {
+        Expression::Array(elements) => {
+            Expression::Array(elements.into_iter().map(self::expression).collect())
+        }
+        Expression::Index(left, index) => Expression::Index(
+            Box::new(self::expression(*left)),
+            Box::new(self::expression(*index)),
+        ),
+        Expression::Hash(pairs) => Expression::Hash(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (self::expression(key), self::expression(value)))
+                .collect(),
+        ),
+        Expression::Prefix(operator, right) => {
+            let right = self::expression(*right);
+
+            constant(&right)
+                .and_then(|right| literal(eval_prefix_expression(&operator, right)))
+                .unwrap_or_else(|| Expression::Prefix(operator, Box::new(right)))
+        }
+        Expression::Infix(left, operator, right) => {
+            let left = self::expression(*left);
+            let right = self::expression(*right);
+
+            fold_infix(&left, &operator, &right)
+                .unwrap_or_else(|| Expression::Infix(Box::new(left), operator, Box::new(right)))
+        }
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            let condition = self::expression(*condition);
+            let consequence = block(consequence);
+            let alternative = alternative.map(block);
+
+            let taken = match decided(&condition) {
+                Some(true) => Some(&consequence),
+                Some(false) => alternative.as_ref(),
+                None => None,
+            };
+            // Only a branch holding a single expression can be swapped in for the `if`; an
+            // empty one would leave nothing to stand for its null value
+            if let Some([Statement::Expression(value)]) = taken.map(|b| b.statements.as_slice()) {
+                return value.clone();
+            }
+
+            Expression::If {
+                condition: Box::new(condition),
+                consequence,
+                alternative,
+            }
+        }
+        Expression::Conditional {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            let condition = self::expression(*condition);
+            let consequence = self::expression(*consequence);
+            let alternative = self::expression(*alternative);
+
+            match decided(&condition) {
+                Some(true) => consequence,
+                Some(false) => alternative,
+                None => Expression::Conditional {
+                    condition: Box::new(condition),
+                    consequence: Box::new(consequence),
+                    alternative: Box::new(alternative),
+                },
+            }
+        }
+        Expression::While { condition, body } => Expression::While {
+            condition: Box::new(self::expression(*condition)),
+            body: block(body),
+        },
+        Expression::Function { parameters, body } => Expression::Function {
+            parameters,
+            body: block(body),
+        },
+        Expression::Call {
+            function,
+            arguments,
+        } => Expression::Call {
+            function: Box::new(self::expression(*function)),
+            arguments: arguments.into_iter().map(self::expression).collect(),
+        },
+        expression => expression,
+    }
+}
+
+/// Fold an infix expression whose operands are known, or whose left operand alone decides the
+/// result of `&&`, `||` or `??`
+///
+/// * `left` - The optimized left operand
+/// * `operator` - The operator
+/// * `right` - The optimized right operand
+fn fold_infix(left: &Expression, operator: &Token, right: &Expression) -> Option<Expression> {
+    // No literal is null, so the right side of `??` is never needed after one
+    if *operator == Token::Coalesce {
+        return constant(left).map(|_| left.clone());
+    }
+    let left = constant(left)?;
+
+    match (operator, is_truthy(&left)) {
+        (Token::And, false) => Some(Expression::Boolean(false)),
+        (Token::Or, true) => Some(Expression::Boolean(true)),
+        (Token::And | Token::Or, _) => Some(Expression::Boolean(is_truthy(&constant(right)?))),
+        _ => literal(eval_infix_expression(operator, left, constant(right)?)),
+    }
+}
+
+/// The value of a literal expression
+fn constant(expression: &Expression) -> Option<Object> {
+    match expression {
+        Expression::Integer(value) => Some(Object::Integer(*value)),
+        Expression::Float(value) => Some(Object::Float(*value)),
+        Expression::Boolean(value) => Some(Object::Boolean(*value)),
+        Expression::String(value) => Some(Object::String(value.clone())),
+        _ => None,
+    }
+}
+
+/// The literal expression for a value, if it has one. Errors have none, so they're left to
+/// happen at runtime.
+fn literal(object: Object) -> Option<Expression> {
+    match object {
+        Object::Integer(value) => Some(Expression::Integer(value)),
+        Object::Float(value) => Some(Expression::Float(value)),
+        Object::Boolean(value) => Some(Expression::Boolean(value)),
+        Object::String(value) => Some(Expression::String(value)),
+        _ => None,
+    }
+}
+
+/// Whether the value of a condition is known before the program runs, and if so whether it's
+/// truthy
+fn decided(condition: &Expression) -> Option<bool> {
+    constant(condition).map(|value| is_truthy(&value))
+}
+
+fn terminates(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Return(_) | Statement::Break | Statement::Continue
+    )
+}
+
+/// Whether a list of statements produces its value from a statement of its own, rather than
+/// being null because it's empty or ends in a `let`
+fn ends_in_value(statements: &[Statement]) -> bool {
+    matches!(
+        statements.last(),
+        Some(Statement::Expression(_) | Statement::Return(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::script::{run_source, Backend, RunOptions};
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        program
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let tests = [
+            ("1 + 2 * 3", "7"),
+            ("-(4 - 6) * 2.5", "5.0"),
+            ("!(1 < 2) == false", "true"),
+            ("\"mon\" + \"key\"", "\"monkey\""),
+            ("false && x", "false"),
+            ("1 || x", "true"),
+            ("x && true", "x && true"),
+            ("x + 1 + 2", "x + 1 + 2"),
+            ("1 / 0", "1 / 0"),
+            ("9223372036854775807 + 1", "9223372036854775807 + 1"),
+            ("[1 + 1, f(2 * 2)][0]", "[2, f(4)][0]"),
+            ("1 ?? x", "1"),
+            ("x ?? 1 + 1", "x ?? 2"),
+            ("1 < 2 ? a : b", "a"),
+            ("x ? 1 + 1 : b", "x ? 2 : b"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                optimize(parse(input), OptLevel::Basic),
+                parse(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let tests = [
+            ("if (1 > 2) { a } else { b }", "b"),
+            ("let x = if (true) { 1 };", "let x = 1;"),
+            ("if (false) { a; } f(); 1", "f(); 1"),
+            ("if (true) { let y = 2; } 3", "let y = 2; 3"),
+            ("if (true) { let y = 2; }", "if (true) { let y = 2; }"),
+            ("fn() { return 1; f(); }", "fn() { return 1; }"),
+            (
+                "while (x) { if (true) { break; } f(); }",
+                "while (x) { break; }",
+            ),
+            ("if (x) { a } else { b }", "if (x) { a } else { b }"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                optimize(parse(input), OptLevel::Basic),
+                parse(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_result() {
+        let inputs = [
+            "let fib = fn(n) { if (n < 2) { return n; len([]); } fib(n - 1) + fib(n - 2) }; fib(10 + 5)",
+            "let x = 1; if (2 * 3 > 5) { x = x + 10 * 2; } x",
+            "let s = 0; let i = 0; while (i < 5) { i += 1; if (false) { break; } s += i * (1 + 1); } s",
+            "if (true) { let y = 2; }",
+            "if (!true) { 1 }",
+            "[1 / 2.0, \"a\" + \"b\", 3 % 2 == 1 && true]",
+            "let h = {}; [h[1] ?? 2 ?? 3, 1 ?? 2, true ? 1 : 2, 0 > 1 ? 1 : h[0]]",
+        ];
+
+        for input in inputs {
+            for backend in [Backend::Eval, Backend::Vm] {
+                let options = RunOptions {
+                    backend,
+                    ..RunOptions::default()
+                };
+                let optimized = RunOptions {
+                    opt_level: OptLevel::Basic,
+                    ..options.clone()
+                };
+
+                assert_eq!(
+                    run_source(input, &optimized).unwrap(),
+                    run_source(input, &options).unwrap(),
+                    "{} on {:?}",
+                    input,
+                    backend
+                );
+            }
+        }
+
+        assert_eq!("1".parse(), Ok(OptLevel::Basic));
+        assert!("2".parse::<OptLevel>().is_err());
+    }
+}
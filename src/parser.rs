@@ -0,0 +1,1716 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::builtins;
+use crate::lexer::{LexError, Lexer};
+use crate::token::{Position, Span, Spanned, Token, TokenTag};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) enum Precedence {
+    Lowest,
+    Conditional,
+    Coalesce,
+    LogicalOr,
+    LogicalAnd,
+    Equals,
+    LessGreater,
+    Sum,
+    Product,
+    Prefix,
+    Call,
+    Index,
+}
+
+/// How deep expressions can nest, counting the blocks and literals that contain them. Each
+/// level takes up to about 13KB of stack in a debug build, so this keeps well clear of
+/// overflowing even a 2MB thread.
+pub const MAX_NESTING: usize = 128;
+
+/// The precedence a token has when it appears as an infix operator
+///
+/// * `token` - The token to look up
+pub(crate) fn precedence_of(token: &Token) -> Precedence {
+    match token {
+        Token::Question => Precedence::Conditional,
+        Token::Coalesce => Precedence::Coalesce,
+        Token::Or => Precedence::LogicalOr,
+        Token::And => Precedence::LogicalAnd,
+        Token::Equal | Token::NotEqual => Precedence::Equals,
+        Token::Lt | Token::Gt | Token::LtEq | Token::GtEq => Precedence::LessGreater,
+        Token::Plus | Token::Minus => Precedence::Sum,
+        Token::Asterisk | Token::Slash | Token::Percent => Precedence::Product,
+        Token::Lparen => Precedence::Call,
+        Token::Lbracket => Precedence::Index,
+        _ => Precedence::Lowest,
+    }
+}
+
+/// Wrap the value bound to a name in a check of its type, if the name was annotated with one
+///
+/// * `value` - The value being bound
+/// * `annotation` - The name of the type it should have
+fn annotated(value: Expression, annotation: Option<String>) -> Expression {
+    match annotation {
+        Some(type_name) => Expression::Annotated {
+            value: Box::new(value),
+            type_name,
+        },
+        None => value,
+    }
+}
+
+/// The kinds of problem found while parsing
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// A token other than the one the grammar requires next
+    UnexpectedToken,
+    /// A token that can't start an expression
+    NoPrefixParse,
+    /// An integer literal that doesn't fit in an `i64`
+    InvalidInteger,
+    /// A float literal that isn't a valid `f64`
+    InvalidFloat,
+    /// Input the lexer couldn't turn into a token
+    Lex(LexError),
+    /// An expression nested more than `MAX_NESTING` deep
+    TooDeep,
+}
+
+/// A problem found while parsing, along with the token it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub message: String,
+    pub span: Span,
+    pub position: Position,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ParseErrorKind::Lex(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Something suspect the parser found in a program it could still parse
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    pub message: String,
+    pub span: Span,
+    pub position: Position,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+/// Something the parser did, recorded when tracing
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseEvent {
+    /// Moved on to the next token
+    Token {
+        token: Token<'static>,
+        span: Span,
+        position: Position,
+    },
+    /// Started on a rule of the grammar, named after the method that parses it
+    Enter { rule: &'static str },
+    /// Finished a rule, whether or not it parsed
+    Exit { rule: &'static str, parsed: bool },
+}
+
+/// A function or macro parameter as written, before any annotation is desugared
+struct Parameter {
+    name: String,
+    annotation: Option<String>,
+    span: Span,
+}
+
+/// Where the parser reads its tokens from
+#[derive(Debug)]
+enum Source<'a> {
+    Lexer(Lexer<'a>),
+    /// Tokens made without a lexer, along with the number of the next one
+    Tokens(std::vec::IntoIter<Token<'a>>, usize),
+}
+
+impl<'a> Source<'a> {
+    /// Read the next token. Tokens made without a lexer are numbered in place of their offset
+    /// and column in a source, and end in as many `Eof` tokens as are read.
+    fn next(&mut self) -> Spanned<Result<Token<'a>, LexError>> {
+        match self {
+            Source::Lexer(lexer) => lexer.try_next_positioned(),
+            Source::Tokens(tokens, index) => {
+                let token = tokens.next().unwrap_or(Token::Eof);
+                let spanned = Spanned {
+                    node: Ok(token),
+                    span: Span {
+                        start: *index,
+                        end: *index + 1,
+                    },
+                    position: Position {
+                        line: 1,
+                        column: *index + 1,
+                    },
+                };
+                *index += 1;
+                spanned
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Parser<'a> {
+    source: Source<'a>,
+    cur_token: Token<'a>,
+    cur_span: Span,
+    cur_position: Position,
+    peek_token: Token<'a>,
+    peek_span: Span,
+    peek_position: Position,
+    errors: Vec<ParseError>,
+    warnings: Vec<ParseWarning>,
+    /// Comments read but not yet placed in the program, if the lexer produces them
+    comments: Vec<(Span, String)>,
+    /// What the parser has done, if it's tracing
+    trace: Option<Vec<ParseEvent>>,
+    /// How many expressions deep the parser is
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Parser<'a> {
+        Parser::with_source(Source::Lexer(lexer))
+    }
+
+    /// Create a parser for tokens made some other way than by lexing a source, e.g. built by
+    /// hand or filtered from a lexer's output. Errors give the number of the token they were
+    /// found at in place of a column.
+    ///
+    /// * `tokens` - The tokens to parse, which needn't end in `Eof`
+    pub fn from_tokens(tokens: Vec<Token<'a>>) -> Parser<'a> {
+        Parser::with_source(Source::Tokens(tokens.into_iter(), 0))
+    }
+
+    fn with_source(source: Source<'a>) -> Parser<'a> {
+        let mut p = Parser {
+            source,
+            cur_token: Token::Eof,
+            cur_span: Span { start: 0, end: 0 },
+            cur_position: Position { line: 1, column: 1 },
+            peek_token: Token::Eof,
+            peek_span: Span { start: 0, end: 0 },
+            peek_position: Position { line: 1, column: 1 },
+            errors: vec![],
+            warnings: vec![],
+            comments: vec![],
+            trace: None,
+            depth: 0,
+        };
+
+        p.next_token();
+        p.next_token();
+        p
+    }
+
+    /// Start parsing a new input, reusing this parser's lexer, if it has one, and clearing any
+    /// errors
+    ///
+    /// * `input` - The new source to parse
+    pub fn reset(&mut self, input: &'a str) {
+        match &mut self.source {
+            Source::Lexer(lexer) => lexer.reset(input),
+            source => *source = Source::Lexer(Lexer::new(input)),
+        }
+        self.errors.clear();
+        self.warnings.clear();
+        self.comments.clear();
+
+        self.next_token();
+        self.next_token();
+    }
+
+    /// The errors encountered so far. Statements with errors are left out of the program.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// The warnings found so far, about code that parsed but is probably a mistake
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
+    /// Record each token the parser moves on to and each rule it enters and exits, starting
+    /// with the current token
+    pub fn with_trace(mut self) -> Parser<'a> {
+        self.trace = Some(vec![]);
+        self.trace_token();
+        self
+    }
+
+    /// Take what the parser has done since tracing started or this was last called
+    pub fn take_trace(&mut self) -> Vec<ParseEvent> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn parse_program(&mut self) -> Program {
+        let (statements, spans) = self.parse_statements(false);
+        Program { statements, spans }
+    }
+
+    /// Parse statements, along with the comments between them, until the end of the input or,
+    /// in a block, its closing brace. Each statement's span runs from its first token to its
+    /// last.
+    ///
+    /// * `block` - Whether the statements are a block's
+    fn parse_statements(&mut self, block: bool) -> (Vec<Statement>, Vec<Span>) {
+        let mut statements = vec![];
+        let mut spans = vec![];
+
+        while self.cur_token != Token::Eof && !(block && self.cur_token == Token::Rbrace) {
+            self.take_comments(self.cur_span.start, &mut statements, &mut spans);
+
+            let start = self.cur_span;
+            if let Some(statement) = self.parse_statement() {
+                statements.push(statement);
+                spans.push(start.merge(&self.cur_span));
+            }
+            self.next_token();
+        }
+        // A block keeps the comments before its closing brace, the program all that are left
+        let end = if block {
+            self.cur_span.start
+        } else {
+            usize::MAX
+        };
+        self.take_comments(end, &mut statements, &mut spans);
+
+        (statements, spans)
+    }
+
+    fn next_token(&mut self) {
+        // Comments are set aside, to be placed between statements by `take_comments`
+        let next = loop {
+            let next = self.source.next();
+            match next.node {
+                Ok(Token::Comment(text)) => self.comments.push((next.span, text.into_owned())),
+                _ => break next,
+            }
+        };
+        // Input that can't be lexed is reported here, then parsed on as an illegal token
+        let token = match next.node {
+            Ok(token) => token,
+            Err(err) => {
+                self.errors.push(ParseError {
+                    kind: ParseErrorKind::Lex(err.clone()),
+                    message: err.to_string(),
+                    span: next.span,
+                    position: next.position,
+                });
+                err.into_illegal()
+            }
+        };
+
+        self.cur_token = std::mem::replace(&mut self.peek_token, token);
+        self.cur_span = std::mem::replace(&mut self.peek_span, next.span);
+        self.cur_position = std::mem::replace(&mut self.peek_position, next.position);
+        self.trace_token();
+    }
+
+    fn trace_token(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(ParseEvent::Token {
+                token: self.cur_token.clone().into_owned(),
+                span: self.cur_span,
+                position: self.cur_position,
+            });
+        }
+    }
+
+    /// Parse a rule of the grammar, recording when it's entered and exited if tracing
+    ///
+    /// * `rule` - The name of the rule
+    /// * `parse` - The method that parses it
+    fn rule<T>(
+        &mut self,
+        rule: &'static str,
+        parse: impl FnOnce(&mut Self) -> Option<T>,
+    ) -> Option<T> {
+        let Some(trace) = &mut self.trace else {
+            return parse(self);
+        };
+        trace.push(ParseEvent::Enter { rule });
+
+        let parsed = parse(self);
+        if let Some(trace) = &mut self.trace {
+            trace.push(ParseEvent::Exit {
+                rule,
+                parsed: parsed.is_some(),
+            });
+        }
+        parsed
+    }
+
+    /// Record an error at the current token
+    ///
+    /// * `kind` - The kind of error
+    /// * `message` - What went wrong
+    fn error_at_cur(&mut self, kind: ParseErrorKind, message: String) {
+        self.errors.push(ParseError {
+            kind,
+            message,
+            span: self.cur_span,
+            position: self.cur_position,
+        });
+    }
+
+    /// Record an error at the peek token
+    ///
+    /// * `kind` - The kind of error
+    /// * `message` - What went wrong
+    fn error_at_peek(&mut self, kind: ParseErrorKind, message: String) {
+        self.errors.push(ParseError {
+            kind,
+            message,
+            span: self.peek_span,
+            position: self.peek_position,
+        });
+    }
+
+    /// Record a warning at the peek token
+    ///
+    /// * `message` - What looks wrong
+    fn warn_at_peek(&mut self, message: String) {
+        self.warnings.push(ParseWarning {
+            message,
+            span: self.peek_span,
+            position: self.peek_position,
+        });
+    }
+
+    fn parse_statement(&mut self) -> Option<Statement> {
+        match self.cur_token {
+            Token::Let => self.rule("let_statement", Self::parse_let_statement),
+            Token::Return => self.rule("return_statement", Self::parse_return_statement),
+            Token::Ident(_)
+                if matches!(
+                    self.peek_token,
+                    Token::Assign
+                        | Token::PlusAssign
+                        | Token::MinusAssign
+                        | Token::AsteriskAssign
+                        | Token::SlashAssign
+                ) =>
+            {
+                self.rule("assign_statement", Self::parse_assign_statement)
+            }
+            Token::Break => {
+                self.skip_semicolon();
+                Some(Statement::Break)
+            }
+            Token::Continue => {
+                self.skip_semicolon();
+                Some(Statement::Continue)
+            }
+            _ => self.rule("expression_statement", Self::parse_expression_statement),
+        }
+    }
+
+    /// Parse a `let` statement. `let add(x, y) { x + y }` is shorthand for
+    /// `let add = fn(x, y) { x + y };`.
+    fn parse_let_statement(&mut self) -> Option<Statement> {
+        if let Token::Ident(name) = &self.peek_token {
+            if builtins::lookup(name).is_some() {
+                self.warn_at_peek(format!("`{}` shadows the builtin of the same name", name));
+            }
+        }
+        self.expect_peek(TokenTag::Ident)?;
+        let name = self.cur_token.to_string();
+
+        if self.peek_token == Token::Lparen {
+            let value = self.parse_function_literal()?;
+            self.skip_semicolon();
+            return Some(Statement::Let(name, value));
+        }
+
+        let annotation = self.parse_annotation()?;
+
+        self.expect_peek(TokenTag::Assign)?;
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+        self.skip_semicolon();
+
+        Some(Statement::Let(name, annotated(value, annotation)))
+    }
+
+    /// Parse the type annotation after a name being bound, as in `let x: int` or `fn(x: int)`,
+    /// if it has one
+    fn parse_annotation(&mut self) -> Option<Option<String>> {
+        if self.peek_token != Token::Colon {
+            return Some(None);
+        }
+        self.next_token();
+
+        // `fn` is a keyword, but names the type of functions here
+        if self.peek_token == Token::Function {
+            self.next_token();
+            return Some(Some("fn".into()));
+        }
+        self.expect_peek(TokenTag::Ident)?;
+        Some(Some(self.cur_token.to_string()))
+    }
+
+    /// Parse an assignment. Compound assignments like `x += 1` are desugared into plain ones
+    /// like `x = x + 1`.
+    fn parse_assign_statement(&mut self) -> Option<Statement> {
+        let name = self.cur_token.to_string();
+        self.next_token();
+
+        let operator = match self.cur_token {
+            Token::PlusAssign => Some(Token::Plus),
+            Token::MinusAssign => Some(Token::Minus),
+            Token::AsteriskAssign => Some(Token::Asterisk),
+            Token::SlashAssign => Some(Token::Slash),
+            _ => None,
+        };
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+        self.skip_semicolon();
+
+        let value = match operator {
+            Some(operator) => Expression::Infix(
+                Box::new(Expression::Identifier(name.clone())),
+                operator,
+                Box::new(value),
+            ),
+            None => value,
+        };
+
+        Some(Statement::Assign(name, value))
+    }
+
+    fn parse_return_statement(&mut self) -> Option<Statement> {
+        if self.peek_token == Token::Semicolon {
+            self.next_token();
+            return Some(Statement::Return(None));
+        }
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+        self.skip_semicolon();
+
+        Some(Statement::Return(Some(value)))
+    }
+
+    fn parse_expression_statement(&mut self) -> Option<Statement> {
+        let expression = self.parse_expression(Precedence::Lowest)?;
+        self.skip_semicolon();
+
+        Some(Statement::Expression(expression))
+    }
+
+    fn parse_block_statement(&mut self) -> BlockStatement {
+        self.rule("block_statement", |p| Some(p.parse_block()))
+            .expect("blocks always parse")
+    }
+
+    fn parse_block(&mut self) -> BlockStatement {
+        self.next_token();
+
+        let (statements, spans) = self.parse_statements(true);
+        BlockStatement { statements, spans }
+    }
+
+    /// Turn the comments that start before an offset into statements. Comments inside a
+    /// statement end up after it, as only statements can hold them.
+    ///
+    /// * `end` - The offset to take comments up to, usually the start of the current token
+    /// * `statements` - Where to add the comments
+    /// * `spans` - Where to add their spans
+    fn take_comments(
+        &mut self,
+        end: usize,
+        statements: &mut Vec<Statement>,
+        spans: &mut Vec<Span>,
+    ) {
+        let count = self
+            .comments
+            .iter()
+            .take_while(|(span, _)| span.start < end)
+            .count();
+
+        for (span, text) in self.comments.drain(..count) {
+            statements.push(Statement::Comment(text));
+            spans.push(span);
+        }
+    }
+
+    /// Parse an expression, consuming infix operators for as long as they bind tighter than
+    /// `precedence`, unless it's nested too deep to parse without overflowing the stack
+    ///
+    /// * `precedence` - The precedence of the operator to the left of this expression
+    fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if self.depth >= MAX_NESTING {
+            let message = format!("expressions nested more than {} deep", MAX_NESTING);
+            self.error_at_cur(ParseErrorKind::TooDeep, message);
+            return None;
+        }
+
+        self.depth += 1;
+        let expression = self.parse_nested_expression(precedence);
+        self.depth -= 1;
+        expression
+    }
+
+    /// Parse an expression like `parse_expression`, once it's known not to be nested too deep
+    ///
+    /// * `precedence` - The precedence of the operator to the left of this expression
+    fn parse_nested_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        let mut left = match &self.cur_token {
+            Token::Ident(name) => Expression::Identifier(name.to_string()),
+            Token::Int(_) => self.rule("integer_literal", Self::parse_integer_literal)?,
+            Token::Float(_) => self.rule("float_literal", Self::parse_float_literal)?,
+            Token::String(_) => self.rule("string_literal", Self::parse_string_literal)?,
+            Token::True => Expression::Boolean(true),
+            Token::False => Expression::Boolean(false),
+            Token::Bang | Token::Minus => {
+                self.rule("prefix_expression", Self::parse_prefix_expression)?
+            }
+            Token::Lparen => self.rule("grouped_expression", Self::parse_grouped_expression)?,
+            Token::Lbracket => self.rule("array_literal", |p| {
+                p.parse_expression_list(TokenTag::Rbracket)
+                    .map(Expression::Array)
+            })?,
+            Token::Lbrace => self.rule("hash_literal", Self::parse_hash_literal)?,
+            Token::If => self.rule("if_expression", Self::parse_if_expression)?,
+            Token::While => self.rule("while_expression", Self::parse_while_expression)?,
+            Token::Function => self.rule("function_literal", Self::parse_function_literal)?,
+            Token::Macro => self.rule("macro_literal", Self::parse_macro_literal)?,
+            // Already reported when it was lexed
+            Token::Illegal(_) => return None,
+            token => {
+                let message = format!("no prefix parse function for {} found", token);
+                self.error_at_cur(ParseErrorKind::NoPrefixParse, message);
+                return None;
+            }
+        };
+
+        while self.peek_token != Token::Semicolon && precedence < precedence_of(&self.peek_token) {
+            self.next_token();
+
+            left = match self.cur_token {
+                Token::Lparen => self.rule("call_expression", |p| p.parse_call_expression(left))?,
+                Token::Lbracket => {
+                    self.rule("index_expression", |p| p.parse_index_expression(left))?
+                }
+                Token::Question => self.rule("conditional_expression", |p| {
+                    p.parse_conditional_expression(left)
+                })?,
+                _ => self.rule("infix_expression", |p| p.parse_infix_expression(left))?,
+            };
+        }
+
+        Some(left)
+    }
+
+    fn parse_integer_literal(&mut self) -> Option<Expression> {
+        let literal = self.cur_token.to_string();
+
+        match literal.parse() {
+            Ok(value) => Some(Expression::Integer(value)),
+            Err(_) => {
+                let message = format!("could not parse {} as integer", literal);
+                self.error_at_cur(ParseErrorKind::InvalidInteger, message);
+                None
+            }
+        }
+    }
+
+    fn parse_float_literal(&mut self) -> Option<Expression> {
+        let literal = self.cur_token.to_string();
+
+        match literal.parse() {
+            Ok(value) => Some(Expression::Float(value)),
+            Err(_) => {
+                let message = format!("could not parse {} as float", literal);
+                self.error_at_cur(ParseErrorKind::InvalidFloat, message);
+                None
+            }
+        }
+    }
+
+    /// Parse a string literal, desugaring any interpolations into concatenation. Each
+    /// interpolated expression becomes a `$` prefix expression, which stringifies its value.
+    fn parse_string_literal(&mut self) -> Option<Expression> {
+        let text = |token: &Token| match token {
+            Token::String(value) => Expression::String(value.to_string()),
+            token => unreachable!("expected a string, got {}", token),
+        };
+        let mut parts = vec![text(&self.cur_token)];
+
+        while self.peek_token == Token::Dollar {
+            self.next_token();
+            self.expect_peek(TokenTag::Lbrace)?;
+            self.next_token();
+
+            let value = self.parse_expression(Precedence::Lowest)?;
+            parts.push(Expression::Prefix(Token::Dollar, Box::new(value)));
+
+            self.expect_peek(TokenTag::Rbrace)?;
+            self.expect_peek(TokenTag::String)?;
+            parts.push(text(&self.cur_token));
+        }
+
+        if parts.len() == 1 {
+            return parts.pop();
+        }
+
+        parts
+            .into_iter()
+            .filter(|part| *part != Expression::String(String::new()))
+            .reduce(|left, right| Expression::Infix(Box::new(left), Token::Plus, Box::new(right)))
+    }
+
+    fn parse_prefix_expression(&mut self) -> Option<Expression> {
+        let operator = self.cur_token.clone().into_owned();
+        self.next_token();
+
+        let right = self.parse_expression(Precedence::Prefix)?;
+
+        Some(Expression::Prefix(operator, Box::new(right)))
+    }
+
+    fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
+        let operator = self.cur_token.clone().into_owned();
+        let precedence = precedence_of(&operator);
+        self.next_token();
+
+        let right = self.parse_expression(precedence)?;
+
+        Some(Expression::Infix(Box::new(left), operator, Box::new(right)))
+    }
+
+    fn parse_conditional_expression(&mut self, condition: Expression) -> Option<Expression> {
+        self.next_token();
+        let consequence = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenTag::Colon)?;
+        self.next_token();
+
+        // Parsing the alternative at the lowest precedence makes `a ? b : c ? d : e` group
+        // to the right
+        let alternative = self.parse_expression(Precedence::Lowest)?;
+
+        Some(Expression::Conditional {
+            condition: Box::new(condition),
+            consequence: Box::new(consequence),
+            alternative: Box::new(alternative),
+        })
+    }
+
+    fn parse_grouped_expression(&mut self) -> Option<Expression> {
+        self.next_token();
+
+        let expression = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenTag::Rparen)?;
+
+        Some(expression)
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Expression> {
+        let mut pairs = vec![];
+
+        while self.peek_token != Token::Rbrace {
+            self.next_token();
+            let key = self.parse_expression(Precedence::Lowest)?;
+
+            self.expect_peek(TokenTag::Colon)?;
+            self.next_token();
+            let value = self.parse_expression(Precedence::Lowest)?;
+
+            pairs.push((key, value));
+
+            if self.peek_token != Token::Rbrace {
+                self.expect_peek(TokenTag::Comma)?;
+            }
+        }
+
+        self.expect_peek(TokenTag::Rbrace)?;
+        Some(Expression::Hash(pairs))
+    }
+
+    fn parse_if_expression(&mut self) -> Option<Expression> {
+        let condition = self.parse_condition()?;
+
+        let consequence = self.parse_block_statement();
+        let alternative = if self.peek_token == Token::Else {
+            self.next_token();
+
+            // `else if` chains on an alternative holding just the next `if`, which is what it
+            // would be if written out in braces
+            if self.peek_token == Token::If {
+                self.next_token();
+                let start = self.cur_span;
+                let elif = self.rule("if_expression", Self::parse_if_expression)?;
+                Some(BlockStatement {
+                    statements: vec![Statement::Expression(elif)],
+                    spans: vec![start.merge(&self.cur_span)],
+                })
+            } else {
+                self.expect_peek(TokenTag::Lbrace)?;
+                Some(self.parse_block_statement())
+            }
+        } else {
+            None
+        };
+
+        Some(Expression::If {
+            condition: Box::new(condition),
+            consequence,
+            alternative,
+        })
+    }
+
+    fn parse_while_expression(&mut self) -> Option<Expression> {
+        let condition = self.parse_condition()?;
+
+        Some(Expression::While {
+            condition: Box::new(condition),
+            body: self.parse_block_statement(),
+        })
+    }
+
+    /// Parse the parenthesized condition of an `if` or `while`, up to the opening brace of its
+    /// body. There are no assignment expressions, so `=` in a condition can only be a mistyped
+    /// `==`, which is warned about on top of the error it still is.
+    fn parse_condition(&mut self) -> Option<Expression> {
+        self.expect_peek(TokenTag::Lparen)?;
+        self.next_token();
+
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        if self.peek_token == Token::Assign {
+            self.warn_at_peek("assignment in a condition, did you mean `==`?".into());
+        }
+
+        self.expect_peek(TokenTag::Rparen)?;
+        self.expect_peek(TokenTag::Lbrace)?;
+        Some(condition)
+    }
+
+    fn parse_function_literal(&mut self) -> Option<Expression> {
+        let (parameters, body) = self.parse_parameters_and_body()?;
+        Some(Expression::Function { parameters, body })
+    }
+
+    fn parse_macro_literal(&mut self) -> Option<Expression> {
+        let (parameters, body) = self.parse_parameters_and_body()?;
+        Some(Expression::Macro { parameters, body })
+    }
+
+    /// Parse the parameter list and body shared by function and macro literals. Annotated
+    /// parameters are desugared into annotated bindings at the start of the body, so
+    /// `fn(x: int) { x }` is `fn(x) { let x: int = x; x }`.
+    fn parse_parameters_and_body(&mut self) -> Option<(Vec<String>, BlockStatement)> {
+        self.expect_peek(TokenTag::Lparen)?;
+        let parameters = self.parse_function_parameters()?;
+
+        self.expect_peek(TokenTag::Lbrace)?;
+        let mut body = self.parse_block_statement();
+
+        let (checks, spans): (Vec<_>, Vec<_>) = parameters
+            .iter()
+            .filter_map(|parameter| {
+                let value = Expression::Identifier(parameter.name.clone());
+                let annotation = Some(parameter.annotation.clone()?);
+                let check = Statement::Let(parameter.name.clone(), annotated(value, annotation));
+                Some((check, parameter.span))
+            })
+            .unzip();
+        body.statements.splice(0..0, checks);
+        body.spans.splice(0..0, spans);
+
+        let parameters = parameters
+            .into_iter()
+            .map(|parameter| parameter.name)
+            .collect();
+        Some((parameters, body))
+    }
+
+    fn parse_function_parameters(&mut self) -> Option<Vec<Parameter>> {
+        let mut parameters = vec![];
+
+        if self.peek_token == Token::Rparen {
+            self.next_token();
+            return Some(parameters);
+        }
+
+        self.expect_peek(TokenTag::Ident)?;
+        parameters.push(self.parse_parameter()?);
+
+        while self.peek_token == Token::Comma {
+            self.next_token();
+            self.expect_peek(TokenTag::Ident)?;
+            parameters.push(self.parse_parameter()?);
+        }
+
+        self.expect_peek(TokenTag::Rparen)?;
+        Some(parameters)
+    }
+
+    /// Parse a parameter name and its type annotation, if it has one
+    fn parse_parameter(&mut self) -> Option<Parameter> {
+        let name = self.cur_token.to_string();
+        let start = self.cur_span;
+        let annotation = self.parse_annotation()?;
+
+        Some(Parameter {
+            name,
+            annotation,
+            span: start.merge(&self.cur_span),
+        })
+    }
+
+    fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+        let arguments = self.parse_expression_list(TokenTag::Rparen)?;
+
+        Some(Expression::Call {
+            function: Box::new(function),
+            arguments,
+        })
+    }
+
+    fn parse_index_expression(&mut self, left: Expression) -> Option<Expression> {
+        self.next_token();
+
+        let index = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenTag::Rbracket)?;
+
+        Some(Expression::Index(Box::new(left), Box::new(index)))
+    }
+
+    /// Parse a comma separated list of expressions, such as call arguments or array elements
+    ///
+    /// * `end` - The kind of token closing the list
+    fn parse_expression_list(&mut self, end: TokenTag) -> Option<Vec<Expression>> {
+        let mut list = vec![];
+
+        if self.peek_token.tag() == end {
+            self.next_token();
+            return Some(list);
+        }
+
+        self.next_token();
+        list.push(self.parse_expression(Precedence::Lowest)?);
+
+        while self.peek_token == Token::Comma {
+            self.next_token();
+            self.next_token();
+            list.push(self.parse_expression(Precedence::Lowest)?);
+        }
+
+        self.expect_peek(end)?;
+        Some(list)
+    }
+
+    /// Advance past the peek token if it's of the expected kind, otherwise record an error
+    ///
+    /// * `tag` - The kind of token expected next
+    fn expect_peek(&mut self, tag: TokenTag) -> Option<()> {
+        if self.peek_token.tag() == tag {
+            self.next_token();
+            Some(())
+        } else {
+            let message = format!(
+                "expected next token to be {:?}, got {} instead",
+                tag, self.peek_token
+            );
+            self.error_at_peek(ParseErrorKind::UnexpectedToken, message);
+            None
+        }
+    }
+
+    /// Advance past an optional trailing semicolon
+    fn skip_semicolon(&mut self) {
+        if self.peek_token == Token::Semicolon {
+            self.next_token();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors(), &[], "input: {}", input);
+        program
+    }
+
+    fn parse_expression(input: &str) -> Expression {
+        let mut program = parse(input);
+        assert_eq!(program.statements.len(), 1);
+
+        match program.statements.remove(0) {
+            Statement::Expression(expression) => expression,
+            statement => panic!("expected an expression statement, got {:?}", statement),
+        }
+    }
+
+    fn ident(name: &str) -> Box<Expression> {
+        Box::new(Expression::Identifier(name.into()))
+    }
+
+    fn int(value: i64) -> Box<Expression> {
+        Box::new(Expression::Integer(value))
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let program = parse(
+            "let x = 5;
+let y = true;
+let foobar = y;",
+        );
+
+        assert_eq!(
+            program.statements,
+            vec![
+                Statement::Let("x".into(), Expression::Integer(5)),
+                Statement::Let("y".into(), Expression::Boolean(true)),
+                Statement::Let("foobar".into(), Expression::Identifier("y".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_let_function_shorthand() {
+        let shorthand = parse("let add(x, y: int) { x + y } let one() { 1 }; add(one(), 2)");
+        let longhand =
+            parse("let add = fn(x, y: int) { x + y }; let one = fn() { 1 }; add(one(), 2)");
+
+        assert_eq!(shorthand, longhand);
+    }
+
+    #[test]
+    fn test_let_statement_errors() {
+        let mut parser = Parser::new(Lexer::new("let x 5; let = 10; let 838383;"));
+        parser.parse_program();
+
+        let messages = parser
+            .errors()
+            .iter()
+            .map(|err| err.message.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            messages,
+            &[
+                "expected next token to be Assign, got 5 instead",
+                "expected next token to be Ident, got = instead",
+                "no prefix parse function for = found",
+                "expected next token to be Ident, got 838383 instead",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_statement_spans() {
+        let input = "let x = 1;\nif (x) {\n  puts(x);\n  x\n}";
+        let program = parse(input);
+        let text = |span: &Span| {
+            input
+                .chars()
+                .skip(span.start)
+                .take(span.end - span.start)
+                .collect::<String>()
+        };
+
+        let spans: Vec<_> = program.spans.iter().map(text).collect();
+        assert_eq!(spans, vec!["let x = 1;", "if (x) {\n  puts(x);\n  x\n}"]);
+
+        let Statement::Expression(Expression::If { consequence, .. }) = &program.statements[1]
+        else {
+            panic!("expected an if, got {}", program.statements[1]);
+        };
+        let spans: Vec<_> = consequence.spans.iter().map(text).collect();
+        assert_eq!(spans, vec!["puts(x);", "x"]);
+    }
+
+    #[test]
+    fn test_from_tokens() {
+        let mut parser = Parser::from_tokens(vec![
+            Token::Let,
+            Token::Ident("x".into()),
+            Token::Assign,
+            Token::Int("5".into()),
+            Token::Plus,
+            Token::Ident("y".into()),
+            Token::Semicolon,
+        ]);
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors(), &[]);
+        assert_eq!(
+            program.statements,
+            vec![Statement::Let(
+                "x".into(),
+                Expression::Infix(int(5), Token::Plus, ident("y"))
+            )]
+        );
+
+        let mut parser = Parser::from_tokens(vec![Token::Let, Token::Assign]);
+        parser.parse_program();
+        assert_eq!(
+            parser.errors()[0].to_string(),
+            "line 1, column 2: expected next token to be Ident, got = instead"
+        );
+
+        parser.reset("let y = 1;");
+        assert_eq!(parser.parse_program().to_string(), "let y = 1;");
+    }
+
+    #[test]
+    fn test_assign_statements() {
+        let program = parse("x = 5; y += x * 2; z /= 2");
+
+        assert_eq!(
+            program.statements,
+            vec![
+                Statement::Assign("x".into(), Expression::Integer(5)),
+                Statement::Assign(
+                    "y".into(),
+                    Expression::Infix(
+                        ident("y"),
+                        Token::Plus,
+                        Box::new(Expression::Infix(ident("x"), Token::Asterisk, int(2))),
+                    ),
+                ),
+                Statement::Assign(
+                    "z".into(),
+                    Expression::Infix(ident("z"), Token::Slash, int(2))
+                ),
+            ]
+        );
+        assert_eq!(program.to_string(), "x = 5;y = (y + (x * 2));z = (z / 2);");
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let program = parse("return 5; return foobar; return;");
+
+        assert_eq!(
+            program.statements,
+            vec![
+                Statement::Return(Some(Expression::Integer(5))),
+                Statement::Return(Some(Expression::Identifier("foobar".into()))),
+                Statement::Return(None),
+            ]
+        );
+        assert_eq!(program.to_string(), "return 5;return foobar;return;");
+
+        let mut parser = Parser::new(Lexer::new("fn() { return"));
+        parser.parse_program();
+        assert_eq!(
+            parser.errors()[0].message,
+            "no prefix parse function for <eof> found"
+        );
+    }
+
+    #[test]
+    fn test_literal_expressions() {
+        assert_eq!(parse_expression("foobar;"), *ident("foobar"));
+        assert_eq!(parse_expression("5;"), *int(5));
+        assert_eq!(parse_expression("2.5;"), Expression::Float(2.5));
+        assert_eq!(parse_expression("false;"), Expression::Boolean(false));
+        assert_eq!(
+            parse_expression("\"hello world\";"),
+            Expression::String("hello world".into())
+        );
+    }
+
+    #[test]
+    fn test_prefix_expressions() {
+        assert_eq!(
+            parse_expression("!5;"),
+            Expression::Prefix(Token::Bang, int(5))
+        );
+        assert_eq!(
+            parse_expression("-foobar;"),
+            Expression::Prefix(Token::Minus, ident("foobar"))
+        );
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let string = |value: &str| Box::new(Expression::String(value.into()));
+        let interpolated = |value| Box::new(Expression::Prefix(Token::Dollar, value));
+
+        assert_eq!(
+            parse_expression("\"Hello, ${name}!\""),
+            Expression::Infix(
+                Box::new(Expression::Infix(
+                    string("Hello, "),
+                    Token::Plus,
+                    interpolated(ident("name"))
+                )),
+                Token::Plus,
+                string("!")
+            )
+        );
+        assert_eq!(
+            parse_expression("\"${a}${1 + 2}\""),
+            Expression::Infix(
+                interpolated(ident("a")),
+                Token::Plus,
+                interpolated(Box::new(Expression::Infix(int(1), Token::Plus, int(2))))
+            )
+        );
+        assert_eq!(parse_expression("\"${a}\""), *interpolated(ident("a")));
+        assert_eq!(
+            parse_expression("\"${a}\"[0]"),
+            Expression::Index(interpolated(ident("a")), int(0))
+        );
+
+        for input in ["\"${}\"", "\"${a b}\"", "\"${a\""] {
+            let mut parser = Parser::new(Lexer::new(input));
+            parser.parse_program();
+            assert!(!parser.errors().is_empty(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_infix_expressions() {
+        let operators = [
+            Token::Plus,
+            Token::Minus,
+            Token::Asterisk,
+            Token::Slash,
+            Token::Gt,
+            Token::Lt,
+            Token::Equal,
+            Token::NotEqual,
+        ];
+
+        for operator in operators {
+            assert_eq!(
+                parse_expression(&format!("5 {} 5;", operator)),
+                Expression::Infix(int(5), operator, int(5))
+            );
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let tests = [
+            ("-a * b", "((-a) * b)"),
+            ("!-a", "(!(-a))"),
+            ("a + b + c", "((a + b) + c)"),
+            ("a + b - c", "((a + b) - c)"),
+            ("a * b * c", "((a * b) * c)"),
+            ("a * b / c", "((a * b) / c)"),
+            ("a + b / c", "(a + (b / c))"),
+            ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+            ("3 + 4; -5 * 5", "(3 + 4)((-5) * 5)"),
+            ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+            ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+            ("a <= b == c >= d", "((a <= b) == (c >= d))"),
+            ("a + b % c * d", "(a + ((b % c) * d))"),
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b || c", "((a && b) || c)"),
+            ("a == b && !c", "((a == b) && (!c))"),
+            ("a ?? b || c ?? d", "((a ?? (b || c)) ?? d)"),
+            ("a || b ? c + 1 : d ?? e", "((a || b) ? (c + 1) : (d ?? e))"),
+            (
+                "a ? b ? c : d : e ? f : g",
+                "(a ? (b ? c : d) : (e ? f : g))",
+            ),
+            (
+                "3 + 4 * 5 == 3 * 1 + 4 * 5",
+                "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))",
+            ),
+            ("true", "true"),
+            ("3 > 5 == false", "((3 > 5) == false)"),
+            ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+            ("(5 + 5) * 2", "((5 + 5) * 2)"),
+            ("-(5 + 5)", "(-(5 + 5))"),
+            ("!(true == true)", "(!(true == true))"),
+            ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+            (
+                "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+                "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
+            ),
+            (
+                "add(a + b + c * d / f + g)",
+                "add((((a + b) + ((c * d) / f)) + g))",
+            ),
+            (
+                "a * [1, 2, 3, 4][b * c] * d",
+                "((a * ([1, 2, 3, 4][(b * c)])) * d)",
+            ),
+            (
+                "add(a * b[2], b[1], 2 * [1, 2][1])",
+                "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parse(input).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_if_expression() {
+        assert_eq!(
+            parse_expression("if (x < y) { x }"),
+            Expression::If {
+                condition: Box::new(Expression::Infix(ident("x"), Token::Lt, ident("y"))),
+                consequence: BlockStatement {
+                    statements: vec![Statement::Expression(*ident("x"))],
+                    spans: vec![],
+                },
+                alternative: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_if_else_expression() {
+        assert_eq!(
+            parse_expression("if (x < y) { x } else { y }"),
+            Expression::If {
+                condition: Box::new(Expression::Infix(ident("x"), Token::Lt, ident("y"))),
+                consequence: BlockStatement {
+                    statements: vec![Statement::Expression(*ident("x"))],
+                    spans: vec![],
+                },
+                alternative: Some(BlockStatement {
+                    statements: vec![Statement::Expression(*ident("y"))],
+                    spans: vec![],
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_else_if_expression() {
+        let block = |expression| BlockStatement {
+            statements: vec![Statement::Expression(expression)],
+            spans: vec![],
+        };
+
+        assert_eq!(
+            parse_expression("if (x) { 1 } else if (y) { 2 } else { 3 }"),
+            Expression::If {
+                condition: ident("x"),
+                consequence: block(*int(1)),
+                alternative: Some(block(Expression::If {
+                    condition: ident("y"),
+                    consequence: block(*int(2)),
+                    alternative: Some(block(*int(3))),
+                })),
+            }
+        );
+        assert_eq!(
+            parse_expression("if (x) { 1 } else if (y) { 2 }"),
+            parse_expression("if (x) { 1 } else { if (y) { 2 } }")
+        );
+    }
+
+    #[test]
+    fn test_conditional_expression() {
+        assert_eq!(
+            parse_expression("x < y ? x : y"),
+            Expression::Conditional {
+                condition: Box::new(Expression::Infix(ident("x"), Token::Lt, ident("y"))),
+                consequence: ident("x"),
+                alternative: ident("y"),
+            }
+        );
+
+        let mut parser = Parser::new(Lexer::new("x ? y; z"));
+        parser.parse_program();
+        assert_eq!(
+            parser.errors()[0].message,
+            "expected next token to be Colon, got ; instead"
+        );
+    }
+
+    #[test]
+    fn test_while_expression() {
+        let expression = parse_expression("while (x < y) { x; break; continue }");
+
+        assert_eq!(
+            expression,
+            Expression::While {
+                condition: Box::new(Expression::Infix(ident("x"), Token::Lt, ident("y"))),
+                body: BlockStatement {
+                    statements: vec![
+                        Statement::Expression(*ident("x")),
+                        Statement::Break,
+                        Statement::Continue,
+                    ],
+                    spans: vec![],
+                },
+            }
+        );
+        assert_eq!(expression.to_string(), "while(x < y) xbreak;continue;");
+    }
+
+    #[test]
+    fn test_function_literal() {
+        assert_eq!(
+            parse_expression("fn(x, y) { x + y; }"),
+            Expression::Function {
+                parameters: vec!["x".into(), "y".into()],
+                body: BlockStatement {
+                    statements: vec![Statement::Expression(Expression::Infix(
+                        ident("x"),
+                        Token::Plus,
+                        ident("y")
+                    ))],
+                    spans: vec![],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_macro_literal() {
+        let expression = parse_expression("macro(x, y) { x + y; }");
+
+        assert_eq!(
+            expression,
+            Expression::Macro {
+                parameters: vec!["x".into(), "y".into()],
+                body: BlockStatement {
+                    statements: vec![Statement::Expression(Expression::Infix(
+                        ident("x"),
+                        Token::Plus,
+                        ident("y")
+                    ))],
+                    spans: vec![],
+                },
+            }
+        );
+        assert_eq!(expression.to_string(), "macro(x, y) (x + y)");
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let program = parse("let x: int = 5; fn(a: string, b) { a }");
+
+        assert_eq!(
+            program.statements,
+            vec![
+                Statement::Let(
+                    "x".into(),
+                    Expression::Annotated {
+                        value: int(5),
+                        type_name: "int".into(),
+                    }
+                ),
+                Statement::Expression(Expression::Function {
+                    parameters: vec!["a".into(), "b".into()],
+                    body: BlockStatement {
+                        statements: vec![
+                            Statement::Let(
+                                "a".into(),
+                                Expression::Annotated {
+                                    value: ident("a"),
+                                    type_name: "string".into(),
+                                }
+                            ),
+                            Statement::Expression(*ident("a")),
+                        ],
+                        spans: vec![],
+                    },
+                }),
+            ]
+        );
+
+        let mut parser = Parser::new(Lexer::new("let x: = 5;"));
+        parser.parse_program();
+        assert_eq!(
+            parser.errors()[0].message,
+            "expected next token to be Ident, got = instead"
+        );
+    }
+
+    #[test]
+    fn test_function_parameters() {
+        let tests: [(&str, &[&str]); 3] = [
+            ("fn() {};", &[]),
+            ("fn(x) {};", &["x"]),
+            ("fn(x, y, z) {};", &["x", "y", "z"]),
+        ];
+
+        for (input, expected) in tests {
+            match parse_expression(input) {
+                Expression::Function { parameters, .. } => assert_eq!(parameters, expected),
+                expression => panic!("expected a function literal, got {:?}", expression),
+            }
+        }
+    }
+
+    #[test]
+    fn test_call_expression() {
+        assert_eq!(
+            parse_expression("add(1, 2 * 3, 4 + 5);"),
+            Expression::Call {
+                function: ident("add"),
+                arguments: vec![
+                    *int(1),
+                    Expression::Infix(int(2), Token::Asterisk, int(3)),
+                    Expression::Infix(int(4), Token::Plus, int(5)),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut parser = Parser::new(Lexer::new("let = 5;"));
+        parser.parse_program();
+        assert!(!parser.errors().is_empty());
+
+        parser.reset("let x = 5;");
+        let program = parser.parse_program();
+
+        assert_eq!(parser.errors(), &[]);
+        assert_eq!(program.to_string(), "let x = 5;");
+    }
+
+    #[test]
+    fn test_array_literal() {
+        assert_eq!(
+            parse_expression("[1, 2 * 2, 3 + 3]"),
+            Expression::Array(vec![
+                *int(1),
+                Expression::Infix(int(2), Token::Asterisk, int(2)),
+                Expression::Infix(int(3), Token::Plus, int(3)),
+            ])
+        );
+        assert_eq!(parse_expression("[]"), Expression::Array(vec![]));
+    }
+
+    #[test]
+    fn test_index_expression() {
+        assert_eq!(
+            parse_expression("myArray[1 + 1]"),
+            Expression::Index(
+                ident("myArray"),
+                Box::new(Expression::Infix(int(1), Token::Plus, int(1)))
+            )
+        );
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let string = |s: &str| Expression::String(s.into());
+
+        assert_eq!(
+            parse_expression("{\"one\": 1, \"two\": 2, \"three\": 3}"),
+            Expression::Hash(vec![
+                (string("one"), *int(1)),
+                (string("two"), *int(2)),
+                (string("three"), *int(3)),
+            ])
+        );
+        assert_eq!(parse_expression("{}"), Expression::Hash(vec![]));
+        assert_eq!(
+            parse_expression("{\"one\": 0 + 1, true: 10 - 8, 3: 15 / 5}"),
+            Expression::Hash(vec![
+                (
+                    string("one"),
+                    Expression::Infix(int(0), Token::Plus, int(1))
+                ),
+                (
+                    Expression::Boolean(true),
+                    Expression::Infix(int(10), Token::Minus, int(8))
+                ),
+                (*int(3), Expression::Infix(int(15), Token::Slash, int(5))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_assignment_in_condition() {
+        let mut parser = Parser::new(Lexer::new("if (x = 5) {}\nwhile (y = 1) {}"));
+        parser.parse_program();
+
+        // The `=` is still an error, with the warning saying what was probably meant
+        let errors: Vec<_> = parser.errors().iter().map(|err| err.to_string()).collect();
+        for expected in [
+            "line 1, column 7: expected next token to be Rparen, got = instead",
+            "line 2, column 10: expected next token to be Rparen, got = instead",
+        ] {
+            assert!(errors.iter().any(|err| err == expected), "{:?}", errors);
+        }
+        assert_eq!(
+            parser.warnings(),
+            &[
+                ParseWarning {
+                    message: "assignment in a condition, did you mean `==`?".into(),
+                    span: Span { start: 6, end: 7 },
+                    position: Position { line: 1, column: 7 },
+                },
+                ParseWarning {
+                    message: "assignment in a condition, did you mean `==`?".into(),
+                    span: Span { start: 23, end: 24 },
+                    position: Position {
+                        line: 2,
+                        column: 10
+                    },
+                },
+            ]
+        );
+
+        let mut parser = Parser::new(Lexer::new("if (x == 5) {} while (x) {}"));
+        parser.parse_program();
+        assert_eq!(parser.warnings(), &[]);
+    }
+
+    #[test]
+    fn test_shadowed_builtins() {
+        let mut parser = Parser::new(Lexer::new("let foo = 5;\nlet len = 5;"));
+        parser.parse_program();
+
+        assert_eq!(
+            parser.warnings(),
+            &[ParseWarning {
+                message: "`len` shadows the builtin of the same name".into(),
+                span: Span { start: 17, end: 20 },
+                position: Position { line: 2, column: 5 },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hash_literal_errors() {
+        let mut parser = Parser::new(Lexer::new("{\"a\" 1}"));
+        parser.parse_program();
+
+        assert_eq!(
+            parser.errors()[0].message,
+            "expected next token to be Colon, got 1 instead"
+        );
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let mut parser = Parser::new(Lexer::new("let x = 1;\nlet y = 2;\n\nlet z 3;"));
+        parser.parse_program();
+
+        assert_eq!(
+            parser.errors(),
+            &[ParseError {
+                kind: ParseErrorKind::UnexpectedToken,
+                message: "expected next token to be Assign, got 3 instead".into(),
+                span: Span { start: 29, end: 30 },
+                position: Position { line: 4, column: 7 },
+            }]
+        );
+        assert_eq!(
+            parser.errors()[0].to_string(),
+            "line 4, column 7: expected next token to be Assign, got 3 instead"
+        );
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let mut parser = Parser::new(Lexer::new("let x = @\nlet y = \"a"));
+        parser.parse_program();
+
+        let span = Span { start: 8, end: 9 };
+        assert_eq!(
+            parser.errors(),
+            &[
+                ParseError {
+                    kind: ParseErrorKind::Lex(LexError::UnknownChar("@".into(), span)),
+                    message: "unknown character @".into(),
+                    span,
+                    position: Position { line: 1, column: 9 },
+                },
+                ParseError {
+                    kind: ParseErrorKind::Lex(LexError::UnterminatedString(Span {
+                        start: 18,
+                        end: 20
+                    })),
+                    message: "unterminated string literal".into(),
+                    span: Span { start: 18, end: 20 },
+                    position: Position { line: 2, column: 9 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth| "(".repeat(depth) + "x" + &")".repeat(depth);
+
+        let input = nested(MAX_NESTING - 1);
+        let mut parser = Parser::new(Lexer::new(&input));
+        parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+        let input = nested(5000);
+        let mut parser = Parser::new(Lexer::new(&input));
+        parser.parse_program();
+        let err = &parser.errors()[0];
+        assert_eq!(err.kind, ParseErrorKind::TooDeep);
+        assert_eq!(
+            err.to_string(),
+            "line 1, column 129: expressions nested more than 128 deep"
+        );
+    }
+
+    /// Parse whatever comes in, with and without comments, only caring that it doesn't panic
+    fn parse_anything(input: &str) {
+        Parser::new(Lexer::new(input)).parse_program();
+        Parser::new(Lexer::new(input).with_comments()).parse_program();
+    }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            parse_anything(&String::from_utf8_lossy(&bytes));
+        }
+
+        #[test]
+        fn test_arbitrary_tokens(input in r#"([a-z_0-9.]{1,3}|let|fn|if|else|return|while|break|continue|macro|true|false|[ \n(){}\[\],;:=+\-*/%<>!&|"$]|/\*|\*/|//){0,64}"#) {
+            parse_anything(&input);
+        }
+
+        #[test]
+        fn test_deep_nesting(
+            openers in prop::collection::vec(
+                prop::sample::select(&["(", "[", "{", "!", "-", "f(", "if (x) { ", "fn() { let y = "][..]),
+                0..1000,
+            )
+        ) {
+            let input = openers.concat() + "x";
+            parse_anything(&input);
+
+            // Each opener nests at least one expression deeper
+            let mut parser = Parser::new(Lexer::new(&input));
+            parser.parse_program();
+            let too_deep = parser.errors().iter().any(|err| err.kind == ParseErrorKind::TooDeep);
+            prop_assert_eq!(too_deep, openers.len() >= MAX_NESTING);
+        }
+    }
+}
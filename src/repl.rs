@@ -1,29 +1,945 @@
-use crate::lexer::Lexer;
+use crate::ast::Statement;
+use crate::builtins::Capture;
+use crate::diagnostic::{render_error, REPL_FILE};
+use crate::environment::Env;
+use crate::error::MonkeyError;
+use crate::evaluator::{EvalHook, Node};
+use crate::interpreter::Interpreter;
+use crate::lexer::{LexError, Lexer};
+use crate::object::Object;
+use crate::optimize::OptLevel;
+use crate::parser::Parser;
+use crate::script::{compile_source, Backend, RunOptions};
+#[cfg(feature = "serde")]
+use crate::snapshot::Snapshot;
 use crate::token::Token;
-use std::io::{self, Write};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
 
 const PROMPT: &str = ">> ";
+/// Shown instead of `PROMPT` while an entry spans several lines
+const CONTINUATION_PROMPT: &str = ".. ";
+/// How `:bytecode` compiles code
+const BYTECODE_OPTIONS: RunOptions = RunOptions {
+    backend: Backend::Vm,
+    opt_level: OptLevel::None,
+    stdlib: false,
+    path: None,
+};
+/// Shown while the debugger waits for a command
+const DEBUG_PROMPT: &str = "debug> ";
+/// Where history is kept between sessions, relative to the home directory
+const HISTORY_FILE: &str = ".monkey_history";
+/// What the name each result is bound to starts with, ahead of its number. Results are referred
+/// to as `_N`, which doesn't lex as a name since digit separators can't lead a number, so
+/// entries are rewritten to use these names instead.
+const RESULT_PREFIX: &str = "_result_";
 
-pub fn start() {
-    loop {
-        print!("{}", PROMPT);
-        io::stdout().flush().unwrap();
+/// How the REPL behaves
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ReplOptions {
+    /// Whether to load the standard library into each new environment
+    pub stdlib: bool,
+    /// Whether to print `null` for entries that produce it, like `let` statements, rather than
+    /// nothing, e.g. to debug what an entry evaluates to
+    pub show_null: bool,
+}
+
+impl Default for ReplOptions {
+    fn default() -> ReplOptions {
+        ReplOptions {
+            stdlib: true,
+            show_null: false,
+        }
+    }
+}
+
+/// Runs the REPL on the terminal, with line editing and history. Falls back to reading plain
+/// lines from stdin if the terminal can't be used for editing.
+///
+/// Besides Monkey code, the REPL accepts meta-commands: `:quit`, `:env` to list the current
+/// bindings, `:tokens <expr>` and `:ast <expr>` to show what the lexer or parser make of some
+/// code, `:bytecode <expr>` to show what it compiles to, `:debug <expr>` to step through it,
+/// `:save <file>` and `:load <file>` to save the session and resume it later, and `:reset` to
+/// clear the bindings and the session's history.
+///
+/// Each value an entry produces, other than `null`, is shown numbered as `_N => value`, and can
+/// be referred to later as `_N`, or as `_` while it's the latest.
+///
+/// * `options` - How the REPL behaves
+pub fn start(options: ReplOptions) {
+    let result = match DefaultEditor::new() {
+        Ok(editor) => {
+            let mut editor = Editor::new(editor);
+            let result = run(&mut editor, io::stdout(), io::sink(), options);
+            editor.save_history();
+            result
+        }
+        Err(_) => run(
+            &mut Lines(io::stdin().lock()),
+            io::stdout(),
+            io::sink(),
+            options,
+        ),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+    }
+}
+
+/// Runs the REPL until `input` runs out, recording everything the user would see, i.e. prompts
+/// followed by their input and the results, into `transcript`
+///
+/// * `input` - Where lines of input are read from
+/// * `output` - Where prompts and results are written
+/// * `transcript` - Where the session is recorded
+pub fn start_recording<R: BufRead, W: Write, T: Write>(
+    input: R,
+    output: W,
+    transcript: T,
+) -> io::Result<()> {
+    run(
+        &mut Lines(input),
+        output,
+        transcript,
+        ReplOptions::default(),
+    )
+}
+
+/// Where the REPL reads its input from
+trait LineReader {
+    /// Show a prompt and read a line, without its line ending. Gives `None` once the input has
+    /// run out.
+    ///
+    /// * `prompt` - The prompt to show
+    /// * `out` - Where to write the prompt, for readers that don't show it themselves
+    fn read_line<W: Write>(&mut self, prompt: &str, out: &mut W) -> io::Result<Option<String>>;
+
+    /// Remember a complete entry, so it can be recalled later
+    ///
+    /// * `entry` - The entry, which may span several lines
+    fn add_history(&mut self, _entry: &str) {}
+}
+
+/// Reads plain lines, e.g. from a pipe or a test
+struct Lines<R>(R);
+
+impl<R: BufRead> LineReader for Lines<R> {
+    fn read_line<W: Write>(&mut self, prompt: &str, out: &mut W) -> io::Result<Option<String>> {
+        write!(out, "{}", prompt)?;
+        out.flush()?;
 
         let mut line = String::new();
-        if io::stdin().read_line(&mut line).is_err() || line == "\n" {
+        if self.0.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+}
+
+/// Reads lines from the terminal with line editing, keeping history in the user's home
+/// directory
+struct Editor {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+}
+
+impl Editor {
+    fn new(mut editor: DefaultEditor) -> Editor {
+        let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(history) = &history {
+            // There's no history the first time round
+            let _ = editor.load_history(history);
+        }
+
+        Editor { editor, history }
+    }
+
+    fn save_history(&mut self) {
+        if let Some(history) = &self.history {
+            if let Err(err) = self.editor.save_history(history) {
+                eprintln!("could not save history: {}", err);
+            }
+        }
+    }
+}
+
+impl LineReader for Editor {
+    fn read_line<W: Write>(&mut self, prompt: &str, _out: &mut W) -> io::Result<Option<String>> {
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(None),
+            Err(ReadlineError::Io(err)) => Err(err),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        let _ = self.editor.add_history_entry(entry);
+    }
+}
+
+/// Writes to an output, copying everything written into a transcript
+struct Recorder<W, T> {
+    output: W,
+    transcript: T,
+}
+
+impl<W: Write, T: Write> Write for Recorder<W, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write_all(buf)?;
+        self.transcript.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()?;
+        self.transcript.flush()
+    }
+}
+
+/// Whether an entry needs more lines to be complete, because it has unclosed brackets or ends
+/// inside a string or block comment
+///
+/// * `entry` - The lines entered so far
+fn is_incomplete(entry: &str) -> bool {
+    let mut lexer = Lexer::new(entry);
+    let mut depth = 0;
+
+    loop {
+        match lexer.try_next_token() {
+            Ok(Token::Eof) => return depth > 0,
+            Ok(Token::Lparen | Token::Lbrace | Token::Lbracket) => depth += 1,
+            Ok(Token::Rparen | Token::Rbrace | Token::Rbracket) => depth -= 1,
+            Err(LexError::UnterminatedString(_) | LexError::UnterminatedComment(_)) => return true,
+            _ => {}
+        }
+    }
+}
+
+fn run<L: LineReader, W: Write, T: Write>(
+    input: &mut L,
+    output: W,
+    transcript: T,
+    options: ReplOptions,
+) -> io::Result<()> {
+    let mut out = Recorder { output, transcript };
+    // What scripts write is collected here, then shown ahead of the result
+    let capture = &Capture::default();
+    // Bindings and macros carry over from one entry to the next, until `:reset`
+    let new_interpreter = || {
+        let interpreter = if options.stdlib {
+            Interpreter::new()
+        } else {
+            Interpreter::bare()
+        };
+        interpreter.with_output(capture.clone())
+    };
+    let mut interpreter = new_interpreter();
+    let mut results = 0;
+    // Every entry and command so far, to save along with the session
+    let mut history = vec![];
+    let mut entry = String::new();
+
+    loop {
+        let prompt = if entry.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let Some(line) = input.read_line(prompt, &mut out)? else {
+            return Ok(());
+        };
+
+        writeln!(out.transcript, "{}", line)?;
+        if entry.is_empty() && line.trim_start().starts_with(':') {
+            input.add_history(line.trim());
+            history.push(line.trim().to_string());
+            let command = run_command(
+                line.trim(),
+                &mut interpreter,
+                &history,
+                capture,
+                input,
+                &mut out,
+            )?;
+            match command {
+                Command::Continue => continue,
+                Command::Reset => {
+                    interpreter = new_interpreter();
+                    results = 0;
+                    history.clear();
+                    continue;
+                }
+                Command::Loaded(loaded) => {
+                    for entry in &loaded {
+                        input.add_history(entry);
+                    }
+                    history = loaded;
+                    results = interpreter
+                        .bindings()
+                        .iter()
+                        .filter_map(|(name, _)| name.strip_prefix(RESULT_PREFIX)?.parse().ok())
+                        .max()
+                        .unwrap_or(0);
+                    continue;
+                }
+                Command::Quit => return Ok(()),
+            }
+        }
+
+        entry.push_str(&line);
+        entry.push('\n');
+
+        if is_incomplete(&entry) {
+            continue;
+        }
+        let entry = std::mem::take(&mut entry);
+        // Without the final newline, errors at the end point just past the last token
+        let source = entry.trim_end();
+        if source.trim_start().is_empty() {
+            continue;
+        }
+        input.add_history(source);
+        history.push(source.to_string());
+
+        let source = &resolve_results(source);
+        let result = interpreter.eval_str(source);
+        write_output(&mut out, capture)?;
+        if !should_print(&result, options) {
             continue;
         }
+        match result {
+            Ok(Object::Null) => writeln!(out, "{}", Object::Null)?,
+            Ok(result) => {
+                results += 1;
+                interpreter.set("_", result.clone());
+                interpreter.set(format!("{}{}", RESULT_PREFIX, results), result.clone());
+                writeln!(out, "_{} => {}", results, result)?;
+            }
+            Err(err) => writeln!(out, "{}", render_error(&err, REPL_FILE, source))?,
+        }
+    }
+}
+
+/// Write what scripts have written since last time, ending it with a newline if it doesn't
+/// have one so what comes next starts on a line of its own
+///
+/// * `out` - Where to write it
+/// * `capture` - Where the scripts wrote
+fn write_output<W: Write>(out: &mut W, capture: &Capture) -> io::Result<()> {
+    let output = capture.take();
+    write!(out, "{}", output)?;
+
+    if output.is_empty() || output.ends_with('\n') {
+        Ok(())
+    } else {
+        writeln!(out)
+    }
+}
+
+/// Whether to print what an entry produced, which is always the case for values and errors
+///
+/// * `result` - What the entry produced
+/// * `options` - How the REPL behaves
+fn should_print(result: &Result<Object, MonkeyError>, options: ReplOptions) -> bool {
+    options.show_null || !matches!(result, Ok(Object::Null))
+}
+
+/// Whether a name is one the REPL binds to an earlier result, i.e. `_` or one for `_N`
+///
+/// * `name` - The name to check
+fn is_result_name(name: &str) -> bool {
+    name == "_"
+        || name
+            .strip_prefix(RESULT_PREFIX)
+            .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Rewrite an entry's references to earlier results, written `_N`, to the names the results
+/// are bound to. Strings and comments are left alone.
+///
+/// * `source` - The entry
+fn resolve_results(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut resolved = String::with_capacity(source.len());
+    let mut copied = 0;
+
+    let mut lexer = Lexer::new(source);
+    loop {
+        match lexer.try_next_token() {
+            Ok(Token::Eof) => break,
+            Err(LexError::MalformedNumber(literal, span))
+                if literal
+                    .strip_prefix('_')
+                    .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit())) =>
+            {
+                resolved.extend(&chars[copied..span.start]);
+                resolved.push_str(RESULT_PREFIX);
+                resolved.extend(&chars[span.start + 1..span.end]);
+                copied = span.end;
+            }
+            _ => {}
+        }
+    }
+    resolved.extend(&chars[copied..]);
+
+    resolved
+}
+
+/// What the REPL should do once a meta-command has run
+enum Command {
+    Continue,
+    /// Start again with no bindings or history
+    Reset,
+    /// Carry on from a saved session, whose history is given
+    Loaded(Vec<String>),
+    Quit,
+}
+
+/// Run a meta-command, i.e. a line starting with `:`
+///
+/// * `line` - The command and its argument, if any
+/// * `interpreter` - The interpreter whose bindings `:env` shows
+/// * `history` - The session's entries and commands, which `:save` saves
+/// * `capture` - Where the interpreter's scripts write
+/// * `input` - Where the debugger reads its commands
+/// * `out` - Where to write the command's output
+fn run_command<L: LineReader, W: Write, T: Write>(
+    line: &str,
+    interpreter: &mut Interpreter,
+    history: &[String],
+    capture: &Capture,
+    input: &mut L,
+    out: &mut Recorder<W, T>,
+) -> io::Result<Command> {
+    let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let arg = arg.trim();
+
+    match command {
+        ":quit" => return Ok(Command::Quit),
+        ":reset" => return Ok(Command::Reset),
+        ":env" => {
+            for (name, value) in interpreter.bindings() {
+                if !is_result_name(&name) {
+                    writeln!(out, "{} = {}", name, value)?;
+                }
+            }
+        }
+        ":tokens" => {
+            for token in Lexer::new(arg) {
+                writeln!(out, "{}", token)?;
+            }
+        }
+        ":ast" => {
+            let mut parser = Parser::new(Lexer::new(arg));
+            let program = parser.parse_program();
+
+            if parser.errors().is_empty() {
+                for statement in &program.statements {
+                    writeln!(out, "{:#?}", statement)?;
+                }
+            } else {
+                let err = MonkeyError::Parse(parser.errors().to_vec());
+                writeln!(out, "{}", render_error(&err, REPL_FILE, arg))?;
+            }
+        }
+        // The entry is compiled on its own, as it would be with `--no-stdlib`
+        ":bytecode" => match compile_source(arg, &BYTECODE_OPTIONS) {
+            Ok(bytecode) => write!(out, "{}", bytecode.disassemble())?,
+            Err(err) => writeln!(out, "{}", render_error(&err, REPL_FILE, arg))?,
+        },
+        ":debug" => {
+            let mut debugger = Debugger {
+                input,
+                out,
+                capture,
+                stepping: true,
+                error: None,
+            };
+            let arg = &resolve_results(arg);
+            let result = interpreter.eval_str_with_hook(arg, &mut debugger);
+            if let Some(err) = debugger.error {
+                return Err(err);
+            }
+
+            write_output(out, capture)?;
+            match result {
+                Ok(result) => writeln!(out, "{}", result)?,
+                Err(err) => writeln!(out, "{}", render_error(&err, REPL_FILE, arg))?,
+            }
+        }
+        ":save" => match save_session(interpreter, history, arg) {
+            Ok(()) => writeln!(out, "saved the session to {}", arg)?,
+            Err(err) => writeln!(out, "could not save the session: {}", err)?,
+        },
+        ":load" => match load_session(interpreter, arg) {
+            Ok(history) => {
+                writeln!(out, "loaded the session from {}", arg)?;
+                return Ok(Command::Loaded(history));
+            }
+            Err(err) => writeln!(out, "could not load the session: {}", err)?,
+        },
+        _ => writeln!(
+            out,
+            "unknown command {}, expected one of :quit, :env, :tokens, :ast, :bytecode, :debug, \
+             :save, :load or :reset",
+            command
+        )?,
+    }
 
-        let mut lexer = Lexer::new(line);
+    Ok(Command::Continue)
+}
+
+/// Save the interpreter's state and the session's history to a file
+///
+/// * `interpreter` - The interpreter to save
+/// * `history` - The session's entries and commands
+/// * `path` - The file to save to
+#[cfg(feature = "serde")]
+fn save_session(interpreter: &Interpreter, history: &[String], path: &str) -> Result<(), String> {
+    let mut snapshot = interpreter.snapshot().map_err(|err| err.to_string())?;
+    snapshot.history = history.to_vec();
+
+    snapshot.save(path).map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+fn save_session(_: &Interpreter, _: &[String], _: &str) -> Result<(), String> {
+    Err("sessions can only be saved when built with the `serde` feature".to_string())
+}
+
+/// Restore the interpreter's state from a file saved by `save_session`, returning the saved
+/// session's history
+///
+/// * `interpreter` - The interpreter to restore
+/// * `path` - The file to load
+#[cfg(feature = "serde")]
+fn load_session(interpreter: &mut Interpreter, path: &str) -> Result<Vec<String>, String> {
+    let snapshot = Snapshot::load(path).map_err(|err| err.to_string())?;
+    interpreter
+        .restore(&snapshot)
+        .map_err(|err| err.to_string())?;
+
+    Ok(snapshot.history)
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_session(_: &mut Interpreter, _: &str) -> Result<Vec<String>, String> {
+    Err("sessions can only be loaded when built with the `serde` feature".to_string())
+}
+
+/// Steps through code for `:debug` a statement at a time, reading commands from the REPL's input
+struct Debugger<'a, L, W, T> {
+    input: &'a mut L,
+    out: &'a mut Recorder<W, T>,
+    /// Where the code being debugged writes, shown before each pause
+    capture: &'a Capture,
+    /// Whether to stop before the next statement, rather than running to the end
+    stepping: bool,
+    /// The first error reading or writing, after which the code runs to the end
+    error: Option<io::Error>,
+}
+
+impl<L: LineReader, W: Write, T: Write> Debugger<'_, L, W, T> {
+    /// Show the statement about to run, then take commands until told to go on
+    ///
+    /// * `statement` - The statement about to run
+    /// * `env` - The environment it runs in
+    /// * `depth` - How many function calls deep it is
+    fn pause(&mut self, statement: &Statement, env: &Env, depth: usize) -> io::Result<()> {
+        write_output(self.out, self.capture)?;
+        writeln!(self.out, "[{}] {}", depth, statement)?;
 
         loop {
-            let token = lexer.next_token();
+            let Some(line) = self.input.read_line(DEBUG_PROMPT, self.out)? else {
+                self.stepping = false;
+                return Ok(());
+            };
+            writeln!(self.out.transcript, "{}", line)?;
 
-            if token == Token::Eof {
-                break;
+            match line.trim() {
+                "" | "s" | "step" => return Ok(()),
+                "c" | "continue" => {
+                    self.stepping = false;
+                    return Ok(());
+                }
+                "l" | "locals" => {
+                    for (name, value) in env.borrow().bindings() {
+                        writeln!(self.out, "{} = {}", name, value)?;
+                    }
+                }
+                command => writeln!(
+                    self.out,
+                    "unknown debugger command {}, expected step, continue or locals",
+                    command
+                )?,
             }
+        }
+    }
+}
+
+impl<L: LineReader, W: Write, T: Write> EvalHook for Debugger<'_, L, W, T> {
+    fn before(&mut self, node: Node<'_>, env: &Env, depth: usize) {
+        let Node::Statement(statement) = node else {
+            return;
+        };
+
+        if self.stepping {
+            if let Err(err) = self.pause(statement, env, depth) {
+                self.stepping = false;
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{RuntimeError, RuntimeErrorKind};
+
+    #[test]
+    fn test_should_print() {
+        let showing = ReplOptions {
+            show_null: true,
+            ..ReplOptions::default()
+        };
+        let error =
+            Err(RuntimeError::new(RuntimeErrorKind::DivisionByZero, "division by zero").into());
+
+        for (result, printed_by_default) in [
+            (Ok(Object::Null), false),
+            (Ok(Object::Integer(5)), true),
+            (error, true),
+        ] {
+            assert_eq!(
+                should_print(&result, ReplOptions::default()),
+                printed_by_default
+            );
+            assert!(should_print(&result, showing));
+        }
+    }
+
+    #[test]
+    fn test_start_recording() {
+        let input = "1 + 2 * 3\n\nlet\n".as_bytes();
+        let mut output = vec![];
+        let mut transcript = vec![];
+
+        start_recording(input, &mut output, &mut transcript).unwrap();
+
+        assert_eq!(
+            String::from_utf8(transcript).unwrap(),
+            ">> 1 + 2 * 3
+_1 => 7
+>> 
+>> let
+parse error: expected next token to be Ident, got <eof> instead
+ --> <repl>:1:4
+  |
+1 | let
+  |    ^
+>> "
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ">> _1 => 7
+>> >> parse error: expected next token to be Ident, got <eof> instead
+ --> <repl>:1:4
+  |
+1 | let
+  |    ^
+>> "
+        );
+    }
+
+    #[test]
+    fn test_multi_line_input() {
+        let input = "if (1 < 2) {\n  [1,\n   2]\n}\n\"a\nb\"\n".as_bytes();
+        let mut output = vec![];
+        let mut transcript = vec![];
 
-            println!("Token: {:?}", token);
+        start_recording(input, &mut output, &mut transcript).unwrap();
+
+        assert_eq!(
+            String::from_utf8(transcript).unwrap(),
+            ">> if (1 < 2) {
+..   [1,
+..    2]
+.. }
+_1 => [1, 2]
+>> \"a
+.. b\"
+_2 => a
+b
+>> "
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ">> .. .. .. _1 => [1, 2]\n>> .. _2 => a\nb\n>> "
+        );
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        let tests = vec![
+            ("1 + 2\n", false),
+            ("fn(x) {\n", true),
+            ("fn(x) {\n x }\n", false),
+            ("[1, (2\n", true),
+            ("[1, (2)]\n", false),
+            ("\"abc\n", true),
+            ("/* comment\n", true),
+            ("}\n", false),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(is_incomplete(input), expected, "{:?}", input);
         }
     }
+
+    #[test]
+    fn test_resolve_results() {
+        assert_eq!(resolve_results("[_, _12] // _3"), "[_, _result_12] // _3");
+        assert_eq!(
+            resolve_results("\"_1 é ${_1}\" + _2"),
+            "\"_1 é ${_result_1}\" + _result_2"
+        );
+        assert_eq!(resolve_results("_1_000 + _x"), "_1_000 + _x");
+    }
+
+    #[test]
+    fn test_commands() {
+        let input = ":tokens let x = 5;
+:ast -a
+let a = 1; let b = a + 1;
+:env
+:reset
+:env
+:ast let
+:bytecode 1 + 2
+:bogus
+:quit
+1
+"
+        .as_bytes();
+        let mut output = vec![];
+
+        start_recording(input, &mut output, io::sink()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#">> let
+x
+=
+5
+;
+<eof>
+>> Expression(
+    Prefix(
+        Minus,
+        Identifier(
+            "a",
+        ),
+    ),
+)
+>> >> a = 1
+b = 2
+>> >> >> parse error: expected next token to be Ident, got <eof> instead
+ --> <repl>:1:4
+  |
+1 | let
+  |    ^
+>> constants:
+0000 1
+0001 2
+instructions:
+0000 OpConstant 0
+0003 OpConstant 1
+0006 OpAdd
+0007 OpReturnValue
+>> unknown command :bogus, expected one of :quit, :env, :tokens, :ast, :bytecode, :debug, :save, :load or :reset
+>> "#
+        );
+    }
+
+    #[test]
+    fn test_persistent_environment() {
+        let input = "let x = 5;
+x * 2
+let double = macro(a) { quote(unquote(a) * 2) };
+double(x + 1)
+:reset
+x
+"
+        .as_bytes();
+        let mut output = vec![];
+
+        start_recording(input, &mut output, io::sink()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ">> >> _1 => 10\n>> >> _2 => 12\n>> >> runtime error: identifier not found: x\n --> <repl>\n>> "
+        );
+    }
+
+    #[test]
+    fn test_script_output() {
+        let input = "puts(\"a\"); print(1, 2); 3\n:debug puts(\"b\"); 4\nc\n".as_bytes();
+        let mut output = vec![];
+        let mut transcript = vec![];
+
+        start_recording(input, &mut output, &mut transcript).unwrap();
+
+        assert_eq!(
+            String::from_utf8(transcript).unwrap(),
+            ">> puts(\"a\"); print(1, 2); 3
+a
+12
+_1 => 3
+>> :debug puts(\"b\"); 4
+[0] puts(\"b\")
+debug> c
+b
+4
+>> "
+        );
+    }
+
+    #[test]
+    fn test_result_history() {
+        let input = "2 * 3
+_ + 1
+let x = _1;
+[_, _2, x]
+:env
+_9
+:reset
+_
+"
+        .as_bytes();
+        let mut output = vec![];
+
+        start_recording(input, &mut output, io::sink()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ">> _1 => 6
+>> _2 => 7
+>> >> _3 => [7, 7, 6]
+>> x = 6
+>> runtime error: identifier not found: _result_9
+ --> <repl>
+>> >> runtime error: identifier not found: _
+ --> <repl>
+>> "
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("monkey-rs-session-{}.json", std::process::id()));
+        let path = path.display();
+
+        let input = format!(
+            "let add = fn(a) {{ fn(b) {{ a + b }} }}(10);\n5\n:save {}\n:load nowhere\n",
+            path
+        );
+        let mut output = vec![];
+        start_recording(input.as_bytes(), &mut output, io::sink()).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("could not load the session: "));
+
+        let input = format!(":load {}\nadd(_1)\n_ * 2\n", path);
+        let mut output = vec![];
+        start_recording(input.as_bytes(), &mut output, io::sink()).unwrap();
+        std::fs::remove_file(path.to_string()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                ">> loaded the session from {}
+>> _2 => 15
+>> _3 => 30
+>> ",
+                path
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_reset_history() {
+        let path = env::temp_dir().join(format!("monkey-rs-reset-{}.json", std::process::id()));
+        let path = path.display();
+
+        let input = format!("let x = 1;\n:reset\nlet y = 2;\n:save {}\n", path);
+        start_recording(input.as_bytes(), io::sink(), io::sink()).unwrap();
+        let snapshot = Snapshot::load(path.to_string()).unwrap();
+        std::fs::remove_file(path.to_string()).unwrap();
+
+        assert_eq!(
+            snapshot.history,
+            vec!["let y = 2;".to_string(), format!(":save {}", path)]
+        );
+    }
+
+    #[test]
+    fn test_debug() {
+        let input = "let x = 1;
+:debug let f = fn(a) { let b = a + x; b * 2 }; f(3)
+s
+step
+
+l
+bogus
+c
+:debug x
+locals
+"
+        .as_bytes();
+        let mut output = vec![];
+        let mut transcript = vec![];
+
+        start_recording(input, &mut output, &mut transcript).unwrap();
+
+        assert_eq!(
+            String::from_utf8(transcript).unwrap(),
+            ">> let x = 1;
+>> :debug let f = fn(a) { let b = a + x; b * 2 }; f(3)
+[0] let f = fn(a) let b = (a + x);(b * 2);
+debug> s
+[0] f(3)
+debug> step
+[1] let b = (a + x);
+debug> 
+[1] (b * 2)
+debug> l
+a = 3
+b = 4
+debug> bogus
+unknown debugger command bogus, expected step, continue or locals
+debug> c
+8
+>> :debug x
+[0] x
+debug> locals
+f = fn(a) {
+let b = (a + x);(b * 2)
+}
+x = 1
+debug> 1
+>> "
+        );
+    }
 }
@@ -0,0 +1,334 @@
+use crate::ast::Program;
+use crate::compiler::{Bytecode, Compiler};
+use crate::environment::{Env, Environment};
+use crate::error::MonkeyError;
+use crate::evaluator::Evaluator;
+use crate::lexer::Lexer;
+use crate::macro_expansion::{define_macros, expand_macros};
+use crate::object::Object;
+use crate::optimize::{optimize, OptLevel};
+use crate::parser::Parser;
+use crate::stdlib;
+use crate::vm::Vm;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Which engine runs a program
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Backend {
+    /// Walk the AST with the `Evaluator`
+    #[default]
+    Eval,
+    /// Compile to bytecode and run it on the `Vm`
+    Vm,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s {
+            "eval" => Ok(Backend::Eval),
+            "vm" => Ok(Backend::Vm),
+            s => Err(format!("unknown backend {}, expected eval or vm", s)),
+        }
+    }
+}
+
+/// How to run a program
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RunOptions {
+    /// The engine to run it with
+    pub backend: Backend,
+    /// How far to optimize it first
+    pub opt_level: OptLevel,
+    /// Whether to run the standard library ahead of it
+    pub stdlib: bool,
+    /// The file it was read from, if any, so its imports are resolved relative to the file
+    pub path: Option<PathBuf>,
+}
+
+impl Default for RunOptions {
+    fn default() -> RunOptions {
+        RunOptions {
+            backend: Backend::default(),
+            opt_level: OptLevel::default(),
+            stdlib: true,
+            path: None,
+        }
+    }
+}
+
+/// Read a file and run it as a whole program, resolving its imports relative to the file
+///
+/// * `path` - The script to run, which takes the place of any path in the options
+/// * `options` - How to run it
+pub fn run_file(path: &Path, options: &RunOptions) -> Result<Object, MonkeyError> {
+    let source = fs::read_to_string(path)?;
+    let options = RunOptions {
+        path: Some(path.to_path_buf()),
+        ..options.clone()
+    };
+
+    run_source(&source, &options)
+}
+
+/// Parse and run a whole program as the options say, returning the value it produced. Only
+/// the evaluator supports imports.
+///
+/// * `source` - The program to run
+/// * `options` - How to run it
+pub fn run_source(source: &str, options: &RunOptions) -> Result<Object, MonkeyError> {
+    let program = prepare(source, options)?;
+
+    match options.backend {
+        Backend::Eval => {
+            let mut evaluator = Evaluator::new();
+            if let Some(path) = &options.path {
+                evaluator = evaluator.with_path(path);
+            }
+            if options.stdlib {
+                evaluator = evaluator.with_stdlib(stdlib::env());
+            }
+            evaluator.try_eval(&program, &Environment::new())
+        }
+        Backend::Vm => Ok(Vm::new(compile(&program)?).run()?),
+    }
+}
+
+/// Parse and evaluate a program in an environment the host provides, so the host can bind
+/// values for it beforehand and read back what it bound afterwards. The standard library isn't
+/// added, as its bindings would end up in the environment too.
+///
+/// * `source` - The program to run
+/// * `env` - The environment to run it in
+pub fn eval_in(source: &str, env: &Env) -> Result<Object, MonkeyError> {
+    let options = RunOptions {
+        stdlib: false,
+        ..RunOptions::default()
+    };
+
+    Evaluator::new().try_eval(&prepare(source, &options)?, env)
+}
+
+/// Parse and compile a whole program without running it, e.g. to disassemble it. The backend
+/// in the options is ignored.
+///
+/// * `source` - The program to compile
+/// * `options` - How to prepare it
+pub fn compile_source(source: &str, options: &RunOptions) -> Result<Bytecode, MonkeyError> {
+    compile(&prepare(source, options)?)
+}
+
+/// Parse a program and expand its macros, then add the standard library and optimize it
+fn prepare(source: &str, options: &RunOptions) -> Result<Program, MonkeyError> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+
+    if !parser.errors().is_empty() {
+        return Err(MonkeyError::Parse(parser.errors().to_vec()));
+    }
+
+    expand(program, options)
+}
+
+/// Expand a parsed program's macros, then add the standard library and optimize it
+///
+/// * `program` - The program, parsed without errors
+/// * `options` - How it will be run
+pub(crate) fn expand(mut program: Program, options: &RunOptions) -> Result<Program, MonkeyError> {
+    let macro_env = Environment::new();
+    define_macros(&mut program, &macro_env);
+    let mut program = expand_macros(program, &macro_env).map_err(MonkeyError::Macro)?;
+
+    if options.stdlib {
+        let mut statements = stdlib::program().statements;
+        statements.append(&mut program.statements);
+        program.statements = statements;
+        // What's left of the spans wouldn't say which source they're in
+        program.spans.clear();
+    }
+
+    Ok(optimize(program, options.opt_level))
+}
+
+fn compile(program: &Program) -> Result<Bytecode, MonkeyError> {
+    let mut compiler = Compiler::new();
+    compiler.compile(program).map_err(MonkeyError::Compile)?;
+
+    Ok(compiler.bytecode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RuntimeErrorKind;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_run_source() {
+        let source = "let add = fn(a, b) { a + b };\nadd(1, 2);\n";
+
+        assert_eq!(
+            run_source(source, &RunOptions::default()).unwrap(),
+            Object::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_eval_in() {
+        let env = Environment::new();
+        env.borrow_mut().set("limit".into(), Object::Integer(10));
+
+        assert_eq!(
+            eval_in("let doubled = limit * 2; doubled + 1", &env).unwrap(),
+            Object::Integer(21)
+        );
+        assert_eq!(env.borrow().get("doubled"), Some(Object::Integer(20)));
+        assert!(matches!(
+            eval_in("missing", &env),
+            Err(MonkeyError::Runtime(err)) if err.kind == RuntimeErrorKind::IdentifierNotFound
+        ));
+    }
+
+    #[test]
+    fn test_run_source_errors() {
+        let err = run_source("let x 5;", &RunOptions::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "parser errors:\n\tline 1, column 7: expected next token to be Assign, got 5 instead"
+        );
+
+        let err = run_source("1 + true;", &RunOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "ERROR: type mismatch: INTEGER + BOOLEAN");
+    }
+
+    #[test]
+    fn test_backends() {
+        let source =
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };\nfib(10)";
+
+        for backend in [Backend::Eval, Backend::Vm] {
+            let options = RunOptions {
+                backend,
+                ..RunOptions::default()
+            };
+            assert_eq!(run_source(source, &options).unwrap(), Object::Integer(55));
+        }
+
+        let source = "let unless = macro(cond, conseq, alt) {
+            quote(if (!(unquote(cond))) { unquote(conseq); } else { unquote(alt); });
+        };
+        unless(10 > 5, 1, 2);";
+
+        for backend in [Backend::Eval, Backend::Vm] {
+            let options = RunOptions {
+                backend,
+                ..RunOptions::default()
+            };
+            assert_eq!(run_source(source, &options).unwrap(), Object::Integer(2));
+        }
+
+        assert_eq!("vm".parse(), Ok(Backend::Vm));
+        assert!("jit".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_compile_source() {
+        let options = RunOptions {
+            stdlib: false,
+            ..RunOptions::default()
+        };
+        let bytecode = compile_source("let f = fn(x) { x * 2 }; f(\"a\")", &options).unwrap();
+
+        assert_eq!(
+            bytecode.disassemble(),
+            "constants:
+0000 2
+0001 fn, 1 parameters, 1 locals
+    0000 OpGetLocal 0
+    0002 OpConstant 0
+    0005 OpMul
+    0006 OpReturnValue
+0002 \"a\"
+instructions:
+0000 OpClosure 1 0
+0004 OpSetGlobal 0
+0007 OpGetGlobal 0
+0010 OpConstant 2
+0013 OpCall 1
+0015 OpReturnValue
+"
+        );
+
+        let err = compile_source("missing", &options).unwrap_err();
+        assert!(matches!(err, MonkeyError::Compile(_)));
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = env::temp_dir().join(format!("monkey-imports-{}", process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        for (file, source) in [
+            ("main.monkey", "let math = import(\"lib/math.monkey\"); math[\"square\"](math[\"three\"])"),
+            ("lib/math.monkey", "let _twice = import(\"../twice.monkey\"); let three = _twice[\"twice\"](1) + 1; let square = fn(x) { x * x };"),
+            ("twice.monkey", "let twice = fn(x) { x * 2 };"),
+            ("cycle.monkey", "import(\"lib/cycle.monkey\")"),
+            ("lib/cycle.monkey", "import(\"../cycle.monkey\")"),
+            ("broken.monkey", "import(\"twice.monkey\"); import(\"missing.monkey\")"),
+            ("lists.monkey", "let lists = import(\"lib/lists.monkey\"); [size(lists), lists[\"squares\"]([1, 2, 3])]"),
+            ("lib/lists.monkey", "let squares = fn(xs) { map(xs, fn(x) { x * x }) };"),
+        ] {
+            fs::write(dir.join(file), source).unwrap();
+        }
+
+        assert_eq!(
+            run_file(&dir.join("main.monkey"), &RunOptions::default()).unwrap(),
+            Object::Integer(9)
+        );
+
+        let err = run_file(&dir.join("cycle.monkey"), &RunOptions::default()).unwrap_err();
+        assert!(matches!(
+            &err,
+            MonkeyError::Runtime(err) if err.kind == RuntimeErrorKind::Import
+        ));
+        assert!(
+            err.to_string().starts_with("ERROR: import cycle: "),
+            "{}",
+            err
+        );
+
+        let err = run_file(&dir.join("broken.monkey"), &RunOptions::default()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("ERROR: could not import missing.monkey: "));
+
+        // Modules can use the standard library without exporting it
+        assert_eq!(
+            run_file(&dir.join("lists.monkey"), &RunOptions::default())
+                .unwrap()
+                .to_string(),
+            "[1, [1, 4, 9]]"
+        );
+
+        let options = RunOptions {
+            backend: Backend::Vm,
+            ..RunOptions::default()
+        };
+        let err = run_file(&dir.join("main.monkey"), &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "compile error: import is only supported by the eval backend"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_file_missing() {
+        let err = run_file(Path::new("does/not/exist.monkey"), &RunOptions::default()).unwrap_err();
+        assert!(matches!(err, MonkeyError::Io(_)));
+    }
+}
@@ -0,0 +1,516 @@
+use crate::ast::{BlockStatement, Expression, Program, Statement};
+use crate::lexer::Lexer;
+use crate::token::{escape, Token};
+use std::error::Error;
+use std::fmt;
+
+/// Dump a program's AST as JSON
+///
+/// * `program` - The program to dump
+pub fn to_json(program: &Program) -> String {
+    serde_json::to_string(program).expect("the AST always serializes")
+}
+
+/// Read back a program dumped by `to_json`
+///
+/// * `json` - The JSON to read
+pub fn from_json(json: &str) -> Result<Program, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Dump a program's AST as a compact S-expression, e.g. `let x = 1 + 2;` becomes
+/// `(program (let x (+ 1 2)))`. Statements and expressions other than identifiers and literals
+/// are lists headed by what they are, with operators as their own heads.
+///
+/// * `program` - The program to dump
+pub fn to_sexpr(program: &Program) -> String {
+    let mut out = String::new();
+    write_list(&mut out, "program", |out| {
+        for statement in &program.statements {
+            out.push(' ');
+            write_statement(out, statement);
+        }
+    });
+    out
+}
+
+/// Read back a program dumped by `to_sexpr`
+///
+/// * `input` - The S-expression to read
+pub fn from_sexpr(input: &str) -> Result<Program, SexprError> {
+    let mut reader = Reader { input, pos: 0 };
+    let sexpr = reader.read()?;
+    reader.skip_whitespace();
+    if reader.pos < input.len() {
+        return Err(SexprError::new("unexpected input after the program"));
+    }
+
+    let items = expect_list(&sexpr, "program")?;
+    let statements = items.iter().map(to_statement).collect::<Result<_, _>>()?;
+    Ok(Program {
+        statements,
+        spans: vec![],
+    })
+}
+
+/// A problem reading an S-expression back into an AST
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SexprError {
+    pub message: String,
+}
+
+impl SexprError {
+    fn new<S: Into<String>>(message: S) -> SexprError {
+        SexprError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SexprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SexprError {}
+
+fn write_statement(out: &mut String, statement: &Statement) {
+    match statement {
+        Statement::Let(name, value) => write_list(out, "let", |out| {
+            out.push(' ');
+            out.push_str(name);
+            write_item(out, value);
+        }),
+        Statement::Assign(name, value) => write_list(out, "assign", |out| {
+            out.push(' ');
+            out.push_str(name);
+            write_item(out, value);
+        }),
+        Statement::Return(Some(value)) => write_list(out, "return", |out| write_item(out, value)),
+        Statement::Return(None) => out.push_str("(return)"),
+        Statement::Expression(expression) => write_expression(out, expression),
+        Statement::Break => out.push_str("(break)"),
+        Statement::Continue => out.push_str("(continue)"),
+        Statement::Comment(text) => out.push_str(&format!("(comment \"{}\")", escape(text))),
+    }
+}
+
+fn write_block(out: &mut String, block: &BlockStatement) {
+    write_list(out, "block", |out| {
+        for statement in &block.statements {
+            out.push(' ');
+            write_statement(out, statement);
+        }
+    });
+}
+
+fn write_expression(out: &mut String, expression: &Expression) {
+    match expression {
+        Expression::Identifier(name) => out.push_str(name),
+        Expression::Integer(value) => out.push_str(&value.to_string()),
+        // `Debug` keeps the `.0` on whole numbers, so they read back as floats
+        Expression::Float(value) => out.push_str(&format!("{:?}", value)),
+        Expression::Boolean(value) => out.push_str(&value.to_string()),
+        Expression::String(value) => out.push_str(&format!("\"{}\"", escape(value))),
+        Expression::Array(elements) => write_list(out, "array", |out| {
+            for element in elements {
+                write_item(out, element);
+            }
+        }),
+        Expression::Index(left, index) => write_list(out, "index", |out| {
+            write_item(out, left);
+            write_item(out, index);
+        }),
+        Expression::Hash(pairs) => write_list(out, "hash", |out| {
+            for (key, value) in pairs {
+                out.push_str(" (");
+                write_expression(out, key);
+                write_item(out, value);
+                out.push(')');
+            }
+        }),
+        Expression::Prefix(operator, right) => {
+            write_list(out, &operator.to_string(), |out| write_item(out, right))
+        }
+        Expression::Infix(left, operator, right) => write_list(out, &operator.to_string(), |out| {
+            write_item(out, left);
+            write_item(out, right);
+        }),
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+        } => write_list(out, "if", |out| {
+            write_item(out, condition);
+            out.push(' ');
+            write_block(out, consequence);
+            if let Some(alternative) = alternative {
+                out.push(' ');
+                write_block(out, alternative);
+            }
+        }),
+        Expression::Conditional {
+            condition,
+            consequence,
+            alternative,
+        } => write_list(out, "?", |out| {
+            write_item(out, condition);
+            write_item(out, consequence);
+            write_item(out, alternative);
+        }),
+        Expression::While { condition, body } => write_list(out, "while", |out| {
+            write_item(out, condition);
+            out.push(' ');
+            write_block(out, body);
+        }),
+        Expression::Function { parameters, body } => write_list(out, "fn", |out| {
+            out.push_str(&format!(" ({}) ", parameters.join(" ")));
+            write_block(out, body);
+        }),
+        Expression::Call {
+            function,
+            arguments,
+        } => write_list(out, "call", |out| {
+            write_item(out, function);
+            for argument in arguments {
+                write_item(out, argument);
+            }
+        }),
+        Expression::Macro { parameters, body } => write_list(out, "macro", |out| {
+            out.push_str(&format!(" ({}) ", parameters.join(" ")));
+            write_block(out, body);
+        }),
+        Expression::Annotated { value, type_name } => write_list(out, "annotated", |out| {
+            write_item(out, value);
+            out.push(' ');
+            out.push_str(type_name);
+        }),
+    }
+}
+
+/// Write a list with the given head, leaving its items, each preceded by a space, to `items`
+fn write_list<F: FnOnce(&mut String)>(out: &mut String, head: &str, items: F) {
+    out.push('(');
+    out.push_str(head);
+    items(out);
+    out.push(')');
+}
+
+/// Write an expression as an item of a list
+fn write_item(out: &mut String, expression: &Expression) {
+    out.push(' ');
+    write_expression(out, expression);
+}
+
+/// An S-expression read from text, before it's turned back into an AST
+#[derive(Debug, PartialEq)]
+enum Sexpr {
+    Atom(String),
+    String(String),
+    List(Vec<Sexpr>),
+}
+
+struct Reader<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn read(&mut self) -> Result<Sexpr, SexprError> {
+        self.skip_whitespace();
+
+        match self.bump() {
+            None => Err(SexprError::new("unexpected end of input")),
+            Some(')') => Err(SexprError::new("unexpected )")),
+            Some('(') => {
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(')') {
+                        self.bump();
+                        return Ok(Sexpr::List(items));
+                    }
+                    items.push(self.read()?);
+                }
+            }
+            Some('"') => {
+                let mut value = String::new();
+                loop {
+                    match self.bump() {
+                        None => return Err(SexprError::new("unterminated string")),
+                        Some('"') => return Ok(Sexpr::String(value)),
+                        Some('\\') => match self.bump() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                            _ => return Err(SexprError::new("invalid escape in string")),
+                        },
+                        Some(c) => value.push(c),
+                    }
+                }
+            }
+            Some(c) => {
+                let mut atom = c.to_string();
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    atom.push(c);
+                    self.bump();
+                }
+                Ok(Sexpr::Atom(atom))
+            }
+        }
+    }
+}
+
+/// The items of a list after its head, checking the head is the one expected
+fn expect_list<'s>(sexpr: &'s Sexpr, head: &str) -> Result<&'s [Sexpr], SexprError> {
+    match sexpr {
+        Sexpr::List(items) if matches!(items.first(), Some(Sexpr::Atom(h)) if h == head) => {
+            Ok(&items[1..])
+        }
+        _ => Err(SexprError::new(format!("expected a ({} ...) list", head))),
+    }
+}
+
+fn expect_name(sexpr: &Sexpr) -> Result<String, SexprError> {
+    match sexpr {
+        Sexpr::Atom(name) => Ok(name.clone()),
+        _ => Err(SexprError::new("expected a name")),
+    }
+}
+
+fn expect_names(sexpr: &Sexpr) -> Result<Vec<String>, SexprError> {
+    match sexpr {
+        Sexpr::List(items) => items.iter().map(expect_name).collect(),
+        _ => Err(SexprError::new("expected a list of parameters")),
+    }
+}
+
+fn to_block(sexpr: &Sexpr) -> Result<BlockStatement, SexprError> {
+    let statements = expect_list(sexpr, "block")?
+        .iter()
+        .map(to_statement)
+        .collect::<Result<_, _>>()?;
+    Ok(BlockStatement {
+        statements,
+        spans: vec![],
+    })
+}
+
+fn to_statement(sexpr: &Sexpr) -> Result<Statement, SexprError> {
+    if let Sexpr::List(items) = sexpr {
+        match (items.first(), &items[1.min(items.len())..]) {
+            (Some(Sexpr::Atom(head)), [name, value]) if head == "let" => {
+                return Ok(Statement::Let(expect_name(name)?, to_expression(value)?));
+            }
+            (Some(Sexpr::Atom(head)), [name, value]) if head == "assign" => {
+                return Ok(Statement::Assign(expect_name(name)?, to_expression(value)?));
+            }
+            (Some(Sexpr::Atom(head)), [value]) if head == "return" => {
+                return Ok(Statement::Return(Some(to_expression(value)?)));
+            }
+            (Some(Sexpr::Atom(head)), []) if head == "return" => {
+                return Ok(Statement::Return(None));
+            }
+            (Some(Sexpr::Atom(head)), []) if head == "break" => return Ok(Statement::Break),
+            (Some(Sexpr::Atom(head)), []) if head == "continue" => return Ok(Statement::Continue),
+            (Some(Sexpr::Atom(head)), [Sexpr::String(text)]) if head == "comment" => {
+                return Ok(Statement::Comment(text.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Statement::Expression(to_expression(sexpr)?))
+}
+
+fn to_expression(sexpr: &Sexpr) -> Result<Expression, SexprError> {
+    let items = match sexpr {
+        Sexpr::String(value) => return Ok(Expression::String(value.clone())),
+        Sexpr::Atom(atom) => return Ok(to_atom(atom)),
+        Sexpr::List(items) => items,
+    };
+    let Some((Sexpr::Atom(head), args)) = items.split_first() else {
+        return Err(SexprError::new("expected a list headed by a name"));
+    };
+
+    let expression = match (head.as_str(), args) {
+        ("array", elements) => Expression::Array(to_expressions(elements)?),
+        ("index", [left, index]) => Expression::Index(
+            Box::new(to_expression(left)?),
+            Box::new(to_expression(index)?),
+        ),
+        ("hash", pairs) => Expression::Hash(
+            pairs
+                .iter()
+                .map(|pair| match pair {
+                    Sexpr::List(pair) if pair.len() == 2 => {
+                        Ok((to_expression(&pair[0])?, to_expression(&pair[1])?))
+                    }
+                    _ => Err(SexprError::new("expected a (key value) pair")),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        ("if", [condition, consequence, alternative @ ..]) if alternative.len() <= 1 => {
+            Expression::If {
+                condition: Box::new(to_expression(condition)?),
+                consequence: to_block(consequence)?,
+                alternative: alternative.first().map(to_block).transpose()?,
+            }
+        }
+        ("?", [condition, consequence, alternative]) => Expression::Conditional {
+            condition: Box::new(to_expression(condition)?),
+            consequence: Box::new(to_expression(consequence)?),
+            alternative: Box::new(to_expression(alternative)?),
+        },
+        ("while", [condition, body]) => Expression::While {
+            condition: Box::new(to_expression(condition)?),
+            body: to_block(body)?,
+        },
+        ("fn", [parameters, body]) => Expression::Function {
+            parameters: expect_names(parameters)?,
+            body: to_block(body)?,
+        },
+        ("macro", [parameters, body]) => Expression::Macro {
+            parameters: expect_names(parameters)?,
+            body: to_block(body)?,
+        },
+        ("annotated", [value, type_name]) => Expression::Annotated {
+            value: Box::new(to_expression(value)?),
+            type_name: expect_name(type_name)?,
+        },
+        ("call", [function, arguments @ ..]) => Expression::Call {
+            function: Box::new(to_expression(function)?),
+            arguments: to_expressions(arguments)?,
+        },
+        (operator, [right]) => {
+            Expression::Prefix(to_operator(operator)?, Box::new(to_expression(right)?))
+        }
+        (operator, [left, right]) => Expression::Infix(
+            Box::new(to_expression(left)?),
+            to_operator(operator)?,
+            Box::new(to_expression(right)?),
+        ),
+        (head, _) => return Err(SexprError::new(format!("unexpected ({} ...) list", head))),
+    };
+
+    Ok(expression)
+}
+
+fn to_expressions(sexprs: &[Sexpr]) -> Result<Vec<Expression>, SexprError> {
+    sexprs.iter().map(to_expression).collect()
+}
+
+/// An identifier or a literal other than a string
+fn to_atom(atom: &str) -> Expression {
+    match atom {
+        "true" => Expression::Boolean(true),
+        "false" => Expression::Boolean(false),
+        _ => match atom.parse() {
+            Ok(value) => Expression::Integer(value),
+            Err(_) => match atom.parse() {
+                Ok(value) if atom.contains('.') => Expression::Float(value),
+                _ => Expression::Identifier(atom.to_string()),
+            },
+        },
+    }
+}
+
+/// The token for an operator, reusing the lexer so every operator it knows is accepted
+fn to_operator(operator: &str) -> Result<Token<'static>, SexprError> {
+    let mut lexer = Lexer::new(operator);
+    let token = lexer.next_token().into_owned();
+
+    match token {
+        Token::Illegal(_)
+        | Token::Ident(_)
+        | Token::Int(_)
+        | Token::Float(_)
+        | Token::String(_)
+        | Token::Eof => Err(SexprError::new(format!("unknown operator {}", operator))),
+        _ if lexer.next_token() != Token::Eof => {
+            Err(SexprError::new(format!("unknown operator {}", operator)))
+        }
+        token => Ok(token),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        program
+    }
+
+    const PROGRAM: &str = "let add = fn(a, b) { return a + b; };
+let h = {\"one\": 1, true: [1.5, -x]};
+x = add(1, 2) * 3;
+if (x >= 9 && !false) { h[\"one\"] } else { \"a\\n\\\"b\\\"\" };
+while (true) { break; continue; };
+let m = macro(a) { quote(unquote(a) % 2) };
+let y = x > 0 ? h[\"two\"] ?? 2 : 0;";
+
+    #[test]
+    fn test_json_round_trip() {
+        let program = parse(PROGRAM);
+        let json = to_json(&program);
+
+        assert!(json.starts_with(r#"{"statements":[{"Let":["add",{"Function":"#));
+        assert_eq!(from_json(&json).unwrap(), program);
+    }
+
+    #[test]
+    fn test_to_sexpr() {
+        assert_eq!(
+            to_sexpr(&parse(PROGRAM)),
+            r#"(program (let add (fn (a b) (block (return (+ a b))))) (let h (hash ("one" 1) (true (array 1.5 (- x))))) (assign x (* (call add 1 2) 3)) (if (&& (>= x 9) (! false)) (block (index h "one")) (block "a\n\"b\"")) (while true (block (break) (continue))) (let m (macro (a) (block (call quote (% (call unquote a) 2))))) (let y (? (> x 0) (?? (index h "two") 2) 0)))"#
+        );
+    }
+
+    #[test]
+    fn test_sexpr_round_trip() {
+        let program = parse(PROGRAM);
+
+        assert_eq!(from_sexpr(&to_sexpr(&program)).unwrap(), program);
+    }
+
+    #[test]
+    fn test_sexpr_errors() {
+        let tests = vec![
+            ("(program", "unexpected end of input"),
+            ("(program) x", "unexpected input after the program"),
+            ("(block)", "expected a (program ...) list"),
+            ("(program (@ 1 2))", "unknown operator @"),
+            ("(program (fn a (block)))", "expected a list of parameters"),
+            ("(program (if))", "unexpected (if ...) list"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(from_sexpr(input).unwrap_err().to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use crate::ast::{BlockStatement, Expression};
+use crate::builtins;
+use crate::environment::{Env, Environment};
+use crate::object::{Function, Object};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// The state of an interpreter, saved so it can be picked up again later, e.g. after the host
+/// restarts. Every environment the interpreter can reach is kept once, so functions come back
+/// sharing the environments they captured, just as they were.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    envs: Vec<SavedEnv>,
+    /// The index of the environment scripts run in
+    env: usize,
+    /// The index of the environment macros are defined in
+    macro_env: usize,
+    /// The entries of the session the snapshot was taken in, oldest first. Interpreters leave
+    /// it empty, for the REPL or a host to fill in.
+    pub history: Vec<String>,
+}
+
+/// One scope, with the index of the scope enclosing it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SavedEnv {
+    outer: Option<usize>,
+    bindings: Vec<(String, SavedValue)>,
+}
+
+/// A value, with environments replaced by their indices
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum SavedValue {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<SavedValue>),
+    Hash(Vec<(SavedValue, SavedValue)>),
+    Null,
+    Function {
+        parameters: Vec<String>,
+        body: BlockStatement,
+        env: usize,
+    },
+    Macro {
+        parameters: Vec<String>,
+        body: BlockStatement,
+        env: usize,
+    },
+    Builtin(String),
+    /// A function the host registered, which is looked up by name again when restoring
+    Native(String),
+    Quote(Expression),
+}
+
+/// A problem saving or restoring a snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// A snapshot that isn't JSON of the right shape
+    Json(serde_json::Error),
+    /// A value that can't be saved, like a function compiled for the VM
+    Unsupported(String),
+    /// A snapshot that's well formed but doesn't make sense, e.g. because it refers to an
+    /// environment it doesn't contain
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::Json(err) => write!(f, "invalid snapshot: {}", err),
+            SnapshotError::Unsupported(message) | SnapshotError::Invalid(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> SnapshotError {
+        SnapshotError::Json(err)
+    }
+}
+
+impl Snapshot {
+    /// Save everything reachable from an interpreter's environments
+    ///
+    /// * `env` - The environment scripts run in
+    /// * `macro_env` - The environment macros are defined in
+    pub(crate) fn capture(env: &Env, macro_env: &Env) -> Result<Snapshot, SnapshotError> {
+        let mut saver = Saver::default();
+        let env = saver.env(env)?;
+        let macro_env = saver.env(macro_env)?;
+
+        Ok(Snapshot {
+            envs: saver.envs,
+            env,
+            macro_env,
+            history: vec![],
+        })
+    }
+
+    /// Rebuild the environments, returning the one scripts run in and the one macros are
+    /// defined in
+    ///
+    /// * `natives` - Where to find the functions the host registered, by name
+    pub(crate) fn restore(&self, natives: &Env) -> Result<(Env, Env), SnapshotError> {
+        let mut restorer = Restorer {
+            saved: &self.envs,
+            envs: vec![None; self.envs.len()],
+            natives,
+        };
+
+        for i in 0..self.envs.len() {
+            restorer.env(i, 0)?;
+        }
+        let envs = restorer.envs.iter().flatten().cloned().collect::<Vec<_>>();
+
+        for (saved, env) in self.envs.iter().zip(&envs) {
+            for (name, value) in &saved.bindings {
+                let value = restorer.value(value, &envs)?;
+                env.borrow_mut().set(name.clone(), value);
+            }
+        }
+
+        Ok((
+            restorer.get(&envs, self.env)?,
+            restorer.get(&envs, self.macro_env)?,
+        ))
+    }
+
+    /// Write the snapshot as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("snapshots always serialize")
+    }
+
+    /// Read back a snapshot written by `to_json`
+    ///
+    /// * `json` - The JSON to read
+    pub fn from_json(json: &str) -> Result<Snapshot, SnapshotError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Write the snapshot to a file as JSON, replacing anything already there
+    ///
+    /// * `path` - The file to write
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        Ok(fs::write(path, self.to_json())?)
+    }
+
+    /// Read a snapshot from a file written by `save`
+    ///
+    /// * `path` - The file to read
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        Snapshot::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Default)]
+struct Saver {
+    envs: Vec<SavedEnv>,
+    /// The index each environment saved so far was given
+    indices: HashMap<*const RefCell<Environment>, usize>,
+}
+
+impl Saver {
+    /// Save an environment and everything it refers to, unless it's already saved, returning
+    /// its index
+    fn env(&mut self, env: &Env) -> Result<usize, SnapshotError> {
+        if let Some(&i) = self.indices.get(&Rc::as_ptr(env)) {
+            return Ok(i);
+        }
+
+        // The index is taken first, so functions that captured the environment they're bound
+        // in find it
+        let i = self.envs.len();
+        self.indices.insert(Rc::as_ptr(env), i);
+        self.envs.push(SavedEnv {
+            outer: None,
+            bindings: vec![],
+        });
+
+        let env = env.borrow();
+        let outer = env.outer().map(|outer| self.env(outer)).transpose()?;
+        let bindings = env
+            .bindings()
+            .into_iter()
+            .map(|(name, value)| Ok((name.to_string(), self.value(value)?)))
+            .collect::<Result<_, SnapshotError>>()?;
+        self.envs[i] = SavedEnv { outer, bindings };
+
+        Ok(i)
+    }
+
+    fn value(&mut self, value: &Object) -> Result<SavedValue, SnapshotError> {
+        Ok(match value {
+            Object::Integer(value) => SavedValue::Integer(*value),
+            Object::Float(value) => SavedValue::Float(*value),
+            Object::Boolean(value) => SavedValue::Boolean(*value),
+            Object::String(value) => SavedValue::String(value.clone()),
+            Object::Array(elements) => SavedValue::Array(
+                elements
+                    .iter()
+                    .map(|element| self.value(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Object::Hash(pairs) => {
+                let mut pairs = pairs.iter().collect::<Vec<_>>();
+                // Hashes are unordered, so they're sorted to save the same way every time
+                pairs.sort_by_key(|(key, _)| key.to_string());
+                SavedValue::Hash(
+                    pairs
+                        .into_iter()
+                        .map(|(key, value)| {
+                            Ok((self.value(&key.clone().into())?, self.value(value)?))
+                        })
+                        .collect::<Result<_, SnapshotError>>()?,
+                )
+            }
+            Object::Null => SavedValue::Null,
+            Object::Function(function) => SavedValue::Function {
+                parameters: function.parameters.clone(),
+                body: function.body.clone(),
+                env: self.env(&function.env)?,
+            },
+            Object::Macro(function) => SavedValue::Macro {
+                parameters: function.parameters.clone(),
+                body: function.body.clone(),
+                env: self.env(&function.env)?,
+            },
+            Object::Builtin(builtin) => SavedValue::Builtin(builtin.name.to_string()),
+            Object::Native(native) => SavedValue::Native(native.name.clone()),
+            Object::Quote(expression) => SavedValue::Quote(expression.clone()),
+            value => {
+                return Err(SnapshotError::Unsupported(format!(
+                    "a {} can't be saved in a snapshot",
+                    value.type_name()
+                )))
+            }
+        })
+    }
+}
+
+struct Restorer<'a> {
+    saved: &'a [SavedEnv],
+    /// The environments created so far, by index
+    envs: Vec<Option<Env>>,
+    natives: &'a Env,
+}
+
+impl Restorer<'_> {
+    /// Create an environment, after the environments enclosing it
+    ///
+    /// * `i` - Its index
+    /// * `depth` - How many environments it encloses, to catch scopes that enclose themselves
+    fn env(&mut self, i: usize, depth: usize) -> Result<Env, SnapshotError> {
+        if let Some(env) = &self.envs[i] {
+            return Ok(env.clone());
+        }
+        if depth > self.saved.len() {
+            return Err(SnapshotError::Invalid(
+                "an environment in the snapshot encloses itself".to_string(),
+            ));
+        }
+
+        let env = match self.saved[i].outer {
+            Some(outer) if outer < self.saved.len() => {
+                Environment::new_enclosed(self.env(outer, depth + 1)?)
+            }
+            Some(outer) => return Err(missing(outer)),
+            None => Environment::new(),
+        };
+        self.envs[i] = Some(env.clone());
+
+        Ok(env)
+    }
+
+    fn get(&self, envs: &[Env], i: usize) -> Result<Env, SnapshotError> {
+        envs.get(i).cloned().ok_or_else(|| missing(i))
+    }
+
+    fn value(&self, value: &SavedValue, envs: &[Env]) -> Result<Object, SnapshotError> {
+        Ok(match value {
+            SavedValue::Integer(value) => Object::Integer(*value),
+            SavedValue::Float(value) => Object::Float(*value),
+            SavedValue::Boolean(value) => Object::Boolean(*value),
+            SavedValue::String(value) => Object::String(value.clone()),
+            SavedValue::Array(elements) => Object::Array(
+                elements
+                    .iter()
+                    .map(|element| self.value(element, envs))
+                    .collect::<Result<_, _>>()?,
+            ),
+            SavedValue::Hash(pairs) => Object::Hash(
+                pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = self
+                            .value(key, envs)?
+                            .hash_key()
+                            .map_err(|err| SnapshotError::Invalid(err.to_string()))?;
+                        Ok((key, self.value(value, envs)?))
+                    })
+                    .collect::<Result<_, SnapshotError>>()?,
+            ),
+            SavedValue::Null => Object::Null,
+            SavedValue::Function {
+                parameters,
+                body,
+                env,
+            } => Object::Function(self.function(parameters, body, *env, envs)?),
+            SavedValue::Macro {
+                parameters,
+                body,
+                env,
+            } => Object::Macro(self.function(parameters, body, *env, envs)?),
+            SavedValue::Builtin(name) => match builtins::lookup(name) {
+                Some(builtin) => Object::Builtin(builtin),
+                None => return Err(SnapshotError::Invalid(format!("unknown builtin {}", name))),
+            },
+            SavedValue::Native(name) => match self.natives.borrow().get(name) {
+                Some(native @ Object::Native(_)) => native,
+                _ => {
+                    return Err(SnapshotError::Invalid(format!(
+                        "the host function {} must be registered before restoring",
+                        name
+                    )))
+                }
+            },
+            SavedValue::Quote(expression) => Object::Quote(expression.clone()),
+        })
+    }
+
+    fn function(
+        &self,
+        parameters: &[String],
+        body: &BlockStatement,
+        env: usize,
+        envs: &[Env],
+    ) -> Result<Rc<Function>, SnapshotError> {
+        let env = self.get(envs, env)?;
+        Environment::capture(&env);
+
+        Ok(Rc::new(Function {
+            parameters: parameters.to_vec(),
+            body: body.clone(),
+            env,
+        }))
+    }
+}
+
+fn missing(i: usize) -> SnapshotError {
+    SnapshotError::Invalid(format!("no environment {} in the snapshot", i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_round_trip() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("double", |args| {
+            Ok(Object::Integer(i64::try_from(args[0].clone())? * 2))
+        });
+        interpreter
+            .eval_str(
+                "let counter = fn() { let n = 0; fn() { n += 1; n } }();
+counter();
+let fib = fn(n) { n < 2 ? n : fib(n - 1) + fib(n - 2) };
+let data = {\"a\": [1, 2.5, if (false) { 1 }], true: quote(1 + x), 3: len};
+let twice = double;
+let unless = macro(c, a) { quote(if (!(unquote(c))) { unquote(a) }) };",
+            )
+            .unwrap();
+
+        let mut snapshot = interpreter.snapshot().unwrap();
+        snapshot.history = vec!["counter();".to_string()];
+        let json = snapshot.to_json();
+        assert_eq!(Snapshot::from_json(&json).unwrap(), snapshot);
+
+        let mut restored = Interpreter::new();
+        restored.register("double", |args| {
+            Ok(Object::Integer(i64::try_from(args[0].clone())? * 2))
+        });
+        restored
+            .restore(&Snapshot::from_json(&json).unwrap())
+            .unwrap();
+
+        for (input, expected) in [
+            ("counter()", "2"),
+            ("counter()", "3"),
+            ("fib(10)", "55"),
+            (
+                "[data[\"a\"], data[true], data[3](\"abc\")]",
+                "[[1, 2.5, null], QUOTE((1 + x)), 3]",
+            ),
+            ("twice(4)", "8"),
+            ("unless(false, map([1], fn(x) { x + 1 }))", "[2]"),
+        ] {
+            assert_eq!(
+                restored.eval_str(input).unwrap().to_string(),
+                expected,
+                "{}",
+                input
+            );
+        }
+        // The original carries on by itself
+        assert_eq!(
+            interpreter.eval_str("counter()").unwrap(),
+            Object::Integer(2)
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("monkey-rs-snapshot-{}.json", process::id()));
+        let mut interpreter = Interpreter::bare();
+        interpreter.eval_str("let x = 41;").unwrap();
+
+        interpreter.snapshot().unwrap().save(&path).unwrap();
+        let snapshot = Snapshot::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut restored = Interpreter::bare();
+        restored.restore(&snapshot.unwrap()).unwrap();
+        assert_eq!(restored.eval_str("x + 1").unwrap(), Object::Integer(42));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interpreter = Interpreter::bare();
+        interpreter.register("host", |_| Ok(Object::Null));
+        interpreter.eval_str("let f = host;").unwrap();
+        let snapshot = interpreter.snapshot().unwrap();
+
+        let err = Interpreter::bare().restore(&snapshot).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the host function host must be registered before restoring"
+        );
+
+        let json = r#"{"envs":[{"outer":0,"bindings":[]}],"env":0,"macro_env":0,"history":[]}"#;
+        let err = Interpreter::bare()
+            .restore(&Snapshot::from_json(json).unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "an environment in the snapshot encloses itself"
+        );
+
+        let json = r#"{"envs":[],"env":0,"macro_env":0,"history":[]}"#;
+        let err = Interpreter::bare()
+            .restore(&Snapshot::from_json(json).unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no environment 0 in the snapshot");
+
+        assert!(matches!(
+            Snapshot::from_json("{"),
+            Err(SnapshotError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::ast::{Program, Statement};
+use crate::environment::{Env, Environment};
+use crate::evaluator::Evaluator;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+
+/// The parts of the standard library written in Monkey, as bundled into the binary. The rest
+/// are builtins.
+pub const SOURCE: &str = include_str!("stdlib.monkey");
+
+/// Parse the standard library, ready to run ahead of a program
+pub fn program() -> Program {
+    let mut parser = Parser::new(Lexer::new(SOURCE));
+    let program = parser.parse_program();
+    assert!(
+        parser.errors().is_empty(),
+        "the standard library doesn't parse: {:?}",
+        parser.errors()
+    );
+
+    program
+}
+
+/// Evaluate the standard library in an environment of its own, for programs to be evaluated in
+/// an enclosure of
+pub fn env() -> Env {
+    let env = Environment::new();
+    let result = Evaluator::new().eval(&program(), &env);
+    assert!(
+        !result.is_error(),
+        "the standard library failed: {}",
+        result
+    );
+
+    env
+}
+
+/// Whether the standard library binds a name
+///
+/// * `name` - The name to look for
+pub fn defines(name: &str) -> bool {
+    program()
+        .statements
+        .iter()
+        .any(|statement| matches!(statement, Statement::Let(bound, _) if bound == name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+    use crate::script::{run_source, Backend, RunOptions};
+
+    #[test]
+    fn test_stdlib() {
+        let tests = [
+            ("map([1, 2, 3], fn(x) { x * 2 })", "[2, 4, 6]"),
+            ("filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })", "[2, 4]"),
+            ("reduce([1, 2, 3, 4], 0, fn(sum, x) { sum + x })", "10"),
+            (
+                "join(map(split(\"a,b\", \",\"), fn(s) { upper(s) }), \"-\")",
+                "A-B",
+            ),
+            ("map([], fn(x) { x })", "[]"),
+        ];
+
+        for backend in [Backend::Eval, Backend::Vm] {
+            let options = RunOptions {
+                backend,
+                ..RunOptions::default()
+            };
+            for (input, expected) in tests {
+                let result = run_source(input, &options).unwrap();
+                assert_eq!(result.to_string(), expected, "{} on {:?}", input, backend);
+            }
+        }
+
+        assert!(defines("map"));
+        assert!(!defines("len"));
+    }
+
+    #[test]
+    fn test_without_stdlib() {
+        let options = RunOptions {
+            stdlib: false,
+            ..RunOptions::default()
+        };
+        let err = run_source("map([1], fn(x) { x })", &options).unwrap_err();
+
+        assert_eq!(err.to_string(), "ERROR: identifier not found: map");
+        assert_eq!(
+            run_source("type(1)", &options).unwrap(),
+            Object::String("INTEGER".into())
+        );
+    }
+}
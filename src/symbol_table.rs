@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+/// Where a name's value lives at runtime
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Builtin,
+    /// A variable captured from an enclosing function
+    Free,
+    /// The name of the function currently being compiled, for recursive calls
+    Function,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+/// The names bound in one scope of a program being compiled, along with the scope enclosing it
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    outer: Option<Box<SymbolTable>>,
+    store: HashMap<String, Symbol>,
+    num_definitions: usize,
+    /// The variables captured from enclosing functions, in the order they were captured
+    pub free_symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Create a table for a function body nested inside another scope
+    ///
+    /// * `outer` - The enclosing scope
+    pub fn new_enclosed(outer: SymbolTable) -> SymbolTable {
+        SymbolTable {
+            outer: Some(Box::new(outer)),
+            ..SymbolTable::default()
+        }
+    }
+
+    /// Give back the enclosing scope, if this isn't the global one
+    pub fn into_outer(self) -> Option<SymbolTable> {
+        self.outer.map(|outer| *outer)
+    }
+
+    /// How many globals or locals have been defined in this scope
+    pub fn num_definitions(&self) -> usize {
+        self.num_definitions
+    }
+
+    /// Bind a name in this scope, as a global at the top level or a local otherwise. Binding a
+    /// name again in the same scope reuses its slot, so code that already refers to it sees
+    /// the new value, e.g. the condition of a loop whose body rebinds it.
+    ///
+    /// * `name` - The name to bind
+    pub fn define(&mut self, name: &str) -> Symbol {
+        let scope = match self.outer {
+            Some(_) => SymbolScope::Local,
+            None => SymbolScope::Global,
+        };
+
+        if let Some(symbol) = self.store.get(name).filter(|symbol| symbol.scope == scope) {
+            return symbol.clone();
+        }
+
+        let symbol = self.insert(name, scope, self.num_definitions);
+        self.num_definitions += 1;
+        symbol
+    }
+
+    /// Bind the name of a builtin function
+    ///
+    /// * `index` - The builtin's position in the list of builtins
+    /// * `name` - The name it's called by
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> Symbol {
+        self.insert(name, SymbolScope::Builtin, index)
+    }
+
+    /// Bind the name of the function this scope is the body of
+    ///
+    /// * `name` - The name the function was bound to by `let`
+    pub fn define_function_name(&mut self, name: &str) -> Symbol {
+        self.insert(name, SymbolScope::Function, 0)
+    }
+
+    /// Look up a name, searching enclosing scopes from the innermost outwards. Locals of an
+    /// enclosing function are captured as free variables of this one.
+    ///
+    /// * `name` - The name to look up
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+
+        let symbol = self.outer.as_mut()?.resolve(name)?;
+
+        match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => Some(symbol),
+            _ => Some(self.define_free(symbol)),
+        }
+    }
+
+    /// Capture a variable from an enclosing function
+    ///
+    /// * `original` - The symbol as resolved in the enclosing scope
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let symbol = self.insert(&original.name, SymbolScope::Free, self.free_symbols.len());
+        self.free_symbols.push(original);
+        symbol
+    }
+
+    fn insert(&mut self, name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope,
+            index,
+        };
+
+        self.store.insert(name.to_string(), symbol.clone());
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        Symbol {
+            name: name.into(),
+            scope,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_define_and_resolve() {
+        let mut global = SymbolTable::new();
+        global.define("a");
+        global.define_builtin(0, "len");
+
+        let mut local = SymbolTable::new_enclosed(global);
+        local.define("b");
+
+        assert_eq!(
+            local.resolve("a"),
+            Some(symbol("a", SymbolScope::Global, 0))
+        );
+        assert_eq!(local.resolve("b"), Some(symbol("b", SymbolScope::Local, 0)));
+        assert_eq!(
+            local.resolve("len"),
+            Some(symbol("len", SymbolScope::Builtin, 0))
+        );
+        assert_eq!(local.resolve("c"), None);
+    }
+
+    #[test]
+    fn test_resolve_free() {
+        let mut global = SymbolTable::new();
+        global.define("a");
+
+        let mut first = SymbolTable::new_enclosed(global);
+        first.define("b");
+
+        let mut second = SymbolTable::new_enclosed(first);
+        second.define("c");
+
+        assert_eq!(
+            second.resolve("a"),
+            Some(symbol("a", SymbolScope::Global, 0))
+        );
+        assert_eq!(second.resolve("b"), Some(symbol("b", SymbolScope::Free, 0)));
+        assert_eq!(
+            second.resolve("c"),
+            Some(symbol("c", SymbolScope::Local, 0))
+        );
+        assert_eq!(
+            second.free_symbols,
+            vec![symbol("b", SymbolScope::Local, 0)]
+        );
+    }
+
+    #[test]
+    fn test_redefine() {
+        let mut global = SymbolTable::new();
+        global.define("a");
+        global.define("b");
+
+        assert_eq!(global.define("a"), symbol("a", SymbolScope::Global, 0));
+        assert_eq!(global.num_definitions(), 2);
+    }
+
+    #[test]
+    fn test_shadowing_function_name() {
+        let mut local = SymbolTable::new_enclosed(SymbolTable::new());
+        local.define_function_name("a");
+        assert_eq!(
+            local.resolve("a"),
+            Some(symbol("a", SymbolScope::Function, 0))
+        );
+
+        local.define("a");
+        assert_eq!(local.resolve("a"), Some(symbol("a", SymbolScope::Local, 0)));
+    }
+}
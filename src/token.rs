@@ -1,33 +1,60 @@
-#[derive(Debug, Eq, PartialEq)]
-pub enum Token {
-    Illegal(String),
+use std::borrow::Cow;
+use std::fmt;
+
+/// A token read from some source. Payloads borrow from the source where they appear in it
+/// verbatim, and are only owned when the lexer had to rewrite them, e.g. to resolve escapes.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Token<'a> {
+    Illegal(Cow<'a, str>),
     Eof,
 
     // Identifiers & literals
-    Ident(String),
-    Int(String),
+    Ident(Cow<'a, str>),
+    Int(Cow<'a, str>),
+    Float(Cow<'a, str>),
+    String(Cow<'a, str>),
+    /// A `//` or `/* */` comment, including its delimiters. Only lexed when asked for.
+    Comment(Cow<'a, str>),
 
     // Operators
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
     Plus,
     Minus,
     Bang,
     Asterisk,
     Slash,
+    Percent,
 
     Lt,
     Gt,
+    LtEq,
+    GtEq,
 
     Equal,
     NotEqual,
+    And,
+    Or,
+    Coalesce,
+    Question,
 
     // Delimiters
     Comma,
     Semicolon,
+    Colon,
     Lparen,
     Rparen,
     Lbrace,
     Rbrace,
+    Lbracket,
+    Rbracket,
+
+    // Reserved for `${...}` interpolation
+    Dollar,
 
     Function,
     Let,
@@ -36,9 +63,365 @@ pub enum Token {
     Return,
     True,
     False,
+    Macro,
+    While,
+    Break,
+    Continue,
 }
 
-pub fn lookup_ident(ident: &str) -> Token {
+/// The kind of a token without its payload, for passing tokens across an FFI boundary
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenTag {
+    Illegal,
+    Eof,
+    Ident,
+    Int,
+    Float,
+    String,
+    Comment,
+    Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+    Plus,
+    Minus,
+    Bang,
+    Asterisk,
+    Slash,
+    Percent,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Coalesce,
+    Question,
+    Comma,
+    Semicolon,
+    Colon,
+    Lparen,
+    Rparen,
+    Lbrace,
+    Rbrace,
+    Lbracket,
+    Rbracket,
+    Dollar,
+    Function,
+    Let,
+    If,
+    Else,
+    Return,
+    True,
+    False,
+    Macro,
+    While,
+    Break,
+    Continue,
+}
+
+impl<'a> Token<'a> {
+    /// The kind of this token, without its payload
+    pub fn tag(&self) -> TokenTag {
+        match self {
+            Token::Illegal(_) => TokenTag::Illegal,
+            Token::Eof => TokenTag::Eof,
+            Token::Ident(_) => TokenTag::Ident,
+            Token::Int(_) => TokenTag::Int,
+            Token::Float(_) => TokenTag::Float,
+            Token::String(_) => TokenTag::String,
+            Token::Comment(_) => TokenTag::Comment,
+            Token::Assign => TokenTag::Assign,
+            Token::PlusAssign => TokenTag::PlusAssign,
+            Token::MinusAssign => TokenTag::MinusAssign,
+            Token::AsteriskAssign => TokenTag::AsteriskAssign,
+            Token::SlashAssign => TokenTag::SlashAssign,
+            Token::Plus => TokenTag::Plus,
+            Token::Minus => TokenTag::Minus,
+            Token::Bang => TokenTag::Bang,
+            Token::Asterisk => TokenTag::Asterisk,
+            Token::Slash => TokenTag::Slash,
+            Token::Percent => TokenTag::Percent,
+            Token::Lt => TokenTag::Lt,
+            Token::Gt => TokenTag::Gt,
+            Token::LtEq => TokenTag::LtEq,
+            Token::GtEq => TokenTag::GtEq,
+            Token::Equal => TokenTag::Equal,
+            Token::NotEqual => TokenTag::NotEqual,
+            Token::And => TokenTag::And,
+            Token::Or => TokenTag::Or,
+            Token::Coalesce => TokenTag::Coalesce,
+            Token::Question => TokenTag::Question,
+            Token::Comma => TokenTag::Comma,
+            Token::Semicolon => TokenTag::Semicolon,
+            Token::Colon => TokenTag::Colon,
+            Token::Lparen => TokenTag::Lparen,
+            Token::Rparen => TokenTag::Rparen,
+            Token::Lbrace => TokenTag::Lbrace,
+            Token::Rbrace => TokenTag::Rbrace,
+            Token::Lbracket => TokenTag::Lbracket,
+            Token::Rbracket => TokenTag::Rbracket,
+            Token::Dollar => TokenTag::Dollar,
+            Token::Function => TokenTag::Function,
+            Token::Let => TokenTag::Let,
+            Token::If => TokenTag::If,
+            Token::Else => TokenTag::Else,
+            Token::Return => TokenTag::Return,
+            Token::True => TokenTag::True,
+            Token::False => TokenTag::False,
+            Token::Macro => TokenTag::Macro,
+            Token::While => TokenTag::While,
+            Token::Break => TokenTag::Break,
+            Token::Continue => TokenTag::Continue,
+        }
+    }
+
+    /// Rebuild a token from its kind and payload. The payload is ignored for kinds without one.
+    ///
+    /// * `tag` - The kind of token
+    /// * `payload` - The text carried by `Illegal`, `Ident`, `Int`, `Float`, `String` and
+    ///   `Comment` tokens
+    pub fn from_tag(tag: TokenTag, payload: String) -> Token<'a> {
+        match tag {
+            TokenTag::Illegal => Token::Illegal(payload.into()),
+            TokenTag::Eof => Token::Eof,
+            TokenTag::Ident => Token::Ident(payload.into()),
+            TokenTag::Int => Token::Int(payload.into()),
+            TokenTag::Float => Token::Float(payload.into()),
+            TokenTag::String => Token::String(payload.into()),
+            TokenTag::Comment => Token::Comment(payload.into()),
+            TokenTag::Assign => Token::Assign,
+            TokenTag::PlusAssign => Token::PlusAssign,
+            TokenTag::MinusAssign => Token::MinusAssign,
+            TokenTag::AsteriskAssign => Token::AsteriskAssign,
+            TokenTag::SlashAssign => Token::SlashAssign,
+            TokenTag::Plus => Token::Plus,
+            TokenTag::Minus => Token::Minus,
+            TokenTag::Bang => Token::Bang,
+            TokenTag::Asterisk => Token::Asterisk,
+            TokenTag::Slash => Token::Slash,
+            TokenTag::Percent => Token::Percent,
+            TokenTag::Lt => Token::Lt,
+            TokenTag::Gt => Token::Gt,
+            TokenTag::LtEq => Token::LtEq,
+            TokenTag::GtEq => Token::GtEq,
+            TokenTag::Equal => Token::Equal,
+            TokenTag::NotEqual => Token::NotEqual,
+            TokenTag::And => Token::And,
+            TokenTag::Or => Token::Or,
+            TokenTag::Coalesce => Token::Coalesce,
+            TokenTag::Question => Token::Question,
+            TokenTag::Comma => Token::Comma,
+            TokenTag::Semicolon => Token::Semicolon,
+            TokenTag::Colon => Token::Colon,
+            TokenTag::Lparen => Token::Lparen,
+            TokenTag::Rparen => Token::Rparen,
+            TokenTag::Lbrace => Token::Lbrace,
+            TokenTag::Rbrace => Token::Rbrace,
+            TokenTag::Lbracket => Token::Lbracket,
+            TokenTag::Rbracket => Token::Rbracket,
+            TokenTag::Dollar => Token::Dollar,
+            TokenTag::Function => Token::Function,
+            TokenTag::Let => Token::Let,
+            TokenTag::If => Token::If,
+            TokenTag::Else => Token::Else,
+            TokenTag::Return => Token::Return,
+            TokenTag::True => Token::True,
+            TokenTag::False => Token::False,
+            TokenTag::Macro => Token::Macro,
+            TokenTag::While => Token::While,
+            TokenTag::Break => Token::Break,
+            TokenTag::Continue => Token::Continue,
+        }
+    }
+
+    /// Copy any borrowed payload, so the token can outlive the source it was read from
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Illegal(s) => Token::Illegal(Cow::Owned(s.into_owned())),
+            Token::Ident(s) => Token::Ident(Cow::Owned(s.into_owned())),
+            Token::Int(s) => Token::Int(Cow::Owned(s.into_owned())),
+            Token::Float(s) => Token::Float(Cow::Owned(s.into_owned())),
+            Token::String(s) => Token::String(Cow::Owned(s.into_owned())),
+            Token::Comment(s) => Token::Comment(Cow::Owned(s.into_owned())),
+            Token::Eof => Token::Eof,
+            Token::Assign => Token::Assign,
+            Token::PlusAssign => Token::PlusAssign,
+            Token::MinusAssign => Token::MinusAssign,
+            Token::AsteriskAssign => Token::AsteriskAssign,
+            Token::SlashAssign => Token::SlashAssign,
+            Token::Plus => Token::Plus,
+            Token::Minus => Token::Minus,
+            Token::Bang => Token::Bang,
+            Token::Asterisk => Token::Asterisk,
+            Token::Slash => Token::Slash,
+            Token::Percent => Token::Percent,
+            Token::Lt => Token::Lt,
+            Token::Gt => Token::Gt,
+            Token::LtEq => Token::LtEq,
+            Token::GtEq => Token::GtEq,
+            Token::Equal => Token::Equal,
+            Token::NotEqual => Token::NotEqual,
+            Token::And => Token::And,
+            Token::Or => Token::Or,
+            Token::Coalesce => Token::Coalesce,
+            Token::Question => Token::Question,
+            Token::Comma => Token::Comma,
+            Token::Semicolon => Token::Semicolon,
+            Token::Colon => Token::Colon,
+            Token::Lparen => Token::Lparen,
+            Token::Rparen => Token::Rparen,
+            Token::Lbrace => Token::Lbrace,
+            Token::Rbrace => Token::Rbrace,
+            Token::Lbracket => Token::Lbracket,
+            Token::Rbracket => Token::Rbracket,
+            Token::Dollar => Token::Dollar,
+            Token::Function => Token::Function,
+            Token::Let => Token::Let,
+            Token::If => Token::If,
+            Token::Else => Token::Else,
+            Token::Return => Token::Return,
+            Token::True => Token::True,
+            Token::False => Token::False,
+            Token::Macro => Token::Macro,
+            Token::While => Token::While,
+            Token::Break => Token::Break,
+            Token::Continue => Token::Continue,
+        }
+    }
+}
+
+impl fmt::Display for Token<'_> {
+    /// Renders the token as it would appear in source
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Illegal(s)
+            | Token::Ident(s)
+            | Token::Int(s)
+            | Token::Float(s)
+            | Token::Comment(s) => write!(f, "{}", s),
+            Token::String(s) => write!(f, "\"{}\"", escape(s)),
+            Token::Eof => write!(f, "<eof>"),
+            Token::Assign => write!(f, "="),
+            Token::PlusAssign => write!(f, "+="),
+            Token::MinusAssign => write!(f, "-="),
+            Token::AsteriskAssign => write!(f, "*="),
+            Token::SlashAssign => write!(f, "/="),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Bang => write!(f, "!"),
+            Token::Asterisk => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Lt => write!(f, "<"),
+            Token::Gt => write!(f, ">"),
+            Token::LtEq => write!(f, "<="),
+            Token::GtEq => write!(f, ">="),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Coalesce => write!(f, "??"),
+            Token::Question => write!(f, "?"),
+            Token::Comma => write!(f, ","),
+            Token::Semicolon => write!(f, ";"),
+            Token::Colon => write!(f, ":"),
+            Token::Lparen => write!(f, "("),
+            Token::Rparen => write!(f, ")"),
+            Token::Lbrace => write!(f, "{{"),
+            Token::Rbrace => write!(f, "}}"),
+            Token::Lbracket => write!(f, "["),
+            Token::Rbracket => write!(f, "]"),
+            Token::Dollar => write!(f, "$"),
+            Token::Function => write!(f, "fn"),
+            Token::Let => write!(f, "let"),
+            Token::If => write!(f, "if"),
+            Token::Else => write!(f, "else"),
+            Token::Return => write!(f, "return"),
+            Token::True => write!(f, "true"),
+            Token::False => write!(f, "false"),
+            Token::Macro => write!(f, "macro"),
+            Token::While => write!(f, "while"),
+            Token::Break => write!(f, "break"),
+            Token::Continue => write!(f, "continue"),
+        }
+    }
+}
+
+/// Escapes a string's contents so it can be written back out as a string literal, including
+/// any `${` that would otherwise start an interpolation
+///
+/// * `s` - The string to escape
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// A region of the input, measured in chars
+///
+/// * `start` - The offset of the first char. This value is inclusive.
+/// * `end` - The offset after the last char. This value is exclusive.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A place in the input, counted in lines and chars from 1
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A value along with where it was found in the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+    /// The position of the start of `span`
+    pub position: Position,
+}
+
+impl Span {
+    /// The smallest span covering both spans, e.g. from a construct's first token to its last
+    ///
+    /// * `other` - The span to merge with
+    pub fn merge(&self, other: &Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+pub fn lookup_ident(ident: &str) -> Token<'_> {
     match ident {
         "fn" => Token::Function,
         "let" => Token::Let,
@@ -47,6 +430,146 @@ pub fn lookup_ident(ident: &str) -> Token {
         "return" => Token::Return,
         "true" => Token::True,
         "false" => Token::False,
+        "macro" => Token::Macro,
+        "while" => Token::While,
+        "break" => Token::Break,
+        "continue" => Token::Continue,
         id => Token::Ident(id.into()),
     }
 }
+
+/// Describes a token in plain English, e.g. for hover help in an editor
+///
+/// * `token` - The token to explain
+pub fn explain_token(token: &Token) -> String {
+    match token {
+        Token::Illegal(s) => format!("an illegal piece of input `{}`", s),
+        Token::Eof => "the end of the input".into(),
+        Token::Ident(s) => format!("the identifier `{}`, naming a binding", s),
+        Token::Int(s) => format!("an integer literal with value {}", s),
+        Token::Float(s) => format!("a floating point literal with value {}", s),
+        Token::String(s) => format!("a string literal with value \"{}\"", escape(s)),
+        Token::Comment(s) => format!("the comment `{}`", s),
+        Token::Assign => "the assignment operator `=`".into(),
+        Token::PlusAssign => "the compound assignment operator `+=`".into(),
+        Token::MinusAssign => "the compound assignment operator `-=`".into(),
+        Token::AsteriskAssign => "the compound assignment operator `*=`".into(),
+        Token::SlashAssign => "the compound assignment operator `/=`".into(),
+        Token::Plus => "the addition operator `+`".into(),
+        Token::Minus => "the subtraction or negation operator `-`".into(),
+        Token::Bang => "the logical not operator `!`".into(),
+        Token::Asterisk => "the multiplication operator `*`".into(),
+        Token::Slash => "the division operator `/`".into(),
+        Token::Percent => "the remainder operator `%`".into(),
+        Token::Lt => "the less than operator `<`".into(),
+        Token::Gt => "the greater than operator `>`".into(),
+        Token::LtEq => "the less than or equal operator `<=`".into(),
+        Token::GtEq => "the greater than or equal operator `>=`".into(),
+        Token::Equal => "the equality operator `==`".into(),
+        Token::NotEqual => "the inequality operator `!=`".into(),
+        Token::And => "the logical and operator `&&`".into(),
+        Token::Or => "the logical or operator `||`".into(),
+        Token::Coalesce => "the null-coalescing operator `??`".into(),
+        Token::Question => "the question mark `?` starting a conditional's branches".into(),
+        Token::Comma => "a comma `,` separating items in a list".into(),
+        Token::Semicolon => "a semicolon `;` ending a statement".into(),
+        Token::Colon => "a colon `:` separating a hash key from its value".into(),
+        Token::Lparen => "an opening parenthesis `(`".into(),
+        Token::Rparen => "a closing parenthesis `)`".into(),
+        Token::Lbrace => "an opening brace `{` starting a block".into(),
+        Token::Rbrace => "a closing brace `}` ending a block".into(),
+        Token::Lbracket => "an opening bracket `[` starting an array or index".into(),
+        Token::Rbracket => "a closing bracket `]` ending an array or index".into(),
+        Token::Dollar => "the dollar sign `$` starting an interpolation in a string".into(),
+        Token::Function => "the `fn` keyword used to define functions".into(),
+        Token::Let => "the `let` keyword used to declare bindings".into(),
+        Token::If => "the `if` keyword starting a conditional".into(),
+        Token::Else => "the `else` keyword starting a conditional's alternative".into(),
+        Token::Return => "the `return` keyword used to return from a function".into(),
+        Token::True => "the boolean literal `true`".into(),
+        Token::False => "the boolean literal `false`".into(),
+        Token::Macro => "the `macro` keyword used to define macros".into(),
+        Token::While => "the `while` keyword starting a loop".into(),
+        Token::Break => "the `break` keyword used to leave a loop".into(),
+        Token::Continue => {
+            "the `continue` keyword used to skip to the next iteration of a loop".into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", Token::NotEqual), "!=");
+        assert_eq!(format!("{}", Token::Int("5".into())), "5");
+        assert_eq!(format!("{}", Token::Ident("five".into())), "five");
+        assert_eq!(format!("{}", Token::Assign), "=");
+        assert_eq!(format!("{}", Token::Lbrace), "{");
+        assert_eq!(format!("{}", Token::Function), "fn");
+        assert_eq!(format!("{}", Token::Eof), "<eof>");
+        assert_eq!(
+            format!("{}", Token::String("say \"hi\"\n".into())),
+            "\"say \\\"hi\\\"\\n\""
+        );
+        assert_eq!(
+            format!("{}", Token::String("$5 ${x}".into())),
+            "\"$5 \\${x}\""
+        );
+    }
+
+    #[test]
+    fn test_precedence_table() {
+        let precedences: HashMap<Token, u8> = HashMap::from([
+            (Token::Equal, 2),
+            (Token::Lt, 3),
+            (Token::Plus, 4),
+            (Token::Asterisk, 5),
+        ]);
+        let token = Token::Plus;
+
+        assert_eq!(precedences.get(&token.clone()), Some(&4));
+        assert_eq!(precedences.get(&Token::Asterisk), Some(&5));
+        assert_eq!(precedences.get(&Token::Ident("x".into())), None);
+        assert_eq!(token, Token::Plus);
+    }
+
+    #[test]
+    fn test_explain_token() {
+        assert_eq!(explain_token(&Token::Plus), "the addition operator `+`");
+        assert_eq!(
+            explain_token(&Token::Int("5".into())),
+            "an integer literal with value 5"
+        );
+        assert_eq!(
+            explain_token(&Token::Let),
+            "the `let` keyword used to declare bindings"
+        );
+        assert_eq!(
+            explain_token(&Token::Ident("x".into())),
+            "the identifier `x`, naming a binding"
+        );
+    }
+
+    #[test]
+    fn test_span_merge() {
+        let first = Span { start: 0, end: 2 };
+        let last = Span { start: 14, end: 15 };
+
+        assert_eq!(first.merge(&last), Span { start: 0, end: 15 });
+        assert_eq!(last.merge(&first), Span { start: 0, end: 15 });
+    }
+
+    #[test]
+    fn test_position_display() {
+        let position = Position {
+            line: 3,
+            column: 14,
+        };
+
+        assert_eq!(position.to_string(), "line 3, column 14");
+    }
+}
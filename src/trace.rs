@@ -0,0 +1,279 @@
+use crate::environment::{Env, Environment};
+use crate::error::MonkeyError;
+use crate::evaluator::{EvalHook, Evaluator, Node};
+use crate::lexer::Lexer;
+use crate::object::Object;
+use crate::parser::{ParseEvent, Parser};
+use crate::script::{expand, RunOptions};
+use crate::stdlib;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// A value in a line of the trace
+enum Field<'a> {
+    Str(&'a str),
+    Int(usize),
+    Bool(bool),
+}
+
+/// Writes what the interpreter does as JSON lines, one object per event, each with an `event`
+/// field saying what it is:
+///
+/// * `token` - The parser moved on to a token, with its `kind`, `literal`, `line` and `column`
+/// * `enter` and `exit` - The parser started or finished a `rule`, and whether it `parsed`
+/// * `eval` - The evaluator is about to evaluate a `statement` or `expression`, whose `code`
+///   is given, `depth` calls deep
+/// * `result` - What that evaluated to, with its `type` and `value`
+///
+/// Writing stops at the first error, which `finish` returns.
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Create a tracer
+    ///
+    /// * `out` - Where to write the trace
+    pub fn new(out: W) -> Tracer<W> {
+        Tracer { out, error: None }
+    }
+
+    /// Write something the parser did
+    ///
+    /// * `event` - What it did
+    pub fn parse_event(&mut self, event: &ParseEvent) {
+        match event {
+            ParseEvent::Token {
+                token, position, ..
+            } => self.line(&[
+                ("event", Field::Str("token")),
+                ("kind", Field::Str(&format!("{:?}", token.tag()))),
+                ("literal", Field::Str(&token.to_string())),
+                ("line", Field::Int(position.line)),
+                ("column", Field::Int(position.column)),
+            ]),
+            ParseEvent::Enter { rule } => {
+                self.line(&[("event", Field::Str("enter")), ("rule", Field::Str(rule))])
+            }
+            ParseEvent::Exit { rule, parsed } => self.line(&[
+                ("event", Field::Str("exit")),
+                ("rule", Field::Str(rule)),
+                ("parsed", Field::Bool(*parsed)),
+            ]),
+        }
+    }
+
+    /// Flush the trace, returning where it was written, or the first error writing it
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+
+    fn line(&mut self, fields: &[(&str, Field<'_>)]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let mut line = String::from("{");
+        for (i, (name, value)) in fields.iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            push_string(&mut line, name);
+            line.push(':');
+            match value {
+                Field::Str(value) => push_string(&mut line, value),
+                Field::Int(value) => line.push_str(&value.to_string()),
+                Field::Bool(value) => line.push_str(&value.to_string()),
+            }
+        }
+        line.push('}');
+
+        if let Err(err) = writeln!(self.out, "{}", line) {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl<W: Write> EvalHook for Tracer<W> {
+    fn before(&mut self, node: Node<'_>, _env: &Env, depth: usize) {
+        let (kind, code) = describe(node);
+        self.line(&[
+            ("event", Field::Str("eval")),
+            ("node", Field::Str(kind)),
+            ("code", Field::Str(&code)),
+            ("depth", Field::Int(depth)),
+        ]);
+    }
+
+    fn after(&mut self, node: Node<'_>, result: &Object, depth: usize) {
+        let (kind, code) = describe(node);
+        self.line(&[
+            ("event", Field::Str("result")),
+            ("node", Field::Str(kind)),
+            ("code", Field::Str(&code)),
+            ("type", Field::Str(result.type_name())),
+            ("value", Field::Str(&result.to_string())),
+            ("depth", Field::Int(depth)),
+        ]);
+    }
+}
+
+/// Parse and evaluate a whole program, writing a trace of the tokens and rules the parser went
+/// through, then each step of evaluating it. The trace is written even if the program fails to
+/// parse. Only the evaluator can be traced, so the backend in the options is ignored.
+///
+/// * `source` - The program to run
+/// * `options` - How to prepare it
+/// * `out` - Where to write the trace
+pub fn trace_source<W: Write>(
+    source: &str,
+    options: &RunOptions,
+    out: W,
+) -> Result<Object, MonkeyError> {
+    let mut tracer = Tracer::new(out);
+
+    let mut parser = Parser::new(Lexer::new(source)).with_trace();
+    let program = parser.parse_program();
+    for event in parser.take_trace() {
+        tracer.parse_event(&event);
+    }
+
+    let result = if parser.errors().is_empty() {
+        expand(program, options).and_then(|program| {
+            let mut evaluator = Evaluator::with_hook(&mut tracer);
+            if let Some(path) = &options.path {
+                evaluator = evaluator.with_path(path);
+            }
+            if options.stdlib {
+                evaluator = evaluator.with_stdlib(stdlib::env());
+            }
+            evaluator.try_eval(&program, &Environment::new())
+        })
+    } else {
+        Err(MonkeyError::Parse(parser.errors().to_vec()))
+    };
+
+    tracer.finish()?;
+    result
+}
+
+/// Whether a node is a statement or expression, and its code
+fn describe(node: Node<'_>) -> (&'static str, String) {
+    match node {
+        Node::Statement(statement) => ("statement", statement.to_string()),
+        Node::Expression(expression) => ("expression", expression.to_string()),
+    }
+}
+
+/// Push a string onto a line of JSON, quoted and escaped
+fn push_string(line: &mut String, value: &str) {
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(source: &str) -> (Result<Object, MonkeyError>, Vec<String>) {
+        let options = RunOptions {
+            stdlib: false,
+            ..RunOptions::default()
+        };
+        let mut out = vec![];
+        let result = trace_source(source, &options, &mut out);
+        let lines = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+
+        (result, lines)
+    }
+
+    #[test]
+    fn test_trace() {
+        let (result, lines) = trace("let x = 2;\nx * \"a\\n\"");
+
+        assert!(result.is_err());
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"token","kind":"Let","literal":"let","line":1,"column":1}"#,
+                r#"{"event":"enter","rule":"let_statement"}"#,
+                r#"{"event":"token","kind":"Ident","literal":"x","line":1,"column":5}"#,
+                r#"{"event":"token","kind":"Assign","literal":"=","line":1,"column":7}"#,
+                r#"{"event":"token","kind":"Int","literal":"2","line":1,"column":9}"#,
+                r#"{"event":"enter","rule":"integer_literal"}"#,
+                r#"{"event":"exit","rule":"integer_literal","parsed":true}"#,
+                r#"{"event":"token","kind":"Semicolon","literal":";","line":1,"column":10}"#,
+                r#"{"event":"exit","rule":"let_statement","parsed":true}"#,
+                r#"{"event":"token","kind":"Ident","literal":"x","line":2,"column":1}"#,
+                r#"{"event":"enter","rule":"expression_statement"}"#,
+                r#"{"event":"token","kind":"Asterisk","literal":"*","line":2,"column":3}"#,
+                r#"{"event":"enter","rule":"infix_expression"}"#,
+                r#"{"event":"token","kind":"String","literal":"\"a\\n\"","line":2,"column":5}"#,
+                r#"{"event":"enter","rule":"string_literal"}"#,
+                r#"{"event":"exit","rule":"string_literal","parsed":true}"#,
+                r#"{"event":"exit","rule":"infix_expression","parsed":true}"#,
+                r#"{"event":"exit","rule":"expression_statement","parsed":true}"#,
+                r#"{"event":"token","kind":"Eof","literal":"<eof>","line":2,"column":10}"#,
+                r#"{"event":"eval","node":"statement","code":"let x = 2;","depth":0}"#,
+                r#"{"event":"eval","node":"expression","code":"2","depth":0}"#,
+                r#"{"event":"result","node":"expression","code":"2","type":"INTEGER","value":"2","depth":0}"#,
+                r#"{"event":"result","node":"statement","code":"let x = 2;","type":"NULL","value":"null","depth":0}"#,
+                r#"{"event":"eval","node":"statement","code":"(x * \"a\\n\")","depth":0}"#,
+                r#"{"event":"eval","node":"expression","code":"(x * \"a\\n\")","depth":0}"#,
+                r#"{"event":"eval","node":"expression","code":"x","depth":0}"#,
+                r#"{"event":"result","node":"expression","code":"x","type":"INTEGER","value":"2","depth":0}"#,
+                r#"{"event":"eval","node":"expression","code":"\"a\\n\"","depth":0}"#,
+                r#"{"event":"result","node":"expression","code":"\"a\\n\"","type":"STRING","value":"a\n","depth":0}"#,
+                r#"{"event":"result","node":"expression","code":"(x * \"a\\n\")","type":"ERROR","value":"ERROR: type mismatch: INTEGER * STRING","depth":0}"#,
+                r#"{"event":"result","node":"statement","code":"(x * \"a\\n\")","type":"ERROR","value":"ERROR: type mismatch: INTEGER * STRING","depth":0}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_parse_error() {
+        let (result, lines) = trace("let = 1;");
+
+        assert!(matches!(result, Err(MonkeyError::Parse(_))));
+        assert_eq!(
+            lines[..3],
+            [
+                r#"{"event":"token","kind":"Let","literal":"let","line":1,"column":1}"#,
+                r#"{"event":"enter","rule":"let_statement"}"#,
+                r#"{"event":"exit","rule":"let_statement","parsed":false}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_push_string() {
+        let mut line = String::new();
+        push_string(&mut line, "a\"b\\c\n\u{1}é");
+
+        assert_eq!(line, r#""a\"b\\c\n\u0001é""#);
+    }
+}
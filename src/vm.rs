@@ -0,0 +1,691 @@
+use crate::builtins::{self, Context, Output};
+use crate::code::{read_u16, Opcode};
+use crate::compiler::Bytecode;
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::evaluator::{
+    check_type, eval_index_expression, eval_infix_expression, eval_prefix_expression, is_truthy,
+};
+use crate::object::{Closure, CompiledFunction, Object};
+use crate::token::Token;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+/// The most values the stack can hold, across all active calls
+const STACK_SIZE: usize = 2048;
+
+/// One active function call
+#[derive(Debug)]
+struct Frame {
+    closure: Rc<Closure>,
+    /// The position of the next instruction to run
+    ip: usize,
+    /// Where the call's arguments and locals start on the stack
+    base_pointer: usize,
+}
+
+/// A stack machine running compiled bytecode. Operators behave exactly as in the evaluator, so
+/// programs give the same results and errors on either backend.
+#[derive(Debug)]
+pub struct Vm {
+    constants: Vec<Object>,
+    stack: Vec<Object>,
+    globals: Vec<Object>,
+    frames: Vec<Frame>,
+    /// Where builtins like `puts` write
+    output: Output<'static>,
+}
+
+impl Vm {
+    pub fn new(bytecode: Bytecode) -> Vm {
+        let main = Closure {
+            function: Rc::new(CompiledFunction {
+                instructions: bytecode.instructions,
+                num_locals: 0,
+                num_parameters: 0,
+            }),
+            free: vec![],
+        };
+
+        Vm {
+            constants: bytecode.constants,
+            stack: Vec::with_capacity(STACK_SIZE),
+            globals: vec![],
+            frames: vec![Frame {
+                closure: Rc::new(main),
+                ip: 0,
+                base_pointer: 0,
+            }],
+            output: Output::default(),
+        }
+    }
+
+    /// Send what builtins like `puts` write somewhere other than standard output, e.g. to
+    /// capture it
+    ///
+    /// * `output` - Where to write
+    pub fn with_output<W: Write + 'static>(mut self, output: W) -> Vm {
+        self.output = Output::new(output);
+        self
+    }
+
+    /// Run the program to completion, returning its value or the message of the first error
+    pub fn run(&mut self) -> Result<Object, RuntimeError> {
+        self.execute(0)
+    }
+
+    /// Run instructions until the call that left `stop` frames below it returns, giving its
+    /// value
+    ///
+    /// * `stop` - How many frames were active before the call
+    fn execute(&mut self, stop: usize) -> Result<Object, RuntimeError> {
+        loop {
+            let frame = self.frames.last_mut().expect("no active frame");
+            let instructions = &frame.closure.function.instructions.0;
+
+            // Compiled programs always end by returning, but stop cleanly if one doesn't
+            let Some(&byte) = instructions.get(frame.ip) else {
+                return Ok(Object::Null);
+            };
+            frame.ip += 1;
+
+            let op = Opcode::from_byte(byte).ok_or_else(|| {
+                RuntimeError::new(
+                    RuntimeErrorKind::InvalidBytecode,
+                    format!("unknown opcode: {}", byte),
+                )
+            })?;
+
+            match op {
+                Opcode::Constant => {
+                    let index = self.read_u16();
+                    self.push(self.constants[index].clone())?;
+                }
+                Opcode::Pop => {
+                    self.pop();
+                }
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Mod
+                | Opcode::Equal
+                | Opcode::NotEqual
+                | Opcode::GreaterThan
+                | Opcode::LessThan
+                | Opcode::GreaterEqual
+                | Opcode::LessEqual => {
+                    let operator = match op {
+                        Opcode::Add => Token::Plus,
+                        Opcode::Sub => Token::Minus,
+                        Opcode::Mul => Token::Asterisk,
+                        Opcode::Div => Token::Slash,
+                        Opcode::Mod => Token::Percent,
+                        Opcode::Equal => Token::Equal,
+                        Opcode::NotEqual => Token::NotEqual,
+                        Opcode::GreaterThan => Token::Gt,
+                        Opcode::LessThan => Token::Lt,
+                        Opcode::GreaterEqual => Token::GtEq,
+                        _ => Token::LtEq,
+                    };
+
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = check(eval_infix_expression(&operator, left, right))?;
+                    self.push(result)?;
+                }
+                Opcode::True => self.push(Object::Boolean(true))?,
+                Opcode::False => self.push(Object::Boolean(false))?,
+                Opcode::Null => self.push(Object::Null)?,
+                Opcode::Minus | Opcode::Bang | Opcode::Stringify => {
+                    let operator = match op {
+                        Opcode::Minus => Token::Minus,
+                        Opcode::Bang => Token::Bang,
+                        _ => Token::Dollar,
+                    };
+
+                    let right = self.pop();
+                    let result = check(eval_prefix_expression(&operator, right))?;
+                    self.push(result)?;
+                }
+                Opcode::Jump => {
+                    let target = self.read_u16();
+                    self.current_frame().ip = target;
+                }
+                // Keeps a value that isn't null as the result, or drops the null to make way for
+                // the alternative
+                Opcode::JumpNotNull => {
+                    let target = self.read_u16();
+                    if self.stack.last() == Some(&Object::Null) {
+                        self.pop();
+                    } else {
+                        self.current_frame().ip = target;
+                    }
+                }
+                Opcode::JumpNotTruthy => {
+                    let target = self.read_u16();
+                    if !is_truthy(&self.pop()) {
+                        self.current_frame().ip = target;
+                    }
+                }
+                Opcode::SetGlobal => {
+                    let index = self.read_u16();
+                    let value = self.pop();
+
+                    if index >= self.globals.len() {
+                        self.globals.resize(index + 1, Object::Null);
+                    }
+                    self.globals[index] = value;
+                }
+                Opcode::GetGlobal => {
+                    let index = self.read_u16();
+                    self.push(self.globals[index].clone())?;
+                }
+                Opcode::SetLocal => {
+                    let index = self.read_u8();
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.stack[base_pointer + index] = self.pop();
+                }
+                Opcode::GetLocal => {
+                    let index = self.read_u8();
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.push(self.stack[base_pointer + index].clone())?;
+                }
+                Opcode::GetBuiltin => {
+                    let index = self.read_u8();
+                    self.push(Object::Builtin(builtins::all()[index]))?;
+                }
+                Opcode::GetFree => {
+                    let index = self.read_u8();
+                    let value = self.current_frame().closure.free[index].clone();
+                    self.push(value)?;
+                }
+                Opcode::CurrentClosure => {
+                    let closure = self.current_frame().closure.clone();
+                    self.push(Object::Closure(closure))?;
+                }
+                Opcode::Array => {
+                    let len = self.read_u16();
+                    let elements = self.stack.split_off(self.stack.len() - len);
+                    self.push(Object::Array(elements))?;
+                }
+                Opcode::Hash => {
+                    let len = self.read_u16();
+                    let elements = self.stack.split_off(self.stack.len() - len);
+
+                    let mut hash = HashMap::with_capacity(len / 2);
+                    let mut elements = elements.into_iter();
+                    while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+                        let key = match key.hash_key() {
+                            Ok(key) => key,
+                            Err(err) => return check(err).map(|_| Object::Null),
+                        };
+                        hash.insert(key, value);
+                    }
+
+                    self.push(Object::Hash(hash))?;
+                }
+                Opcode::Index => {
+                    let index = self.pop();
+                    let left = self.pop();
+                    let result = check(eval_index_expression(left, index))?;
+                    self.push(result)?;
+                }
+                Opcode::Call => {
+                    let num_args = self.read_u8();
+                    self.call(num_args)?;
+                }
+                Opcode::ReturnValue | Opcode::Return => {
+                    let value = match op {
+                        Opcode::ReturnValue => self.pop(),
+                        _ => Object::Null,
+                    };
+
+                    let frame = self.frames.pop().expect("no active frame");
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+
+                    // Drop the call's arguments and locals along with the function itself
+                    self.stack.truncate(frame.base_pointer - 1);
+                    if self.frames.len() == stop {
+                        return Ok(value);
+                    }
+                    self.push(value)?;
+                }
+                Opcode::CheckType => {
+                    let index = self.read_u16();
+                    let Object::String(type_name) = self.constants[index].clone() else {
+                        return Err(RuntimeError::new(
+                            RuntimeErrorKind::InvalidBytecode,
+                            format!("not a type name: {}", self.constants[index]),
+                        ));
+                    };
+
+                    let value = check(check_type(self.pop(), &type_name))?;
+                    self.push(value)?;
+                }
+                Opcode::Closure => {
+                    let index = self.read_u16();
+                    let num_free = self.read_u8();
+
+                    let Object::CompiledFunction(function) = &self.constants[index] else {
+                        return Err(RuntimeError::new(
+                            RuntimeErrorKind::InvalidBytecode,
+                            format!("not a compiled function: {}", self.constants[index]),
+                        ));
+                    };
+                    let closure = Closure {
+                        function: function.clone(),
+                        free: self.stack.split_off(self.stack.len() - num_free),
+                    };
+
+                    self.push(Object::Closure(Rc::new(closure)))?;
+                }
+            }
+        }
+    }
+
+    /// Call the function sitting below its arguments on the stack
+    ///
+    /// * `num_args` - How many arguments were pushed after the function
+    fn call(&mut self, num_args: usize) -> Result<(), RuntimeError> {
+        let base_pointer = self.stack.len() - num_args;
+
+        match &self.stack[base_pointer - 1] {
+            Object::Closure(closure) => {
+                let closure = closure.clone();
+                let function = &closure.function;
+
+                if function.num_parameters != num_args {
+                    return Err(RuntimeError::new(
+                        RuntimeErrorKind::WrongArgumentCount,
+                        format!(
+                            "wrong number of arguments: want={}, got={}",
+                            function.num_parameters, num_args
+                        ),
+                    ));
+                }
+
+                let stack_top = base_pointer + function.num_locals;
+                if stack_top > STACK_SIZE {
+                    return Err(stack_overflow());
+                }
+                self.stack.resize(stack_top, Object::Null);
+
+                self.frames.push(Frame {
+                    closure,
+                    ip: 0,
+                    base_pointer,
+                });
+            }
+            Object::Builtin(builtin) => {
+                let func = builtin.func;
+                let args = self.stack.split_off(base_pointer);
+                self.pop();
+
+                let result = check(func(args, self))?;
+                self.push(result)?;
+            }
+            Object::Native(native) => {
+                let native = Rc::clone(native);
+                let args = self.stack.split_off(base_pointer);
+                self.pop();
+
+                let result = native.call(&args)?;
+                self.push(result)?;
+            }
+            Object::Memoized(memoized) => {
+                let memoized = Rc::clone(memoized);
+                let args = self.stack.split_off(base_pointer);
+                self.pop();
+
+                let result = check(memoized.call(args, self))?;
+                self.push(result)?;
+            }
+            function => {
+                return Err(RuntimeError::new(
+                    RuntimeErrorKind::NotAFunction,
+                    format!("not a function: {}", function.type_name()),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Call a function with arguments a builtin has, running it to completion
+    ///
+    /// * `function` - The function to call
+    /// * `args` - The arguments to call it with
+    fn call_value(&mut self, function: Object, args: Vec<Object>) -> Result<Object, RuntimeError> {
+        let frames = self.frames.len();
+        let num_args = args.len();
+
+        self.push(function)?;
+        for arg in args {
+            self.push(arg)?;
+        }
+        self.call(num_args)?;
+
+        // Only closures push a frame, builtins leave their value on the stack straight away
+        if self.frames.len() > frames {
+            self.execute(frames)
+        } else {
+            Ok(self.pop())
+        }
+    }
+
+    fn current_frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("no active frame")
+    }
+
+    fn read_u16(&mut self) -> usize {
+        let frame = self.current_frame();
+        let operand = read_u16(&frame.closure.function.instructions.0[frame.ip..]);
+        frame.ip += 2;
+        operand
+    }
+
+    fn read_u8(&mut self) -> usize {
+        let frame = self.current_frame();
+        let operand = frame.closure.function.instructions.0[frame.ip] as usize;
+        frame.ip += 1;
+        operand
+    }
+
+    fn push(&mut self, object: Object) -> Result<(), RuntimeError> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(stack_overflow());
+        }
+
+        self.stack.push(object);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("stack underflow")
+    }
+}
+
+impl Context for Vm {
+    fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    fn call(&mut self, function: Object, args: Vec<Object>) -> Object {
+        self.call_value(function, args)
+            .unwrap_or_else(Object::Error)
+    }
+}
+
+/// Turn an error object from the shared operator implementations into an error
+///
+/// * `result` - The result of an operation
+fn check(result: Object) -> Result<Object, RuntimeError> {
+    match result {
+        Object::Error(err) => Err(err),
+        object => Ok(object),
+    }
+}
+
+fn stack_overflow() -> RuntimeError {
+    RuntimeError::new(RuntimeErrorKind::StackOverflow, "stack overflow")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::Capture;
+    use crate::compiler::Compiler;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(input: &str) -> Result<Object, RuntimeError> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert_eq!(parser.errors(), &[], "input: {}", input);
+
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).expect("program should compile");
+        Vm::new(compiler.bytecode()).run()
+    }
+
+    /// Run a program on both backends, checking they agree
+    fn run_both(input: &str) -> Result<Object, RuntimeError> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        let evaluated = match Evaluator::new().eval(&program, &Environment::new()) {
+            Object::Error(err) => Err(err),
+            object => Ok(object),
+        };
+
+        let result = run(input);
+        assert_eq!(result, evaluated, "input: {}", input);
+        result
+    }
+
+    #[test]
+    fn test_expressions() {
+        for (input, expected) in [
+            ("1 + 2 * 3 - 4 / 2", Object::Integer(5)),
+            ("-5 + 10", Object::Integer(5)),
+            ("-1.5 * 2 + 1", Object::Float(-2.0)),
+            ("10 % 4 >= 2 && 1 <= 0.5", Object::Boolean(false)),
+            (
+                "let fail = fn() { 1 / 0 }; false && fail()",
+                Object::Boolean(false),
+            ),
+            (
+                "let fail = fn() { 1 / 0 }; true || fail()",
+                Object::Boolean(true),
+            ),
+            ("if (false) { 1 } || [1]", Object::Boolean(true)),
+            (
+                "let h = {\"a\": 1}; [h[\"a\"] ?? 2, h[\"b\"] ?? 2]",
+                Object::Array(vec![Object::Integer(1), Object::Integer(2)]),
+            ),
+            ("let fail = fn() { 1 / 0 }; 0 ?? fail()", Object::Integer(0)),
+            (
+                "let fail = fn() { 1 / 0 }; [1 > 2 ? fail() : 3, true ? 4 : fail()]",
+                Object::Array(vec![Object::Integer(3), Object::Integer(4)]),
+            ),
+            (
+                "let x = 5; if (x < 3) { 1 } else if (x < 6) { 2 } else { 3 }",
+                Object::Integer(2),
+            ),
+            ("!(1 < 2) == false", Object::Boolean(true)),
+            ("\"mon\" + \"key\"", Object::String("monkey".into())),
+            (
+                "let x = 3; \"${x} * 2 = ${x * 2}, ${[x, {}]}\"",
+                Object::String("3 * 2 = 6, [3, {}]".into()),
+            ),
+            ("if (1 > 2) { 10 }", Object::Null),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (true) { }", Object::Null),
+            ("if (true) { let a = 1; }", Object::Null),
+            ("[1, 2 + 3][1]", Object::Integer(5)),
+            ("[1][5]", Object::Null),
+            ("{1: 2, \"a\": 3}[\"a\"]", Object::Integer(3)),
+            ("{}[1]", Object::Null),
+            ("let a = 1; let b = a + 1; b", Object::Integer(2)),
+            ("let a = 1;", Object::Null),
+            (
+                "let x = 10; x -= 3; x *= 2; x /= 7; x += 5; x",
+                Object::Integer(7),
+            ),
+            (
+                "let i = 0; let sum = 0; while (i < 4) { i += 1; sum += i; } sum",
+                Object::Integer(10),
+            ),
+            (
+                "let count = 0; let bump = fn() { count += 1; }; bump(); bump(); count",
+                Object::Integer(2),
+            ),
+            (
+                "let x = 1; let f = fn(x) { x = 5; x }; f(2) + x",
+                Object::Integer(6),
+            ),
+            (
+                "let i = 0; while (i < 5) { let i = i + 1; } i",
+                Object::Integer(5),
+            ),
+            (
+                "let i = 0; let sum = 0;
+                while (true) {
+                    let i = i + 1;
+                    if (i > 10) { break; }
+                    if (i % 2 == 0) { continue; }
+                    let sum = sum + i;
+                }
+                sum",
+                Object::Integer(25),
+            ),
+            (
+                "fn(n) { let i = 0; while (i < n) { let i = i + 1; } i }(4)",
+                Object::Integer(4),
+            ),
+        ] {
+            assert_eq!(run_both(input), Ok(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        for (input, expected) in [
+            ("fn() { }()", Object::Null),
+            ("fn(a, b) { return a * b; 0 }(3, 4)", Object::Integer(12)),
+            ("fn() { return; 1 }()", Object::Null),
+            ("let add(a, b) { a + b } add(3, 4)", Object::Integer(7)),
+            ("let x: int = 5; x", Object::Integer(5)),
+            (
+                "let f: fn = fn(n: int) { if (n == 0) { 0 } else { f(n - 1) } }; f(3)",
+                Object::Integer(0),
+            ),
+            (
+                "let g = fn() { let h: fn = fn(n) { n < 1 ? n : h(n - 1) }; h(2) }; g()",
+                Object::Integer(0),
+            ),
+            ("let f = fn(a) { let b = a + 1; b }; f(1) + f(2)", Object::Integer(5)),
+            (
+                "let adder = fn(a) { fn(b) { fn(c) { a + b + c } } }; adder(1)(2)(3)",
+                Object::Integer(6),
+            ),
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+                Object::Integer(610),
+            ),
+            (
+                "let f = fn() { let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(5) }; f()",
+                Object::Integer(0),
+            ),
+            ("if (true) { return 1; } 2", Object::Integer(1)),
+            ("len(\"four\") + first([1, 2])", Object::Integer(5)),
+            (
+                "let g = group_by([1, 2, 3, 4, 5], fn(x) { x % 2 == 0 }); [g[true], g[false]]",
+                Object::Array(vec![
+                    Object::Array(vec![Object::Integer(2), Object::Integer(4)]),
+                    Object::Array(vec![
+                        Object::Integer(1),
+                        Object::Integer(3),
+                        Object::Integer(5),
+                    ]),
+                ]),
+            ),
+            (
+                "len(group_by([\"a\", \"bb\", \"cc\"], len)[2])",
+                Object::Integer(2),
+            ),
+            ("let len = fn(x) { 0 }; len(\"four\")", Object::Integer(0)),
+        ] {
+            assert_eq!(run_both(input), Ok(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        let input =
+            "let i = 0; while (i < 3) { print(i, \" \"); i += 1; } puts(\"\", \"${i} done\")";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).unwrap();
+
+        let capture = Capture::default();
+        let result = Vm::new(compiler.bytecode())
+            .with_output(capture.clone())
+            .run();
+
+        assert_eq!(result, Ok(Object::Null));
+        assert_eq!(capture.take(), "0 1 2 \n3 done\n");
+        assert_eq!(capture.take(), "");
+    }
+
+    #[test]
+    fn test_memoize() {
+        let input = "let add = memoize(fn(a, b) { puts(\"adding ${a} and ${b}\"); a + b });
+[add(1, 2), add(1, 2), add(2, 1), add(1, 2)]";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        let expected = Object::Array(vec![Object::Integer(3); 4]);
+
+        let capture = Capture::default();
+        let evaluated = Evaluator::new()
+            .with_output(capture.clone())
+            .eval(&program, &Environment::new());
+        assert_eq!(evaluated, expected);
+        assert_eq!(capture.take(), "adding 1 and 2\nadding 2 and 1\n");
+
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).unwrap();
+        let result = Vm::new(compiler.bytecode())
+            .with_output(capture.clone())
+            .run();
+        assert_eq!(result, Ok(expected));
+        assert_eq!(capture.take(), "adding 1 and 2\nadding 2 and 1\n");
+
+        // Errors aren't cached, so the function runs again
+        let env = Environment::new();
+        let mut evaluator = Evaluator::new().with_output(capture.clone());
+        let parse = |input| Parser::new(Lexer::new(input)).parse_program();
+        evaluator.eval(
+            &parse("let inverse = memoize(fn(x) { puts(x); 1 / x });"),
+            &env,
+        );
+        for _ in 0..2 {
+            assert!(evaluator.eval(&parse("inverse(0)"), &env).is_error());
+        }
+        assert_eq!(capture.take(), "0\n0\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        for input in [
+            "1 + true",
+            "-true",
+            "\"a\" - \"b\"",
+            "1 / 0",
+            "1 % 0",
+            "let fail = fn() { 1 / 0 }; true && fail()",
+            "9223372036854775807 + 1",
+            "{[1]: 2}",
+            "1[0]",
+            "fn(a) { a }()",
+            "1()",
+            "len(1)",
+            "let a = 5 + true; 10",
+            "let x: int = \"5\"; x",
+            "fn(n: int) { n }(true)",
+            "assert_eq([1], [2], \"lists\")",
+        ] {
+            assert!(run_both(input).is_err(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        assert_eq!(run("let f = fn() { f() + 1 }; f()"), Err(stack_overflow()));
+    }
+}
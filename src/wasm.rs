@@ -0,0 +1,113 @@
+use crate::diagnostic::render_error;
+use crate::error::MonkeyError;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::serialize::to_json;
+use crate::token::{Span, Token, TokenTag};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// The name to report for source passed in from JavaScript
+const SOURCE_FILE: &str = "<playground>";
+
+/// A token as handed to JavaScript
+#[derive(Serialize)]
+struct TokenJson {
+    kind: TokenTag,
+    literal: String,
+    span: Span,
+}
+
+/// Lex some source into a JSON array of `{ kind, literal, span }` objects, ending with `Eof`
+///
+/// * `src` - The source to lex
+#[wasm_bindgen]
+pub fn tokenize(src: &str) -> String {
+    let mut lexer = Lexer::new(src);
+    let mut tokens = Vec::new();
+
+    loop {
+        let (token, span) = lexer.next_spanned();
+        let eof = token == Token::Eof;
+        tokens.push(TokenJson {
+            kind: token.tag(),
+            literal: token.to_string(),
+            span,
+        });
+
+        if eof {
+            break;
+        }
+    }
+
+    serde_json::to_string(&tokens).expect("tokens always serialize")
+}
+
+/// Parse some source into the JSON form of its AST, or throw the rendered parse errors
+///
+/// * `src` - The source to parse
+#[wasm_bindgen]
+pub fn parse_to_json(src: &str) -> Result<String, JsError> {
+    try_parse_to_json(src).map_err(|err| JsError::new(&err))
+}
+
+/// Run some source, returning the value it produced as text, or throw the rendered error
+///
+/// * `src` - The source to run
+#[wasm_bindgen]
+pub fn eval(src: &str) -> Result<String, JsError> {
+    try_eval(src).map_err(|err| JsError::new(&err))
+}
+
+fn try_parse_to_json(src: &str) -> Result<String, String> {
+    let mut parser = Parser::new(Lexer::new(src));
+    let program = parser.parse_program();
+
+    if !parser.errors().is_empty() {
+        let err = MonkeyError::Parse(parser.errors().to_vec());
+        return Err(render_error(&err, SOURCE_FILE, src));
+    }
+
+    Ok(to_json(&program))
+}
+
+fn try_eval(src: &str) -> Result<String, String> {
+    match Interpreter::new().eval_str(src) {
+        Ok(result) => Ok(result.to_string()),
+        Err(err) => Err(render_error(&err, SOURCE_FILE, src)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("let x"),
+            r#"[{"kind":"Let","literal":"let","span":{"start":0,"end":3}},{"kind":"Ident","literal":"x","span":{"start":4,"end":5}},{"kind":"Eof","literal":"<eof>","span":{"start":5,"end":5}}]"#
+        );
+    }
+
+    #[test]
+    fn test_parse_to_json() {
+        assert_eq!(
+            try_parse_to_json("-a;").unwrap(),
+            r#"{"statements":[{"Expression":{"Prefix":["Minus",{"Identifier":"a"}]}}]}"#
+        );
+        assert!(try_parse_to_json("let = 1;")
+            .unwrap_err()
+            .starts_with("parse error: expected next token to be Ident, got = instead"));
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(try_eval("let x = 2; x * 3").unwrap(), "6");
+        assert_eq!(
+            try_eval("1 / 0").unwrap_err(),
+            "runtime error: division by zero\n --> <playground>"
+        );
+    }
+}